@@ -0,0 +1,71 @@
+//! Storages shared by the benchmarks. Every benchmark uses only some of the helpers.
+#![allow(dead_code)]
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use muck::clock::ManualClock;
+use muck::gossip::{setup_storage, Node, NodeConfig, Storage};
+use muck::membership::PeerState;
+use muck::memory_net::MemoryNetwork;
+use muck::node_id::NodeId;
+use muck::snapshot::{SnapshotEntry, StorageSnapshot};
+
+pub const NOW: u64 = 1_700_000_000;
+
+pub fn clock() -> ManualClock {
+    ManualClock::new(NOW)
+}
+
+pub fn id(node: usize) -> NodeId {
+    NodeId::new(format!("node-{}", node)).unwrap()
+}
+
+/// A distinct address for each of 65536 nodes.
+pub fn address(node: usize) -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::from(0x0a00_0000 + node as u32), 8000))
+}
+
+/// The entries of nodes `1..size`, as a peer that knows all of them would export them.
+pub fn peers(size: usize) -> StorageSnapshot {
+    StorageSnapshot {
+        owner: id(1),
+        entries: (1..size)
+            .map(|node| SnapshotEntry {
+                id: id(node),
+                address: address(node),
+                timestamp: NOW - (node % 10) as u64,
+                leaving: false,
+                state: PeerState::Alive,
+                updates_received: 1,
+                duplicates_received: 0,
+                provisional: false,
+            })
+            .collect(),
+        values: vec![],
+    }
+}
+
+/// The storage of node 0, without any peers yet.
+pub fn empty_storage() -> Storage {
+    setup_storage(id(0), address(0), vec![], &clock())
+}
+
+/// The storage of node 0 in a cluster of `size` nodes, once it heard of all of them.
+pub fn storage(size: usize) -> Storage {
+    let mut storage = empty_storage();
+    storage.merge(peers(size), &clock());
+    storage
+}
+
+/// Node 0 of a cluster of `size` nodes on an in-memory network, never run, so that its storage
+/// can be read through the node's own lock.
+pub fn node(size: usize) -> Node {
+    let config = NodeConfig::builder()
+        .id("node-0")
+        .address(address(0).to_string())
+        .memory_network(Arc::new(MemoryNetwork::new()))
+        .build()
+        .unwrap();
+    Node::new(config, storage(size)).unwrap()
+}
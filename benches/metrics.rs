@@ -0,0 +1,35 @@
+//! The cost of one node's share of a metrics sample, which the simulation takes of every node
+//! once a tick, so a tick costs this times the number of nodes. The metrics are computed under
+//! the storage's read lock; `copy` clones the storage first, as the metrics thread used to.
+
+mod common;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use muck::defaults::{DEAD_TIMEOUT, SUSPECT_TIMEOUT};
+
+use common::{clock, node};
+
+fn node_metrics(c: &mut Criterion) {
+    let clock = clock();
+    let mut group = c.benchmark_group("node_metrics");
+    for size in [100, 1000] {
+        let node = node(size);
+        group.bench_with_input(BenchmarkId::new("with_read", size), &node, |b, node| {
+            b.iter(|| {
+                node.storage()
+                    .with_read(|storage| storage.metrics(&clock, SUSPECT_TIMEOUT, DEAD_TIMEOUT))
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("copy", size), &node, |b, node| {
+            b.iter(|| {
+                node.storage()
+                    .snapshot()
+                    .metrics(&clock, SUSPECT_TIMEOUT, DEAD_TIMEOUT)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, node_metrics);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+//! Picking the targets of a forwarded heartbeat. Selection runs against the storage under its
+//! read lock; `clone_then_select` copies the whole storage first, as forwarding used to.
+
+mod common;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use muck::defaults::HEARTBEAT_SPREAD;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use common::{address, id, storage};
+
+fn select_targets(c: &mut Criterion) {
+    let own_id = id(0);
+    let sender = id(1);
+    let excluded_ids = [&own_id, &sender];
+    let excluded_addresses = [address(0), address(1)];
+    let mut group = c.benchmark_group("select_targets");
+    for size in [100, 1000] {
+        let storage = storage(size);
+        let mut rng = StdRng::seed_from_u64(0);
+        group.bench_with_input(BenchmarkId::new("select", size), &storage, |b, storage| {
+            b.iter(|| {
+                storage.select_n_random_addresses(
+                    HEARTBEAT_SPREAD,
+                    &excluded_ids,
+                    &excluded_addresses,
+                    &mut rng,
+                )
+            })
+        });
+        group.bench_with_input(
+            BenchmarkId::new("clone_then_select", size),
+            &storage,
+            |b, storage| {
+                b.iter(|| {
+                    storage.clone().select_n_random_addresses(
+                        HEARTBEAT_SPREAD,
+                        &excluded_ids,
+                        &excluded_addresses,
+                        &mut rng,
+                    )
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, select_targets);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+//! Forwarding a heartbeat to the picked targets over UDP. Targets are selected as parsed
+//! addresses and sent to directly; `reparse` turns each one into a string and parses it back
+//! before sending, as forwarding did while the storage kept addresses as strings.
+
+mod common;
+
+use std::net::{SocketAddr, UdpSocket};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use muck::defaults::HEARTBEAT_SPREAD;
+use muck::gossip::Heartbeat;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use common::{address, clock, empty_storage, id, peers};
+
+const SIZE: usize = 100;
+
+fn forward(c: &mut Criterion) {
+    // the peers listen on loopback, so that every datagram has somewhere to go
+    let receivers: Vec<_> = (1..SIZE)
+        .map(|_| UdpSocket::bind("127.0.0.1:0").unwrap())
+        .collect();
+    let mut snapshot = peers(SIZE);
+    for (entry, receiver) in snapshot.entries.iter_mut().zip(&receivers) {
+        entry.address = receiver.local_addr().unwrap();
+    }
+    let mut storage = empty_storage();
+    storage.merge(snapshot, &clock());
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let message = serde_json::to_vec(&Heartbeat::new(id(0), address(0), &clock())).unwrap();
+    let own_id = id(0);
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut select = || {
+        storage
+            .select_n_random_addresses(HEARTBEAT_SPREAD, &[&own_id], &[address(0)], &mut rng)
+            .unwrap()
+    };
+
+    let mut group = c.benchmark_group("forward");
+    group.bench_function("parsed", |b| {
+        b.iter(|| {
+            for target in select() {
+                socket.send_to(&message, target).unwrap();
+            }
+        })
+    });
+    group.bench_function("reparse", |b| {
+        b.iter(|| {
+            for target in select() {
+                let target: SocketAddr = target.to_string().parse().unwrap();
+                socket.send_to(&message, target).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, forward);
+criterion_main!(benches);
@@ -0,0 +1,73 @@
+//! Inserts from the receive path racing with target selection, on a [`ShardedStorage`]. With a
+//! single shard, every insert and every selection takes the one lock over the one map, like the
+//! node's storage does; more shards let them run side by side.
+
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use muck::clock::Clock;
+use muck::defaults::HEARTBEAT_SPREAD;
+use muck::gossip::Heartbeat;
+use muck::sharded::ShardedStorage;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use common::{address, clock, id, storage};
+
+const SIZE: usize = 1000;
+const WRITERS: usize = 4;
+const READERS: usize = 2;
+const SELECTIONS: usize = 20;
+
+fn concurrent_inserts(c: &mut Criterion) {
+    let clock = clock();
+    let nodes: Vec<_> = (1..SIZE).map(|node| (id(node), address(node))).collect();
+    let own_id = id(0);
+    let mut group = c.benchmark_group("concurrent_inserts");
+    for shards in [1, 16] {
+        let sharded = ShardedStorage::new(storage(SIZE), shards);
+        group.bench_with_input(
+            BenchmarkId::new("shards", shards),
+            &sharded,
+            |b, sharded| {
+                b.iter(|| {
+                    // every round brings a newer heartbeat of every peer
+                    clock.advance(Duration::from_secs(1));
+                    let now = clock.now();
+                    thread::scope(|scope| {
+                        for writer in nodes.chunks(nodes.len().div_ceil(WRITERS)) {
+                            let clock = &clock;
+                            scope.spawn(move || {
+                                for (id, address) in writer {
+                                    let heartbeat = Heartbeat::new(id.clone(), *address, clock);
+                                    sharded.insert(heartbeat, now).unwrap();
+                                }
+                            });
+                        }
+                        for reader in 0..READERS {
+                            let own_id = &own_id;
+                            scope.spawn(move || {
+                                let mut rng = StdRng::seed_from_u64(reader as u64);
+                                for _ in 0..SELECTIONS {
+                                    sharded.select_n_random_addresses(
+                                        HEARTBEAT_SPREAD,
+                                        &[own_id],
+                                        &[address(0)],
+                                        &mut rng,
+                                    );
+                                }
+                            });
+                        }
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_inserts);
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+//! The burst of discovery after startup: a fresh storage learns of every node of the cluster at
+//! once. `hinted` reserves room for the expected cluster size first, as a node configured with
+//! `expected_cluster_size` does; `growing` lets the maps grow entry by entry.
+
+mod common;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+use common::{clock, empty_storage, peers};
+
+fn discover_cluster(c: &mut Criterion) {
+    let clock = clock();
+    let mut group = c.benchmark_group("discover_cluster");
+    for size in [100, 1000] {
+        let peers = peers(size);
+        group.bench_with_input(BenchmarkId::new("hinted", size), &peers, |b, peers| {
+            b.iter_batched(
+                || (empty_storage(), peers.clone()),
+                |(mut storage, peers)| {
+                    storage.reserve_for(size);
+                    storage.merge(peers, &clock)
+                },
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("growing", size), &peers, |b, peers| {
+            b.iter_batched(
+                || (empty_storage(), peers.clone()),
+                |(mut storage, peers)| storage.merge(peers, &clock),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, discover_cluster);
+criterion_main!(benches);
@@ -0,0 +1,61 @@
+//! Readers of one node's storage contending for its lock: the metrics of the simulation and the
+//! node's own target selection read it at once. The node shares its storage behind an `RwLock`;
+//! the `mutex` baseline takes the exclusive lock every reader used to.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use muck::defaults::{DEAD_TIMEOUT, SUSPECT_TIMEOUT};
+
+use common::{clock, node, storage};
+
+const READERS: usize = 4;
+const READS: usize = 100;
+
+fn concurrent_reads(c: &mut Criterion) {
+    let clock = clock();
+    let mut group = c.benchmark_group("concurrent_reads");
+    for size in [100, 1000] {
+        let node = node(size);
+        group.bench_with_input(BenchmarkId::new("rwlock", size), &node, |b, node| {
+            b.iter(|| {
+                thread::scope(|scope| {
+                    for _ in 0..READERS {
+                        scope.spawn(|| {
+                            for _ in 0..READS {
+                                node.storage().with_read(|storage| {
+                                    storage.metrics(&clock, SUSPECT_TIMEOUT, DEAD_TIMEOUT)
+                                });
+                            }
+                        });
+                    }
+                })
+            })
+        });
+        let shared = Arc::new(Mutex::new(storage(size)));
+        group.bench_with_input(BenchmarkId::new("mutex", size), &shared, |b, shared| {
+            b.iter(|| {
+                thread::scope(|scope| {
+                    for _ in 0..READERS {
+                        scope.spawn(|| {
+                            for _ in 0..READS {
+                                shared.lock().unwrap().metrics(
+                                    &clock,
+                                    SUSPECT_TIMEOUT,
+                                    DEAD_TIMEOUT,
+                                );
+                            }
+                        });
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_reads);
+criterion_main!(benches);
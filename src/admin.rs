@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::gossip::HeartbeatError;
+
+// largest slice of a reply body sent in one datagram, escaping can grow it a little on the wire
+pub const ADMIN_CHUNK_SIZE: usize = 8 * 1024;
+// most storage events in a reply to the events query, the latest ones
+pub const ADMIN_EVENT_LIMIT: usize = 1000;
+const ADMIN_RECEIVE_BUFFER: usize = 64 * 1024;
+const ADMIN_CLIENT_ADDRESS: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+
+/// What an admin client can ask a running node. Queries are sent to the node's gossip address
+/// and only answered for sources in the node's admin allowlist.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminQuery {
+    /// the node's member list, see [`crate::gossip::Node::members`]
+    Members,
+    /// the node's message counters, see [`crate::gossip::Node::stats`]
+    Stats,
+    /// every entry of the node's storage, see [`crate::snapshot::StorageSnapshot`]
+    Storage,
+    /// the latest changes to the node's storage, see [`crate::event_log`]
+    Events,
+}
+
+/// Wire format of a query, e.g. `{"admin":"members"}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AdminRequest {
+    pub admin: AdminQuery,
+}
+
+/// One datagram of a reply. The JSON body is split into `chunks` pieces that are concatenated
+/// in `chunk` order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AdminResponse {
+    pub chunk: u32,
+    pub chunks: u32,
+    pub body: String,
+}
+
+/// Splits a reply body into datagrams of at most [`ADMIN_CHUNK_SIZE`] bytes of body each.
+pub(crate) fn response_chunks(body: &str) -> Vec<AdminResponse> {
+    let mut pieces = vec![];
+    let mut rest = body;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(ADMIN_CHUNK_SIZE);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (piece, tail) = rest.split_at(end);
+        pieces.push(piece);
+        rest = tail;
+    }
+    if pieces.is_empty() {
+        pieces.push("");
+    }
+
+    let chunks = pieces.len() as u32;
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(chunk, body)| AdminResponse {
+            chunk: chunk as u32,
+            chunks,
+            body: body.to_string(),
+        })
+        .collect()
+}
+
+/// Sends `query` to the node listening on `target` and waits up to `timeout` for the complete
+/// reply. Nodes don't answer sources outside their allowlist, which shows up as a timeout.
+pub fn query(
+    target: SocketAddr,
+    query: AdminQuery,
+    timeout: Duration,
+) -> Result<serde_json::Value, HeartbeatError> {
+    let socket = UdpSocket::bind(ADMIN_CLIENT_ADDRESS).map_err(|source| HeartbeatError::Bind {
+        address: ADMIN_CLIENT_ADDRESS,
+        source,
+    })?;
+    let request = serde_json::to_vec(&AdminRequest { admin: query })?;
+    socket
+        .send_to(&request, target)
+        .map_err(|source| HeartbeatError::Send { target, source })?;
+
+    let deadline = Instant::now() + timeout;
+    let mut received: BTreeMap<u32, String> = BTreeMap::new();
+    let mut buf = vec![0; ADMIN_RECEIVE_BUFFER];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(HeartbeatError::Timeout { target, timeout });
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let size = match socket.recv_from(&mut buf).map_err(HeartbeatError::from) {
+            Ok((size, _src)) => size,
+            Err(HeartbeatError::WouldBlock) => continue,
+            Err(e) => return Err(e),
+        };
+        let response: AdminResponse =
+            serde_json::from_slice(&buf[..size]).map_err(|source| HeartbeatError::Decode {
+                source,
+                bytes_len: size,
+            })?;
+        let chunks = response.chunks;
+        received.insert(response.chunk, response.body);
+        if received.len() as u32 == chunks {
+            let body: String = received.into_values().collect();
+            return serde_json::from_str(&body).map_err(|source| HeartbeatError::Decode {
+                source,
+                bytes_len: body.len(),
+            });
+        }
+    }
+}
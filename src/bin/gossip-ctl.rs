@@ -0,0 +1,55 @@
+use muck::admin::{self, AdminQuery};
+use std::process::ExitCode;
+use std::time::Duration;
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Asks a running node about its state.
+///
+/// ```sh
+/// gossip-ctl 127.0.0.1:8000 members
+/// gossip-ctl 127.0.0.1:8000 stats
+/// gossip-ctl 127.0.0.1:8000 storage
+/// gossip-ctl 127.0.0.1:8000 events
+/// ```
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [target, query] = args.as_slice() else {
+        eprintln!("usage: gossip-ctl <node address> <members|stats|storage|events>");
+        return ExitCode::FAILURE;
+    };
+    let Ok(target) = target.parse() else {
+        eprintln!(
+            "invalid node address: {}, expected e.g. 127.0.0.1:8000",
+            target
+        );
+        return ExitCode::FAILURE;
+    };
+    let query = match query.as_str() {
+        "members" => AdminQuery::Members,
+        "stats" => AdminQuery::Stats,
+        "storage" => AdminQuery::Storage,
+        "events" => AdminQuery::Events,
+        other => {
+            eprintln!(
+                "unknown query: {}, expected members, stats, storage or events",
+                other
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match admin::query(target, query, REPLY_TIMEOUT) {
+        Ok(reply) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&reply).expect("reply is valid json")
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("query failed: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
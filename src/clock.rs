@@ -0,0 +1,104 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{self, Duration, Instant};
+
+/// Source of the current time, in seconds since the unix epoch. Everything time dependent in a
+/// node (heartbeat timestamps, staleness, suspicion) reads the time through this so that it can
+/// be driven by a [`ManualClock`] instead of the wall clock.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> u64;
+
+    /// The current time in milliseconds since the unix epoch, for measuring how long gossip
+    /// takes to travel. Clocks that only know whole seconds leave the milliseconds at 0.
+    fn now_millis(&self) -> u64 {
+        self.now() * 1000
+    }
+
+    /// Time since a point of the clock's choosing, what a node loop schedules its work by. Unlike
+    /// [`Clock::now`] it is only ever compared with itself, so the system clock measures it with
+    /// an [`Instant`] that doesn't jump when the wall clock is set.
+    fn elapsed(&self) -> Duration {
+        Duration::from_millis(self.now_millis())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn now_millis(&self) -> u64 {
+        time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+
+    fn elapsed(&self) -> Duration {
+        static ORIGIN: OnceLock<Instant> = OnceLock::new();
+        ORIGIN.get_or_init(Instant::now).elapsed()
+    }
+}
+
+/// Clock that only moves when told to.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(now: u64) -> Self {
+        ManualClock {
+            now: AtomicU64::new(now),
+        }
+    }
+
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.fetch_add(by.as_secs(), Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_manual_clock_only_moves_when_told_to() {
+        let clock = ManualClock::new(1_700_000_000);
+        assert_eq!(clock.now(), 1_700_000_000);
+        assert_eq!(clock.now_millis(), 1_700_000_000_000);
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.now(), 1_700_000_005);
+        // only whole seconds count
+        clock.advance(Duration::from_millis(999));
+        assert_eq!(clock.now(), 1_700_000_005);
+        clock.set(10);
+        assert_eq!(clock.now(), 10);
+    }
+
+    #[test]
+    fn the_system_clock_agrees_with_itself() {
+        let clock = SystemClock;
+        let millis = clock.now_millis();
+        let now = clock.now();
+        assert!(now >= millis / 1000);
+        assert!(now - millis / 1000 <= 1);
+    }
+}
@@ -0,0 +1,34 @@
+//! Default values of every node tunable. Used by [`crate::gossip::NodeConfig::default`] and the
+//! builder for anything left unset, and by the simulation binary.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+pub const ADDRESS: &str = "0.0.0.0:8000";
+/// time between two heartbeats of a node
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// number of peers every heartbeat, own or forwarded, is sent to
+pub const HEARTBEAT_SPREAD: usize = 5;
+/// how quickly the forwarding probability drops with the number of times a heartbeat was
+/// received
+pub const DECAY_FACTOR: f64 = 0.8;
+/// longest time the node loop waits between checks of the socket
+pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// a peer is healthy as long as its latest heartbeat is younger than this
+pub const SUSPECT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEAD_TIMEOUT: Duration = Duration::from_secs(60);
+/// a peer whose latest heartbeat is older than this is dropped from storage
+pub const ENTRY_TTL: Duration = Duration::from_secs(300);
+/// how far ahead of the local clock a heartbeat's timestamp may be
+pub const MAX_FUTURE_SKEW: Duration = Duration::from_secs(60);
+/// number of storage events kept for debugging, see [`crate::event_log`]
+pub const EVENT_LOG_CAPACITY: usize = 4096;
+/// number of entry changes kept for incremental consumers, see [`crate::journal`]
+pub const CHANGE_JOURNAL_CAPACITY: usize = 4096;
+pub const LEAVE_LINGER: Duration = Duration::from_millis(200);
+pub const LEAVE_GRACE: Duration = Duration::ZERO;
+/// sources whose admin queries are answered
+pub const ADMIN_ALLOWLIST: [IpAddr; 2] = [
+    IpAddr::V4(Ipv4Addr::LOCALHOST),
+    IpAddr::V6(Ipv6Addr::LOCALHOST),
+];
@@ -0,0 +1,174 @@
+//! Bounded log of the notable changes to a storage, to find out after the fact how a node came to
+//! its view of the cluster. Kept by every [`crate::gossip::Storage`], read with
+//! [`crate::gossip::Storage::recent_events`] or the `events` admin query.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+use crate::membership::PeerState;
+use crate::node_id::NodeId;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StorageEvent {
+    /// clock time of the node when it happened
+    pub at: u64,
+    pub id: NodeId,
+    #[serde(flatten)]
+    pub kind: StorageEventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StorageEventKind {
+    /// first heartbeat of a node not in the storage
+    FirstSeen {
+        timestamp: u64,
+    },
+    /// a newer heartbeat replaced the stored one
+    Updated {
+        from: u64,
+        to: u64,
+    },
+    StateChanged {
+        from: PeerState,
+        to: PeerState,
+    },
+    Removed {
+        cause: RemovalCause,
+    },
+    /// a heartbeat advertising an address that can't be sent to
+    Rejected {
+        address: SocketAddr,
+    },
+    /// a heartbeat from too far in the future, clamped to the bound or rejected
+    FutureTimestamp {
+        timestamp: u64,
+        clamped: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalCause {
+    /// older than the entry ttl
+    Expired,
+    /// dropped to make room, the storage was full
+    Evicted,
+    /// removed through [`crate::gossip::Storage::remove`]
+    Requested,
+}
+
+/// Ring of the latest `capacity` events. The ring grows up to its capacity and then reuses its
+/// slots, and an event shares its node's id with the storage rather than copying it, so
+/// recording an event never allocates once the ring is full. A capacity of 0 records nothing.
+#[derive(Debug, Clone)]
+pub struct EventLog {
+    events: VecDeque<StorageEvent>,
+    capacity: usize,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        EventLog {
+            events: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn record(&mut self, event: StorageEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Changes the capacity, dropping the oldest events that no longer fit.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        let excess = self.events.len().saturating_sub(capacity);
+        self.events.drain(..excess);
+        self.capacity = capacity;
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// The latest `n` events, oldest first.
+    pub fn recent(&self, n: usize) -> Vec<StorageEvent> {
+        let skip = self.events.len().saturating_sub(n);
+        self.events.iter().skip(skip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(at: u64) -> StorageEvent {
+        StorageEvent {
+            at,
+            id: NodeId::new("node-1").unwrap(),
+            kind: StorageEventKind::FirstSeen { timestamp: at },
+        }
+    }
+
+    fn times(events: &[StorageEvent]) -> Vec<u64> {
+        events.iter().map(|event| event.at).collect()
+    }
+
+    #[test]
+    fn the_log_keeps_the_latest_events() {
+        let mut log = EventLog::new(3);
+        for at in 0..5 {
+            log.record(event(at));
+        }
+        assert_eq!(log.len(), 3);
+        assert_eq!(times(&log.recent(10)), [2, 3, 4]);
+        assert_eq!(times(&log.recent(2)), [3, 4]);
+
+        log.set_capacity(1);
+        assert_eq!(times(&log.recent(10)), [4]);
+        log.set_capacity(0);
+        log.record(event(5));
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn events_share_the_id_they_were_recorded_with() {
+        let mut log = EventLog::new(1);
+        let id = NodeId::new("node-1").unwrap();
+        log.record(StorageEvent {
+            id: id.clone(),
+            ..event(0)
+        });
+        let recorded = &log.events[0].id;
+        assert_eq!(recorded.as_str().as_ptr(), id.as_str().as_ptr());
+    }
+
+    #[test]
+    fn events_are_tagged_by_kind() {
+        let json = serde_json::to_string(&StorageEvent {
+            kind: StorageEventKind::Removed {
+                cause: RemovalCause::Expired,
+            },
+            ..event(7)
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            r#"{"at":7,"id":"node-1","kind":"removed","cause":"expired"}"#
+        );
+    }
+}
@@ -0,0 +1,805 @@
+//! Fault injection for simulations. A [`FaultInjector`] shared by the nodes of a simulated
+//! cluster sits between each node and its socket and decides the fate of every datagram the
+//! node sends, so the protocol can be watched under an unreliable network on loopback. Handed
+//! to the nodes with [`crate::gossip::NodeConfigBuilder::faults`]; admin replies are never
+//! touched.
+//!
+//! Partitions cut groups of nodes off from each other for a while, measured from the creation of
+//! the injector on its clock, see [`FaultInjector::clock`]; datagrams already delayed when a
+//! partition starts still arrive.
+//!
+//! Byzantine nodes corrupt some of the datagrams they send on top of that, see [`Misbehavior`],
+//! to check that the honest nodes shrug off what they can't trust.
+//!
+//! Delayed datagrams wait in a queue served by a thread of the injector, which sends them from
+//! a socket of its own once they are due: they arrive from another address than their sender's,
+//! and are still delivered after their sender stopped, like datagrams already on the wire. On a
+//! [`crate::memory_net::MemoryNetwork`] they wait in the inbox of their receiver instead and keep
+//! their sender's address.
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::cmp::{self, Reverse};
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::clock::{Clock, SystemClock};
+use crate::memory_net::MemoryNetwork;
+
+// datagrams a byzantine node remembers to replay later
+const REPLAY_HISTORY: usize = 64;
+// how far ahead of the real one a forged timestamp lies, in seconds
+const FORGED_SKEW: std::ops::RangeInclusive<u64> = 60..=3600;
+
+/// What happens to a datagram, see [`FaultInjector::fate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fate {
+    Deliver,
+    Drop,
+    /// delivered after this long, through [`FaultInjector::hold`]
+    Delay(Duration),
+}
+
+/// How a byzantine node corrupts a datagram it sends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Misbehavior {
+    /// heartbeats claim a timestamp up to an hour ahead of the real one
+    FutureTimestamps,
+    /// heartbeats come under made up node ids
+    PhantomIds,
+    /// one of the node's earlier datagrams is sent instead
+    Replay,
+    /// a random bit of the datagram is flipped
+    FlipBytes,
+}
+
+impl Misbehavior {
+    pub const ALL: [Misbehavior; 4] = [
+        Misbehavior::FutureTimestamps,
+        Misbehavior::PhantomIds,
+        Misbehavior::Replay,
+        Misbehavior::FlipBytes,
+    ];
+}
+
+impl fmt::Display for Misbehavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Misbehavior::FutureTimestamps => "future-timestamps",
+            Misbehavior::PhantomIds => "phantom-ids",
+            Misbehavior::Replay => "replay",
+            Misbehavior::FlipBytes => "flip-bytes",
+        })
+    }
+}
+
+impl std::str::FromStr for Misbehavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Misbehavior::ALL
+            .into_iter()
+            .find(|misbehavior| misbehavior.to_string() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown misbehavior `{}`, expected future-timestamps, phantom-ids, replay or \
+                     flip-bytes",
+                    s
+                )
+            })
+    }
+}
+
+/// The misbehaviors of a byzantine node, one of which corrupts a datagram with probability
+/// `rate`.
+#[derive(Debug, Clone)]
+struct Byzantine {
+    misbehaviors: Vec<Misbehavior>,
+    rate: f64,
+}
+
+/// Counters of a [`FaultInjector`], totals over every node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    /// datagrams the nodes handed to the injector
+    pub datagrams: u64,
+    /// datagrams dropped instead of sent, other than by a partition
+    pub dropped: u64,
+    /// datagrams dropped because a partition separated their sender and receiver
+    pub partitioned: u64,
+    /// datagrams held back before being sent
+    pub delayed: u64,
+    /// sum of the delays of the delayed datagrams
+    pub total_delay: Duration,
+    /// extra copies of datagrams sent
+    pub duplicated: u64,
+    /// datagrams held back to arrive after newer ones, counted in `delayed` too
+    pub reordered: u64,
+    /// heartbeats of byzantine nodes sent with a timestamp from the future
+    pub forged_timestamps: u64,
+    /// heartbeats of byzantine nodes sent under a made up id
+    pub phantoms: u64,
+    /// earlier datagrams byzantine nodes sent again
+    pub replayed: u64,
+    /// datagrams of byzantine nodes with a flipped bit
+    pub flipped: u64,
+}
+
+impl FaultStats {
+    /// Datagrams corrupted by byzantine nodes, whichever way.
+    pub fn tampered(&self) -> u64 {
+        self.forged_timestamps + self.phantoms + self.replayed + self.flipped
+    }
+}
+
+impl FaultStats {
+    pub fn mean_delay(&self) -> Duration {
+        if self.delayed == 0 {
+            return Duration::ZERO;
+        }
+        self.total_delay.div_f64(self.delayed as f64)
+    }
+}
+
+/// Datagrams sent over one direction of a link, and those of them that weren't dropped, see
+/// [`FaultInjector::links`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    pub sent: u64,
+    pub delivered: u64,
+}
+
+impl LinkStats {
+    pub fn delivery_rate(&self) -> f64 {
+        if self.sent == 0 {
+            return 1.0;
+        }
+        self.delivered as f64 / self.sent as f64
+    }
+}
+
+#[derive(Debug, Default)]
+struct LinkCounters {
+    sent: AtomicU64,
+    delivered: AtomicU64,
+}
+
+/// Nodes split into groups that can't reach each other from `from` until `until`. Nodes in none
+/// of the groups aren't affected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    pub groups: Vec<HashSet<SocketAddr>>,
+    /// since the creation of the injector
+    pub from: Duration,
+    /// never heals when unset
+    pub until: Option<Duration>,
+}
+
+impl Partition {
+    pub fn is_active(&self, elapsed: Duration) -> bool {
+        elapsed >= self.from && self.until.is_none_or(|until| elapsed < until)
+    }
+
+    pub fn group_of(&self, address: SocketAddr) -> Option<usize> {
+        self.groups
+            .iter()
+            .position(|group| group.contains(&address))
+    }
+
+    fn separates(&self, from: SocketAddr, to: SocketAddr) -> bool {
+        match (self.group_of(from), self.group_of(to)) {
+            (Some(from), Some(to)) => from != to,
+            _ => false,
+        }
+    }
+}
+
+/// Drops datagrams with a probability given for all of them, for those of one sender, or for
+/// those from one sender to one receiver; the most specific probability applies. Datagrams that
+/// aren't dropped are delayed by a latency, given for all of them or for those from one sender to
+/// one receiver, plus a random jitter; a jitter larger than the gaps between the datagrams makes
+/// them arrive out of order. Nodes are told apart by their addresses. On top of that, every
+/// datagram between the groups of an active [`Partition`] is dropped, partitions may overlap.
+/// A share of the datagrams that make it may be sent more than once, like a network that
+/// duplicates packets, and another share held back for up to a reorder window, so they arrive
+/// after the newer datagrams sent in the meantime.
+///
+/// Links given a loss or latency of their own are watched in both directions: the injector
+/// counts the datagrams sent over each direction and those delivered, so one-way failures show.
+///
+/// Byzantine nodes corrupt a share of the datagrams they send before their fate is decided.
+///
+/// Every sender draws from its own random stream derived from the seed, so as long as each node
+/// sends the same datagrams in the same order, the same ones are dropped and delayed alike.
+#[derive(Debug)]
+pub struct FaultInjector {
+    seed: u64,
+    // bits of the f64 probability, changed by set_loss while the nodes run
+    loss: AtomicU64,
+    source_loss: HashMap<SocketAddr, f64>,
+    pair_loss: HashMap<(SocketAddr, SocketAddr), f64>,
+    latency: Duration,
+    jitter: Duration,
+    pair_latency: HashMap<(SocketAddr, SocketAddr), Duration>,
+    duplication: f64,
+    duplicate_copies: u64,
+    reorder: f64,
+    reorder_window: Duration,
+    // both directions of every link with a loss or latency of its own
+    links: HashMap<(SocketAddr, SocketAddr), LinkCounters>,
+    partitions: RwLock<Vec<Partition>>,
+    byzantine: HashMap<SocketAddr, Byzantine>,
+    // latest datagrams of every byzantine node, for replays
+    history: Mutex<HashMap<SocketAddr, VecDeque<Vec<u8>>>>,
+    clock: Arc<dyn Clock>,
+    // when the injector was created, on the clock's elapsed time
+    started: Duration,
+    rngs: Mutex<HashMap<SocketAddr, StdRng>>,
+    // started with the first delayed datagram
+    queue: OnceLock<Arc<DelayQueue>>,
+    datagrams: AtomicU64,
+    dropped: AtomicU64,
+    partitioned: AtomicU64,
+    delayed: AtomicU64,
+    total_delay_us: AtomicU64,
+    duplicated: AtomicU64,
+    reordered: AtomicU64,
+    forged_timestamps: AtomicU64,
+    phantoms: AtomicU64,
+    replayed: AtomicU64,
+    flipped: AtomicU64,
+}
+
+impl FaultInjector {
+    /// An injector that lets everything through until told otherwise.
+    pub fn new(seed: u64) -> Self {
+        FaultInjector {
+            seed,
+            loss: AtomicU64::new(0.0_f64.to_bits()),
+            source_loss: HashMap::new(),
+            pair_loss: HashMap::new(),
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            pair_latency: HashMap::new(),
+            duplication: 0.0,
+            duplicate_copies: 1,
+            reorder: 0.0,
+            reorder_window: Duration::ZERO,
+            links: HashMap::new(),
+            partitions: RwLock::new(vec![]),
+            byzantine: HashMap::new(),
+            history: Mutex::new(HashMap::new()),
+            clock: Arc::new(SystemClock),
+            started: SystemClock.elapsed(),
+            rngs: Mutex::new(HashMap::new()),
+            queue: OnceLock::new(),
+            datagrams: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            partitioned: AtomicU64::new(0),
+            delayed: AtomicU64::new(0),
+            total_delay_us: AtomicU64::new(0),
+            duplicated: AtomicU64::new(0),
+            reordered: AtomicU64::new(0),
+            forged_timestamps: AtomicU64::new(0),
+            phantoms: AtomicU64::new(0),
+            replayed: AtomicU64::new(0),
+            flipped: AtomicU64::new(0),
+        }
+    }
+
+    /// Drops every datagram with probability `p`, clamped to `0..=1`.
+    pub fn loss(self, p: f64) -> Self {
+        self.set_loss(p);
+        self
+    }
+
+    /// Changes the probability with which every datagram is dropped, for the datagrams sent from
+    /// now on.
+    pub fn set_loss(&self, p: f64) {
+        self.loss.store(probability(p).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Drops the datagrams sent by `from` with probability `p`.
+    pub fn source_loss(mut self, from: SocketAddr, p: f64) -> Self {
+        self.source_loss.insert(from, probability(p));
+        self
+    }
+
+    /// Drops the datagrams sent by `from` to `to` with probability `p`.
+    pub fn pair_loss(mut self, from: SocketAddr, to: SocketAddr, p: f64) -> Self {
+        self.pair_loss.insert((from, to), probability(p));
+        self.watch_link(from, to);
+        self
+    }
+
+    /// Schedules the partitions by `clock` instead of the wall clock, e.g. the simulated time
+    /// of a stepped [`crate::sim_runtime::SimRuntime`]. Delayed datagrams are held on the same
+    /// clock on a [`MemoryNetwork`] with it, over UDP they always wait on the wall clock.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.started = clock.elapsed();
+        self.clock = clock;
+        self
+    }
+
+    /// Delays every datagram by `latency` plus a jitter drawn uniformly up to `jitter`.
+    pub fn latency(mut self, latency: Duration, jitter: Duration) -> Self {
+        self.latency = latency;
+        self.jitter = jitter;
+        self
+    }
+
+    /// Delays the datagrams sent by `from` to `to` by `latency` instead, plus the jitter.
+    pub fn pair_latency(mut self, from: SocketAddr, to: SocketAddr, latency: Duration) -> Self {
+        self.pair_latency.insert((from, to), latency);
+        self.watch_link(from, to);
+        self
+    }
+
+    /// Sends the datagrams that aren't dropped `copies` more times with probability `p`.
+    pub fn duplication(mut self, p: f64, copies: u64) -> Self {
+        self.duplication = probability(p);
+        self.duplicate_copies = copies;
+        self
+    }
+
+    /// Holds back the datagrams that aren't dropped with probability `p`, for an extra delay
+    /// drawn uniformly up to `window`: a window longer than the gaps between a node's datagrams
+    /// makes them arrive after newer ones.
+    pub fn reorder(mut self, p: f64, window: Duration) -> Self {
+        self.reorder = probability(p);
+        self.reorder_window = window;
+        self
+    }
+
+    fn watch_link(&mut self, a: SocketAddr, b: SocketAddr) {
+        self.links.entry((a, b)).or_default();
+        self.links.entry((b, a)).or_default();
+    }
+
+    /// Both directions of every link given a loss or latency of its own, ordered by sender and
+    /// receiver.
+    pub fn links(&self) -> Vec<(SocketAddr, SocketAddr, LinkStats)> {
+        let mut links: Vec<_> = self
+            .links
+            .iter()
+            .map(|(&(from, to), counters)| {
+                let stats = LinkStats {
+                    sent: counters.sent.load(Ordering::Relaxed),
+                    delivered: counters.delivered.load(Ordering::Relaxed),
+                };
+                (from, to, stats)
+            })
+            .collect();
+        links.sort_by_key(|&(from, to, _)| (from, to));
+        links
+    }
+
+    /// Makes `from` byzantine: with probability `rate`, every datagram it sends is corrupted by
+    /// one of `misbehaviors`, picked at random.
+    pub fn byzantine(mut self, from: SocketAddr, misbehaviors: &[Misbehavior], rate: f64) -> Self {
+        self.byzantine.insert(
+            from,
+            Byzantine {
+                misbehaviors: misbehaviors.to_vec(),
+                rate: probability(rate),
+            },
+        );
+        self
+    }
+
+    pub fn is_byzantine(&self, node: SocketAddr) -> bool {
+        self.byzantine.contains_key(&node)
+    }
+
+    pub fn partition(self, partition: Partition) -> Self {
+        self.add_partition(partition);
+        self
+    }
+
+    /// Adds a partition while the nodes run, e.g. one starting right away at
+    /// [`FaultInjector::elapsed`].
+    pub fn add_partition(&self, partition: Partition) {
+        self.partitions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(partition);
+    }
+
+    /// Every partition so far, in the order they were added.
+    pub fn partitions(&self) -> Vec<Partition> {
+        self.partitions
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Time since the injector was created, the clock partitions are scheduled by.
+    pub fn elapsed(&self) -> Duration {
+        self.clock.elapsed().saturating_sub(self.started)
+    }
+
+    /// Decides what happens to the datagram `from` is about to send to `to`.
+    pub fn fate(&self, from: SocketAddr, to: SocketAddr) -> Fate {
+        let fate = self.decide(from, to);
+        if let Some(link) = self.links.get(&(from, to)) {
+            link.sent.fetch_add(1, Ordering::Relaxed);
+            if fate != Fate::Drop {
+                link.delivered.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        fate
+    }
+
+    fn decide(&self, from: SocketAddr, to: SocketAddr) -> Fate {
+        self.datagrams.fetch_add(1, Ordering::Relaxed);
+        let elapsed = self.elapsed();
+        let separated = self
+            .partitions
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .any(|partition| partition.is_active(elapsed) && partition.separates(from, to));
+        if separated {
+            self.partitioned.fetch_add(1, Ordering::Relaxed);
+            return Fate::Drop;
+        }
+        let p = self
+            .pair_loss
+            .get(&(from, to))
+            .or_else(|| self.source_loss.get(&from))
+            .copied()
+            .unwrap_or_else(|| f64::from_bits(self.loss.load(Ordering::Relaxed)));
+        let latency = self
+            .pair_latency
+            .get(&(from, to))
+            .copied()
+            .unwrap_or(self.latency);
+        if p <= 0.0 && latency.is_zero() && self.jitter.is_zero() && self.reorder <= 0.0 {
+            return Fate::Deliver;
+        }
+
+        let (dropped, delay, reordered) = {
+            let mut rngs = self.rngs();
+            let rng = rngs
+                .entry(from)
+                .or_insert_with(|| StdRng::seed_from_u64(self.stream_seed(from)));
+            let dropped = p > 0.0 && rng.gen_bool(p);
+            let jitter = if self.jitter.is_zero() {
+                Duration::ZERO
+            } else {
+                rng.gen_range(Duration::ZERO..=self.jitter)
+            };
+            let held_back = if self.reorder > 0.0 && rng.gen_bool(self.reorder) {
+                Some(rng.gen_range(Duration::ZERO..=self.reorder_window))
+            } else {
+                None
+            };
+            (dropped, latency + jitter, held_back)
+        };
+        if dropped {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Fate::Drop;
+        }
+        let delay = match reordered {
+            Some(held_back) => {
+                self.reordered.fetch_add(1, Ordering::Relaxed);
+                delay + held_back
+            }
+            None => delay,
+        };
+        if delay.is_zero() {
+            return Fate::Deliver;
+        }
+        self.delayed.fetch_add(1, Ordering::Relaxed);
+        self.total_delay_us
+            .fetch_add(delay.as_micros() as u64, Ordering::Relaxed);
+        Fate::Delay(delay)
+    }
+
+    /// How many more copies of a datagram `from` sends that wasn't dropped, to be sent like the
+    /// datagram itself.
+    pub fn duplicates(&self, from: SocketAddr) -> u64 {
+        if self.duplication <= 0.0 {
+            return 0;
+        }
+        let duplicated = {
+            let mut rngs = self.rngs();
+            let rng = rngs
+                .entry(from)
+                .or_insert_with(|| StdRng::seed_from_u64(self.stream_seed(from)));
+            rng.gen_bool(self.duplication)
+        };
+        if !duplicated {
+            return 0;
+        }
+        self.duplicated
+            .fetch_add(self.duplicate_copies, Ordering::Relaxed);
+        self.duplicate_copies
+    }
+
+    /// The datagram `from` sends instead of `message` if it is byzantine and corrupts this one,
+    /// None if `message` goes out as it is. Misbehaviors that only apply to heartbeats leave
+    /// other datagrams alone.
+    pub fn tamper(&self, from: SocketAddr, message: &[u8]) -> Option<Vec<u8>> {
+        let byzantine = self.byzantine.get(&from)?;
+        let (misbehavior, draw) = {
+            let mut rngs = self.rngs();
+            let rng = rngs
+                .entry(from)
+                .or_insert_with(|| StdRng::seed_from_u64(self.stream_seed(from)));
+            let misbehavior = match byzantine.misbehaviors.choose(rng) {
+                Some(&misbehavior) if rng.gen_bool(byzantine.rate) => Some(misbehavior),
+                _ => None,
+            };
+            (misbehavior, rng.gen::<u64>())
+        };
+
+        let replay = {
+            let mut history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+            let sent = history.entry(from).or_default();
+            let replay = (!sent.is_empty()).then(|| sent[draw as usize % sent.len()].clone());
+            if sent.len() == REPLAY_HISTORY {
+                sent.pop_front();
+            }
+            sent.push_back(message.to_vec());
+            replay
+        };
+
+        let (tampered, counter) = match misbehavior? {
+            Misbehavior::FutureTimestamps => (
+                rewrite_heartbeat(message, |heartbeat| {
+                    let skew =
+                        FORGED_SKEW.start() + draw % (FORGED_SKEW.end() - FORGED_SKEW.start() + 1);
+                    let timestamp = heartbeat.get("timestamp")?.as_u64()?;
+                    heartbeat.insert("timestamp".to_string(), (timestamp + skew).into());
+                    Some(())
+                })?,
+                &self.forged_timestamps,
+            ),
+            Misbehavior::PhantomIds => (
+                rewrite_heartbeat(message, |heartbeat| {
+                    heartbeat.insert("id".to_string(), format!("phantom-{}", draw as u32).into());
+                    Some(())
+                })?,
+                &self.phantoms,
+            ),
+            Misbehavior::Replay => (replay?, &self.replayed),
+            Misbehavior::FlipBytes => {
+                let mut flipped = message.to_vec();
+                if flipped.is_empty() {
+                    return None;
+                }
+                let position = (draw >> 3) as usize % flipped.len();
+                flipped[position] ^= 1 << (draw & 7);
+                (flipped, &self.flipped)
+            }
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        Some(tampered)
+    }
+
+    /// Sends `message` to `to` once `delay` has passed.
+    pub fn hold(&self, message: Vec<u8>, to: SocketAddr, delay: Duration) {
+        self.queue
+            .get_or_init(DelayQueue::start)
+            .push(Instant::now() + delay, message, to);
+    }
+
+    /// Delivers `message` from `from` to `to` on `network` once `delay` has passed. Unlike
+    /// over UDP, the datagram still arrives from its sender's address.
+    pub fn hold_in(
+        &self,
+        network: &MemoryNetwork,
+        message: Vec<u8>,
+        from: SocketAddr,
+        to: SocketAddr,
+        delay: Duration,
+    ) {
+        network.deliver_after(&message, from, to, delay);
+    }
+
+    pub fn stats(&self) -> FaultStats {
+        FaultStats {
+            datagrams: self.datagrams.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            partitioned: self.partitioned.load(Ordering::Relaxed),
+            delayed: self.delayed.load(Ordering::Relaxed),
+            total_delay: Duration::from_micros(self.total_delay_us.load(Ordering::Relaxed)),
+            duplicated: self.duplicated.load(Ordering::Relaxed),
+            reordered: self.reordered.load(Ordering::Relaxed),
+            forged_timestamps: self.forged_timestamps.load(Ordering::Relaxed),
+            phantoms: self.phantoms.load(Ordering::Relaxed),
+            replayed: self.replayed.load(Ordering::Relaxed),
+            flipped: self.flipped.load(Ordering::Relaxed),
+        }
+    }
+
+    fn rngs(&self) -> MutexGuard<'_, HashMap<SocketAddr, StdRng>> {
+        self.rngs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn stream_seed(&self, from: SocketAddr) -> u64 {
+        // DefaultHasher::new is keyed the same in every process
+        let mut hasher = DefaultHasher::new();
+        (self.seed, from).hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl Drop for FaultInjector {
+    fn drop(&mut self) {
+        // datagrams still waiting are lost with the network they were on
+        if let Some(queue) = self.queue.get() {
+            queue.close();
+        }
+    }
+}
+
+/// Datagrams waiting for their delivery time, earliest first, and the thread delivering them.
+#[derive(Debug, Default)]
+struct DelayQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    pending: BinaryHeap<Reverse<Held>>,
+    sequence: u64,
+    closed: bool,
+}
+
+/// A delayed datagram, ordered by when it is due.
+#[derive(Debug)]
+struct Held {
+    due: Instant,
+    // keeps datagrams due at the same instant in the order they were sent
+    sequence: u64,
+    to: SocketAddr,
+    message: Vec<u8>,
+}
+
+impl PartialEq for Held {
+    fn eq(&self, other: &Self) -> bool {
+        (self.due, self.sequence) == (other.due, other.sequence)
+    }
+}
+
+impl Eq for Held {}
+
+impl PartialOrd for Held {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Held {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (self.due, self.sequence).cmp(&(other.due, other.sequence))
+    }
+}
+
+impl DelayQueue {
+    fn start() -> Arc<Self> {
+        let queue = Arc::new(DelayQueue::default());
+        let serving = queue.clone();
+        thread::Builder::new()
+            .name("fault-delay".to_string())
+            .spawn(move || serving.serve())
+            .expect("failed to spawn the delay queue thread");
+        queue
+    }
+
+    fn state(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn push(&self, due: Instant, message: Vec<u8>, to: SocketAddr) {
+        let mut state = self.state();
+        let sequence = state.sequence;
+        state.sequence += 1;
+        state.pending.push(Reverse(Held {
+            due,
+            sequence,
+            to,
+            message,
+        }));
+        self.changed.notify_one();
+    }
+
+    fn close(&self) {
+        self.state().closed = true;
+        self.changed.notify_one();
+    }
+
+    fn serve(&self) {
+        let mut sockets: HashMap<bool, UdpSocket> = HashMap::new();
+        let mut state = self.state();
+        while !state.closed {
+            let now = Instant::now();
+            match state.pending.peek() {
+                Some(Reverse(held)) if held.due <= now => {
+                    let Some(Reverse(Held { to, message, .. })) = state.pending.pop() else {
+                        continue;
+                    };
+                    // sending doesn't block, the lock can be kept
+                    if let Err(e) = send_from(&mut sockets, &message, to) {
+                        debug!(
+                            target = to.to_string(),
+                            error = e.to_string(),
+                            "failed to deliver delayed datagram"
+                        );
+                    }
+                }
+                Some(Reverse(held)) => {
+                    let wait = held.due - now;
+                    state = self
+                        .changed
+                        .wait_timeout(state, wait)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0;
+                }
+                None => {
+                    state = self
+                        .changed
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            }
+        }
+    }
+}
+
+/// Sends from a socket of the queue bound for the address family of `to`.
+fn send_from(
+    sockets: &mut HashMap<bool, UdpSocket>,
+    message: &[u8],
+    to: SocketAddr,
+) -> io::Result<()> {
+    let socket = match sockets.entry(to.is_ipv4()) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let any: SocketAddr = if to.is_ipv4() {
+                ([0, 0, 0, 0], 0).into()
+            } else {
+                ([0; 16], 0).into()
+            };
+            let socket = UdpSocket::bind(any)?;
+            socket.set_nonblocking(true)?;
+            entry.insert(socket)
+        }
+    };
+    socket.send_to(message, to).map(|_| ())
+}
+
+/// Re-encodes `message` with `rewrite` applied to its fields, if it is a heartbeat.
+fn rewrite_heartbeat(
+    message: &[u8],
+    rewrite: impl FnOnce(&mut serde_json::Map<String, serde_json::Value>) -> Option<()>,
+) -> Option<Vec<u8>> {
+    let mut datagram: serde_json::Value = serde_json::from_slice(message).ok()?;
+    let heartbeat = datagram.as_object_mut()?;
+    if !heartbeat.contains_key("id") || !heartbeat.contains_key("timestamp") {
+        return None;
+    }
+    rewrite(heartbeat)?;
+    serde_json::to_vec(&datagram).ok()
+}
+
+fn probability(p: f64) -> f64 {
+    if p.is_nan() {
+        0.0
+    } else {
+        p.clamp(0.0, 1.0)
+    }
+}
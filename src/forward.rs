@@ -0,0 +1,49 @@
+use rand::{Rng, RngCore};
+use std::fmt::Debug;
+
+/// Decides whether a node passes on a heartbeat it just received. Set on a running node with
+/// [`crate::gossip::Node::set_forward_policy`].
+pub trait ForwardPolicy: Debug + Send + Sync {
+    /// `received_count` is how often the node has now seen this heartbeat, `decay_factor` the
+    /// node's current decay factor.
+    fn should_forward(&self, received_count: u64, decay_factor: f64, rng: &mut dyn RngCore)
+        -> bool;
+
+    fn name(&self) -> &'static str;
+}
+
+/// The default policy: forwards with probability `exp(-decay_factor * received_count)`, so a
+/// heartbeat that keeps coming back is passed on less and less.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DecayPolicy;
+
+impl ForwardPolicy for DecayPolicy {
+    fn should_forward(
+        &self,
+        received_count: u64,
+        decay_factor: f64,
+        rng: &mut dyn RngCore,
+    ) -> bool {
+        let probability = f64::exp(-decay_factor * received_count as f64);
+        rng.gen::<f64>() < probability
+    }
+
+    fn name(&self) -> &'static str {
+        "decay"
+    }
+}
+
+/// Never forwards. A node using it still sends its own heartbeats and takes in everybody
+/// else's, which suits leaf nodes that should know the cluster without amplifying its traffic.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DisabledPolicy;
+
+impl ForwardPolicy for DisabledPolicy {
+    fn should_forward(&self, _: u64, _: f64, _: &mut dyn RngCore) -> bool {
+        false
+    }
+
+    fn name(&self) -> &'static str {
+        "disabled"
+    }
+}
@@ -1,495 +1,5232 @@
-use rand::Rng;
-use rand::{seq::SliceRandom, thread_rng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
-use serde_json;
 use serde_json::Error as SerdeError;
-use std::collections::HashMap;
+use std::any::Any;
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::f64;
+use std::hash::{BuildHasherDefault, DefaultHasher};
 use std::io::{self};
-use std::net::UdpSocket;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex, PoisonError};
-use std::time::Duration;
-use std::{f64, fmt};
-use std::{thread, time};
-use tracing::{error, info, span, Level};
+use std::net::{AddrParseError, IpAddr, SocketAddr, UdpSocket};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{
+    Arc, Condvar, LockResult, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard,
+    RwLockWriteGuard,
+};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::{debug, error, info, info_span, warn, Span};
+use uuid::Uuid;
 
-pub struct Node {
-    id: String,
-    address: String,
-    shared_storage: Arc<Mutex<Storage>>,
-    shared_channel: Arc<Mutex<UdapChannel>>,
-    heartbeat_interval_secs: u64,
-    heartbeat_spread: usize,
-    poll_interval_milisecs: u64,
-    decay_factor: f64,
-    is_alive: Arc<AtomicBool>,
+use crate::admin::{self, AdminQuery, AdminRequest, AdminResponse};
+use crate::clock::{Clock, SystemClock};
+use crate::defaults;
+use crate::event_log::{EventLog, RemovalCause, StorageEvent, StorageEventKind};
+use crate::faults::{Fate, FaultInjector};
+use crate::forward::{DecayPolicy, ForwardPolicy};
+use crate::health::{HealthIssue, HealthReport, FAILED_SENDS_THRESHOLD};
+use crate::journal::{ChangeJournal, ChangeKind, ChangeRecord, ResyncRequired};
+use crate::kv::{KeyValue, KvStore};
+use crate::membership::{
+    AgeSource, ConvergenceChange, ConvergenceEvent, ConvergenceWatch, EventBus, MemberInfo,
+    MembershipEvent, MembershipTracker, PeerChange, PeerState, StateTransition, Subscription,
+    STATE_HISTORY_LEN,
+};
+use crate::memory_net::{MemoryNetwork, MemorySocket};
+use crate::node_id::{NodeId, NodeIdError};
+use crate::sim_runtime::{Scheduler, SimRuntime, Task};
+use crate::snapshot::{self, SnapshotEntry, SnapshotError, StorageSnapshot, SNAPSHOT_INTERVAL};
+use crate::stats::{
+    AgeHistogram, ByteCounters, LatencyHistogram, NodeStats, StatsCounters, StorageMetrics,
+};
+use crate::summary::{EntrySummary, NodeSummary, StorageSummary, SUMMARY_DETAIL_LIMIT};
+use crate::trace::HeartbeatTrace;
+
+// each random stream of a node is seeded with rng_seed + stream * RNG_STREAM_STRIDE
+const RNG_STREAM_STRIDE: u64 = 0x9E37_79B9_7F4A_7C15;
+const HEARTBEAT_RNG_STREAM: u64 = 0;
+const GOSSIP_RNG_STREAM: u64 = 1;
+const ANNOUNCE_RNG_STREAM: u64 = 2;
+// largest heartbeat, value or admin query a node sends or receives, larger ones would be cut off
+pub(crate) const MAX_DATAGRAM_SIZE: usize = 256;
+// how often the node loop re-evaluates the health of known peers
+const MEMBERSHIP_EVAL_INTERVAL: Duration = Duration::from_secs(1);
+// how often the node's own entry is renewed, independent of its heartbeats
+const SELF_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    pub id: NodeId,
+    pub address: SocketAddr,
+    pub heartbeat_interval: Duration,
+    pub heartbeat_spread: usize,
+    pub poll_interval: Duration,
+    pub decay_factor: f64,
+    /// a peer whose latest heartbeat is older than this is reported as suspect
+    pub suspect_timeout: Duration,
+    /// a peer whose latest heartbeat is older than this is reported as dead
+    pub dead_timeout: Duration,
+    /// a peer whose latest heartbeat is older than this is pruned from storage, see
+    /// [`Storage::prune`]
+    pub entry_ttl: Duration,
+    /// most entries the storage holds, the stalest peers are evicted beyond it; unbounded when
+    /// unset
+    pub max_entries: Option<usize>,
+    /// file the node's view is saved to and restored from, see [`crate::snapshot`]
+    pub snapshot_path: Option<PathBuf>,
+    /// number of storage events kept for debugging, see [`crate::event_log`]; 0 keeps none
+    pub event_log_capacity: usize,
+    /// number of nodes the cluster is expected to have, used to size the per-peer maps up front
+    pub expected_cluster_size: Option<usize>,
+    /// number of peers a leave announcement is sent to
+    pub leave_fanout: usize,
+    /// how long leave() waits for the announcement to go out before stopping the heartbeats
+    pub leave_linger: Duration,
+    /// how long the node keeps forwarding gossip after leaving to answer stragglers
+    pub leave_grace: Duration,
+    pub clock: Arc<dyn Clock>,
+    /// seed for peer selection and forwarding decisions, drawn from entropy when unset
+    pub rng_seed: Option<u64>,
+    /// resend this node's heartbeat right away when another node is seen claiming its id
+    pub reannounce_on_conflict: bool,
+    /// clock a peer's age is measured against for its state and the membership events
+    pub age_source: AgeSource,
+    /// how far ahead of the local clock a heartbeat's timestamp may be, see [`TimestampBounds`]
+    pub max_future_skew: Duration,
+    /// what happens to heartbeats further ahead than `max_future_skew`
+    pub future_timestamps: FutureTimestampPolicy,
+    /// sources whose admin queries are answered, see [`crate::admin`]
+    pub admin_allowlist: Vec<IpAddr>,
+    /// network faults applied to the datagrams the node sends, see [`crate::faults`]
+    pub faults: Option<Arc<FaultInjector>>,
+    /// in-memory network the node binds its address on instead of a UDP socket, see
+    /// [`crate::memory_net`]
+    pub memory_network: Option<Arc<MemoryNetwork>>,
+    /// records the way one heartbeat spreads, see [`crate::trace`]
+    pub trace: Option<Arc<HeartbeatTrace>>,
+    /// address of the http status endpoint, off when unset
+    #[cfg(feature = "http")]
+    pub http_address: Option<SocketAddr>,
 }
 
-impl Node {
-    pub fn new(
-        id: String,
-        address: String,
-        shared_storage: Arc<Mutex<Storage>>,
-        heartbeat_interval_secs: u64,
-        heartbeat_spread: usize,
-        poll_interval_milisecs: u64,
-        decay_factor: f64,
-        is_alive: Arc<AtomicBool>,
-    ) -> Self {
-        let socket = UdpSocket::bind(&address).expect("Could not bind socket");
-        socket
-            .set_nonblocking(true)
-            .expect("Could not set socket to nonblocking");
-        let channel = UdapChannel { socket };
-
-        Node {
+impl NodeConfig {
+    /// Builder starting from the values in [`defaults`]; anything left unset keeps its default.
+    pub fn builder() -> NodeConfigBuilder {
+        NodeConfigBuilder::default()
+    }
+}
+
+impl Default for NodeConfig {
+    /// Config with every tunable at its value in [`defaults`] and a random id.
+    fn default() -> Self {
+        NodeConfig::builder()
+            .build()
+            .expect("default config is valid")
+    }
+}
+
+#[derive(Default)]
+pub struct NodeConfigBuilder {
+    id: Option<String>,
+    address: Option<String>,
+    heartbeat_interval: Option<Duration>,
+    heartbeat_spread: Option<usize>,
+    poll_interval: Option<Duration>,
+    decay_factor: Option<f64>,
+    suspect_timeout: Option<Duration>,
+    dead_timeout: Option<Duration>,
+    entry_ttl: Option<Duration>,
+    max_entries: Option<usize>,
+    snapshot_path: Option<PathBuf>,
+    event_log_capacity: Option<usize>,
+    expected_cluster_size: Option<usize>,
+    leave_fanout: Option<usize>,
+    leave_linger: Option<Duration>,
+    leave_grace: Option<Duration>,
+    clock: Option<Arc<dyn Clock>>,
+    rng_seed: Option<u64>,
+    reannounce_on_conflict: bool,
+    age_source: AgeSource,
+    max_future_skew: Option<Duration>,
+    future_timestamps: FutureTimestampPolicy,
+    admin_allowlist: Option<Vec<IpAddr>>,
+    faults: Option<Arc<FaultInjector>>,
+    memory_network: Option<Arc<MemoryNetwork>>,
+    trace: Option<Arc<HeartbeatTrace>>,
+    #[cfg(feature = "http")]
+    http_address: Option<String>,
+}
+
+impl NodeConfigBuilder {
+    /// Identity of the node, a random UUIDv4 when not set.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn address(mut self, address: impl Into<String>) -> Self {
+        self.address = Some(address.into());
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = Some(interval);
+        self
+    }
+
+    pub fn spread(mut self, spread: usize) -> Self {
+        self.heartbeat_spread = Some(spread);
+        self
+    }
+
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = Some(interval);
+        self
+    }
+
+    pub fn decay(mut self, decay_factor: f64) -> Self {
+        self.decay_factor = Some(decay_factor);
+        self
+    }
+
+    pub fn suspect_timeout(mut self, timeout: Duration) -> Self {
+        self.suspect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn dead_timeout(mut self, timeout: Duration) -> Self {
+        self.dead_timeout = Some(timeout);
+        self
+    }
+
+    pub fn entry_ttl(mut self, ttl: Duration) -> Self {
+        self.entry_ttl = Some(ttl);
+        self
+    }
+
+    /// Caps the number of entries, see [`Storage::evict_stalest`]. The node itself and its seeds
+    /// count towards the cap but are never evicted.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Saves the node's view to `path` while it runs and restores it when the node is created
+    /// or restarted, see [`crate::snapshot`].
+    pub fn snapshot_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.snapshot_path = Some(path.into());
+        self
+    }
+
+    pub fn event_log_capacity(mut self, capacity: usize) -> Self {
+        self.event_log_capacity = Some(capacity);
+        self
+    }
+
+    /// Sizes the storage and other per-peer maps for `size` nodes up front, sparing them the
+    /// rehashing while the node discovers the cluster.
+    pub fn expected_cluster_size(mut self, size: usize) -> Self {
+        self.expected_cluster_size = Some(size);
+        self
+    }
+
+    pub fn leave_fanout(mut self, fanout: usize) -> Self {
+        self.leave_fanout = Some(fanout);
+        self
+    }
+
+    pub fn leave_linger(mut self, linger: Duration) -> Self {
+        self.leave_linger = Some(linger);
+        self
+    }
+
+    pub fn leave_grace(mut self, grace: Duration) -> Self {
+        self.leave_grace = Some(grace);
+        self
+    }
+
+    /// Clock used for heartbeat timestamps and staleness checks, [`SystemClock`] by default.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Makes the node's random choices reproducible.
+    pub fn rng_seed(mut self, seed: u64) -> Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
+    pub fn reannounce_on_conflict(mut self, reannounce: bool) -> Self {
+        self.reannounce_on_conflict = reannounce;
+        self
+    }
+
+    /// Judges peers by when their heartbeats arrived rather than by the timestamps they claim,
+    /// see [`AgeSource`].
+    pub fn age_source(mut self, age_source: AgeSource) -> Self {
+        self.age_source = age_source;
+        self
+    }
+
+    /// Bounds how far in the future a heartbeat's timestamp may lie, see [`TimestampBounds`].
+    pub fn max_future_skew(mut self, skew: Duration) -> Self {
+        self.max_future_skew = Some(skew);
+        self
+    }
+
+    pub fn future_timestamps(mut self, policy: FutureTimestampPolicy) -> Self {
+        self.future_timestamps = policy;
+        self
+    }
+
+    /// Sources allowed to send admin queries, localhost only by default. An empty list turns
+    /// admin queries off.
+    pub fn admin_allowlist(mut self, allowlist: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.admin_allowlist = Some(allowlist.into_iter().collect());
+        self
+    }
+
+    /// Passes every datagram the node sends through `faults`, usually shared by all the nodes of
+    /// a simulation.
+    pub fn faults(mut self, faults: Arc<FaultInjector>) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+
+    /// Binds the node's address on `network` instead of a UDP socket, usually shared by all the
+    /// nodes of a simulation. Only nodes on the same network can reach the node.
+    pub fn memory_network(mut self, network: Arc<MemoryNetwork>) -> Self {
+        self.memory_network = Some(network);
+        self
+    }
+
+    /// Reports the heartbeats the node sends and receives to `trace`, usually shared by all the
+    /// nodes of a simulation.
+    pub fn trace(mut self, trace: Arc<HeartbeatTrace>) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Serves the node's status over http on `address` while it runs, see [`crate::http`].
+    #[cfg(feature = "http")]
+    pub fn http_address(mut self, address: impl Into<String>) -> Self {
+        self.http_address = Some(address.into());
+        self
+    }
+
+    pub fn build(self) -> Result<NodeConfig, ConfigError> {
+        let id = NodeId::new(self.id.unwrap_or_else(|| Uuid::new_v4().to_string()))
+            .map_err(ConfigError::InvalidId)?;
+        let address = parse_address(
+            "address",
+            self.address.as_deref().unwrap_or(defaults::ADDRESS),
+        )?;
+        #[cfg(feature = "http")]
+        let http_address = self
+            .http_address
+            .map(|address| parse_address("http_address", &address))
+            .transpose()?;
+        let heartbeat_interval = self
+            .heartbeat_interval
+            .unwrap_or(defaults::HEARTBEAT_INTERVAL);
+        let heartbeat_spread = self.heartbeat_spread.unwrap_or(defaults::HEARTBEAT_SPREAD);
+        let poll_interval = self.poll_interval.unwrap_or(defaults::POLL_INTERVAL);
+        let decay_factor = self.decay_factor.unwrap_or(defaults::DECAY_FACTOR);
+        let suspect_timeout = self.suspect_timeout.unwrap_or(defaults::SUSPECT_TIMEOUT);
+        let dead_timeout = self.dead_timeout.unwrap_or(defaults::DEAD_TIMEOUT);
+        let entry_ttl = self.entry_ttl.unwrap_or(defaults::ENTRY_TTL);
+        let leave_fanout = self.leave_fanout.unwrap_or(heartbeat_spread);
+        let leave_linger = self.leave_linger.unwrap_or(defaults::LEAVE_LINGER);
+        let leave_grace = self.leave_grace.unwrap_or(defaults::LEAVE_GRACE);
+        let clock = self.clock.unwrap_or_else(|| Arc::new(SystemClock));
+
+        if heartbeat_interval.is_zero() {
+            return Err(ConfigError::ZeroInterval("heartbeat_interval"));
+        }
+        if poll_interval.is_zero() {
+            return Err(ConfigError::ZeroInterval("poll_interval"));
+        }
+        if heartbeat_spread == 0 {
+            return Err(ConfigError::ZeroSpread);
+        }
+        if decay_factor.is_nan() || decay_factor < 0.0 {
+            return Err(ConfigError::InvalidDecay(decay_factor));
+        }
+        if suspect_timeout.is_zero() {
+            return Err(ConfigError::ZeroInterval("suspect_timeout"));
+        }
+        if dead_timeout <= suspect_timeout {
+            return Err(ConfigError::DeadBeforeSuspect);
+        }
+        if entry_ttl <= dead_timeout {
+            return Err(ConfigError::TtlBeforeDead);
+        }
+        if leave_fanout == 0 {
+            return Err(ConfigError::ZeroLeaveFanout);
+        }
+        if self.max_entries == Some(0) {
+            return Err(ConfigError::ZeroMaxEntries);
+        }
+
+        Ok(NodeConfig {
             id,
             address,
-            shared_storage,
-            shared_channel: Arc::new(Mutex::new(channel)),
-            heartbeat_interval_secs,
+            heartbeat_interval,
             heartbeat_spread,
-            poll_interval_milisecs,
+            poll_interval,
             decay_factor,
-            is_alive,
-        }
-    }
-
-    pub fn run(&self) -> Result<(), String> {
-        let node_span = span!(
-            Level::INFO,
-            "node",
-            node_id = &self.id,
-            address = &self.address,
-            thread = "main",
-        );
-        let _enter = node_span.enter();
-
-        info!("Running Node");
-        let poll_interval_milisecs = self.poll_interval_milisecs;
-        let heartbeat_interval_secs = self.heartbeat_interval_secs;
-        let heartbeat_spread = self.heartbeat_spread;
-        let decay_factor = self.decay_factor;
-        let id = self.id.clone();
-        let address = self.address.clone();
-        let shared_storage = self.shared_storage.clone();
-        let shared_channel = self.shared_channel.clone();
-        let shared_storage_clone = shared_storage.clone();
-        let shared_channel_clone = shared_channel.clone();
-        let shared_is_alive = self.is_alive.clone();
-
-        let span_clone = node_span.clone();
-        let _ = thread::spawn(move || {
-            let _enter = span_clone.enter();
-            periodic_heartbeat(
-                id,
-                address,
-                heartbeat_interval_secs,
-                heartbeat_spread,
-                shared_storage_clone,
-                shared_channel_clone,
-                shared_is_alive,
-            )
+            suspect_timeout,
+            dead_timeout,
+            entry_ttl,
+            max_entries: self.max_entries,
+            snapshot_path: self.snapshot_path,
+            event_log_capacity: self
+                .event_log_capacity
+                .unwrap_or(defaults::EVENT_LOG_CAPACITY),
+            expected_cluster_size: self.expected_cluster_size,
+            leave_fanout,
+            leave_linger,
+            leave_grace,
+            clock,
+            rng_seed: self.rng_seed,
+            reannounce_on_conflict: self.reannounce_on_conflict,
+            age_source: self.age_source,
+            max_future_skew: self.max_future_skew.unwrap_or(defaults::MAX_FUTURE_SKEW),
+            future_timestamps: self.future_timestamps,
+            admin_allowlist: self
+                .admin_allowlist
+                .unwrap_or_else(|| defaults::ADMIN_ALLOWLIST.to_vec()),
+            faults: self.faults,
+            memory_network: self.memory_network,
+            trace: self.trace,
+            #[cfg(feature = "http")]
+            http_address,
+        })
+    }
+}
+
+/// Parameters of a running node that can be changed through [`Node::update_config`]. Fields
+/// left as `None` keep their current value.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigUpdate {
+    pub heartbeat_interval: Option<Duration>,
+    pub heartbeat_spread: Option<usize>,
+    pub decay_factor: Option<f64>,
+}
+
+/// The subset of the config the node loop re-reads on every iteration.
+#[derive(Debug, Clone, Copy)]
+struct GossipParams {
+    heartbeat_interval: Duration,
+    heartbeat_spread: usize,
+    decay_factor: f64,
+}
+
+impl GossipParams {
+    fn from_config(config: &NodeConfig) -> Self {
+        GossipParams {
+            heartbeat_interval: config.heartbeat_interval,
+            heartbeat_spread: config.heartbeat_spread,
+            decay_factor: config.decay_factor,
+        }
+    }
+
+    fn apply(&self, update: &ConfigUpdate) -> Result<Self, ConfigError> {
+        let params = GossipParams {
+            heartbeat_interval: update.heartbeat_interval.unwrap_or(self.heartbeat_interval),
+            heartbeat_spread: update.heartbeat_spread.unwrap_or(self.heartbeat_spread),
+            decay_factor: update.decay_factor.unwrap_or(self.decay_factor),
+        };
+        if params.heartbeat_interval.is_zero() {
+            return Err(ConfigError::ZeroInterval("heartbeat_interval"));
+        }
+        if params.heartbeat_spread == 0 {
+            return Err(ConfigError::ZeroSpread);
+        }
+        if params.decay_factor.is_nan() || params.decay_factor < 0.0 {
+            return Err(ConfigError::InvalidDecay(params.decay_factor));
+        }
+        Ok(params)
+    }
+
+    fn read(params: &RwLock<GossipParams>) -> GossipParams {
+        match params.read() {
+            Ok(guard) => *guard,
+            Err(poisoned) => *poisoned.into_inner(),
+        }
+    }
+}
+
+// how long a shutdown waits for the node loop to exit before detaching it
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+// extra time the node loop may take beyond two poll intervals before it counts as stalled
+const LOOP_STALL_GRACE: Duration = Duration::from_millis(500);
+// how often a panicking loop is restarted before the node is given up on
+pub const MAX_LOOP_RESTARTS: u32 = 3;
+const LOOP_RESTART_BACKOFF: Duration = Duration::from_millis(100);
+// how often a restarted node tries to bind its addresses again
+const BIND_ATTEMPTS: u32 = 5;
+const BIND_BACKOFF: Duration = Duration::from_millis(50);
+
+/// A gossip node. Dropping it stops the node like [`Node::stop`] does, so keep it around for as
+/// long as the node should run; its [`NodeHandle`]s don't keep it running.
+pub struct Node {
+    runtime: Arc<NodeRuntime>,
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        // a loop that doesn't stop in time is detached by stop(), the socket is released anyway
+        if let Err(e) = self.runtime.stop() {
+            warn!(
+                error = e.to_string(),
+                "node stopped with an error when dropped"
+            );
+        }
+    }
+}
+
+impl Node {
+    pub fn new(config: NodeConfig, storage: Storage) -> Result<Self, HeartbeatError> {
+        Node::create(config, storage, None)
+    }
+
+    /// Creates a node whose loop runs on the worker pool of `runtime` instead of a thread of
+    /// its own.
+    pub fn new_hosted(
+        config: NodeConfig,
+        storage: Storage,
+        runtime: &SimRuntime,
+    ) -> Result<Self, HeartbeatError> {
+        Node::create(config, storage, Some(runtime.scheduler()))
+    }
+
+    fn create(
+        config: NodeConfig,
+        mut storage: Storage,
+        host: Option<Arc<Scheduler>>,
+    ) -> Result<Self, HeartbeatError> {
+        let stats = StatsCounters::default();
+        let channel = UdapChannel::bind(&config, stats.bytes.clone())?;
+        let seeds = storage
+            .data
+            .values()
+            .filter(|data| data.heartbeat.id != config.id)
+            .map(|data| (data.heartbeat.id.clone(), data.heartbeat.address))
+            .collect();
+        prepare_storage(&config, &mut storage);
+        let params = GossipParams::from_config(&config);
+        let announce_rng = stream_rng(config.rng_seed, ANNOUNCE_RNG_STREAM);
+        let created = config.clock.elapsed();
+
+        Ok(Node {
+            runtime: Arc::new(NodeRuntime {
+                config,
+                storage: StorageHandle::new(storage),
+                shared_channel: Mutex::new(Some(channel)),
+                paused: AtomicBool::new(false),
+                params: RwLock::new(params),
+                events: EventBus::default(),
+                stats,
+                announce_rng: Mutex::new(announce_rng),
+                shutdown: Shutdown::default(),
+                heartbeat_stop: Shutdown::default(),
+                left: AtomicBool::new(false),
+                failed: AtomicBool::new(false),
+                threads: Mutex::new(Vec::new()),
+                host,
+                hosted_done: Shutdown::default(),
+                loop_started: AtomicBool::new(false),
+                created,
+                last_heartbeat_ms: AtomicU64::new(0),
+                last_tick_ms: AtomicU64::new(0),
+                consecutive_send_failures: AtomicU32::new(0),
+                convergence: Mutex::new(None),
+                seeds,
+                generation: AtomicU64::new(0),
+                forward_policy: RwLock::new(Arc::new(DecayPolicy)),
+            }),
+        })
+    }
+
+    pub fn run(&self) -> Result<NodeHandle, HeartbeatError> {
+        start(&self.runtime)
+    }
+
+    /// Brings a stopped, failed or departed node back as if its process had been restarted: the
+    /// loop is stopped if it still runs, the socket is bound again, the storage is rebuilt from
+    /// the seeds it was created with, and a fresh loop is started. The node's generation goes up
+    /// by one, counters and subscriptions are kept.
+    pub fn restart(&self) -> Result<NodeHandle, HeartbeatError> {
+        self.runtime.restart()?;
+        start(&self.runtime)
+    }
+
+    /// Number of times the node has been restarted.
+    pub fn generation(&self) -> u64 {
+        self.runtime.generation()
+    }
+
+    /// Shared access to this node's storage.
+    pub fn storage(&self) -> StorageHandle {
+        self.runtime.storage.clone()
+    }
+
+    /// Returns a snapshot of every entry in this node's storage, including the node itself.
+    pub fn members(&self) -> Vec<MemberInfo> {
+        self.runtime.members()
+    }
+
+    /// Members whose latest heartbeat is younger than `threshold`.
+    pub fn alive_members(&self, threshold: Duration) -> Vec<MemberInfo> {
+        self.members()
+            .into_iter()
+            .filter(|member| member.age < threshold)
+            .collect()
+    }
+
+    /// State of the peer `id` as of the node's latest evaluation, which runs about once a second.
+    pub fn peer_state(&self, id: &str) -> Option<PeerState> {
+        self.runtime
+            .storage
+            .read()
+            .data
+            .get(id)
+            .map(|data| data.state)
+    }
+
+    /// Latest state changes of the peer `id`, oldest first. Empty for unknown peers.
+    pub fn peer_history(&self, id: &str) -> Vec<StateTransition> {
+        self.runtime
+            .storage
+            .read()
+            .data
+            .get(id)
+            .map(|data| data.history.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Peers whose latest heartbeat is at least `threshold` old, as measured by the node's
+    /// clock. The node itself is never included; peers that left are, until they are removed.
+    pub fn dead_peers(&self, threshold: Duration) -> Vec<MemberInfo> {
+        self.runtime.dead_peers(threshold)
+    }
+
+    /// Age of the heartbeat stored for `id`, measured like the peer's state is, see
+    /// [`NodeConfig::age_source`], or `None` if the node is unknown.
+    pub fn staleness(&self, id: &str) -> Option<Duration> {
+        self.runtime.staleness(id)
+    }
+
+    /// Puts `value` under `key` and gossips it to the cluster, see [`crate::kv`]. Fails without
+    /// storing anything if the entry takes more than [`crate::kv::MAX_ENTRY_SIZE`] bytes encoded
+    /// or the node already has values under [`crate::kv::MAX_KEYS_PER_NODE`] other keys.
+    pub fn put(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+    ) -> Result<(), HeartbeatError> {
+        self.runtime.put(key.into(), value.into())
+    }
+
+    /// The value put last under `key` by any node, as far as this node knows.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.runtime.get(key)
+    }
+
+    pub fn member(&self, id: &str) -> Option<MemberInfo> {
+        let storage = self.runtime.storage.read();
+        storage
+            .data
+            .get(id)
+            .filter(|data| !data.provisional)
+            .map(|data| {
+                self.runtime
+                    .member_info(data, self.runtime.config.clock.now())
+            })
+    }
+
+    /// Short overview of the node for debugging, see [`NodeSummary`]. Holds the storage lock
+    /// only for as long as it takes to count the entries.
+    pub fn summary(&self) -> NodeSummary {
+        let now = self.runtime.config.clock.now();
+        let (storage, peers_known, peers_alive) = self.runtime.storage.with_read(|storage| {
+            let peers_alive = storage
+                .data
+                .values()
+                .filter(|data| data.heartbeat.id != self.runtime.config.id && !data.provisional)
+                .filter(|data| {
+                    PeerState::of(
+                        &data.heartbeat,
+                        data.age(now, self.runtime.config.age_source),
+                        self.runtime.config.suspect_timeout,
+                        self.runtime.config.dead_timeout,
+                    ) == PeerState::Alive
+                })
+                .count();
+            (storage.summary(now), storage.count_known(), peers_alive)
+        });
+
+        NodeSummary {
+            id: self.runtime.config.id.clone(),
+            address: self.runtime.config.address,
+            paused: self.is_paused(),
+            peers_known: peers_known.saturating_sub(1),
+            peers_alive,
+            messages_sent: self.stats().messages_sent(),
+            storage,
+        }
+    }
+
+    /// Self-check of the node: is its loop running, sending heartbeats and able to send.
+    pub fn health(&self) -> HealthReport {
+        self.runtime.health()
+    }
+
+    /// Shorthand for `health().is_healthy()`.
+    pub fn is_healthy(&self) -> bool {
+        self.health().is_healthy()
+    }
+
+    /// Returns a snapshot of this node's message counters.
+    pub fn stats(&self) -> NodeStats {
+        self.runtime.stats.snapshot()
+    }
+
+    /// Counts of the node's storage by state as of now, measured with the node's clock and
+    /// timeouts, see [`Storage::metrics`].
+    pub fn storage_metrics(&self) -> StorageMetrics {
+        self.runtime.storage_metrics()
+    }
+
+    /// How long the heartbeats of other nodes the node applied took to get here, since it was
+    /// created.
+    pub fn propagation_latency(&self) -> LatencyHistogram {
+        self.runtime.stats.propagation.snapshot()
+    }
+
+    /// Subscribes to membership changes observed by this node. Events are only produced while
+    /// the node is running.
+    pub fn subscribe(&self) -> Subscription {
+        self.runtime.events.subscribe()
+    }
+
+    /// Calls `callback` with [`ConvergenceEvent::Converged`] once the node's view satisfies
+    /// `watch`, and with [`ConvergenceEvent::Diverged`] once it stops doing so. The view is
+    /// checked by the node loop about once a second, and the callback runs on the loop's thread,
+    /// so it should return quickly and must not register another callback. Replaces a previously
+    /// registered callback.
+    pub fn on_convergence(
+        &self,
+        watch: ConvergenceWatch,
+        callback: impl FnMut(ConvergenceEvent) + Send + 'static,
+    ) {
+        let mut convergence = self
+            .runtime
+            .convergence
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *convergence = Some(ConvergenceCallback {
+            watch,
+            callback: Box::new(callback),
+            converged: false,
         });
+    }
+
+    /// Stops the node loop and releases the socket. Safe to call more than once, and on a node
+    /// that was never run. Returns an error if the loop had panicked.
+    pub fn stop(&self) -> Result<(), HeartbeatError> {
+        self.runtime.stop()
+    }
+
+    /// Changes the heartbeat interval, spread and decay factor of a running node. The loop picks
+    /// up the new values on its next iteration, so a shorter heartbeat interval takes effect
+    /// within one old interval at worst.
+    pub fn update_config(&self, update: ConfigUpdate) -> Result<(), ConfigError> {
+        self.runtime.update_config(update)
+    }
+
+    /// The policy deciding which received heartbeats are forwarded, [`DecayPolicy`] unless
+    /// changed.
+    pub fn forward_policy(&self) -> Arc<dyn ForwardPolicy> {
+        self.runtime.forward_policy()
+    }
+
+    /// Swaps the forward policy of a running node, e.g. for [`crate::forward::DisabledPolicy`]
+    /// to turn it into a leaf node. The loop uses the new policy from the next heartbeat on.
+    pub fn set_forward_policy(&self, policy: Arc<dyn ForwardPolicy>) {
+        self.runtime.set_forward_policy(policy);
+    }
+
+    /// Simulates a crashed node without tearing it down: while paused the node sends nothing,
+    /// datagrams arriving at its socket are drained and discarded, and its heartbeat timer is
+    /// suspended. Its own storage entry is still refreshed by the loop, so the node doesn't look
+    /// stale to itself; peers see it age as they stop hearing from it.
+    pub fn pause(&self) {
+        self.runtime.pause();
+    }
+
+    /// Undoes [`Node::pause`] and immediately sends a fresh heartbeat so peers learn about the
+    /// recovery without waiting for the next heartbeat interval.
+    pub fn resume(&self) -> Result<(), HeartbeatError> {
+        self.runtime.resume()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.runtime.paused.load(Ordering::SeqCst)
+    }
+
+    /// Announces to `leave_fanout` peers that this node is leaving, waits `leave_linger` for
+    /// the announcement to go out and stops sending heartbeats. The node keeps
+    /// forwarding gossip for another `leave_grace` before the node is stopped completely.
+    ///
+    /// A node that has left can't be run again; restart it or create a new one to rejoin the
+    /// cluster. A leave whose announcement fails leaves the node as it was, so it can be tried
+    /// again.
+    ///
+    /// Blocks for `leave_linger + leave_grace`.
+    pub fn leave(&self) -> Result<(), HeartbeatError> {
+        if self.runtime.left.swap(true, Ordering::SeqCst) {
+            return Err(HeartbeatError::Left);
+        }
+
+        if let Err(e) = self
+            .runtime
+            .announce(true, self.runtime.config.leave_fanout)
+        {
+            self.runtime.left.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+        info!("Leave announced");
+
+        match &self.runtime.host {
+            Some(host) => host.sleep(self.runtime.config.leave_linger),
+            None => thread::sleep(self.runtime.config.leave_linger),
+        }
+        self.runtime.heartbeat_stop.trigger();
+
+        if !self.runtime.config.leave_grace.is_zero() {
+            self.runtime.sleep(self.runtime.config.leave_grace);
+        }
+        self.runtime.stop()
+    }
+}
+
+/// Handle to the loop of a running node, returned by [`Node::run`].
+#[derive(Clone)]
+pub struct NodeHandle {
+    runtime: Arc<NodeRuntime>,
+}
+
+impl NodeHandle {
+    /// Blocks until the node loop has exited. Returns an error if it panicked for good.
+    pub fn wait(&self) -> Result<(), HeartbeatError> {
+        if let Some(host) = &self.runtime.host {
+            if host.is_stepped() {
+                // the loop only makes progress while the caller sleeps
+                while !self.runtime.hosted_done.is_triggered() {
+                    host.sleep(self.runtime.config.poll_interval);
+                }
+            } else {
+                self.runtime.hosted_done.wait();
+            }
+            if self.runtime.failed.load(Ordering::SeqCst) {
+                return Err(HeartbeatError::LoopPanicked {
+                    name: "node",
+                    message: format!("gave up after {} restarts", MAX_LOOP_RESTARTS),
+                });
+            }
+            return Ok(());
+        }
+
+        let threads: Vec<(&'static str, JoinHandle<()>)> =
+            self.runtime.threads().drain(..).collect();
+
+        let mut result = Ok(());
+        for (name, handle) in threads {
+            if let Err(payload) = handle.join() {
+                result = Err(HeartbeatError::LoopPanicked {
+                    name,
+                    message: panic_message(&payload),
+                });
+            }
+        }
+
+        result
+    }
+
+    /// Stops the node loop and releases the socket, same as [`Node::stop`].
+    pub fn shutdown(&self) -> Result<(), HeartbeatError> {
+        self.runtime.stop()
+    }
+
+    /// Same as [`Node::generation`].
+    pub fn generation(&self) -> u64 {
+        self.runtime.generation()
+    }
+
+    /// Same as [`Node::restart`]; this handle stays valid for the restarted node.
+    pub fn restart(&self) -> Result<(), HeartbeatError> {
+        self.runtime.restart()?;
+        start(&self.runtime).map(|_| ())
+    }
+
+    /// Same as [`Node::stats`].
+    pub fn stats(&self) -> NodeStats {
+        self.runtime.stats.snapshot()
+    }
+
+    /// Same as [`Node::members`].
+    pub fn members(&self) -> Vec<MemberInfo> {
+        self.runtime.members()
+    }
+
+    /// Same as [`Node::storage_metrics`].
+    pub fn storage_metrics(&self) -> StorageMetrics {
+        self.runtime.storage_metrics()
+    }
+
+    /// Same as [`Node::propagation_latency`].
+    pub fn propagation_latency(&self) -> LatencyHistogram {
+        self.runtime.stats.propagation.snapshot()
+    }
+
+    /// Same as [`Node::storage`].
+    pub fn storage(&self) -> StorageHandle {
+        self.runtime.storage.clone()
+    }
+
+    /// Same as [`Node::dead_peers`].
+    pub fn dead_peers(&self, threshold: Duration) -> Vec<MemberInfo> {
+        self.runtime.dead_peers(threshold)
+    }
+
+    /// Same as [`Node::staleness`].
+    pub fn staleness(&self, id: &str) -> Option<Duration> {
+        self.runtime.staleness(id)
+    }
+
+    /// Same as [`Node::put`].
+    pub fn put(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+    ) -> Result<(), HeartbeatError> {
+        self.runtime.put(key.into(), value.into())
+    }
+
+    /// Same as [`Node::get`].
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.runtime.get(key)
+    }
+
+    #[cfg(feature = "http")]
+    pub(crate) fn is_shut_down(&self) -> bool {
+        self.runtime.shutdown.is_triggered()
+    }
+
+    /// Same as [`Node::update_config`].
+    pub fn update_config(&self, update: ConfigUpdate) -> Result<(), ConfigError> {
+        self.runtime.update_config(update)
+    }
+
+    /// Same as [`Node::forward_policy`].
+    pub fn forward_policy(&self) -> Arc<dyn ForwardPolicy> {
+        self.runtime.forward_policy()
+    }
+
+    /// Same as [`Node::set_forward_policy`].
+    pub fn set_forward_policy(&self, policy: Arc<dyn ForwardPolicy>) {
+        self.runtime.set_forward_policy(policy);
+    }
+
+    /// Same as [`Node::pause`].
+    pub fn pause(&self) {
+        self.runtime.pause();
+    }
+
+    /// Same as [`Node::resume`].
+    pub fn resume(&self) -> Result<(), HeartbeatError> {
+        self.runtime.resume()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.runtime.paused.load(Ordering::SeqCst)
+    }
+
+    /// True once the node loop kept panicking after [`MAX_LOOP_RESTARTS`] restarts. A failed
+    /// node is shut down; [`NodeHandle::wait`] reports the last panic.
+    pub fn is_failed(&self) -> bool {
+        self.runtime.failed.load(Ordering::SeqCst)
+    }
+
+    /// Returns false once the node has been shut down or its loop has exited.
+    pub fn is_running(&self) -> bool {
+        self.runtime.is_running()
+    }
+
+    /// Same as [`Node::health`].
+    pub fn health(&self) -> HealthReport {
+        self.runtime.health()
+    }
+}
+
+/// State shared between a node, its handles and its loop.
+struct NodeRuntime {
+    config: NodeConfig,
+    storage: StorageHandle,
+    shared_channel: Mutex<Option<UdapChannel>>,
+    paused: AtomicBool,
+    params: RwLock<GossipParams>,
+    events: EventBus,
+    stats: StatsCounters,
+    // used by pause/resume and leave, which run on the caller's thread
+    announce_rng: Mutex<StdRng>,
+    shutdown: Shutdown,
+    // stops only the heartbeats, triggered when the node leaves
+    heartbeat_stop: Shutdown,
+    left: AtomicBool,
+    // set when the loop panicked more often than it may be restarted
+    failed: AtomicBool,
+    threads: Mutex<Vec<(&'static str, JoinHandle<()>)>>,
+    // worker pool the loop runs on instead of `threads`, see Node::new_hosted
+    host: Option<Arc<Scheduler>>,
+    // triggered once a hosted loop has finished
+    hosted_done: Shutdown,
+    // set by run(), before that the node is not running even when hosted
+    loop_started: AtomicBool,
+    // time base of the timestamps below, on the clock's elapsed time
+    created: Duration,
+    // when the node loop last sent a heartbeat successfully and last came around, see
+    // elapsed_ms
+    last_heartbeat_ms: AtomicU64,
+    last_tick_ms: AtomicU64,
+    consecutive_send_failures: AtomicU32,
+    convergence: Mutex<Option<ConvergenceCallback>>,
+    // other nodes the storage started out with, restored by Node::restart
+    seeds: Vec<(NodeId, SocketAddr)>,
+    // bumped by every restart
+    generation: AtomicU64,
+    forward_policy: RwLock<Arc<dyn ForwardPolicy>>,
+}
+
+/// Registered by [`Node::on_convergence`], evaluated along with the membership transitions.
+struct ConvergenceCallback {
+    watch: ConvergenceWatch,
+    callback: Box<dyn FnMut(ConvergenceEvent) + Send>,
+    converged: bool,
+}
+
+impl NodeRuntime {
+    /// Takes the guard even if a previous holder panicked. Nothing the node keeps behind a lock
+    /// is left in a state that matters by a panic midway, so the node carries on rather than
+    /// skipping that work forever; the recovery is counted and logged so the panic isn't
+    /// masked.
+    fn recover<G>(&self, what: &'static str, result: LockResult<G>) -> G {
+        result.unwrap_or_else(|poisoned| {
+            self.stats.locks_recovered.fetch_add(1, Ordering::Relaxed);
+            warn!(what, "recovered a lock whose holder panicked");
+            poisoned.into_inner()
+        })
+    }
+
+    fn channel(&self) -> MutexGuard<'_, Option<UdapChannel>> {
+        self.recover("shared channel", self.shared_channel.lock())
+    }
+
+    fn threads(&self) -> MutexGuard<'_, Vec<(&'static str, JoinHandle<()>)>> {
+        self.recover("node threads", self.threads.lock())
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    fn forward_policy(&self) -> Arc<dyn ForwardPolicy> {
+        match self.forward_policy.read() {
+            Ok(guard) => guard.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    fn set_forward_policy(&self, policy: Arc<dyn ForwardPolicy>) {
+        info!(policy = policy.name(), "Forward policy changed");
+        match self.forward_policy.write() {
+            Ok(mut guard) => *guard = policy,
+            Err(poisoned) => *poisoned.into_inner() = policy,
+        }
+    }
+
+    /// Stops the node and resets it to the state it was created in, except for its counters,
+    /// subscriptions and convergence callback. The caller starts the new loop.
+    fn restart(&self) -> Result<(), HeartbeatError> {
+        if let Err(e) = self.stop() {
+            warn!(
+                error = e.to_string(),
+                "node loop had failed before the restart"
+            );
+        }
+        // loops of the previous generation exit on their next iteration
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        let channel =
+            bind_with_backoff(|| UdapChannel::bind(&self.config, self.stats.bytes.clone()))?;
+        let mut storage = setup_storage(
+            self.config.id.clone(),
+            self.config.address,
+            self.seeds.clone(),
+            self.config.clock.as_ref(),
+        );
+        prepare_storage(&self.config, &mut storage);
+        *self.storage.write() = storage;
+        *self.channel() = Some(channel);
+
+        self.shutdown.reset();
+        self.heartbeat_stop.reset();
+        self.hosted_done.reset();
+        self.left.store(false, Ordering::SeqCst);
+        self.failed.store(false, Ordering::SeqCst);
+        self.paused.store(false, Ordering::SeqCst);
+        self.loop_started.store(false, Ordering::SeqCst);
+        self.consecutive_send_failures.store(0, Ordering::Relaxed);
+        if let Some(convergence) = self
+            .convergence
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+        {
+            convergence.converged = false;
+        }
+        node_span(self).in_scope(|| info!("Node restarted"));
+        Ok(())
+    }
+
+    fn is_running(&self) -> bool {
+        if !self.loop_started.load(Ordering::SeqCst) || self.shutdown.is_triggered() {
+            return false;
+        }
+        if self.host.is_some() {
+            return !self.hosted_done.is_triggered();
+        }
+        let threads = self.threads();
+        !threads.is_empty() && threads.iter().all(|(_, handle)| !handle.is_finished())
+    }
+
+    fn health(&self) -> HealthReport {
+        let mut issues = vec![];
+        if !self.is_running() {
+            issues.push(HealthIssue::NotRunning);
+            return HealthReport { issues };
+        }
+
+        let now = self.elapsed_ms();
+        if self.heartbeat_stop.is_triggered() {
+            issues.push(HealthIssue::Left);
+        } else if self.paused.load(Ordering::SeqCst) {
+            issues.push(HealthIssue::Paused);
+        } else {
+            let interval = GossipParams::read(&self.params).heartbeat_interval;
+            let since = now.saturating_sub(self.last_heartbeat_ms.load(Ordering::Relaxed));
+            if u128::from(since) > 2 * interval.as_millis() {
+                issues.push(HealthIssue::HeartbeatStale {
+                    since: Duration::from_millis(since),
+                });
+            }
+        }
+
+        let since = now.saturating_sub(self.last_tick_ms.load(Ordering::Relaxed));
+        if Duration::from_millis(since) > 2 * self.config.poll_interval + LOOP_STALL_GRACE {
+            issues.push(HealthIssue::LoopStalled {
+                since: Duration::from_millis(since),
+            });
+        }
+
+        let consecutive = self.consecutive_send_failures.load(Ordering::Relaxed);
+        if consecutive >= FAILED_SENDS_THRESHOLD {
+            issues.push(HealthIssue::SendsFailing { consecutive });
+        }
+
+        HealthReport { issues }
+    }
+
+    /// Sleeps like [`Shutdown::sleep`]. A node hosted on a stepped runtime sleeps through the
+    /// whole `timeout` in the runtime's time, which only moves while the caller sleeps.
+    fn sleep(&self, timeout: Duration) -> bool {
+        match &self.host {
+            Some(host) if host.is_stepped() => {
+                host.sleep(timeout);
+                self.shutdown.is_triggered()
+            }
+            _ => self.shutdown.sleep(timeout),
+        }
+    }
+
+    /// Milliseconds since the node was created, the time base of the loop timestamps.
+    fn elapsed_ms(&self) -> u64 {
+        self.config
+            .clock
+            .elapsed()
+            .saturating_sub(self.created)
+            .as_millis() as u64
+    }
+
+    /// Counts a send towards the stats and the health check.
+    fn record_send(
+        &self,
+        counter: &AtomicU64,
+        n_targets: usize,
+        result: &Result<(), HeartbeatError>,
+    ) {
+        match result {
+            Ok(()) => {
+                counter.fetch_add(n_targets as u64, Ordering::Relaxed);
+                self.consecutive_send_failures.store(0, Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.stats.sends_failed.fetch_add(1, Ordering::Relaxed);
+                self.consecutive_send_failures
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn members(&self) -> Vec<MemberInfo> {
+        let storage = self.storage.read();
+        let now = self.config.clock.now();
+        storage
+            .data
+            .values()
+            .filter(|data| !data.provisional)
+            .map(|data| self.member_info(data, now))
+            .collect()
+    }
+
+    fn storage_metrics(&self) -> StorageMetrics {
+        self.storage.with_read(|storage| {
+            storage.metrics(
+                self.config.clock.as_ref(),
+                self.config.suspect_timeout,
+                self.config.dead_timeout,
+            )
+        })
+    }
+
+    /// Saves the storage, if the node has a snapshot path.
+    fn save_snapshot(&self) {
+        let Some(path) = &self.config.snapshot_path else {
+            return;
+        };
+        let storage = self.storage.with_read(Storage::snapshot);
+        let n_entries = storage.entries.len();
+        match snapshot::save(path, storage, self.config.clock.now()) {
+            Ok(()) => debug!(path = %path.display(), n_entries, "Snapshot saved"),
+            Err(e) => warn!(
+                error = e.to_string(),
+                path = %path.display(),
+                "failed to save snapshot"
+            ),
+        }
+    }
+
+    fn dead_peers(&self, threshold: Duration) -> Vec<MemberInfo> {
+        let storage = self.storage.read();
+        let now = self.config.clock.now();
+        storage
+            .data
+            .values()
+            .filter(|data| data.heartbeat.id != self.config.id && !data.provisional)
+            .map(|data| self.member_info(data, now))
+            .filter(|member| member.age >= threshold)
+            .collect()
+    }
+
+    fn staleness(&self, id: &str) -> Option<Duration> {
+        let storage = self.storage.read();
+        let now = self.config.clock.now();
+        storage
+            .data
+            .get(id)
+            .filter(|data| !data.provisional)
+            .map(|data| Duration::from_secs(data.age(now, self.config.age_source)))
+    }
+
+    fn member_info(&self, data: &NodeHeartbeatData, now: u64) -> MemberInfo {
+        let state = PeerState::of(
+            &data.heartbeat,
+            data.age(now, self.config.age_source),
+            self.config.suspect_timeout,
+            self.config.dead_timeout,
+        );
+        MemberInfo::of(data, now, state)
+    }
+
+    fn update_config(&self, update: ConfigUpdate) -> Result<(), ConfigError> {
+        let mut params = match self.params.write() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *params = params.apply(&update)?;
+        info!(?update, "Config updated");
+        Ok(())
+    }
+
+    fn pause(&self) {
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            info!("Node paused");
+        }
+    }
+
+    fn resume(&self) -> Result<(), HeartbeatError> {
+        if !self.paused.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        info!("Node resumed");
+        self.announce(false, GossipParams::read(&self.params).heartbeat_spread)
+    }
+
+    fn put(&self, key: String, value: Vec<u8>) -> Result<(), HeartbeatError> {
+        let entry = {
+            let mut storage = self.storage.write();
+            let entry = KeyValue {
+                version: storage.values.next_version(&key, &self.config.id),
+                key,
+                value,
+                origin: self.config.id.clone(),
+                address: self.config.address,
+                timestamp: self.config.clock.now(),
+            };
+            storage.values.apply(entry.clone())?;
+            entry
+        };
+        self.events
+            .publish(key_changed(&entry, self.config.clock.now()));
+
+        let addresses = {
+            let storage = self.storage.read();
+            let mut rng = self
+                .announce_rng
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            storage.select_n_random_addresses(
+                GossipParams::read(&self.params).heartbeat_spread,
+                &[&self.config.id],
+                &[self.config.address],
+                &mut *rng,
+            )?
+        };
+        let channel = self.channel();
+        let Some(channel) = channel.as_ref() else {
+            return Err(HeartbeatError::Stopped);
+        };
+        let n_targets = addresses.len();
+        let result = channel.send(&entry, &addresses);
+        self.record_send(&self.stats.values_sent, n_targets, &result);
+        result
+    }
+
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.storage
+            .read()
+            .values
+            .get(key)
+            .map(|entry| entry.value.clone())
+    }
+
+    /// Inserts a fresh heartbeat for this node into its storage and sends it to `fanout`
+    /// random peers right away, outside of the regular heartbeat interval.
+    fn announce(&self, leaving: bool, fanout: usize) -> Result<(), HeartbeatError> {
+        let heartbeat = Heartbeat {
+            leaving,
+            ..Heartbeat::new(
+                self.config.id.clone(),
+                self.config.address,
+                self.config.clock.as_ref(),
+            )
+        };
+
+        self.storage
+            .write()
+            .insert(heartbeat.clone(), self.config.clock.now())?;
+        let addresses = {
+            let storage = self.storage.read();
+            let mut rng = match self.announce_rng.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            storage.select_n_random_addresses(
+                fanout,
+                &[&self.config.id],
+                &[self.config.address],
+                &mut *rng,
+            )?
+        };
+
+        let channel = self.channel();
+        let Some(channel) = channel.as_ref() else {
+            return Err(HeartbeatError::Stopped);
+        };
+        let n_targets = addresses.len();
+        let result = channel.send(&heartbeat, &addresses);
+        self.record_send(&self.stats.heartbeats_sent, n_targets, &result);
+        if result.is_ok() {
+            self.last_heartbeat_ms
+                .store(self.elapsed_ms(), Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn stop(&self) -> Result<(), HeartbeatError> {
+        self.shutdown.trigger();
+        self.heartbeat_stop.trigger();
+
+        let threads: Vec<(&'static str, JoinHandle<()>)> = self.threads().drain(..).collect();
+
+        let mut result = Ok(());
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        for (name, handle) in threads {
+            while !handle.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(5));
+            }
+            if !handle.is_finished() {
+                warn!(loop_name = name, "loop did not stop in time, detaching it");
+                continue;
+            }
+            if let Err(payload) = handle.join() {
+                result = Err(HeartbeatError::LoopPanicked {
+                    name,
+                    message: panic_message(&payload),
+                });
+            }
+        }
+
+        if self.loop_started.load(Ordering::SeqCst) {
+            self.save_snapshot();
+        }
+
+        // dropping the channel closes the socket
+        *self.channel() = None;
+
+        result
+    }
+}
+
+/// Applies the storage settings of `config` to a freshly set up `storage` and restores the
+/// node's snapshot into it.
+fn prepare_storage(config: &NodeConfig, storage: &mut Storage) {
+    storage.set_event_log_capacity(config.event_log_capacity);
+    storage.set_timestamp_bounds(TimestampBounds {
+        max_future_skew: config.max_future_skew,
+        future_timestamps: config.future_timestamps,
+        stale_after: config.suspect_timeout,
+    });
+    if let Some(size) = config.expected_cluster_size {
+        storage.reserve_for(size);
+    }
+    restore_snapshot(config, storage);
+}
+
+/// Adds the entries of the node's snapshot, if it has one, to a freshly set up `storage`. A
+/// missing file is expected on the very first start; a corrupt one or one of another version is
+/// ignored with a warning.
+fn restore_snapshot(config: &NodeConfig, storage: &mut Storage) {
+    let Some(path) = &config.snapshot_path else {
+        return;
+    };
+    match snapshot::load(path) {
+        Ok(snapshot) => {
+            let restored = storage.restore(
+                snapshot,
+                config.clock.as_ref(),
+                config.suspect_timeout,
+                config.entry_ttl,
+            );
+            info!(path = %path.display(), restored, "Snapshot restored");
+        }
+        Err(SnapshotError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+            debug!(path = %path.display(), "No snapshot to restore");
+        }
+        Err(e) => warn!(
+            error = e.to_string(),
+            path = %path.display(),
+            "ignoring snapshot"
+        ),
+    }
+}
+
+/// Tries `bind` a few times, waiting a little longer after every failure, for addresses that are
+/// still held by a socket that is about to be closed.
+fn bind_with_backoff<T>(bind: impl Fn() -> Result<T, HeartbeatError>) -> Result<T, HeartbeatError> {
+    let mut backoff = BIND_BACKOFF;
+    for _ in 1..BIND_ATTEMPTS {
+        match bind() {
+            Ok(bound) => return Ok(bound),
+            Err(e) => warn!(error = e.to_string(), "failed to bind, retrying"),
+        }
+        thread::sleep(backoff);
+        backoff *= 2;
+    }
+    bind()
+}
+
+/// Span every log line of a node is recorded in. Created anew for each thread or task working
+/// for the node, and after a restart. It has no parent, so a task spawned while another node's
+/// span is entered doesn't end up nested in it.
+fn node_span(runtime: &NodeRuntime) -> Span {
+    info_span!(
+        parent: None,
+        "node",
+        node_id = runtime.config.id.as_str(),
+        address = %runtime.config.address,
+        generation = runtime.generation(),
+    )
+}
+
+/// Span of one part of the node loop, `"heartbeat"` or `"gossip"`, nested in [`node_span`].
+fn loop_span(name: &'static str) -> Span {
+    info_span!("loop", "loop" = name)
+}
+
+/// Starts the loop of a node, see [`Node::run`].
+fn start(runtime: &Arc<NodeRuntime>) -> Result<NodeHandle, HeartbeatError> {
+    let _enter = node_span(runtime).entered();
+
+    if runtime.left.load(Ordering::SeqCst) {
+        return Err(HeartbeatError::Left);
+    }
+    if runtime.shutdown.is_triggered() {
+        return Err(HeartbeatError::Stopped);
+    }
+
+    info!("Running Node");
+    // the health check measures from here until the loop reports for the first time
+    let now = runtime.elapsed_ms();
+    runtime.last_heartbeat_ms.store(now, Ordering::Relaxed);
+    runtime.last_tick_ms.store(now, Ordering::Relaxed);
+    runtime.loop_started.store(true, Ordering::SeqCst);
+    #[cfg(feature = "http")]
+    if let Some(address) = runtime.config.http_address {
+        let handle = NodeHandle {
+            runtime: runtime.clone(),
+        };
+        // after a restart the previous listener may take a moment to close
+        bind_with_backoff(|| {
+            crate::http::serve(address, handle.clone())
+                .map_err(|source| HeartbeatError::Bind { address, source })
+        })?;
+    }
+
+    if let Some(host) = &runtime.host {
+        host.spawn(Box::new(HostedLoop::new(runtime.clone())));
+        return Ok(NodeHandle {
+            runtime: runtime.clone(),
+        });
+    }
+
+    let mut threads = runtime.threads();
+
+    let loop_runtime = runtime.clone();
+    let handle = thread::spawn(move || {
+        // spans don't follow a thread, the loop needs its own
+        node_span(&loop_runtime).in_scope(|| supervise("node", loop_runtime.clone(), run_loop))
+    });
+    threads.push(("node", handle));
+
+    Ok(NodeHandle {
+        runtime: runtime.clone(),
+    })
+}
+
+/// Runs the node loop and restarts it if it panics, waiting [`LOOP_RESTART_BACKOFF`]
+/// before the first restart and twice as long before each following one. Every panic is
+/// reported to subscribers; after [`MAX_LOOP_RESTARTS`] restarts the node is marked failed, shut
+/// down and the panic is passed on to whoever joins the thread.
+fn supervise(name: &'static str, runtime: Arc<NodeRuntime>, run_loop: fn(Arc<NodeRuntime>, u64)) {
+    let generation = runtime.generation();
+    let mut restarts = 0;
+    let mut backoff = LOOP_RESTART_BACKOFF;
+    loop {
+        let result =
+            panic::catch_unwind(AssertUnwindSafe(|| run_loop(runtime.clone(), generation)));
+        let Err(payload) = result else {
+            return;
+        };
+
+        let giving_up = restarts >= MAX_LOOP_RESTARTS;
+        report_panic(&runtime, name, &payload, restarts, giving_up);
+        if giving_up {
+            panic::resume_unwind(payload);
+        }
+        if runtime.shutdown.sleep(backoff) {
+            return;
+        }
+        restarts += 1;
+        backoff *= 2;
+    }
+}
+
+/// Logs a loop panic and tells subscribers about it. When `giving_up` the node is marked failed
+/// and shut down.
+fn report_panic(
+    runtime: &NodeRuntime,
+    name: &str,
+    payload: &Box<dyn Any + Send>,
+    restarts: u32,
+    giving_up: bool,
+) {
+    let message = panic_message(payload);
+    error!(loop_name = name, restarts, error = message, "loop panicked");
+    runtime
+        .events
+        .publish(MembershipEvent::LoopPanicked(PeerChange {
+            id: runtime.config.id.clone(),
+            address: runtime.config.address,
+            last_heartbeat: 0,
+            observed_at: runtime.config.clock.now(),
+            reason: if giving_up {
+                format!("{} loop panicked: {}, giving up", name, message)
+            } else {
+                format!("{} loop panicked: {}, restarting", name, message)
+            },
+        }));
+
+    if giving_up {
+        runtime.failed.store(true, Ordering::SeqCst);
+        runtime.shutdown.trigger();
+        runtime.heartbeat_stop.trigger();
+    }
+}
+
+/// The node loop run on a [`SimRuntime`] worker: every run is one iteration. Panics are handled
+/// like [`supervise`] does for a node thread, except that the backoff is spent in the queue.
+struct HostedLoop {
+    runtime: Arc<NodeRuntime>,
+    span: Span,
+    state: LoopState,
+    restarts: u32,
+    backoff: Duration,
+}
+
+impl HostedLoop {
+    fn new(runtime: Arc<NodeRuntime>) -> Self {
+        let state = LoopState::new(&runtime.config, runtime.generation());
+        HostedLoop {
+            span: node_span(&runtime),
+            runtime,
+            state,
+            restarts: 0,
+            backoff: LOOP_RESTART_BACKOFF,
+        }
+    }
+}
+
+impl Task for HostedLoop {
+    fn run(&mut self) -> Option<Duration> {
+        let _enter = self.span.enter();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| tick(&self.runtime, &mut self.state)));
+        let payload = match result {
+            Ok(wait) => return wait,
+            Err(payload) => payload,
+        };
+
+        let giving_up = self.restarts >= MAX_LOOP_RESTARTS;
+        report_panic(&self.runtime, "node", &payload, self.restarts, giving_up);
+        if giving_up {
+            return None;
+        }
+        self.state = LoopState::new(&self.runtime.config, self.state.generation);
+        self.restarts += 1;
+        let backoff = self.backoff;
+        self.backoff *= 2;
+        Some(backoff)
+    }
+}
+
+impl Drop for HostedLoop {
+    fn drop(&mut self) {
+        // the loop of a previous generation has nothing to report to the current one
+        if self.state.generation == self.runtime.generation() {
+            self.runtime.hosted_done.trigger();
+        }
+    }
+}
+
+fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Shutdown signal shared between a node and its loop. Sleeping through it lets the loop wake
+/// up as soon as the node is stopped instead of finishing its current interval.
+#[derive(Default)]
+struct Shutdown {
+    triggered: Mutex<bool>,
+    wakeup: Condvar,
+}
+
+impl Shutdown {
+    fn trigger(&self) {
+        let mut triggered = match self.triggered.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *triggered = true;
+        self.wakeup.notify_all();
+    }
+
+    fn is_triggered(&self) -> bool {
+        match self.triggered.lock() {
+            Ok(guard) => *guard,
+            Err(poisoned) => *poisoned.into_inner(),
+        }
+    }
+
+    /// Arms the signal again, used when a stopped node is restarted.
+    fn reset(&self) {
+        let mut triggered = match self.triggered.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *triggered = false;
+    }
+
+    /// Blocks until triggered.
+    fn wait(&self) {
+        let triggered = match self.triggered.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let _triggered = self
+            .wakeup
+            .wait_while(triggered, |triggered| !*triggered)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+
+    /// Sleeps for `timeout` or until shutdown is triggered. Returns true if triggered.
+    fn sleep(&self, timeout: Duration) -> bool {
+        let triggered = match self.triggered.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let result = self
+            .wakeup
+            .wait_timeout_while(triggered, timeout, |triggered| !*triggered);
+        match result {
+            Ok((guard, _)) => *guard,
+            Err(poisoned) => *poisoned.into_inner().0,
+        }
+    }
+}
+
+/// State the node loop carries from one iteration to the next.
+struct LoopState {
+    // generation of the node the loop was started for, see Node::restart
+    generation: u64,
+    heartbeat_rng: StdRng,
+    gossip_rng: StdRng,
+    tracker: MembershipTracker,
+    // on the elapsed time of the node's clock
+    next_heartbeat: Duration,
+    last_evaluation: Option<Duration>,
+    last_self_refresh: Option<Duration>,
+    last_reannounce: Option<Duration>,
+    last_snapshot: Duration,
+}
+
+impl LoopState {
+    fn new(config: &NodeConfig, generation: u64) -> Self {
+        LoopState {
+            generation,
+            heartbeat_rng: stream_rng(config.rng_seed, HEARTBEAT_RNG_STREAM),
+            gossip_rng: stream_rng(config.rng_seed, GOSSIP_RNG_STREAM),
+            tracker: MembershipTracker::new(
+                config.id.clone(),
+                config.suspect_timeout,
+                config.dead_timeout,
+            )
+            .with_age_source(config.age_source)
+            .with_expected_size(config.expected_cluster_size.unwrap_or(0)),
+            next_heartbeat: config.clock.elapsed(),
+            last_evaluation: None,
+            last_self_refresh: None,
+            last_reannounce: None,
+            last_snapshot: config.clock.elapsed(),
+        }
+    }
+}
+
+/// The single loop of a node: sends the node's heartbeat whenever the heartbeat interval has
+/// elapsed, and in between handles every datagram that reached the socket, waking up at least
+/// every poll interval.
+fn run_loop(runtime: Arc<NodeRuntime>, generation: u64) {
+    let mut state = LoopState::new(&runtime.config, generation);
+    while let Some(wait) = tick(&runtime, &mut state) {
+        if runtime.shutdown.sleep(wait) {
+            break;
+        }
+    }
+}
+
+/// One iteration of the node loop. Returns how long to wait before the next one, or None once
+/// the node has been stopped.
+fn tick(runtime: &NodeRuntime, state: &mut LoopState) -> Option<Duration> {
+    let config = &runtime.config;
+    // a loop left over from before a restart must not run alongside the new one
+    if runtime.shutdown.is_triggered() || runtime.generation() != state.generation {
+        return None;
+    }
+    runtime
+        .last_tick_ms
+        .store(runtime.elapsed_ms(), Ordering::Relaxed);
+    let elapsed = || config.clock.elapsed();
+
+    // the node's own entry stays fresh whether or not its heartbeats get out
+    if state
+        .last_self_refresh
+        .is_none_or(|at| elapsed().saturating_sub(at) >= SELF_REFRESH_INTERVAL)
+    {
+        state.last_self_refresh = Some(elapsed());
+        runtime.storage.write().refresh_owner(config.clock.as_ref());
+    }
+
+    if runtime.paused.load(Ordering::SeqCst) {
+        // a paused node behaves as if it was down, so whatever reached its socket is lost
+        if let Some(channel) = runtime.channel().as_ref() {
+            channel.drain();
+        }
+        // resume() announces the node right away, the regular heartbeats follow one
+        // interval later
+        state.next_heartbeat = elapsed() + GossipParams::read(&runtime.params).heartbeat_interval;
+        return Some(config.poll_interval);
+    }
+
+    let params = GossipParams::read(&runtime.params);
+    // heartbeats stop for good once the node announced that it leaves
+    if !runtime.heartbeat_stop.is_triggered() && elapsed() >= state.next_heartbeat {
+        loop_span("heartbeat")
+            .in_scope(|| send_heartbeat(runtime, &params, &mut state.heartbeat_rng));
+        state.next_heartbeat = elapsed() + params.heartbeat_interval;
+    }
+
+    if state
+        .last_evaluation
+        .is_none_or(|at| elapsed().saturating_sub(at) >= MEMBERSHIP_EVAL_INTERVAL)
+    {
+        state.last_evaluation = Some(elapsed());
+        let now = config.clock.now();
+        let pruned = {
+            let mut storage = runtime.storage.write();
+            storage.evaluate(
+                config.clock.as_ref(),
+                config.suspect_timeout,
+                config.dead_timeout,
+                config.age_source,
+            );
+            storage.prune(config.entry_ttl, config.clock.as_ref())
+        };
+        if !pruned.is_empty() {
+            info!(pruned = ?pruned, "Pruned expired entries");
+        }
+        // pruned peers are reported as removed by the tracker
+        let transitions = state.tracker.evaluate(&runtime.storage.read(), now);
+        for event in transitions {
+            runtime.events.publish(event);
+        }
+        check_convergence(runtime, now);
+    }
+
+    if elapsed().saturating_sub(state.last_snapshot) >= SNAPSHOT_INTERVAL {
+        state.last_snapshot = elapsed();
+        runtime.save_snapshot();
+    }
+
+    let gossip = loop_span("gossip").entered();
+    while let Some(heartbeat) = receive_heartbeat(runtime, state) {
+        handle_heartbeat(runtime, state, heartbeat);
+    }
+    gossip.exit();
+
+    let until_heartbeat = state.next_heartbeat.saturating_sub(elapsed());
+    Some(config.poll_interval.min(until_heartbeat))
+}
+
+/// Tells the convergence callback, if any, when the node's view starts or stops covering the
+/// cluster.
+fn check_convergence(runtime: &NodeRuntime, now: u64) {
+    let mut convergence = runtime
+        .convergence
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let Some(convergence) = convergence.as_mut() else {
+        return;
+    };
+
+    // the storage is released before the callback runs, so the callback may query the node
+    let (members, missing) = convergence.watch.check(&runtime.storage.read(), now);
+    let event = match (convergence.converged, missing) {
+        (false, None) => ConvergenceEvent::Converged(ConvergenceChange {
+            members,
+            observed_at: now,
+            reason: format!(
+                "all {} members fresher than {}s",
+                members,
+                convergence.watch.threshold.as_secs()
+            ),
+        }),
+        (true, Some(reason)) => ConvergenceEvent::Diverged(ConvergenceChange {
+            members,
+            observed_at: now,
+            reason,
+        }),
+        _ => return,
+    };
+    convergence.converged = matches!(event, ConvergenceEvent::Converged(_));
+    (convergence.callback)(event);
+}
+
+fn send_heartbeat(runtime: &NodeRuntime, params: &GossipParams, rng: &mut StdRng) {
+    let config = &runtime.config;
+    let address = config.address;
+    let heartbeat = Heartbeat::new(config.id.clone(), address, config.clock.as_ref());
+
+    if let Err(e) = runtime
+        .storage
+        .write()
+        .insert(heartbeat.clone(), config.clock.now())
+    {
+        error!(error = e.to_string(), "failed insert heartbeat");
+        return;
+    }
+
+    let addresses;
+    {
+        let storage = runtime.storage.read();
+
+        addresses = match storage.select_n_random_addresses(
+            params.heartbeat_spread,
+            // we filter out the node itself
+            &[&config.id],
+            &[address],
+            rng,
+        ) {
+            Ok(addresses) => addresses,
+            Err(e) => {
+                error!(error = e.to_string(), "failed to select n random addresses");
+                return;
+            }
+        };
+    }
+
+    let channel = runtime.channel();
+    let Some(channel) = channel.as_ref() else {
+        return;
+    };
+
+    // a heartbeat sent to nobody has nowhere to be traced
+    if let Some(trace) = config.trace.as_ref().filter(|_| !addresses.is_empty()) {
+        trace.sent(&heartbeat);
+    }
+    let n_targets = addresses.len();
+    let result = channel.send(&heartbeat, &addresses);
+    runtime.record_send(&runtime.stats.heartbeats_sent, n_targets, &result);
+    match result {
+        Ok(()) => {
+            runtime
+                .last_heartbeat_ms
+                .store(runtime.elapsed_ms(), Ordering::Relaxed);
+            info!(targets = ?addresses, "Heartbeat sent successfully");
+        }
+        Err(e) => error!(
+            error = e.to_string(),
+            targets = ?addresses,
+            "failed to send heartbeat"
+        ),
+    }
+
+    // the node's own values ride along, so nodes that missed a put or joined later catch up
+    let values: Vec<KeyValue> = runtime
+        .storage
+        .read()
+        .values
+        .by_origin(&config.id)
+        .cloned()
+        .collect();
+    for value in &values {
+        let result = channel.send(value, &addresses);
+        runtime.record_send(&runtime.stats.values_sent, n_targets, &result);
+        if let Err(e) = result {
+            error!(
+                error = e.to_string(),
+                key = value.key,
+                "failed to send value"
+            );
+        }
+    }
+}
+
+/// Reads the next heartbeat queued on the socket, skipping datagrams that fail to decode and
+/// answering admin queries on the way. Returns None once the socket is empty or the node has
+/// been stopped.
+fn receive_heartbeat(runtime: &NodeRuntime, state: &mut LoopState) -> Option<Heartbeat> {
+    loop {
+        let received = { runtime.channel().as_ref()?.receive() };
+        match received {
+            Ok((Datagram::Heartbeat(heartbeat), src)) => {
+                runtime
+                    .stats
+                    .messages_received
+                    .fetch_add(1, Ordering::Relaxed);
+                if let Some(trace) = &runtime.config.trace {
+                    trace.received(&heartbeat, src, &runtime.config.id, runtime.config.address);
+                }
+                return Some(heartbeat);
+            }
+            Ok((Datagram::Admin(request), src)) => {
+                runtime
+                    .stats
+                    .messages_received
+                    .fetch_add(1, Ordering::Relaxed);
+                handle_admin_query(runtime, request.admin, src);
+            }
+            Ok((Datagram::Value(entry), _src)) => {
+                runtime
+                    .stats
+                    .messages_received
+                    .fetch_add(1, Ordering::Relaxed);
+                handle_value(runtime, state, entry);
+            }
+            Err(HeartbeatError::WouldBlock) => return None,
+            Err(e @ HeartbeatError::Decode { .. }) => {
+                runtime
+                    .stats
+                    .messages_received
+                    .fetch_add(1, Ordering::Relaxed);
+                runtime.stats.decode_errors.fetch_add(1, Ordering::Relaxed);
+                // anyone can send garbage, it says nothing about this node
+                warn!(error = e.to_string(), "failed to decode heartbeat");
+            }
+            Err(e) => {
+                error!(error = e.to_string(), "failed to receive heartbeat");
+                return None;
+            }
+        }
+    }
+}
+
+/// Replies to an admin query if its source is allowed to ask.
+fn handle_admin_query(runtime: &NodeRuntime, query: AdminQuery, src: SocketAddr) {
+    if !runtime.config.admin_allowlist.contains(&src.ip()) {
+        runtime.stats.admin_rejected.fetch_add(1, Ordering::Relaxed);
+        warn!(
+            source = src.to_string(),
+            "ignoring admin query from source not in allowlist"
+        );
+        return;
+    }
+
+    let body = match query {
+        AdminQuery::Members => serde_json::to_string(&runtime.members()),
+        AdminQuery::Stats => serde_json::to_string(&runtime.stats.snapshot()),
+        AdminQuery::Storage => serde_json::to_string(&runtime.storage.with_read(Storage::snapshot)),
+        AdminQuery::Events => serde_json::to_string(
+            &runtime
+                .storage
+                .with_read(|storage| storage.recent_events(admin::ADMIN_EVENT_LIMIT)),
+        ),
+    };
+    let body = match body {
+        Ok(body) => body,
+        Err(e) => {
+            error!(error = e.to_string(), "failed to serialize admin reply");
+            return;
+        }
+    };
+
+    let channel = runtime.channel();
+    let Some(channel) = channel.as_ref() else {
+        return;
+    };
+    for response in admin::response_chunks(&body) {
+        if let Err(e) = channel.reply(&response, src) {
+            error!(error = e.to_string(), "failed to send admin reply");
+            return;
+        }
+    }
+}
+
+/// Stores a received heartbeat and passes it on to a few peers, unless the decay says it has
+/// been seen often enough.
+fn handle_heartbeat(runtime: &NodeRuntime, state: &mut LoopState, heartbeat: Heartbeat) {
+    let config = &runtime.config;
+    let address = config.address;
+
+    if heartbeat.id == config.id && heartbeat.address != address {
+        handle_id_conflict(runtime, &heartbeat, &mut state.last_reannounce);
+        return;
+    }
+
+    // a straggler older than the ttl would bring back an entry that was just pruned
+    let age = config.clock.now().saturating_sub(heartbeat.timestamp);
+    if age > config.entry_ttl.as_secs() {
+        debug!(
+            node_id = heartbeat.id.as_str(),
+            age, "Ignoring expired heartbeat"
+        );
+        return;
+    }
+
+    let now = config.clock.now();
+    // the storage clamps or rejects it, see TimestampBounds
+    if heartbeat.timestamp > now.saturating_add(config.max_future_skew.as_secs()) {
+        runtime
+            .stats
+            .future_timestamps
+            .fetch_add(1, Ordering::Relaxed);
+        warn!(
+            node_id = heartbeat.id.as_str(),
+            timestamp = heartbeat.timestamp,
+            now,
+            policy = ?config.future_timestamps,
+            "Heartbeat from the future"
+        );
+    }
+    let outcome: InsertOutcome;
+    let observed: Option<MembershipEvent>;
+    let evicted: Vec<Heartbeat>;
+    // what is passed on: the heartbeat as stored, its timestamp clamped if it was from the future
+    let forwarded: Heartbeat;
+    {
+        let mut storage = runtime.storage.write();
+
+        outcome = match storage.insert(heartbeat.clone(), now) {
+            Ok(outcome) => outcome,
+            Err(e @ HeartbeatError::UnreachableAddress { .. }) => {
+                runtime
+                    .stats
+                    .addresses_rejected
+                    .fetch_add(1, Ordering::Relaxed);
+                debug!(error = e.to_string(), "Ignoring heartbeat");
+                return;
+            }
+            Err(e @ HeartbeatError::FutureTimestamp { .. }) => {
+                debug!(error = e.to_string(), "Ignoring heartbeat");
+                return;
+            }
+            Err(e) => {
+                error!(error = e.to_string(), "failed to insert heartbeat");
+                return;
+            }
+        };
+        evicted = match config.max_entries {
+            Some(max_entries) => storage.evict_stalest(max_entries, config.clock.as_ref()),
+            None => vec![],
+        };
+        // observe the stored entry, which may be newer than a late duplicate we just received
+        let stored = storage.data.get(&heartbeat.id);
+        observed = stored.and_then(|stored| state.tracker.observe_entry(stored, now));
+        // a stale heartbeat isn't forwarded at all, an evicted one as it came
+        forwarded = stored.map_or_else(|| heartbeat.clone(), |stored| stored.heartbeat.clone());
+    }
+
+    if !evicted.is_empty() {
+        runtime
+            .stats
+            .entries_evicted
+            .fetch_add(evicted.len() as u64, Ordering::Relaxed);
+        for evicted in &evicted {
+            if let Some(event) = state
+                .tracker
+                .forget(evicted, now, "evicted, storage is full")
+            {
+                runtime.events.publish(event);
+            }
+        }
+    }
+
+    if let Some(event) = observed {
+        runtime.events.publish(event);
+    }
+
+    // only the first receipt and copies of the stored heartbeat count towards forwarding, an
+    // older heartbeat carries nothing worth spreading
+    if outcome != InsertOutcome::Applied {
+        runtime
+            .stats
+            .duplicates_received
+            .fetch_add(1, Ordering::Relaxed);
+    } else if heartbeat.id != config.id {
+        runtime
+            .stats
+            .propagation
+            .record(heartbeat.stamped_millis(), config.clock.now_millis());
+    }
+    let times_received = match outcome {
+        InsertOutcome::Applied => 1,
+        InsertOutcome::DuplicateOfCurrent { duplicates } => duplicates + 1,
+        InsertOutcome::Stale => {
+            runtime
+                .stats
+                .stale_heartbeats
+                .fetch_add(1, Ordering::Relaxed);
+            debug!(
+                node_id = heartbeat.id.as_str(),
+                timestamp = heartbeat.timestamp,
+                "Not forwarding stale heartbeat"
+            );
+            return;
+        }
+    };
+    let params = GossipParams::read(&runtime.params);
+    let policy = runtime.forward_policy();
+    if !policy.should_forward(times_received, params.decay_factor, &mut state.gossip_rng) {
+        runtime
+            .stats
+            .forwards_suppressed
+            .fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    if times_received > 1 {
+        runtime
+            .stats
+            .duplicate_forwards
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    let addresses;
+    {
+        let storage = runtime.storage.read();
+
+        addresses = match storage.select_n_random_addresses(
+            params.heartbeat_spread,
+            // we filter out the node itself and the node the heartbeat came from
+            &[&config.id, &heartbeat.id],
+            &[address, heartbeat.address],
+            &mut state.gossip_rng,
+        ) {
+            Ok(addresses) => addresses,
+            Err(e) => {
+                error!(error = e.to_string(), "failed to select n random addresses");
+                return;
+            }
+        };
+    }
+
+    if addresses.is_empty() {
+        return;
+    }
+
+    let channel = runtime.channel();
+    let Some(channel) = channel.as_ref() else {
+        return;
+    };
+    let n_targets = addresses.len();
+    let result = channel.send(&forwarded, &addresses);
+    runtime.record_send(&runtime.stats.gossip_forwarded, n_targets, &result);
+    match result {
+        Ok(()) => info!(targets = ?addresses, "Heartbeat forwarded"),
+        Err(e) => error!(
+            error = e.to_string(),
+            targets = ?addresses,
+            "failed to forward heartbeat"
+        ),
+    }
+}
+
+/// Stores a received value and passes it on like a heartbeat, see [`handle_heartbeat`].
+fn handle_value(runtime: &NodeRuntime, state: &mut LoopState, entry: KeyValue) {
+    let config = &runtime.config;
+    // the node's own values only ever change through Node::put
+    if entry.origin == config.id {
+        return;
+    }
+
+    let outcome = match runtime.storage.write().values.apply(entry.clone()) {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            debug!(error = e.to_string(), "Ignoring value");
+            return;
+        }
+    };
+    let times_received = match outcome {
+        InsertOutcome::Applied => {
+            runtime
+                .events
+                .publish(key_changed(&entry, config.clock.now()));
+            1
+        }
+        InsertOutcome::DuplicateOfCurrent { duplicates } => duplicates + 1,
+        InsertOutcome::Stale => return,
+    };
+    let params = GossipParams::read(&runtime.params);
+    let policy = runtime.forward_policy();
+    if !policy.should_forward(times_received, params.decay_factor, &mut state.gossip_rng) {
+        runtime
+            .stats
+            .forwards_suppressed
+            .fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    let addresses = match runtime.storage.read().select_n_random_addresses(
+        params.heartbeat_spread,
+        &[&config.id, &entry.origin],
+        &[config.address, entry.address],
+        &mut state.gossip_rng,
+    ) {
+        Ok(addresses) => addresses,
+        Err(e) => {
+            error!(error = e.to_string(), "failed to select n random addresses");
+            return;
+        }
+    };
+    if addresses.is_empty() {
+        return;
+    }
+
+    let channel = runtime.channel();
+    let Some(channel) = channel.as_ref() else {
+        return;
+    };
+    let n_targets = addresses.len();
+    let result = channel.send(&entry, &addresses);
+    runtime.record_send(&runtime.stats.gossip_forwarded, n_targets, &result);
+    if let Err(e) = result {
+        error!(
+            error = e.to_string(),
+            targets = ?addresses,
+            "failed to forward value"
+        );
+    }
+}
+
+fn key_changed(entry: &KeyValue, now: u64) -> MembershipEvent {
+    MembershipEvent::KeyChanged(PeerChange {
+        id: entry.origin.clone(),
+        address: entry.address,
+        last_heartbeat: entry.timestamp,
+        observed_at: now,
+        reason: format!("key {} changed to version {}", entry.key, entry.version),
+    })
+}
+
+/// Called when a heartbeat carrying this node's id arrives from another address. The heartbeat is
+/// never stored, as it would replace the node's own entry.
+fn handle_id_conflict(
+    runtime: &NodeRuntime,
+    heartbeat: &Heartbeat,
+    last_reannounce: &mut Option<Duration>,
+) {
+    let config = &runtime.config;
+    let is_new = runtime
+        .storage
+        .write()
+        .record_conflict(&heartbeat.id, heartbeat.address);
+
+    if is_new {
+        error!(
+            node_id = config.id.as_str(),
+            conflicting_address = %heartbeat.address,
+            "another node is using this node's id"
+        );
+        runtime
+            .events
+            .publish(MembershipEvent::IdConflict(PeerChange {
+                id: heartbeat.id.clone(),
+                address: heartbeat.address,
+                last_heartbeat: heartbeat.timestamp,
+                observed_at: config.clock.now(),
+                reason: format!("id also claimed by {}", heartbeat.address),
+            }));
+    }
+
+    if !config.reannounce_on_conflict {
+        return;
+    }
+    // re-assert our own entry, at most once per heartbeat interval
+    let params = GossipParams::read(&runtime.params);
+    let now = config.clock.elapsed();
+    if last_reannounce.is_some_and(|at| now.saturating_sub(at) < params.heartbeat_interval) {
+        return;
+    }
+    *last_reannounce = Some(now);
+    if let Err(e) = runtime.announce(false, params.heartbeat_spread * 2) {
+        error!(
+            error = e.to_string(),
+            "failed to re-announce after id conflict"
+        );
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Heartbeat {
+    id: NodeId,
+    address: SocketAddr,
+    timestamp: u64,
+    /// set on the final heartbeat a node sends when it leaves the cluster
+    #[serde(default, skip_serializing_if = "is_false")]
+    leaving: bool,
+    /// milliseconds past `timestamp` the heartbeat was stamped at, for receivers to measure its
+    /// propagation latency; 0 from nodes that don't send them
+    #[serde(default, skip_serializing_if = "is_zero")]
+    millis: u16,
+}
+
+impl Heartbeat {
+    fn from_entry(entry: &SnapshotEntry) -> Self {
+        Heartbeat {
+            id: entry.id.clone(),
+            address: entry.address,
+            timestamp: entry.timestamp,
+            leaving: entry.leaving,
+            millis: 0,
+        }
+    }
+
+    /// Heartbeat for the node `id` listening on `address`, stamped with the current time of
+    /// `clock`.
+    pub fn new(id: NodeId, address: SocketAddr, clock: &dyn Clock) -> Self {
+        let now = clock.now_millis();
+        Heartbeat {
+            id,
+            address,
+            timestamp: now / 1000,
+            leaving: false,
+            millis: (now % 1000) as u16,
+        }
+    }
+
+    pub fn id(&self) -> &NodeId {
+        &self.id
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// When the heartbeat was stamped, in milliseconds since the unix epoch.
+    pub fn stamped_millis(&self) -> u64 {
+        self.timestamp * 1000 + u64::from(self.millis)
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Whether this is the final heartbeat of a node leaving the cluster.
+    pub fn is_leaving(&self) -> bool {
+        self.leaving
+    }
+
+    /// Orders two heartbeats of the same node, the newer one greater: by timestamp, then by the
+    /// milliseconds within its second. Heartbeats without milliseconds, from older nodes, a
+    /// snapshot or a self-refresh, can still tie, so ties are broken by the rest of the payload:
+    /// a leave announcement wins, then the greater address. Every node orders the same
+    /// heartbeats the same way, and only heartbeats equal in all of these compare equal.
+    pub fn cmp_version(&self, other: &Heartbeat) -> cmp::Ordering {
+        (self.timestamp, self.millis, self.leaving, self.address).cmp(&(
+            other.timestamp,
+            other.millis,
+            other.leaving,
+            other.address,
+        ))
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_zero(value: &u16) -> bool {
+    *value == 0
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeHeartbeatData {
+    pub heartbeat: Heartbeat,
+    /// heartbeats applied because they were newer than the stored one, the first one included
+    pub updates_received: u64,
+    /// copies received of the stored heartbeat or of older ones
+    pub duplicates_received: u64,
+    /// copies received of the stored heartbeat, reset by every update
+    pub duplicates_of_current: u64,
+    /// set on the entries of seeds until their first genuine heartbeat arrives; the stored one
+    /// was made up at bootstrap. Provisional entries are gossip targets but not members.
+    pub provisional: bool,
+    /// local clock time the current heartbeat arrived at; duplicates don't move it
+    pub last_received_at: u64,
+    /// state as of the latest [`Storage::evaluate`]
+    pub state: PeerState,
+    /// latest changes of `state`, oldest first and at most [`STATE_HISTORY_LEN`] of them
+    pub history: VecDeque<StateTransition>,
+}
+
+impl NodeHeartbeatData {
+    /// Entry for a peer seen for the first time, alive until evaluated otherwise. Counts as
+    /// received when it was sent until [`Storage::insert`] says otherwise.
+    pub fn new(heartbeat: Heartbeat) -> Self {
+        NodeHeartbeatData {
+            last_received_at: heartbeat.timestamp,
+            heartbeat,
+            updates_received: 0,
+            duplicates_received: 0,
+            duplicates_of_current: 0,
+            provisional: false,
+            state: PeerState::Alive,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Age in seconds at `now` according to the heartbeat's own timestamp.
+    pub fn claimed_age(&self, now: u64) -> u64 {
+        now.saturating_sub(self.heartbeat.timestamp)
+    }
+
+    /// Seconds since the heartbeat arrived here, at `now`.
+    pub fn observed_age(&self, now: u64) -> u64 {
+        now.saturating_sub(self.last_received_at)
+    }
+
+    pub fn age(&self, now: u64, source: AgeSource) -> u64 {
+        match source {
+            AgeSource::SenderTimestamp => self.claimed_age(now),
+            AgeSource::LocalReceipt => self.observed_age(now),
+        }
+    }
+
+    /// Seconds the heartbeat took from its sender to here, as far as the two clocks agree.
+    pub fn propagation_delay(&self) -> u64 {
+        self.last_received_at
+            .saturating_sub(self.heartbeat.timestamp)
+    }
+
+    /// Entry for a peer whose first heartbeat arrived at local time `now`.
+    pub(crate) fn first_received(heartbeat: Heartbeat, now: u64) -> Self {
+        NodeHeartbeatData {
+            updates_received: 1,
+            last_received_at: now,
+            ..NodeHeartbeatData::new(heartbeat)
+        }
+    }
+
+    /// Whether `heartbeat` replaces the stored one: it does if it is newer by
+    /// [`Heartbeat::cmp_version`], and any genuine heartbeat replaces a provisional entry.
+    pub fn is_superseded_by(&self, heartbeat: &Heartbeat) -> bool {
+        self.provisional || heartbeat.cmp_version(&self.heartbeat) == cmp::Ordering::Greater
+    }
+
+    /// Takes `heartbeat`, received at local time `now`, if it supersedes the stored one and
+    /// counts it as a duplicate otherwise.
+    pub(crate) fn apply(&mut self, heartbeat: Heartbeat, now: u64) -> InsertOutcome {
+        if !self.is_superseded_by(&heartbeat) {
+            self.duplicates_received += 1;
+            if heartbeat.cmp_version(&self.heartbeat) == cmp::Ordering::Less {
+                return InsertOutcome::Stale;
+            }
+            self.duplicates_of_current += 1;
+            return InsertOutcome::DuplicateOfCurrent {
+                duplicates: self.duplicates_of_current,
+            };
+        }
+        // the state and its history stay until the next evaluation
+        self.heartbeat = heartbeat;
+        self.updates_received += 1;
+        self.duplicates_of_current = 0;
+        self.provisional = false;
+        self.last_received_at = now;
+        InsertOutcome::Applied
+    }
+
+    pub(crate) fn snapshot_entry(&self) -> SnapshotEntry {
+        SnapshotEntry {
+            id: self.heartbeat.id.clone(),
+            address: self.heartbeat.address,
+            timestamp: self.heartbeat.timestamp,
+            leaving: self.heartbeat.leaving,
+            state: self.state,
+            updates_received: self.updates_received,
+            duplicates_received: self.duplicates_received,
+            provisional: self.provisional,
+        }
+    }
+
+    fn record(&mut self, transition: StateTransition) {
+        if self.history.len() >= STATE_HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.state = transition.state;
+        self.history.push_back(transition);
+    }
+}
+
+/// What [`Storage::merge`] did with the entries it was given.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// entries that were new or newer than the stored ones, `new_ids` included
+    pub applied: usize,
+    /// entries no newer than the stored ones
+    pub ignored_stale: usize,
+    /// entries advertising an address that can't be sent to
+    pub rejected: usize,
+    /// peers that were not in the storage before
+    pub new_ids: Vec<NodeId>,
+}
+
+/// What storing a received heartbeat did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertOutcome {
+    /// the heartbeat was newer than the stored one, or the first of its node, and replaced it
+    Applied,
+    /// the heartbeat was the stored one again; `duplicates` counts the copies so far
+    DuplicateOfCurrent { duplicates: u64 },
+    /// an older heartbeat than the stored one
+    Stale,
+}
+
+/// What a storage does with a heartbeat whose timestamp is further ahead of the local clock than
+/// [`TimestampBounds::max_future_skew`]. Such a heartbeat would win every comparison until the
+/// real clock caught up with it, shadowing the genuine heartbeats of its node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FutureTimestampPolicy {
+    /// store it with its timestamp pulled back to the bound, so genuine heartbeats take over
+    /// once they are `max_future_skew` newer than the time of receipt
+    #[default]
+    Clamp,
+    /// drop it with a [`HeartbeatError::FutureTimestamp`]
+    Reject,
+}
+
+/// Plausibility bounds on the timestamps of inserted heartbeats, set with
+/// [`Storage::set_timestamp_bounds`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampBounds {
+    pub max_future_skew: Duration,
+    pub future_timestamps: FutureTimestampPolicy,
+    /// the first heartbeat of an unknown node that is at least this old is stored as suspect,
+    /// counting as received when it was sent; a node passes its suspect timeout
+    pub stale_after: Duration,
+}
+
+impl TimestampBounds {
+    /// The timestamp of `heartbeat` if it lies beyond the bound at local time `now`.
+    pub(crate) fn future_timestamp(&self, heartbeat: &Heartbeat, now: u64) -> Option<u64> {
+        (heartbeat.timestamp > self.latest_allowed(now)).then_some(heartbeat.timestamp)
+    }
+
+    /// Pulls a timestamp beyond the bound back to it, or rejects the heartbeat, as the policy
+    /// says.
+    pub(crate) fn clamp(&self, heartbeat: &mut Heartbeat, now: u64) -> Result<(), HeartbeatError> {
+        let latest_allowed = self.latest_allowed(now);
+        if heartbeat.timestamp <= latest_allowed {
+            return Ok(());
+        }
+        match self.future_timestamps {
+            FutureTimestampPolicy::Clamp => {
+                heartbeat.timestamp = latest_allowed;
+                Ok(())
+            }
+            FutureTimestampPolicy::Reject => Err(HeartbeatError::FutureTimestamp {
+                id: heartbeat.id.clone(),
+                timestamp: heartbeat.timestamp,
+                now,
+            }),
+        }
+    }
+
+    /// Whether `heartbeat`, as the first one of its node, is too old to pass for alive. Such a
+    /// heartbeat is second-hand news: it counts as received when it was sent.
+    pub(crate) fn is_stale(&self, heartbeat: &Heartbeat, now: u64) -> bool {
+        now.saturating_sub(heartbeat.timestamp) >= self.stale_after.as_secs()
+    }
+
+    fn latest_allowed(&self, now: u64) -> u64 {
+        now.saturating_add(self.max_future_skew.as_secs())
+    }
+}
+
+impl Default for TimestampBounds {
+    fn default() -> Self {
+        TimestampBounds {
+            max_future_skew: defaults::MAX_FUTURE_SKEW,
+            future_timestamps: FutureTimestampPolicy::default(),
+            stale_after: defaults::SUSPECT_TIMEOUT,
+        }
+    }
+}
+
+/// The entries of a [`Storage`]. They are hashed with fixed keys rather than per process, so that
+/// peers are picked from the same order, and a seeded node gossips the same way, every run.
+pub type Entries = HashMap<NodeId, NodeHeartbeatData, BuildHasherDefault<DefaultHasher>>;
+
+/// A node's view of the cluster, created with [`setup_storage`].
+#[derive(Debug, Clone)]
+pub struct Storage {
+    pub data: Entries,
+    pub sent_to_data: HashMap<NodeId, Vec<SocketAddr>>,
+    /// other addresses seen claiming an id, keyed by that id
+    pub id_conflicts: HashMap<NodeId, Vec<SocketAddr>>,
+    /// entries that are never pruned: the node itself and its seeds
+    pub pinned: HashSet<NodeId>,
+    /// the node this storage belongs to, whose entry can't be removed
+    pub owner: NodeId,
+    /// application values gossiped alongside the heartbeats
+    pub values: KvStore,
+    // bumped by every change to an entry, see Storage::version
+    version: u64,
+    events: EventLog,
+    journal: ChangeJournal,
+    bounds: TimestampBounds,
+}
+
+impl Storage {
+    /// Counter that goes up whenever an entry is added, replaced by a newer heartbeat, changes
+    /// state or is removed. Duplicate heartbeats only bump their entry's received count and
+    /// leave the version alone.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The latest `n` changes to the storage, oldest first, see [`crate::event_log`].
+    pub fn recent_events(&self, n: usize) -> Vec<StorageEvent> {
+        self.events.recent(n)
+    }
+
+    /// Makes room for the entries of a cluster of `size` nodes, so they can be inserted without
+    /// growing the maps.
+    pub fn reserve_for(&mut self, size: usize) {
+        self.data.reserve(size.saturating_sub(self.data.len()));
+        self.sent_to_data
+            .reserve(size.saturating_sub(self.sent_to_data.len()));
+    }
+
+    /// Keeps at most `capacity` events, 0 turns the log off.
+    pub fn set_event_log_capacity(&mut self, capacity: usize) {
+        self.events.set_capacity(capacity);
+    }
+
+    /// Changes how heartbeats with implausible timestamps are stored from now on.
+    pub fn set_timestamp_bounds(&mut self, bounds: TimestampBounds) {
+        self.bounds = bounds;
+    }
+
+    pub fn timestamp_bounds(&self) -> TimestampBounds {
+        self.bounds
+    }
+
+    fn log(&mut self, at: u64, id: &NodeId, kind: StorageEventKind) {
+        self.events.record(StorageEvent {
+            at,
+            id: id.clone(),
+            kind,
+        });
+    }
+
+    /// Every entry, the owner's included, without copying any of them.
+    pub fn iter_entries(&self) -> impl Iterator<Item = &NodeHeartbeatData> {
+        self.data.values()
+    }
+
+    pub fn for_each_entry(&self, f: impl FnMut(&NodeHeartbeatData)) {
+        self.iter_entries().for_each(f);
+    }
+
+    /// Number of entries that are not provisional, the node itself included.
+    pub fn count_known(&self) -> usize {
+        self.iter_entries().filter(|data| !data.provisional).count()
+    }
+
+    /// Counts the entries by state and sums up their ages and counters in a single pass,
+    /// without allocating. Ages are taken from the heartbeats' own timestamps.
+    pub fn metrics(
+        &self,
+        clock: &dyn Clock,
+        suspect_after: Duration,
+        dead_after: Duration,
+    ) -> StorageMetrics {
+        let now = clock.now();
+        let mut metrics = StorageMetrics::default();
+        let mut ages = AgeHistogram::new();
+        for data in self.iter_entries() {
+            metrics.entries += 1;
+            metrics.total_updates += data.updates_received;
+            metrics.total_duplicates += data.duplicates_received;
+            if data.provisional {
+                metrics.provisional += 1;
+                continue;
+            }
+            let age = data.claimed_age(now);
+            match PeerState::of(&data.heartbeat, age, suspect_after, dead_after) {
+                PeerState::Alive => metrics.alive += 1,
+                PeerState::Suspect => metrics.suspect += 1,
+                PeerState::Dead => metrics.dead += 1,
+                PeerState::Left => metrics.left += 1,
+            }
+            let age = Duration::from_secs(age);
+            metrics.oldest_age = metrics.oldest_age.max(Some(age));
+            ages.add(age.as_secs());
+        }
+        metrics.median_age = ages.median();
+        metrics
+    }
+
+    /// Renews the owner's own entry with a heartbeat stamped with the current time, so the node
+    /// never looks stale to itself. Does nothing once the owner announced that it leaves, or if
+    /// the entry is already that fresh. Returns whether the entry was renewed.
+    ///
+    /// A renewal isn't a change of the storage: the version stays and nothing is journalled or
+    /// logged, so that a node nobody talks to doesn't report a change every second.
+    pub fn refresh_owner(&mut self, clock: &dyn Clock) -> bool {
+        let now = clock.now();
+        let Some(own) = self.data.get_mut(&self.owner) else {
+            return false;
+        };
+        if own.heartbeat.leaving || own.heartbeat.timestamp >= now {
+            return false;
+        }
+        let heartbeat = Heartbeat {
+            timestamp: now,
+            millis: 0,
+            ..own.heartbeat.clone()
+        };
+        own.apply(heartbeat, now) == InsertOutcome::Applied
+    }
+
+    /// Whether any entry changed since the storage was at `version`.
+    pub fn changed_since(&self, version: u64) -> bool {
+        self.version != version
+    }
+
+    /// What changed since the storage was at `version`, oldest first, see [`crate::journal`].
+    /// Fails once the journal has dropped some of those changes, or if the storage never was at
+    /// `version`.
+    pub fn changes_since(&self, version: u64) -> Result<Vec<ChangeRecord>, ResyncRequired> {
+        self.journal.since(version, self.version)
+    }
+
+    // records a change at the current version, bump the version first
+    fn journal(&mut self, id: &NodeId, kind: ChangeKind) {
+        self.journal.record(ChangeRecord {
+            version: self.version,
+            id: id.clone(),
+            kind,
+        });
+    }
+
+    /// Removes the entry of `id` and returns it. The owner's own entry is never removed, `None`
+    /// is returned for it just like for an unknown id.
+    pub fn remove(&mut self, id: &str, clock: &dyn Clock) -> Option<NodeHeartbeatData> {
+        self.remove_logged(id, clock.now(), RemovalCause::Requested)
+    }
+
+    fn remove_logged(
+        &mut self,
+        id: &str,
+        now: u64,
+        cause: RemovalCause,
+    ) -> Option<NodeHeartbeatData> {
+        if self.owner == id {
+            warn!(node_id = id, "refusing to remove the node's own entry");
+            return None;
+        }
+        self.sent_to_data.remove(id);
+        let removed = self.data.remove(id);
+        if let Some(data) = &removed {
+            self.version += 1;
+            self.journal(&data.heartbeat.id, ChangeKind::Removed);
+            self.log(now, &data.heartbeat.id, StorageEventKind::Removed { cause });
+        }
+        removed
+    }
+
+    /// Removes every entry whose latest heartbeat is older than `max_age` and returns the ids
+    /// removed. Pinned entries stay, so a node can always find its way back through its seeds.
+    ///
+    /// Pruning is purely local; nodes don't gossip about removals. Peers that left are pruned
+    /// like any other once their leave announcement is older than `max_age`. A pruned peer whose
+    /// heartbeats resume is inserted again as if it was seen for the first time.
+    pub fn prune(&mut self, max_age: Duration, clock: &dyn Clock) -> Vec<NodeId> {
+        let now = clock.now();
+        let expired: Vec<NodeId> = self
+            .data
+            .iter()
+            .filter(|(id, _)| !self.pinned.contains(*id))
+            .filter(|(_, data)| now.saturating_sub(data.heartbeat.timestamp) > max_age.as_secs())
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            self.remove_logged(id.as_str(), now, RemovalCause::Expired);
+        }
+        expired
+    }
+
+    /// Exports every entry, the node's own included.
+    pub fn snapshot(&self) -> StorageSnapshot {
+        StorageSnapshot {
+            owner: self.owner.clone(),
+            entries: self
+                .data
+                .values()
+                .map(NodeHeartbeatData::snapshot_entry)
+                .collect(),
+            values: self.values.iter().cloned().collect(),
+        }
+    }
+
+    /// Rebuilds a storage from an export. Only the owner's entry is pinned, the seeds the
+    /// exporting node was set up with are not part of the snapshot.
+    pub fn from_snapshot(snapshot: StorageSnapshot) -> Storage {
+        let mut values = KvStore::default();
+        for entry in snapshot.values {
+            // an export never holds more than a store accepts
+            let _ = values.apply(entry);
+        }
+        let data = snapshot
+            .entries
+            .into_iter()
+            .map(|entry| {
+                let mut data = NodeHeartbeatData::new(Heartbeat::from_entry(&entry));
+                data.state = entry.state;
+                data.updates_received = entry.updates_received;
+                data.duplicates_received = entry.duplicates_received;
+                data.provisional = entry.provisional;
+                (entry.id, data)
+            })
+            .collect();
+        Storage {
+            data,
+            sent_to_data: HashMap::new(),
+            id_conflicts: HashMap::new(),
+            pinned: HashSet::from([snapshot.owner.clone()]),
+            owner: snapshot.owner,
+            version: 0,
+            values,
+            events: EventLog::new(defaults::EVENT_LOG_CAPACITY),
+            journal: ChangeJournal::new(defaults::CHANGE_JOURNAL_CAPACITY),
+            bounds: TimestampBounds::default(),
+        }
+    }
+
+    /// Adds entries restored from a snapshot and returns how many were added. Entries already
+    /// present, i.e. the node itself and its seeds, win, and entries older than `max_age` are
+    /// dropped. The rest are back-dated to at least `stale_after` old, so a restored peer counts
+    /// as suspect until a heartbeat of its own arrives.
+    pub fn restore(
+        &mut self,
+        snapshot: StorageSnapshot,
+        clock: &dyn Clock,
+        stale_after: Duration,
+        max_age: Duration,
+    ) -> usize {
+        let now = clock.now();
+        let stale_at = now.saturating_sub(stale_after.as_secs());
+        let entries = snapshot
+            .entries
+            .into_iter()
+            .filter(|entry| {
+                !self.data.contains_key(&entry.id)
+                    && now.saturating_sub(entry.timestamp) <= max_age.as_secs()
+            })
+            .map(|entry| SnapshotEntry {
+                timestamp: entry.timestamp.min(stale_at),
+                ..entry
+            })
+            .collect();
+        self.merge(
+            StorageSnapshot {
+                owner: snapshot.owner,
+                entries,
+                values: snapshot.values,
+            },
+            clock,
+        )
+        .applied
+    }
+
+    /// Merges the entries of another storage's export into this one, entry by entry: a newer
+    /// heartbeat replaces the stored one just as if it had been received. Merged heartbeats
+    /// count as received when they were sent, they are second-hand news. The states in
+    /// `snapshot` are ignored; new peers show up as joined with the next membership evaluation.
+    /// Values are merged like received ones and don't count towards the report.
+    pub fn merge(&mut self, snapshot: StorageSnapshot, clock: &dyn Clock) -> MergeReport {
+        let now = clock.now();
+        let mut report = MergeReport::default();
+        for entry in snapshot.values {
+            if let Err(e) = self.values.apply(entry) {
+                debug!(error = e.to_string(), "Ignoring merged value");
+            }
+        }
+        for entry in snapshot.entries {
+            let heartbeat = Heartbeat::from_entry(&entry);
+            let received_at = heartbeat.timestamp.min(now);
+            let is_new = !self.data.contains_key(&entry.id);
+            // a made up entry only fills a gap, it never replaces a stored one
+            if entry.provisional {
+                if !is_reachable(heartbeat.address) {
+                    report.rejected += 1;
+                } else if is_new {
+                    let data = NodeHeartbeatData {
+                        provisional: true,
+                        ..NodeHeartbeatData::new(heartbeat)
+                    };
+                    self.data.insert(entry.id.clone(), data);
+                    self.version += 1;
+                    self.journal(&entry.id, ChangeKind::Added);
+                    report.applied += 1;
+                    report.new_ids.push(entry.id);
+                } else {
+                    report.ignored_stale += 1;
+                }
+                continue;
+            }
+            match self.insert_received(heartbeat, now, received_at) {
+                Ok(InsertOutcome::Applied) => {
+                    report.applied += 1;
+                    if is_new {
+                        report.new_ids.push(entry.id);
+                    }
+                }
+                Ok(_) => report.ignored_stale += 1,
+                Err(_) => report.rejected += 1,
+            }
+        }
+        report
+    }
+
+    /// Evicts the entries with the oldest heartbeats until at most `max_entries` are left and
+    /// returns the heartbeats evicted. Pinned entries are never evicted, so the storage may stay
+    /// above the cap when there are more of them than `max_entries`. An evicted peer is inserted
+    /// again like a new one when a newer heartbeat of it arrives.
+    pub fn evict_stalest(&mut self, max_entries: usize, clock: &dyn Clock) -> Vec<Heartbeat> {
+        let now = clock.now();
+        let excess = self.data.len().saturating_sub(max_entries);
+        if excess == 0 {
+            return vec![];
+        }
+        let mut candidates: Vec<(u64, NodeId)> = self
+            .data
+            .iter()
+            .filter(|(id, _)| !self.pinned.contains(*id))
+            .map(|(id, data)| (data.heartbeat.timestamp, id.clone()))
+            .collect();
+        // only the evicted candidates are sorted, a full storage evicts one entry per insert
+        if excess < candidates.len() {
+            candidates.select_nth_unstable(excess);
+            candidates.truncate(excess);
+        }
+        candidates.sort_unstable();
+        candidates
+            .into_iter()
+            .filter_map(|(_, id)| {
+                self.remove_logged(id.as_str(), now, RemovalCause::Evicted)
+                    .map(|data| data.heartbeat)
+            })
+            .collect()
+    }
+
+    /// Records that `address` sent a heartbeat for `id` although `id` belongs to someone else.
+    /// Returns false if the conflict was already known.
+    fn record_conflict(&mut self, id: &NodeId, address: SocketAddr) -> bool {
+        let claimants = self.id_conflicts.entry(id.clone()).or_default();
+        if claimants.contains(&address) {
+            return false;
+        }
+        claimants.push(address);
+        true
+    }
+
+    /// The `n` entries that arrived here the longest ago, stalest first: the peers we know least
+    /// about, the ones worth probing or repairing first. Provisional entries are left out, as
+    /// is the node's own entry with `exclude_self`. Only the `n` picked entries are sorted.
+    pub fn stalest(&self, n: usize, exclude_self: bool) -> Vec<&NodeHeartbeatData> {
+        let mut entries: Vec<&NodeHeartbeatData> = self
+            .iter_entries()
+            .filter(|data| !data.provisional)
+            .filter(|data| !exclude_self || data.heartbeat.id != self.owner)
+            .collect();
+        if n == 0 {
+            return vec![];
+        }
+        if n < entries.len() {
+            entries.select_nth_unstable_by_key(n - 1, |data| data.last_received_at);
+            entries.truncate(n);
+        }
+        entries.sort_unstable_by_key(|data| data.last_received_at);
+        entries
+    }
+
+    /// Summarises the storage as seen at `now`. Only the stalest entries are copied.
+    pub fn summary(&self, now: u64) -> StorageSummary {
+        let mut entries: Vec<&NodeHeartbeatData> = self.data.values().collect();
+        entries.sort_unstable_by_key(|data| data.heartbeat.timestamp);
+        let age = |data: &NodeHeartbeatData| {
+            Duration::from_secs(now.saturating_sub(data.heartbeat.timestamp))
+        };
+
+        StorageSummary {
+            entries: entries.len(),
+            oldest_age: entries.first().map(|data| age(data)),
+            stalest: entries
+                .iter()
+                .take(SUMMARY_DETAIL_LIMIT)
+                .map(|data| EntrySummary {
+                    id: data.heartbeat.id.clone(),
+                    address: data.heartbeat.address,
+                    age: age(data),
+                    updates_received: data.updates_received,
+                    duplicates_received: data.duplicates_received,
+                })
+                .collect(),
+            remaining: entries.len().saturating_sub(SUMMARY_DETAIL_LIMIT),
+        }
+    }
+
+    /// Picks up to `n` distinct addresses of entries that are neither one of `excluded_ids` nor
+    /// at one of `excluded_addresses`. Excluding by id as well keeps a node from gossiping back
+    /// to a peer whose address changed, or to itself when bound to an unspecified address.
+    pub fn select_n_random_addresses(
+        &self,
+        n: usize,
+        excluded_ids: &[&NodeId],
+        excluded_addresses: &[SocketAddr],
+        rng: &mut impl Rng,
+    ) -> Result<Vec<SocketAddr>, HeartbeatError> {
+        let addresses = selectable_addresses(self.data.values(), excluded_ids, excluded_addresses);
+        let selected_addresses = select_random_n(addresses, n, rng);
+        Ok(selected_addresses)
+    }
+
+    /// Stores `heartbeat`, received at local time `now`, unless the stored heartbeat of its node
+    /// is as new or newer.
+    fn insert(&mut self, heartbeat: Heartbeat, now: u64) -> Result<InsertOutcome, HeartbeatError> {
+        self.insert_received(heartbeat, now, now)
+    }
+
+    /// Same as [`Storage::insert`], for a heartbeat that counts as received at `received_at`.
+    /// Timestamps are checked against the storage's [`TimestampBounds`] first.
+    fn insert_received(
+        &mut self,
+        mut heartbeat: Heartbeat,
+        now: u64,
+        mut received_at: u64,
+    ) -> Result<InsertOutcome, HeartbeatError> {
+        if !is_reachable(heartbeat.address) {
+            let address = heartbeat.address;
+            self.log(now, &heartbeat.id, StorageEventKind::Rejected { address });
+            return Err(HeartbeatError::UnreachableAddress {
+                id: heartbeat.id,
+                address,
+            });
+        }
+        if let Some(timestamp) = self.bounds.future_timestamp(&heartbeat, now) {
+            let clamped = self.bounds.future_timestamps == FutureTimestampPolicy::Clamp;
+            self.log(
+                now,
+                &heartbeat.id,
+                StorageEventKind::FutureTimestamp { timestamp, clamped },
+            );
+            self.bounds.clamp(&mut heartbeat, now)?;
+        }
+        let stale = !self.data.contains_key(&heartbeat.id) && self.bounds.is_stale(&heartbeat, now);
+        if stale {
+            received_at = received_at.min(heartbeat.timestamp);
+        }
+        let id = heartbeat.id.clone();
+        let to = heartbeat.timestamp;
+        let kind = match self.data.get_mut(&heartbeat.id) {
+            Some(d) => {
+                let from = d.heartbeat.timestamp;
+                let outcome = d.apply(heartbeat, received_at);
+                if outcome != InsertOutcome::Applied {
+                    return Ok(outcome);
+                }
+                // the node's own heartbeats would crowd out everything else
+                if id != self.owner {
+                    self.log(now, &id, StorageEventKind::Updated { from, to });
+                }
+                self.sent_to_data.insert(id.clone(), vec![]);
+                ChangeKind::Updated
+            }
+            None => {
+                self.log(now, &id, StorageEventKind::FirstSeen { timestamp: to });
+                let mut data = NodeHeartbeatData::first_received(heartbeat, received_at);
+                if stale {
+                    data.state = PeerState::Suspect;
+                }
+                self.data.insert(id.clone(), data);
+                ChangeKind::Added
+            }
+        };
+        self.version += 1;
+        self.journal(&id, kind);
+        Ok(InsertOutcome::Applied)
+    }
+
+    /// Re-derives the state of every peer from the age of its latest heartbeat, measured with
+    /// `age_source`, and records the entries whose state changed. Returns the ids of those
+    /// entries.
+    pub fn evaluate(
+        &mut self,
+        clock: &dyn Clock,
+        suspect_after: Duration,
+        dead_after: Duration,
+        age_source: AgeSource,
+    ) -> Vec<NodeId> {
+        let now = clock.now();
+        let mut changed = vec![];
+        for (id, data) in self.data.iter_mut() {
+            if *id == self.owner || data.provisional {
+                continue;
+            }
+            let age = data.age(now, age_source);
+            let state = PeerState::of(&data.heartbeat, age, suspect_after, dead_after);
+            if state == data.state {
+                continue;
+            }
+            let from = data.state;
+            data.record(StateTransition {
+                state,
+                at: now,
+                cause: state.cause(age, suspect_after, dead_after),
+            });
+            changed.push((id.clone(), from, state));
+        }
+        if !changed.is_empty() {
+            self.version += 1;
+        }
+        changed
+            .into_iter()
+            .map(|(id, from, to)| {
+                self.journal(&id, ChangeKind::StateChanged);
+                self.log(now, &id, StorageEventKind::StateChanged { from, to });
+                id
+            })
+            .collect()
+    }
+}
+
+/// Shared access to the storage of a node, handed out by [`Node::storage`]. Every method takes
+/// the lock only for its own duration and recovers the storage if a previous holder panicked.
+/// Readers share the lock, only inserting a heartbeat takes it exclusively.
+#[derive(Debug, Clone)]
+pub struct StorageHandle {
+    inner: Arc<RwLock<Storage>>,
+}
+
+impl StorageHandle {
+    fn new(storage: Storage) -> Self {
+        StorageHandle {
+            inner: Arc::new(RwLock::new(storage)),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Storage> {
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Storage> {
+        self.inner.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Copy of the whole storage. Prefer [`StorageHandle::with_read`] for large clusters.
+    pub fn snapshot(&self) -> Storage {
+        self.read().clone()
+    }
+
+    /// Runs `f` with the storage read-locked. `f` must not call back into the node.
+    pub fn with_read<R>(&self, f: impl FnOnce(&Storage) -> R) -> R {
+        f(&self.read())
+    }
+
+    /// Number of entries, including the node itself.
+    pub fn len(&self) -> usize {
+        self.read().data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().data.is_empty()
+    }
+
+    pub fn get(&self, id: &str) -> Option<NodeHeartbeatData> {
+        self.read().data.get(id).cloned()
+    }
+}
+
+/// Anything a node can receive on its socket.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Datagram {
+    Heartbeat(Heartbeat),
+    Admin(AdminRequest),
+    Value(KeyValue),
+}
+
+/// What a channel sends and receives through: a UDP socket, or an address bound on an
+/// in-memory network.
+enum Socket {
+    Udp(UdpSocket),
+    Memory(MemorySocket),
+}
+
+impl Socket {
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self {
+            Socket::Udp(socket) => socket.recv_from(buf),
+            Socket::Memory(socket) => socket.recv_from(buf),
+        }
+    }
+
+    fn send_to(&self, message: &[u8], target: SocketAddr) -> io::Result<usize> {
+        match self {
+            Socket::Udp(socket) => socket.send_to(message, target),
+            Socket::Memory(socket) => socket.send_to(message, target),
+        }
+    }
+}
+
+struct UdapChannel {
+    socket: Socket,
+    address: SocketAddr,
+    faults: Option<Arc<FaultInjector>>,
+    bytes: Arc<ByteCounters>,
+}
+
+impl UdapChannel {
+    /// Binds the node's address, on its in-memory network if it has one, counting the bytes
+    /// through it in `bytes`.
+    fn bind(config: &NodeConfig, bytes: Arc<ByteCounters>) -> Result<Self, HeartbeatError> {
+        let address = config.address;
+        let bind = || -> io::Result<Socket> {
+            if let Some(network) = &config.memory_network {
+                return network.bind(address).map(Socket::Memory);
+            }
+            let socket = UdpSocket::bind(address)?;
+            socket.set_nonblocking(true)?;
+            Ok(Socket::Udp(socket))
+        };
+        bind()
+            .map(|socket| UdapChannel {
+                socket,
+                address,
+                faults: config.faults.clone(),
+                bytes,
+            })
+            .map_err(|source| HeartbeatError::Bind { address, source })
+    }
+
+    /// Reads and discards every datagram currently queued on the socket.
+    fn drain(&self) {
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+        while self.socket.recv_from(&mut buf).is_ok() {}
+    }
+
+    fn receive(&self) -> Result<(Datagram, SocketAddr), HeartbeatError> {
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+        let (size, src) = self.socket.recv_from(&mut buf)?;
+        self.bytes
+            .received
+            .fetch_add(size as u64, Ordering::Relaxed);
+        let datagram = serde_json::from_slice::<Datagram>(&buf[..size]).map_err(|source| {
+            HeartbeatError::Decode {
+                source,
+                bytes_len: size,
+            }
+        })?;
+
+        Ok((datagram, src))
+    }
+
+    fn reply(&self, response: &AdminResponse, target: SocketAddr) -> Result<(), HeartbeatError> {
+        let msg = serde_json::to_vec(response)?;
+        let size = self
+            .socket
+            .send_to(&msg, target)
+            .map_err(|source| HeartbeatError::Send { target, source })?;
+        self.bytes.sent.fetch_add(size as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn send(
+        &self,
+        message: &impl Serialize,
+        target_addresses: &[SocketAddr],
+    ) -> Result<(), HeartbeatError> {
+        let msg = serde_json::to_vec(message)?;
+        if msg.len() > MAX_DATAGRAM_SIZE {
+            return Err(HeartbeatError::MessageTooLarge {
+                size: msg.len(),
+                max: MAX_DATAGRAM_SIZE,
+            });
+        }
+        // the node wrote the message once per target, whether the network then loses, holds,
+        // duplicates or tampers with it
+        let written = || {
+            self.bytes
+                .sent
+                .fetch_add(msg.len() as u64, Ordering::Relaxed)
+        };
+        for &address in target_addresses {
+            let tampered = self
+                .faults
+                .as_ref()
+                .and_then(|faults| faults.tamper(self.address, &msg));
+            let msg = tampered.as_deref().unwrap_or(&msg);
+            let mut copies = 1;
+            if let Some(faults) = &self.faults {
+                let fate = faults.fate(self.address, address);
+                if fate != Fate::Drop {
+                    copies += faults.duplicates(self.address);
+                }
+                match fate {
+                    Fate::Deliver => {}
+                    Fate::Drop => {
+                        written();
+                        continue;
+                    }
+                    Fate::Delay(delay) => {
+                        for _ in 0..copies {
+                            match &self.socket {
+                                Socket::Udp(_) => faults.hold(msg.to_vec(), address, delay),
+                                Socket::Memory(socket) => faults.hold_in(
+                                    socket.network(),
+                                    msg.to_vec(),
+                                    self.address,
+                                    address,
+                                    delay,
+                                ),
+                            }
+                        }
+                        written();
+                        continue;
+                    }
+                }
+            }
+            for _ in 0..copies {
+                self.socket
+                    .send_to(msg, address)
+                    .map_err(|source| HeartbeatError::Send {
+                        target: address,
+                        source,
+                    })?;
+            }
+            written();
+        }
+        Ok(())
+    }
+}
+
+pub fn setup_storage(
+    id: NodeId,
+    address: SocketAddr,
+    seed_nodes: Vec<(NodeId, SocketAddr)>,
+    clock: &dyn Clock,
+) -> Storage {
+    let mut data = Entries::default();
+    let mut pinned: HashSet<NodeId> = seed_nodes.iter().map(|(id, _)| id.clone()).collect();
+    pinned.insert(id.clone());
+
+    // add seed nodes, provisional as we have not heard from them yet
+    for (id, address) in &seed_nodes {
+        data.insert(
+            id.clone(),
+            NodeHeartbeatData {
+                provisional: true,
+                ..NodeHeartbeatData::new(Heartbeat::new(id.clone(), *address, clock))
+            },
+        );
+    }
+
+    // add node itself
+    data.insert(
+        id.clone(),
+        NodeHeartbeatData::new(Heartbeat::new(id.clone(), address, clock)),
+    );
+
+    Storage {
+        data,
+        sent_to_data: HashMap::new(),
+        id_conflicts: HashMap::new(),
+        pinned,
+        owner: id,
+        version: 0,
+        values: KvStore::default(),
+        events: EventLog::new(defaults::EVENT_LOG_CAPACITY),
+        journal: ChangeJournal::new(defaults::CHANGE_JOURNAL_CAPACITY),
+        bounds: TimestampBounds::default(),
+    }
+}
+
+/// Whether heartbeats can be sent to `address`. Addresses are parsed when a heartbeat is decoded,
+/// this rejects the ones that parse but lead nowhere.
+pub(crate) fn is_reachable(address: SocketAddr) -> bool {
+    address.port() != 0 && !address.ip().is_multicast()
+}
+
+/// Addresses of `entries` apart from the excluded ones, each address once.
+pub(crate) fn selectable_addresses<'a>(
+    entries: impl IntoIterator<Item = &'a NodeHeartbeatData>,
+    excluded_ids: &[&NodeId],
+    excluded_addresses: &[SocketAddr],
+) -> Vec<SocketAddr> {
+    let mut seen = HashSet::new();
+    entries
+        .into_iter()
+        .filter(|data| !excluded_ids.contains(&&data.heartbeat.id))
+        .map(|data| data.heartbeat.address)
+        .filter(|address| !excluded_addresses.contains(address))
+        .filter(|address| seen.insert(*address))
+        .collect()
+}
+
+/// Picks `n` random elements of `a`, or all of them in random order if there are fewer. Only the
+/// picked elements are shuffled rather than all of `a`.
+pub(crate) fn select_random_n<T>(mut a: Vec<T>, n: usize, rng: &mut impl Rng) -> Vec<T> {
+    let n = n.min(a.len());
+    // partial_shuffle moves the picked elements to the end
+    a.partial_shuffle(rng, n);
+    a.split_off(a.len() - n)
+}
+
+/// Creates the RNG for one of a node's random streams. Seeded nodes give each stream its own
+/// seed so that heartbeats and forwarding don't consume each other's random numbers; unseeded
+/// nodes draw from entropy.
+fn stream_rng(seed: Option<u64>, stream: u64) -> StdRng {
+    match seed {
+        Some(seed) => {
+            StdRng::seed_from_u64(seed.wrapping_add(stream.wrapping_mul(RNG_STREAM_STRIDE)))
+        }
+        None => StdRng::from_entropy(),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum HeartbeatError {
+    #[error("IO error: {0}")]
+    Io(#[source] io::Error),
+    #[error("failed to set up socket on {address}: {source}")]
+    Bind {
+        address: SocketAddr,
+        source: io::Error,
+    },
+    #[error("failed to send to {target}: {source}")]
+    Send {
+        target: SocketAddr,
+        source: io::Error,
+    },
+    #[error("failed to encode message: {0}")]
+    Encode(#[from] SerdeError),
+    #[error("failed to decode {bytes_len} byte message: {source}")]
+    Decode {
+        source: SerdeError,
+        bytes_len: usize,
+    },
+    #[error("operation would block")]
+    WouldBlock,
+    #[error("invalid {field}: {source}")]
+    InvalidConfig {
+        field: &'static str,
+        source: ConfigError,
+    },
+    #[error("message of {size} bytes exceeds the maximum of {max} bytes")]
+    MessageTooLarge { size: usize, max: usize },
+    #[error("node has been stopped")]
+    Stopped,
+    #[error("node has left the cluster")]
+    Left,
+    #[error("{name} loop panicked: {message}")]
+    LoopPanicked { name: &'static str, message: String },
+    #[error("no complete reply from {target} within {timeout:?}")]
+    Timeout {
+        target: SocketAddr,
+        timeout: Duration,
+    },
+    #[error("node {id} advertises {address}, which can't be sent to")]
+    UnreachableAddress { id: NodeId, address: SocketAddr },
+    #[error("heartbeat of node {id} is from {timestamp}, too far ahead of the local time {now}")]
+    FutureTimestamp {
+        id: NodeId,
+        timestamp: u64,
+        now: u64,
+    },
+    #[error("value of {size} bytes encoded exceeds the maximum of {max} bytes")]
+    ValueTooLarge { size: usize, max: usize },
+    #[error("node {origin} already has values under {max} keys")]
+    TooManyKeys { origin: NodeId, max: usize },
+}
+
+impl From<io::Error> for HeartbeatError {
+    fn from(err: io::Error) -> HeartbeatError {
+        if err.kind() == io::ErrorKind::WouldBlock {
+            HeartbeatError::WouldBlock
+        } else {
+            HeartbeatError::Io(err)
+        }
+    }
+}
+
+impl From<ConfigError> for HeartbeatError {
+    fn from(err: ConfigError) -> HeartbeatError {
+        HeartbeatError::InvalidConfig {
+            field: err.field(),
+            source: err,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ConfigError {
+    #[error("invalid id: {0}")]
+    InvalidId(#[source] NodeIdError),
+    #[error("{field} {address:?} is not a valid socket address: {source}")]
+    InvalidAddress {
+        field: &'static str,
+        address: String,
+        source: AddrParseError,
+    },
+    #[error("{0} must be nonzero")]
+    ZeroInterval(&'static str),
+    #[error("heartbeat_spread must be greater than 0")]
+    ZeroSpread,
+    #[error("decay_factor must be >= 0, got {0}")]
+    InvalidDecay(f64),
+    #[error("dead_timeout must be longer than suspect_timeout")]
+    DeadBeforeSuspect,
+    #[error("entry_ttl must be longer than dead_timeout")]
+    TtlBeforeDead,
+    #[error("leave_fanout must be greater than 0")]
+    ZeroLeaveFanout,
+    #[error("max_entries must be greater than 0")]
+    ZeroMaxEntries,
+}
+
+/// Parses an address given to the config builder, e.g. `127.0.0.1:8000` or `[::1]:8000`.
+fn parse_address(field: &'static str, address: &str) -> Result<SocketAddr, ConfigError> {
+    address
+        .parse()
+        .map_err(|source| ConfigError::InvalidAddress {
+            field,
+            address: address.to_string(),
+            source,
+        })
+}
+
+impl ConfigError {
+    /// Name of the config field that was rejected.
+    pub fn field(&self) -> &'static str {
+        match self {
+            ConfigError::InvalidId(_) => "id",
+            ConfigError::InvalidAddress { field, .. } => field,
+            ConfigError::ZeroInterval(field) => field,
+            ConfigError::ZeroSpread => "heartbeat_spread",
+            ConfigError::InvalidDecay(_) => "decay_factor",
+            ConfigError::DeadBeforeSuspect => "dead_timeout",
+            ConfigError::TtlBeforeDead => "entry_ttl",
+            ConfigError::ZeroLeaveFanout => "leave_fanout",
+            ConfigError::ZeroMaxEntries => "max_entries",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::kv::MAX_ENTRY_SIZE;
+
+    const NOW: u64 = 1_700_000_000;
+
+    /// Node `node-0` at 10.0.0.1 on `network`, passing on every heartbeat it receives. Its loop
+    /// isn't running, the tests drive it.
+    fn node_on(network: &Arc<MemoryNetwork>, clock: Arc<dyn Clock>) -> Node {
+        let config = NodeConfig::builder()
+            .id("node-0")
+            .address("10.0.0.1:8000")
+            .decay(0.0)
+            .memory_network(network.clone())
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        let storage = setup_storage(config.id.clone(), config.address, vec![], clock.as_ref());
+        Node::new(config, storage).unwrap()
+    }
+
+    /// A node on an in-memory network of its own, its clock at [`NOW`].
+    fn lone_node() -> Node {
+        node_on(
+            &Arc::new(MemoryNetwork::new()),
+            Arc::new(ManualClock::new(NOW)),
+        )
+    }
+
+    /// A node on UDP port `port` of the loopback address, its clock at [`NOW`]. Each test that
+    /// binds real ports uses a range of its own.
+    fn udp_node(port: u16) -> Node {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let config = NodeConfig::builder()
+            .id("node-0")
+            .address(format!("127.0.0.1:{}", port))
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        let storage = setup_storage(config.id.clone(), config.address, vec![], clock.as_ref());
+        Node::new(config, storage).expect("address taken")
+    }
+
+    fn storage() -> Storage {
+        let id = NodeId::new("node-0").unwrap();
+        setup_storage(
+            id,
+            "10.0.0.1:8000".parse().unwrap(),
+            vec![],
+            &ManualClock::new(NOW),
+        )
+    }
+
+    /// A heartbeat of `id` on 10.0.1.1 stamped `timestamp` seconds and `millis` milliseconds.
+    fn heartbeat(id: &str, timestamp: u64, millis: u16) -> Heartbeat {
+        Heartbeat {
+            id: NodeId::new(id).unwrap(),
+            address: "10.0.1.1:8000".parse().unwrap(),
+            timestamp,
+            leaving: false,
+            millis,
+        }
+    }
+
+    /// The key that makes the first put of `value` on [`lone_node`] take `size` bytes encoded.
+    fn key_for_size(value: &[u8], size: usize) -> String {
+        let mut entry = KeyValue {
+            key: String::new(),
+            value: value.to_vec(),
+            origin: NodeId::new("node-0").unwrap(),
+            address: "10.0.0.1:8000".parse().unwrap(),
+            version: 1,
+            timestamp: NOW,
+        };
+        entry.key = "k".repeat(size - entry.size());
+        assert_eq!(entry.size(), size);
+        entry.key
+    }
+
+    #[test]
+    fn put_accepts_values_up_to_the_entry_limit() {
+        let node = lone_node();
+        let key = key_for_size(b"value", MAX_ENTRY_SIZE);
+        node.put(key.clone(), "value").unwrap();
+        assert_eq!(node.get(&key), Some(b"value".to_vec()));
+        assert_eq!(node.stats().sends_failed, 0);
+    }
+
+    #[test]
+    fn put_rejects_values_over_the_entry_limit_without_storing_them() {
+        let node = lone_node();
+        let key = key_for_size(b"value", MAX_ENTRY_SIZE + 1);
+        assert!(matches!(
+            node.put(key.clone(), "value"),
+            Err(HeartbeatError::ValueTooLarge { size, max })
+                if size == MAX_ENTRY_SIZE + 1 && max == MAX_ENTRY_SIZE
+        ));
+        assert_eq!(node.get(&key), None);
+        // a 40 byte value takes more than a datagram once encoded
+        assert!(node.put("short", vec![200; 40]).is_err());
+        assert_eq!(node.get("short"), None);
+        assert_eq!(node.stats().sends_failed, 0);
+    }
+
+    #[test]
+    fn heartbeats_of_the_same_second_are_ordered_by_their_millis() {
+        let earlier = heartbeat("node-1", NOW, 100);
+        let later = heartbeat("node-1", NOW, 900);
+        assert_eq!(later.cmp_version(&earlier), cmp::Ordering::Greater);
+        assert_eq!(earlier.cmp_version(&later), cmp::Ordering::Less);
+        assert_eq!(later.cmp_version(&later.clone()), cmp::Ordering::Equal);
+
+        let mut storage = storage();
+        storage.insert(earlier.clone(), NOW).unwrap();
+        assert_eq!(
+            storage.insert(later.clone(), NOW).unwrap(),
+            InsertOutcome::Applied
+        );
+        assert_eq!(
+            storage.data["node-1"].heartbeat.stamped_millis(),
+            later.stamped_millis()
+        );
+        assert_eq!(storage.insert(earlier, NOW).unwrap(), InsertOutcome::Stale);
+        assert!(matches!(
+            storage.insert(later, NOW).unwrap(),
+            InsertOutcome::DuplicateOfCurrent { .. }
+        ));
+    }
+
+    #[test]
+    fn a_genuine_heartbeat_wins_over_a_clamped_one() {
+        let mut storage = storage();
+        let skew = storage.timestamp_bounds().max_future_skew.as_secs();
+        let forged = heartbeat("node-1", NOW + 3600, 0);
+        assert_eq!(storage.insert(forged, NOW).unwrap(), InsertOutcome::Applied);
+        assert_eq!(storage.data["node-1"].heartbeat.timestamp, NOW + skew);
+
+        // stamped no later than the bound, a genuine heartbeat doesn't get through yet
+        let early = heartbeat("node-1", NOW + skew, 0);
+        assert!(matches!(
+            storage.insert(early, NOW + 1).unwrap(),
+            InsertOutcome::DuplicateOfCurrent { .. }
+        ));
+        let genuine = heartbeat("node-1", NOW + skew + 1, 0);
+        assert_eq!(
+            storage.insert(genuine.clone(), NOW + skew + 1).unwrap(),
+            InsertOutcome::Applied
+        );
+        assert_eq!(
+            storage.data["node-1"].heartbeat.timestamp,
+            genuine.timestamp
+        );
+    }
+
+    #[test]
+    fn heartbeats_from_the_future_are_forwarded_clamped() {
+        let network = Arc::new(MemoryNetwork::new());
+        let node = node_on(&network, Arc::new(ManualClock::new(NOW)));
+        let peer_address: SocketAddr = "10.0.2.1:8000".parse().unwrap();
+        let peer = network.bind(peer_address).unwrap();
+        let peer_heartbeat = Heartbeat {
+            address: peer_address,
+            ..heartbeat("node-2", NOW, 0)
+        };
+        node.runtime
+            .storage
+            .write()
+            .insert(peer_heartbeat, NOW)
+            .unwrap();
+
+        let mut state = LoopState::new(&node.runtime.config, node.runtime.generation());
+        handle_heartbeat(
+            &node.runtime,
+            &mut state,
+            heartbeat("node-1", NOW + 3600, 0),
+        );
+
+        let skew = node.runtime.config.max_future_skew.as_secs();
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+        let (size, from) = peer.recv_from(&mut buf).expect("nothing forwarded");
+        assert_eq!(from, node.runtime.config.address);
+        let forwarded: Heartbeat = serde_json::from_slice(&buf[..size]).unwrap();
+        assert_eq!(forwarded.timestamp, NOW + skew);
+        assert_eq!(node.stats().gossip_forwarded, 1);
+    }
+
+    #[test]
+    fn summary_and_staleness_measure_ages_like_the_peer_states() {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let config = NodeConfig::builder()
+            .id("node-0")
+            .address("10.0.0.1:8000")
+            .age_source(AgeSource::LocalReceipt)
+            .memory_network(Arc::new(MemoryNetwork::new()))
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        let storage = setup_storage(config.id.clone(), config.address, vec![], clock.as_ref());
+        let node = Node::new(config, storage).unwrap();
+        // sent 20s before it arrived, by a peer whose clock lags
+        node.runtime
+            .storage
+            .write()
+            .insert(heartbeat("node-1", NOW - 20, 0), NOW)
+            .unwrap();
+        clock.advance(Duration::from_secs(15));
+
+        assert_eq!(node.member("node-1").unwrap().state, PeerState::Alive);
+        assert_eq!(node.summary().peers_alive, 1);
+        assert_eq!(node.staleness("node-1"), Some(Duration::from_secs(15)));
+    }
+
+    #[test]
+    fn refreshing_the_own_entry_is_not_a_change() {
+        let mut storage = storage();
+        let clock = ManualClock::new(NOW + 1);
+        let version = storage.version();
+        assert!(storage.refresh_owner(&clock));
+        assert_eq!(storage.data["node-0"].heartbeat.timestamp, NOW + 1);
+        // already that fresh
+        assert!(!storage.refresh_owner(&clock));
+        assert_eq!(storage.version(), version);
+        assert!(!storage.changed_since(version));
+        assert!(storage.changes_since(version).unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_paused_node_keeps_its_own_entry_fresh() {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let node = node_on(&Arc::new(MemoryNetwork::new()), clock.clone());
+        let mut state = LoopState::new(&node.runtime.config, node.runtime.generation());
+        node.pause();
+        clock.advance(Duration::from_secs(5));
+        tick(&node.runtime, &mut state).expect("loop stopped");
+        let own = node.storage().get("node-0").unwrap();
+        assert_eq!(own.heartbeat.timestamp, NOW + 5);
+        assert_eq!(node.stats().heartbeats_sent, 0);
+    }
+
+    #[test]
+    fn the_builder_starts_from_the_defaults() {
+        let config = NodeConfig::builder().id("node-0").build().unwrap();
+        assert_eq!(config.id.as_str(), "node-0");
+        assert_eq!(config.address, defaults::ADDRESS.parse().unwrap());
+        assert_eq!(config.heartbeat_interval, defaults::HEARTBEAT_INTERVAL);
+        assert_eq!(config.heartbeat_spread, defaults::HEARTBEAT_SPREAD);
+        assert_eq!(config.suspect_timeout, defaults::SUSPECT_TIMEOUT);
+        assert_eq!(config.dead_timeout, defaults::DEAD_TIMEOUT);
+        assert_eq!(config.entry_ttl, defaults::ENTRY_TTL);
+        assert_eq!(config.leave_fanout, defaults::HEARTBEAT_SPREAD);
+        assert_eq!(config.max_entries, None);
+        // ids default to a fresh uuid
+        assert_ne!(NodeConfig::default().id, NodeConfig::default().id);
+    }
+
+    #[test]
+    fn the_builder_rejects_inconsistent_settings() {
+        let build = |builder: NodeConfigBuilder| builder.build().unwrap_err().field();
+        let builder = NodeConfig::builder;
+        assert_eq!(build(builder().id("not an id")), "id");
+        assert_eq!(build(builder().address("localhost")), "address");
+        assert_eq!(
+            build(builder().heartbeat_interval(Duration::ZERO)),
+            "heartbeat_interval"
+        );
+        assert_eq!(build(builder().spread(0)), "heartbeat_spread");
+        assert_eq!(build(builder().decay(-1.0)), "decay_factor");
+        assert_eq!(build(builder().decay(f64::NAN)), "decay_factor");
+        assert_eq!(
+            build(builder().dead_timeout(defaults::SUSPECT_TIMEOUT)),
+            "dead_timeout"
+        );
+        assert_eq!(
+            build(builder().entry_ttl(defaults::DEAD_TIMEOUT)),
+            "entry_ttl"
+        );
+        assert_eq!(build(builder().max_entries(0)), "max_entries");
+        assert_eq!(build(builder().leave_fanout(0)), "leave_fanout");
+    }
+
+    #[test]
+    fn stopping_a_node_ends_its_loop_and_releases_its_address() {
+        for port in 47_100..47_150 {
+            let node = udp_node(port);
+            let handle = node.run().unwrap();
+            assert!(handle.is_running());
+            node.stop().unwrap();
+            handle.wait().unwrap();
+            assert!(!handle.is_running());
+            // stopping again is harmless
+            node.stop().unwrap();
+            UdpSocket::bind(("127.0.0.1", port)).expect("address still taken after stop");
+        }
+    }
+
+    #[test]
+    fn creating_a_node_on_a_taken_address_fails() {
+        let network = Arc::new(MemoryNetwork::new());
+        let clock: Arc<dyn Clock> = Arc::new(ManualClock::new(NOW));
+        let _node = node_on(&network, clock.clone());
+        let config = NodeConfig::builder()
+            .id("node-1")
+            .address("10.0.0.1:8000")
+            .memory_network(network.clone())
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        let storage = setup_storage(config.id.clone(), config.address, vec![], clock.as_ref());
+        assert!(matches!(
+            Node::new(config, storage),
+            Err(HeartbeatError::Bind { address, source })
+                if address == "10.0.0.1:8000".parse().unwrap()
+                    && source.kind() == io::ErrorKind::AddrInUse
+        ));
+    }
+
+    #[test]
+    fn leaving_announces_the_departure_and_stops_the_node() {
+        let network = Arc::new(MemoryNetwork::new());
+        let clock: Arc<dyn Clock> = Arc::new(ManualClock::new(NOW));
+        let config = NodeConfig::builder()
+            .id("node-0")
+            .address("10.0.0.1:8000")
+            .leave_linger(Duration::ZERO)
+            .leave_grace(Duration::ZERO)
+            .memory_network(network.clone())
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        let peer_address: SocketAddr = "10.0.2.1:8000".parse().unwrap();
+        let peer = network.bind(peer_address).unwrap();
+        let storage = setup_storage(
+            config.id.clone(),
+            config.address,
+            vec![(NodeId::new("node-2").unwrap(), peer_address)],
+            clock.as_ref(),
+        );
+        let node = Node::new(config, storage).unwrap();
+        node.run().unwrap();
+
+        node.leave().unwrap();
+        let announcement = std::iter::from_fn(|| {
+            let mut buf = [0; MAX_DATAGRAM_SIZE];
+            let (size, _) = peer.recv_from(&mut buf).ok()?;
+            serde_json::from_slice::<Heartbeat>(&buf[..size]).ok()
+        })
+        .find(Heartbeat::is_leaving)
+        .expect("no leave announcement");
+        assert_eq!(announcement.id.as_str(), "node-0");
+        assert!(node.storage().get("node-0").unwrap().heartbeat.is_leaving());
+        assert_eq!(network.len(), 1);
+        assert!(matches!(node.leave(), Err(HeartbeatError::Left)));
+    }
+
+    #[test]
+    fn a_leave_that_could_not_be_announced_can_be_retried() {
+        let node = lone_node();
+        node.run().unwrap();
+        node.stop().unwrap();
+
+        assert!(matches!(node.leave(), Err(HeartbeatError::Stopped)));
+        assert!(matches!(node.leave(), Err(HeartbeatError::Stopped)));
+    }
+
+    #[test]
+    fn a_paused_node_drops_what_it_receives_and_announces_itself_on_resume() {
+        let network = Arc::new(MemoryNetwork::new());
+        let node = node_on(&network, Arc::new(ManualClock::new(NOW)));
+        let address = node.runtime.config.address;
+        let peer_address: SocketAddr = "10.0.2.1:8000".parse().unwrap();
+        let peer = network.bind(peer_address).unwrap();
+        let peer_heartbeat = Heartbeat {
+            address: peer_address,
+            ..heartbeat("node-2", NOW, 0)
+        };
+        node.runtime
+            .storage
+            .write()
+            .insert(peer_heartbeat, NOW)
+            .unwrap();
+        let mut state = LoopState::new(&node.runtime.config, node.runtime.generation());
+        let gossip = serde_json::to_vec(&heartbeat("node-1", NOW, 0)).unwrap();
+
+        node.pause();
+        assert!(node.is_paused());
+        peer.send_to(&gossip, address).unwrap();
+        tick(&node.runtime, &mut state).expect("loop stopped");
+        assert!(node.storage().get("node-1").is_none());
+
+        node.resume().unwrap();
+        assert!(!node.is_paused());
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+        let (size, _) = peer.recv_from(&mut buf).expect("no announcement");
+        let announcement: Heartbeat = serde_json::from_slice(&buf[..size]).unwrap();
+        assert_eq!(announcement.id.as_str(), "node-0");
+        assert!(!announcement.is_leaving());
+        // resuming a running node doesn't announce it again
+        node.resume().unwrap();
+        assert_eq!(node.stats().heartbeats_sent, 1);
+
+        peer.send_to(&gossip, address).unwrap();
+        tick(&node.runtime, &mut state).expect("loop stopped");
+        assert!(node.storage().get("node-1").is_some());
+    }
+
+    #[test]
+    fn a_panicking_loop_is_restarted() {
+        static RUNS: AtomicU32 = AtomicU32::new(0);
+        let node = lone_node();
+        let events = node.subscribe();
+        supervise("test", node.runtime.clone(), |_, _| {
+            if RUNS.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("first run");
+            }
+        });
+        assert_eq!(RUNS.load(Ordering::SeqCst), 2);
+        assert!(!node.runtime.failed.load(Ordering::SeqCst));
+        assert!(matches!(
+            events.try_recv(),
+            Some(MembershipEvent::LoopPanicked(change)) if change.reason.ends_with("restarting")
+        ));
+    }
+
+    #[test]
+    fn a_loop_that_keeps_panicking_fails_the_node() {
+        let node = lone_node();
+        let events = node.subscribe();
+        let runtime = node.runtime.clone();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            supervise("test", runtime, |_, _| panic!("always"))
+        }));
+        assert_eq!(panic_message(&result.unwrap_err()), "always");
+        assert!(node.runtime.failed.load(Ordering::SeqCst));
+        assert!(node.runtime.shutdown.is_triggered());
+        let reasons: Vec<String> = std::iter::from_fn(|| events.try_recv())
+            .filter_map(|event| match event {
+                MembershipEvent::LoopPanicked(change) => Some(change.reason),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(reasons.len() as u32, MAX_LOOP_RESTARTS + 1);
+        assert!(reasons.last().unwrap().ends_with("giving up"));
+    }
+
+    #[test]
+    fn the_health_check_reports_what_keeps_a_node_from_gossiping() {
+        let node = lone_node();
+        assert_eq!(node.health().issues, vec![HealthIssue::NotRunning]);
+        assert!(!node.is_healthy());
+
+        node.run().unwrap();
+        assert!(node.is_healthy(), "{}", node.health());
+        node.pause();
+        assert_eq!(node.health().issues, vec![HealthIssue::Paused]);
+        node.resume().unwrap();
+        node.runtime
+            .consecutive_send_failures
+            .store(FAILED_SENDS_THRESHOLD, Ordering::Relaxed);
+        assert_eq!(
+            node.health().issues,
+            vec![HealthIssue::SendsFailing {
+                consecutive: FAILED_SENDS_THRESHOLD
+            }]
+        );
+        node.stop().unwrap();
+        assert_eq!(node.health().issues, vec![HealthIssue::NotRunning]);
+    }
+
+    #[test]
+    fn errors_name_what_failed_and_keep_their_source() {
+        use std::error::Error as _;
+
+        let network = Arc::new(MemoryNetwork::new());
+        let node = node_on(&network, Arc::new(ManualClock::new(NOW)));
+        let taken = Node::new(node.runtime.config.clone(), storage())
+            .err()
+            .unwrap();
+        assert!(taken.to_string().contains("10.0.0.1:8000"), "{}", taken);
+        assert!(taken.source().is_some());
+
+        let peer = network.bind("10.0.2.1:8000".parse().unwrap()).unwrap();
+        peer.send_to(b"{not json", node.runtime.config.address)
+            .unwrap();
+        let garbled = node
+            .runtime
+            .channel()
+            .as_ref()
+            .unwrap()
+            .receive()
+            .err()
+            .unwrap();
+        assert!(matches!(
+            garbled,
+            HeartbeatError::Decode { bytes_len: 9, .. }
+        ));
+        assert!(garbled.to_string().contains("9 byte"), "{}", garbled);
+        assert!(garbled.source().is_some());
+        assert!(matches!(
+            node.runtime.channel().as_ref().unwrap().receive(),
+            Err(HeartbeatError::WouldBlock)
+        ));
+
+        let invalid: HeartbeatError = ConfigError::ZeroSpread.into();
+        assert!(matches!(
+            invalid,
+            HeartbeatError::InvalidConfig {
+                field: "heartbeat_spread",
+                ..
+            }
+        ));
+        assert!(
+            invalid.to_string().contains("heartbeat_spread"),
+            "{}",
+            invalid
+        );
+        assert!(invalid.source().unwrap().is::<ConfigError>());
+
+        let invalid_id = NodeConfig::builder().id("").build().err().unwrap();
+        assert!(matches!(
+            invalid_id,
+            ConfigError::InvalidId(NodeIdError::Empty)
+        ));
+        assert!(invalid_id.source().unwrap().is::<NodeIdError>());
 
-        let shared_storage_clone = shared_storage.clone();
-        let shared_channel_clone = shared_channel.clone();
-        let is_alive = self.is_alive.clone();
-        let address = self.address.clone();
-        let span_clone = node_span.clone();
-        let _ = thread::spawn(move || {
-            let _enter = span_clone.enter();
-            gossip(
-                address,
-                poll_interval_milisecs,
-                heartbeat_spread,
-                decay_factor,
-                shared_storage_clone,
-                shared_channel_clone,
-                is_alive,
-            )
-        });
+        let io: HeartbeatError = io::Error::other("disk full").into();
+        assert!(matches!(io, HeartbeatError::Io(_)));
+        assert_eq!(io.source().unwrap().to_string(), "disk full");
+    }
 
-        Ok(())
+    #[test]
+    fn dropping_a_running_node_stops_it() {
+        for _ in 0..20 {
+            let node = udp_node(47_200);
+            let handle = node.run().unwrap();
+            drop(node);
+            handle.wait().unwrap();
+            assert!(!handle.is_running());
+        }
+        UdpSocket::bind("127.0.0.1:47200").expect("address still taken after drop");
     }
-}
 
-fn periodic_heartbeat(
-    node_id: String,
-    address: String,
-    heartbeat_interval_secs: u64,
-    heartbeat_spread: usize,
-    shared_storage: Arc<Mutex<Storage>>,
-    shared_channel: Arc<Mutex<UdapChannel>>,
-    is_alive: Arc<AtomicBool>,
-) {
-    loop {
-        if !is_alive.load(Ordering::SeqCst) {
-            thread::sleep(Duration::from_secs(1));
-            continue;
+    /// The spans a test created, with their fields and the span each was nested in.
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<Mutex<RecordedSpans>>);
+
+    #[derive(Default)]
+    struct RecordedSpans {
+        // every span created, its id is its index plus one
+        spans: Vec<RecordedSpan>,
+        entered: Vec<u64>,
+    }
+
+    struct RecordedSpan {
+        name: &'static str,
+        fields: Vec<(String, String)>,
+        parent: Option<u64>,
+    }
+
+    impl RecordedSpan {
+        fn field(&self, name: &str) -> Option<&str> {
+            self.fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value.as_str())
         }
+    }
 
-        let heartbeat = Heartbeat {
-            id: node_id.clone(),
-            address: address.clone(),
-            timestamp: now_unix(),
-        };
+    struct FieldRecorder<'a>(&'a mut Vec<(String, String)>);
 
-        {
-            let mut storage = match shared_storage.lock() {
-                Ok(guard) => guard,
-                Err(PoisonError { .. }) => {
-                    error!("failed to lock shared storage");
-                    continue;
-                }
-            };
+    impl tracing::field::Visit for FieldRecorder<'_> {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.push((field.name().to_string(), value.to_string()));
+        }
 
-            match storage.insert(heartbeat.clone()) {
-                Ok(_) => (),
-                Err(e) => {
-                    error!(error = e.to_string(), "failed insert heartbeat");
-                    continue;
-                }
-            }
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .push((field.name().to_string(), format!("{:?}", value)));
         }
+    }
 
-        let addresses;
-        {
-            let storage = match shared_storage.lock() {
-                Ok(guard) => guard,
-                Err(PoisonError { .. }) => {
-                    error!("failed to lock shared storage");
-                    continue;
-                }
-            };
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
 
-            addresses = match storage.select_n_random_addresses(
-                heartbeat_spread,
-                // we filter out address to node itself and node we got heartbeat from
-                vec![address.clone(), heartbeat.address.clone()],
-            ) {
-                Ok(addresses) => addresses,
-                Err(e) => {
-                    error!(error = e.to_string(), "failed to select n random addresses");
-                    continue;
-                }
+        fn new_span(&self, attributes: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut recorded = self.0.lock().unwrap();
+            let parent = if attributes.is_contextual() {
+                recorded.entered.last().copied()
+            } else {
+                attributes.parent().map(tracing::span::Id::into_u64)
             };
+            let mut fields = vec![];
+            attributes.record(&mut FieldRecorder(&mut fields));
+            recorded.spans.push(RecordedSpan {
+                name: attributes.metadata().name(),
+                fields,
+                parent,
+            });
+            tracing::span::Id::from_u64(recorded.spans.len() as u64)
         }
 
-        {
-            let channel = match shared_channel.lock() {
-                Ok(guard) => guard,
-                Err(PoisonError { .. }) => {
-                    error!("failed to lock shared channel");
-                    continue;
-                }
-            };
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
 
-            match channel.send(heartbeat.clone(), addresses.clone()) {
-                Ok(_) => info!("Heartbeat sent successfully"),
-                Err(e) => {
-                    error!(error = e.to_string(), "failed to send heartbeat");
-                    continue;
-                }
-            };
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, span: &tracing::span::Id) {
+            self.0.lock().unwrap().entered.push(span.into_u64());
         }
 
-        thread::sleep(Duration::from_secs(heartbeat_interval_secs))
+        fn exit(&self, _: &tracing::span::Id) {
+            self.0.lock().unwrap().entered.pop();
+        }
     }
-}
 
-fn gossip(
-    address: String,
-    poll_interval_milisecs: u64,
-    heartbeat_spread: usize,
-    decay_factor: f64,
-    shared_storage: Arc<Mutex<Storage>>,
-    shared_channel: Arc<Mutex<UdapChannel>>,
-    is_alive: Arc<AtomicBool>,
-) {
-    loop {
-        if !is_alive.load(Ordering::SeqCst) {
-            thread::sleep(Duration::from_secs(1));
-            continue;
-        }
+    #[test]
+    fn loop_spans_nest_in_a_node_span_of_their_own() {
+        let recorder = SpanRecorder::default();
+        let node = lone_node();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let _other = info_span!("node", node_id = "node-9").entered();
+            node_span(&node.runtime).in_scope(|| loop_span("gossip").in_scope(|| {}));
+        });
 
-        thread::sleep(Duration::from_millis(poll_interval_milisecs));
+        let recorded = recorder.0.lock().unwrap();
+        let node = &recorded.spans[1];
+        assert_eq!(node.name, "node");
+        assert_eq!(node.field("node_id"), Some("node-0"));
+        assert_eq!(node.field("address"), Some("10.0.0.1:8000"));
+        assert_eq!(node.field("generation"), Some("0"));
+        // not nested in whatever span was entered when it was created
+        assert_eq!(node.parent, None);
+        let gossip = &recorded.spans[2];
+        assert_eq!(gossip.name, "loop");
+        assert_eq!(gossip.field("loop"), Some("gossip"));
+        assert_eq!(gossip.parent, Some(2));
+    }
 
-        let heartbeat: Heartbeat;
-        {
-            let channel = match shared_channel.lock() {
-                Ok(guard) => guard,
-                Err(PoisonError { .. }) => {
-                    error!("failed to lock shared channel");
-                    continue;
-                }
-            };
-            heartbeat = match channel.receive() {
-                Ok(heartbeat) => heartbeat,
-                Err(HeartbeatError::WouldBlock) => continue,
-                Err(e) => {
-                    error!(error = e.to_string(), "failed to receive heartbeat");
-                    continue;
-                }
-            };
-        };
+    #[test]
+    fn readers_share_the_storage() {
+        let handle = StorageHandle::new(storage());
+        let len = handle.with_read(|storage| {
+            let reader = handle.clone();
+            // would wait forever if reading took the lock exclusively
+            let other = thread::spawn(move || reader.len()).join().unwrap();
+            assert_eq!(other, storage.data.len());
+            other
+        });
+        assert_eq!(len, 1);
+    }
 
-        let n_times_received: u64;
-        {
-            let mut storage = match shared_storage.lock() {
-                Ok(guard) => guard,
-                Err(PoisonError { .. }) => {
-                    error!("failed to lock shared storage");
-                    continue;
-                }
-            };
+    #[test]
+    fn a_storage_poisoned_by_a_panicking_writer_is_still_usable() {
+        let handle = StorageHandle::new(storage());
+        let writer = handle.clone();
+        let result = thread::spawn(move || {
+            let mut storage = writer.write();
+            storage.insert(heartbeat("node-1", NOW, 0), NOW).unwrap();
+            panic!("writer failed");
+        })
+        .join();
+        assert!(result.is_err());
+        assert_eq!(handle.len(), 2);
+        assert!(handle.get("node-1").is_some());
+    }
 
-            n_times_received = match storage.insert(heartbeat.clone()) {
-                Ok(count) => count,
-                Err(e) => {
-                    error!(error = e.to_string(), "failed to insert heartbeat");
-                    continue;
-                }
-            };
-        }
+    #[test]
+    fn pruning_removes_expired_peers_but_not_pinned_ones() {
+        let clock = ManualClock::new(NOW);
+        let seed = NodeId::new("seed").unwrap();
+        let mut storage = setup_storage(
+            NodeId::new("node-0").unwrap(),
+            "10.0.0.1:8000".parse().unwrap(),
+            vec![(seed.clone(), "10.0.3.1:8000".parse().unwrap())],
+            &clock,
+        );
+        storage.insert(heartbeat("node-1", NOW, 0), NOW).unwrap();
+        storage
+            .insert(heartbeat("node-2", NOW + 50, 0), NOW + 50)
+            .unwrap();
 
-        if !should_forward(n_times_received, decay_factor) {
-            continue;
+        clock.set(NOW + 100);
+        assert!(storage.prune(Duration::from_secs(100), &clock).is_empty());
+        let version = storage.version();
+        assert_eq!(
+            storage.prune(Duration::from_secs(60), &clock),
+            vec![NodeId::new("node-1").unwrap()]
+        );
+        assert!(!storage.data.contains_key("node-1"));
+        assert!(storage.data.contains_key("node-2"));
+        // the node itself and its seeds stay however old they are
+        assert!(storage.data.contains_key("node-0"));
+        assert!(storage.data.contains_key(&seed));
+        let changes = storage.changes_since(version).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].kind, ChangeKind::Removed);
+    }
+
+    #[test]
+    fn evicting_drops_the_stalest_peers_down_to_the_cap() {
+        let clock = ManualClock::new(NOW);
+        let mut storage = storage();
+        for (node, timestamp) in [
+            ("node-1", NOW - 30),
+            ("node-2", NOW - 10),
+            ("node-3", NOW - 20),
+        ] {
+            storage.insert(heartbeat(node, timestamp, 0), NOW).unwrap();
         }
+        assert!(storage.evict_stalest(4, &clock).is_empty());
 
-        let addresses;
-        {
-            let storage = match shared_storage.lock() {
-                Ok(guard) => guard.clone(),
-                Err(PoisonError { .. }) => {
-                    error!("failed to lock shared storage");
-                    continue;
-                }
-            };
+        let evicted: Vec<String> = storage
+            .evict_stalest(2, &clock)
+            .iter()
+            .map(|heartbeat| heartbeat.id.to_string())
+            .collect();
+        assert_eq!(evicted, ["node-1", "node-3"]);
+        assert!(storage.data.contains_key("node-2"));
+        // the node's own entry is pinned, so a cap below it leaves it alone
+        storage.evict_stalest(0, &clock);
+        assert_eq!(storage.data.keys().collect::<Vec<_>>(), ["node-0"]);
 
-            addresses = match storage.select_n_random_addresses(
-                heartbeat_spread,
-                // we filter out address to node itself and node we got heartbeat from
-                vec![address.clone(), heartbeat.address.clone()],
-            ) {
-                Ok(addresses) => addresses,
-                Err(e) => {
-                    error!(error = e.to_string(), "failed to select n random addresses");
-                    continue;
-                }
+        // an evicted peer comes back like a new one
+        storage.insert(heartbeat("node-1", NOW, 0), NOW).unwrap();
+        assert_eq!(storage.data["node-1"].updates_received, 1);
+    }
+
+    #[test]
+    fn a_flooded_node_keeps_the_freshest_peers_up_to_the_cap() {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let config = NodeConfig::builder()
+            .id("node-0")
+            .address("10.0.0.1:8000")
+            .max_entries(1_000)
+            .memory_network(Arc::new(MemoryNetwork::new()))
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        let storage = setup_storage(config.id.clone(), config.address, vec![], clock.as_ref());
+        let node = Node::new(config, storage).unwrap();
+        let mut state = LoopState::new(&node.runtime.config, node.runtime.generation());
+        // 999 fresh peers among 9001 stale ones, all arriving interleaved
+        let fresh = 9_002..=10_000;
+        for i in (0..10_000).map(|i| i * 7_919 % 10_000 + 1) {
+            let timestamp = if fresh.contains(&i) {
+                NOW - i % 20
+            } else {
+                NOW - 100 - i % 100
             };
+            handle_heartbeat(
+                &node.runtime,
+                &mut state,
+                heartbeat(&format!("node-{}", i), timestamp, 0),
+            );
         }
 
-        if addresses.is_empty() {
-            continue;
+        let storage = node.runtime.storage.read();
+        assert_eq!(storage.data.len(), 1_000);
+        assert!(storage.data.contains_key("node-0"));
+        for i in fresh {
+            assert!(storage.data.contains_key(format!("node-{}", i).as_str()));
         }
+        assert_eq!(node.stats().entries_evicted, 9_001);
+    }
 
-        {
-            let channel = match shared_channel.lock() {
-                Ok(guard) => guard,
-                Err(PoisonError { .. }) => {
-                    error!("failed to lock shared channel");
-                    continue;
-                }
-            };
-            match channel.send(heartbeat.clone(), addresses.clone()) {
-                Ok(_) => (),
-                Err(e) => error!(error = e.to_string(), "failed to send heartbeat"),
+    #[test]
+    fn a_stopped_node_is_restored_from_its_snapshot() {
+        let path = std::env::temp_dir().join(format!("muck-restore-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let clock: Arc<dyn Clock> = Arc::new(ManualClock::new(NOW));
+        let create = |network: Arc<MemoryNetwork>| {
+            let config = NodeConfig::builder()
+                .id("node-0")
+                .address("10.0.0.1:8000")
+                .snapshot_path(&path)
+                .memory_network(network)
+                .clock(clock.clone())
+                .build()
+                .unwrap();
+            let storage = setup_storage(config.id.clone(), config.address, vec![], clock.as_ref());
+            Node::new(config, storage).unwrap()
+        };
+
+        let node = create(Arc::new(MemoryNetwork::new()));
+        node.runtime
+            .storage
+            .write()
+            .insert(heartbeat("node-1", NOW, 0), NOW)
+            .unwrap();
+        node.run().unwrap();
+        node.stop().unwrap();
+
+        let restored = create(Arc::new(MemoryNetwork::new()));
+        let peer = restored.storage().get("node-1").expect("peer not restored");
+        // restored peers count as suspect until they are heard from again
+        let suspect_timeout = restored.runtime.config.suspect_timeout.as_secs();
+        assert_eq!(peer.heartbeat.timestamp, NOW - suspect_timeout);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn evaluating_records_each_change_of_a_peers_state() {
+        let clock = ManualClock::new(NOW);
+        let mut storage = storage();
+        storage.insert(heartbeat("node-1", NOW, 0), NOW).unwrap();
+        let evaluate_at = |storage: &mut Storage, at: u64| {
+            clock.set(at);
+            storage.evaluate(
+                &clock,
+                Duration::from_secs(10),
+                Duration::from_secs(30),
+                AgeSource::SenderTimestamp,
+            )
+        };
+        assert!(evaluate_at(&mut storage, NOW + 5).is_empty());
+        assert_eq!(evaluate_at(&mut storage, NOW + 10), ["node-1"]);
+        assert!(evaluate_at(&mut storage, NOW + 20).is_empty());
+        assert_eq!(evaluate_at(&mut storage, NOW + 30), ["node-1"]);
+        assert_eq!(storage.data["node-1"].state, PeerState::Dead);
+        // the peer comes back
+        storage
+            .insert(heartbeat("node-1", NOW + 32, 0), NOW + 32)
+            .unwrap();
+        assert_eq!(evaluate_at(&mut storage, NOW + 33), ["node-1"]);
+
+        let peer = &storage.data["node-1"];
+        assert_eq!(peer.state, PeerState::Alive);
+        let history: Vec<(PeerState, u64, &str)> = peer
+            .history
+            .iter()
+            .map(|transition| (transition.state, transition.at, transition.cause.as_str()))
+            .collect();
+        assert_eq!(
+            history,
+            [
+                (
+                    PeerState::Suspect,
+                    NOW + 10,
+                    "no heartbeat for 10s, suspect after 10s"
+                ),
+                (
+                    PeerState::Dead,
+                    NOW + 30,
+                    "no heartbeat for 30s, dead after 30s"
+                ),
+                (PeerState::Alive, NOW + 33, "fresh heartbeat, 1s old"),
+            ]
+        );
+        // the node never judges itself
+        assert_eq!(storage.data["node-0"].state, PeerState::Alive);
+        assert!(storage.data["node-0"].history.is_empty());
+    }
+
+    #[test]
+    fn the_state_history_keeps_the_latest_transitions() {
+        let mut data = NodeHeartbeatData::new(heartbeat("node-1", NOW, 0));
+        for at in 0..STATE_HISTORY_LEN as u64 + 5 {
+            let state = if at % 2 == 0 {
+                PeerState::Suspect
+            } else {
+                PeerState::Alive
             };
+            data.record(StateTransition {
+                state,
+                at,
+                cause: String::new(),
+            });
         }
+        assert_eq!(data.history.len(), STATE_HISTORY_LEN);
+        assert_eq!(data.history.front().unwrap().at, 5);
+        assert_eq!(
+            data.history.back().unwrap().at,
+            STATE_HISTORY_LEN as u64 + 4
+        );
+        assert_eq!(data.state, data.history.back().unwrap().state);
     }
-}
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Heartbeat {
-    id: String,
-    address: String,
-    pub timestamp: u64,
-}
+    #[test]
+    fn the_version_moves_with_every_change_and_only_then() {
+        let clock = ManualClock::new(NOW);
+        let mut storage = storage();
+        let mut version = storage.version();
+        let mut changed = |storage: &Storage| {
+            let changed = storage.changed_since(version);
+            version = storage.version();
+            changed
+        };
 
-#[derive(Debug, Clone)]
-pub struct NodeHeartbeatData {
-    pub heartbeat: Heartbeat,
-    pub received_count: u64,
-}
+        storage.insert(heartbeat("node-1", NOW, 0), NOW).unwrap();
+        assert!(changed(&storage));
+        storage.insert(heartbeat("node-1", NOW, 0), NOW).unwrap();
+        storage
+            .insert(heartbeat("node-1", NOW - 1, 0), NOW)
+            .unwrap();
+        assert!(!changed(&storage));
+        storage
+            .insert(heartbeat("node-1", NOW + 1, 0), NOW)
+            .unwrap();
+        assert!(changed(&storage));
 
-#[derive(Debug, Clone)]
-pub struct Storage {
-    pub data: HashMap<String, NodeHeartbeatData>,
-    pub sent_to_data: HashMap<String, Vec<String>>,
-}
+        clock.set(NOW + 20);
+        let evaluate = |storage: &mut Storage| {
+            storage.evaluate(
+                &clock,
+                Duration::from_secs(10),
+                Duration::from_secs(30),
+                AgeSource::SenderTimestamp,
+            )
+        };
+        evaluate(&mut storage);
+        assert!(changed(&storage));
+        evaluate(&mut storage);
+        assert!(!changed(&storage));
 
-impl Storage {
-    fn select_n_random_addresses(
-        &self,
-        n: usize,
-        filter_out: Vec<String>,
-    ) -> Result<Vec<String>, HeartbeatError> {
-        let addresses: Vec<String> = self
-            .data
-            .iter()
-            .map(|(_, v)| v.heartbeat.address.clone())
-            .filter(|a| !filter_out.contains(a))
-            .collect();
-        let selected_addresses = select_random_n_strings(addresses, n);
-        return Ok(selected_addresses);
+        storage.remove("node-1", &clock).unwrap();
+        assert!(changed(&storage));
+        assert!(storage.remove("node-1", &clock).is_none());
+        assert!(!changed(&storage));
     }
 
-    fn insert(&mut self, heartbeat: Heartbeat) -> Result<u64, HeartbeatError> {
-        let received_count = match self.data.get(&heartbeat.id) {
-            Some(d) => {
-                if heartbeat.timestamp > d.heartbeat.timestamp {
-                    self.sent_to_data.insert(heartbeat.id.clone(), vec![]);
-                    1
-                } else {
-                    d.received_count + 1
-                }
-            }
-            None => 1,
+    #[test]
+    fn random_picks_are_distinct_and_cover_every_element() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut picked = HashSet::new();
+        for _ in 0..100 {
+            let pick = select_random_n((0..10).collect(), 3, &mut rng);
+            assert_eq!(pick.len(), 3);
+            assert_eq!(pick.iter().collect::<HashSet<_>>().len(), 3);
+            picked.extend(pick);
+        }
+        assert_eq!(picked.len(), 10);
+
+        let mut all = select_random_n(vec![1, 2], 5, &mut rng);
+        all.sort_unstable();
+        assert_eq!(all, [1, 2]);
+        assert_eq!(
+            select_random_n(
+                (0..10).collect::<Vec<_>>(),
+                3,
+                &mut StdRng::seed_from_u64(1)
+            ),
+            select_random_n(
+                (0..10).collect::<Vec<_>>(),
+                3,
+                &mut StdRng::seed_from_u64(1)
+            )
+        );
+    }
+
+    #[test]
+    fn targets_are_excluded_by_id_and_by_address() {
+        let mut storage = storage();
+        let at = |node: &str, address: &str| Heartbeat {
+            address: address.parse().unwrap(),
+            ..heartbeat(node, NOW, 0)
         };
+        storage.insert(at("node-1", "10.0.1.1:8000"), NOW).unwrap();
+        storage.insert(at("node-2", "10.0.1.2:8000"), NOW).unwrap();
+        // a node that took over the address node-2 had before
+        storage.insert(at("node-3", "10.0.1.2:8000"), NOW).unwrap();
+        storage.insert(at("node-4", "10.0.1.4:8000"), NOW).unwrap();
 
-        self.data.insert(
-            heartbeat.id.clone(),
-            NodeHeartbeatData {
-                heartbeat,
-                received_count,
-            },
+        let own = NodeId::new("node-0").unwrap();
+        let sender = NodeId::new("node-1").unwrap();
+        let mut targets = storage
+            .select_n_random_addresses(
+                10,
+                &[&own, &sender],
+                &["10.0.1.4:8000".parse().unwrap()],
+                &mut StdRng::seed_from_u64(1),
+            )
+            .unwrap();
+        targets.sort_unstable();
+        assert_eq!(targets, ["10.0.1.2:8000".parse().unwrap()]);
+    }
+
+    #[test]
+    fn inserts_count_updates_and_duplicates_apart() {
+        let mut storage = storage();
+        let mut insert = |timestamp| storage.insert(heartbeat("node-1", timestamp, 0), NOW);
+        assert_eq!(insert(NOW).unwrap(), InsertOutcome::Applied);
+        assert_eq!(
+            insert(NOW).unwrap(),
+            InsertOutcome::DuplicateOfCurrent { duplicates: 1 }
+        );
+        assert_eq!(
+            insert(NOW).unwrap(),
+            InsertOutcome::DuplicateOfCurrent { duplicates: 2 }
+        );
+        assert_eq!(insert(NOW - 1).unwrap(), InsertOutcome::Stale);
+        assert_eq!(insert(NOW + 1).unwrap(), InsertOutcome::Applied);
+        assert_eq!(
+            insert(NOW + 1).unwrap(),
+            InsertOutcome::DuplicateOfCurrent { duplicates: 1 }
         );
 
-        Ok(received_count)
+        let peer = &storage.data["node-1"];
+        assert_eq!(peer.updates_received, 2);
+        assert_eq!(peer.duplicates_received, 4);
+        assert_eq!(peer.duplicates_of_current, 1);
     }
-}
 
-struct UdapChannel {
-    socket: UdpSocket,
-}
+    #[test]
+    fn seeds_stay_provisional_until_heard_from() {
+        let clock = ManualClock::new(NOW);
+        let mut storage = setup_storage(
+            NodeId::new("node-0").unwrap(),
+            "10.0.0.1:8000".parse().unwrap(),
+            vec![(
+                NodeId::new("node-1").unwrap(),
+                "10.0.1.1:8000".parse().unwrap(),
+            )],
+            &clock,
+        );
+        assert!(storage.data["node-1"].provisional);
+        assert_eq!(storage.count_known(), 1);
+        // a seed is gossiped to before it is known
+        let targets = storage
+            .select_n_random_addresses(3, &[], &[], &mut StdRng::seed_from_u64(1))
+            .unwrap();
+        assert!(targets.contains(&"10.0.1.1:8000".parse().unwrap()));
 
-impl UdapChannel {
-    fn receive(&self) -> Result<Heartbeat, HeartbeatError> {
-        let mut buf = [0; 256];
-        let (size, _src) = self.socket.recv_from(&mut buf)?;
-        let heartbeat = serde_json::from_slice::<Heartbeat>(&buf[..size])?;
+        // the made up entry is as new as the genuine heartbeat, which replaces it anyway
+        assert_eq!(
+            storage.insert(heartbeat("node-1", NOW, 0), NOW).unwrap(),
+            InsertOutcome::Applied
+        );
+        let seed = &storage.data["node-1"];
+        assert!(!seed.provisional);
+        assert_eq!(seed.updates_received, 1);
+        assert_eq!(storage.count_known(), 2);
+    }
+
+    #[test]
+    fn metrics_count_peers_by_state_and_age() {
+        let clock = ManualClock::new(NOW);
+        let mut storage = setup_storage(
+            NodeId::new("node-0").unwrap(),
+            "10.0.0.1:8000".parse().unwrap(),
+            vec![(
+                NodeId::new("seed").unwrap(),
+                "10.0.3.1:8000".parse().unwrap(),
+            )],
+            &clock,
+        );
+        storage
+            .insert(heartbeat("node-1", NOW - 2, 0), NOW)
+            .unwrap();
+        storage
+            .insert(heartbeat("node-1", NOW - 2, 0), NOW)
+            .unwrap();
+        storage
+            .insert(heartbeat("node-2", NOW - 15, 0), NOW)
+            .unwrap();
+        storage
+            .insert(heartbeat("node-3", NOW - 40, 0), NOW)
+            .unwrap();
+        let leaving = Heartbeat {
+            leaving: true,
+            ..heartbeat("node-4", NOW - 1, 0)
+        };
+        storage.insert(leaving, NOW).unwrap();
 
-        Ok(heartbeat)
+        let metrics = storage.metrics(&clock, Duration::from_secs(10), Duration::from_secs(30));
+        assert_eq!(metrics.entries, 6);
+        assert_eq!(metrics.provisional, 1);
+        // the node itself counts as alive
+        assert_eq!(metrics.alive, 2);
+        assert_eq!(metrics.suspect, 1);
+        assert_eq!(metrics.dead, 1);
+        assert_eq!(metrics.left, 1);
+        assert_eq!(metrics.oldest_age, Some(Duration::from_secs(40)));
+        assert_eq!(metrics.median_age, Some(Duration::from_secs(2)));
+        assert_eq!(metrics.total_updates, 4);
+        assert_eq!(metrics.total_duplicates, 1);
     }
 
-    fn send(
-        &self,
-        heartbeat: Heartbeat,
-        target_addresses: Vec<String>,
-    ) -> Result<(), HeartbeatError> {
-        let msg = serde_json::to_string(&heartbeat).map_err(|e| e.to_string())?;
-        for address in target_addresses {
-            self.socket
-                .send_to(msg.as_bytes(), address)
-                .map_err(|e| e.to_string())?;
+    #[test]
+    fn ties_are_broken_the_same_way_in_any_order() {
+        let plain = heartbeat("node-1", NOW, 0);
+        let leaving = Heartbeat {
+            leaving: true,
+            ..plain.clone()
+        };
+        let moved = Heartbeat {
+            address: "10.0.1.2:8000".parse().unwrap(),
+            ..plain.clone()
+        };
+        assert_eq!(leaving.cmp_version(&moved), cmp::Ordering::Greater);
+        assert_eq!(moved.cmp_version(&plain), cmp::Ordering::Greater);
+
+        for order in [
+            [&plain, &moved, &leaving],
+            [&leaving, &moved, &plain],
+            [&moved, &leaving, &plain],
+        ] {
+            let mut storage = storage();
+            for heartbeat in order {
+                storage.insert(heartbeat.clone(), NOW).unwrap();
+            }
+            let stored = &storage.data["node-1"].heartbeat;
+            assert!(stored.is_leaving());
+            assert_eq!(stored.address, plain.address);
         }
-        Ok(())
     }
-}
 
-pub fn setup_storage(id: String, address: String, seed_nodes: Vec<(String, String)>) -> Storage {
-    let mut data = HashMap::new();
+    #[test]
+    fn storage_events_tell_how_the_view_came_about() {
+        let clock = ManualClock::new(NOW);
+        let mut storage = storage();
+        storage.insert(heartbeat("node-1", NOW, 0), NOW).unwrap();
+        storage
+            .insert(heartbeat("node-1", NOW + 1, 0), NOW)
+            .unwrap();
+        let unreachable = Heartbeat {
+            address: "10.0.1.1:0".parse().unwrap(),
+            ..heartbeat("node-2", NOW, 0)
+        };
+        assert!(storage.insert(unreachable, NOW).is_err());
+        storage.remove("node-1", &clock);
 
-    // add seed nodes
-    for (id, address) in &seed_nodes {
-        data.insert(
-            id.to_string(),
-            NodeHeartbeatData {
-                received_count: 0,
-                heartbeat: Heartbeat {
-                    id: id.to_string(),
-                    address: address.to_string(),
-                    timestamp: now_unix(),
+        let kinds: Vec<StorageEventKind> = storage
+            .recent_events(10)
+            .into_iter()
+            .map(|event| event.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                StorageEventKind::FirstSeen { timestamp: NOW },
+                StorageEventKind::Updated {
+                    from: NOW,
+                    to: NOW + 1
                 },
-            },
+                StorageEventKind::Rejected {
+                    address: "10.0.1.1:0".parse().unwrap()
+                },
+                StorageEventKind::Removed {
+                    cause: RemovalCause::Requested
+                },
+            ]
         );
     }
 
-    // add node itself
-    data.insert(
-        id.to_string(),
-        NodeHeartbeatData {
-            heartbeat: Heartbeat {
-                id: id.to_string(),
-                address: address.to_string(),
-                timestamp: now_unix(),
-            },
-            received_count: 0,
-        },
-    );
+    #[test]
+    fn merging_reports_what_it_did_with_each_entry() {
+        let clock = ManualClock::new(NOW);
+        let mut other = storage();
+        other.insert(heartbeat("node-1", NOW + 1, 0), NOW).unwrap();
+        other.insert(heartbeat("node-2", NOW - 1, 0), NOW).unwrap();
+        other.insert(heartbeat("node-3", NOW - 5, 0), NOW).unwrap();
+        let mut snapshot = other.snapshot();
+        let mut storage = storage();
+        storage.insert(heartbeat("node-1", NOW, 0), NOW).unwrap();
+        storage.insert(heartbeat("node-2", NOW, 0), NOW).unwrap();
+        snapshot
+            .entries
+            .retain(|entry| entry.id.as_str() != "node-0");
+        let provisional = |id: &str, address: &str| SnapshotEntry {
+            provisional: true,
+            ..NodeHeartbeatData::new(Heartbeat {
+                address: address.parse().unwrap(),
+                ..heartbeat(id, NOW + 10, 0)
+            })
+            .snapshot_entry()
+        };
+        snapshot
+            .entries
+            .push(provisional("node-1", "10.0.1.1:8000"));
+        snapshot
+            .entries
+            .push(provisional("node-4", "10.0.1.4:8000"));
+        snapshot.entries.push(provisional("node-5", "10.0.1.5:0"));
 
-    let storage = Storage {
-        data,
-        sent_to_data: HashMap::new(),
-    };
-    return storage;
-}
+        let mut report = storage.merge(snapshot, &clock);
+        report.new_ids.sort();
+        assert_eq!(
+            report,
+            MergeReport {
+                applied: 3,
+                ignored_stale: 2,
+                rejected: 1,
+                new_ids: vec![
+                    NodeId::new("node-3").unwrap(),
+                    NodeId::new("node-4").unwrap()
+                ],
+            }
+        );
+        assert_eq!(storage.data["node-1"].heartbeat.timestamp, NOW + 1);
+        assert_eq!(storage.data["node-2"].heartbeat.timestamp, NOW);
+        // merged heartbeats count as received when they were sent
+        assert_eq!(storage.data["node-3"].last_received_at, NOW - 5);
+        assert!(storage.data["node-4"].provisional);
+    }
 
-fn select_random_n_strings(a: Vec<String>, n: usize) -> Vec<String> {
-    let mut a = a;
-    let mut rng = thread_rng();
-    a.shuffle(&mut rng);
+    #[test]
+    fn the_stalest_entries_come_first() {
+        let clock = ManualClock::new(NOW);
+        let mut storage = setup_storage(
+            NodeId::new("node-0").unwrap(),
+            "10.0.0.1:8000".parse().unwrap(),
+            vec![(
+                NodeId::new("seed").unwrap(),
+                "10.0.3.1:8000".parse().unwrap(),
+            )],
+            &clock,
+        );
+        for (node, at) in [
+            ("node-1", NOW - 30),
+            ("node-2", NOW - 10),
+            ("node-3", NOW - 20),
+        ] {
+            storage.insert(heartbeat(node, at, 0), at).unwrap();
+        }
+        let ids = |entries: Vec<&NodeHeartbeatData>| -> Vec<String> {
+            entries
+                .iter()
+                .map(|data| data.heartbeat.id.to_string())
+                .collect()
+        };
+        assert_eq!(ids(storage.stalest(2, true)), ["node-1", "node-3"]);
+        // the provisional seed is never among them, the node itself only when asked for
+        assert_eq!(
+            ids(storage.stalest(10, true)),
+            ["node-1", "node-3", "node-2"]
+        );
+        assert_eq!(ids(storage.stalest(10, false)).len(), 4);
+        assert!(storage.stalest(0, false).is_empty());
+    }
+
+    #[test]
+    fn a_poisoned_channel_lock_is_recovered_and_counted() {
+        let node = lone_node();
+        node.runtime
+            .storage
+            .write()
+            .insert(heartbeat("node-1", NOW, 0), NOW)
+            .unwrap();
+        let runtime = node.runtime.clone();
+        let result = thread::spawn(move || {
+            let _channel = runtime.channel();
+            panic!("sender failed");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(node.runtime.shared_channel.is_poisoned());
 
-    if a.len() < n {
-        return a;
+        // the node still gets its heartbeats out rather than skipping them for good
+        node.pause();
+        node.resume().unwrap();
+        assert_eq!(node.stats().locks_recovered, 1);
+        assert_eq!(node.stats().heartbeats_sent, 1);
     }
-    a[..n].to_vec()
-}
 
-fn should_forward(n_times_receieved: u64, decay_factor: f64) -> bool {
-    let base_probability = 1.0;
-    let probability = base_probability * f64::exp(-decay_factor * n_times_receieved as f64);
-    let mut rng = rand::thread_rng();
-    rng.gen::<f64>() < probability
-}
+    #[test]
+    fn changes_since_a_version_name_each_changed_entry() {
+        let clock = ManualClock::new(NOW);
+        let mut storage = storage();
+        let version = storage.version();
+        storage.insert(heartbeat("node-1", NOW, 0), NOW).unwrap();
+        storage
+            .insert(heartbeat("node-1", NOW + 1, 0), NOW)
+            .unwrap();
+        clock.set(NOW + 20);
+        storage.evaluate(
+            &clock,
+            Duration::from_secs(10),
+            Duration::from_secs(30),
+            AgeSource::SenderTimestamp,
+        );
+        storage.remove("node-1", &clock);
 
-pub fn now_unix() -> u64 {
-    return time::SystemTime::now()
-        .duration_since(time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-}
+        let changes: Vec<(String, ChangeKind)> = storage
+            .changes_since(version)
+            .unwrap()
+            .into_iter()
+            .map(|change| (change.id.to_string(), change.kind))
+            .collect();
+        let change = |kind| ("node-1".to_string(), kind);
+        assert_eq!(
+            changes,
+            [
+                change(ChangeKind::Added),
+                change(ChangeKind::Updated),
+                change(ChangeKind::StateChanged),
+                change(ChangeKind::Removed),
+            ]
+        );
+    }
 
-#[derive(Debug)]
-pub enum HeartbeatError {
-    Io(io::Error),
-    Serde(SerdeError),
-    WouldBlock,
-    Other(String),
-}
+    #[test]
+    fn timestamp_bounds_clamp_or_reject_beyond_the_skew() {
+        let bounds = TimestampBounds {
+            max_future_skew: Duration::from_secs(10),
+            future_timestamps: FutureTimestampPolicy::Clamp,
+            stale_after: Duration::from_secs(30),
+        };
+        let mut within = heartbeat("node-1", NOW + 10, 0);
+        assert_eq!(bounds.future_timestamp(&within, NOW), None);
+        bounds.clamp(&mut within, NOW).unwrap();
+        assert_eq!(within.timestamp, NOW + 10);
 
-impl fmt::Display for HeartbeatError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            HeartbeatError::Io(err) => write!(f, "IO error: {}", err),
-            HeartbeatError::Serde(err) => write!(f, "Serialization error: {}", err),
-            HeartbeatError::WouldBlock => write!(f, "Operation would block"),
-            HeartbeatError::Other(err) => write!(f, "Other error: {}", err),
-        }
+        let mut beyond = heartbeat("node-1", NOW + 11, 0);
+        assert_eq!(bounds.future_timestamp(&beyond, NOW), Some(NOW + 11));
+        bounds.clamp(&mut beyond, NOW).unwrap();
+        assert_eq!(beyond.timestamp, NOW + 10);
+
+        let reject = TimestampBounds {
+            future_timestamps: FutureTimestampPolicy::Reject,
+            ..bounds
+        };
+        assert!(matches!(
+            reject.clamp(&mut heartbeat("node-1", NOW + 11, 0), NOW),
+            Err(HeartbeatError::FutureTimestamp { timestamp, now, .. })
+                if timestamp == NOW + 11 && now == NOW
+        ));
+
+        assert!(!bounds.is_stale(&heartbeat("node-1", NOW - 29, 0), NOW));
+        assert!(bounds.is_stale(&heartbeat("node-1", NOW - 30, 0), NOW));
     }
-}
 
-impl std::error::Error for HeartbeatError {}
+    #[test]
+    fn rejected_future_heartbeats_are_logged_but_not_stored() {
+        let mut storage = storage();
+        storage.set_timestamp_bounds(TimestampBounds {
+            future_timestamps: FutureTimestampPolicy::Reject,
+            ..TimestampBounds::default()
+        });
+        let version = storage.version();
+        assert!(storage
+            .insert(heartbeat("node-1", NOW + 3600, 0), NOW)
+            .is_err());
+        assert!(!storage.data.contains_key("node-1"));
+        assert_eq!(storage.version(), version);
+        assert_eq!(
+            storage.recent_events(1)[0].kind,
+            StorageEventKind::FutureTimestamp {
+                timestamp: NOW + 3600,
+                clamped: false
+            }
+        );
+    }
 
-impl From<io::Error> for HeartbeatError {
-    fn from(err: io::Error) -> HeartbeatError {
-        if err.kind() == io::ErrorKind::WouldBlock {
-            HeartbeatError::WouldBlock
-        } else {
-            HeartbeatError::Io(err)
-        }
+    #[test]
+    fn an_old_first_heartbeat_is_stored_as_suspect() {
+        let mut storage = storage();
+        let stale_after = storage.timestamp_bounds().stale_after.as_secs();
+        storage
+            .insert(heartbeat("node-1", NOW - stale_after, 0), NOW)
+            .unwrap();
+        let peer = &storage.data["node-1"];
+        assert_eq!(peer.state, PeerState::Suspect);
+        // second-hand news counts as received when it was sent
+        assert_eq!(peer.last_received_at, NOW - stale_after);
+
+        // a known peer's old heartbeat is just an update
+        storage
+            .insert(heartbeat("node-1", NOW - stale_after + 1, 0), NOW)
+            .unwrap();
+        assert_eq!(storage.data["node-1"].last_received_at, NOW);
     }
-}
 
-impl From<SerdeError> for HeartbeatError {
-    fn from(err: SerdeError) -> HeartbeatError {
-        HeartbeatError::Serde(err)
+    #[test]
+    fn the_own_entry_stays_fresh_once_heartbeats_stop() {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let node = node_on(&Arc::new(MemoryNetwork::new()), clock.clone());
+        let mut state = LoopState::new(&node.runtime.config, node.runtime.generation());
+        node.runtime.heartbeat_stop.trigger();
+        clock.advance(Duration::from_secs(5));
+        tick(&node.runtime, &mut state).expect("loop stopped");
+        assert_eq!(
+            node.storage().get("node-0").unwrap().heartbeat.timestamp,
+            NOW + 5
+        );
+        assert_eq!(node.stats().heartbeats_sent, 0);
+        assert_eq!(node.runtime.last_heartbeat_ms.load(Ordering::Relaxed), 0);
     }
-}
 
-impl From<String> for HeartbeatError {
-    fn from(err: String) -> HeartbeatError {
-        HeartbeatError::Other(err)
+    /// Invariants of [`Storage::insert`] and [`Storage::merge`] over arbitrary interleavings of
+    /// heartbeats, and round-trips of what goes on the wire or into snapshots.
+    mod properties {
+        use super::*;
+        use proptest::collection::vec;
+        use proptest::prelude::*;
+
+        fn arb_heartbeat() -> impl Strategy<Value = Heartbeat> {
+            (
+                1..4u8,
+                8000..8003u16,
+                NOW - 60..=NOW,
+                0..1000u16,
+                any::<bool>(),
+            )
+                .prop_map(|(node, port, timestamp, millis, leaving)| Heartbeat {
+                    leaving,
+                    address: SocketAddr::from(([10, 0, 1, node], port)),
+                    ..heartbeat(&format!("node-{}", node), timestamp, millis)
+                })
+        }
+
+        /// Heartbeats in the order they arrive, and the same ones in another order.
+        fn arb_interleavings() -> impl Strategy<Value = (Vec<Heartbeat>, Vec<Heartbeat>)> {
+            vec(arb_heartbeat(), 1..40).prop_flat_map(|heartbeats| {
+                (Just(heartbeats.clone()), Just(heartbeats).prop_shuffle())
+            })
+        }
+
+        /// The newest of `heartbeats` for every node, by [`Heartbeat::cmp_version`].
+        fn newest(heartbeats: &[Heartbeat]) -> HashMap<NodeId, Heartbeat> {
+            let mut newest: HashMap<NodeId, Heartbeat> = HashMap::new();
+            for heartbeat in heartbeats {
+                let stored = newest
+                    .entry(heartbeat.id.clone())
+                    .or_insert_with(|| heartbeat.clone());
+                if heartbeat.cmp_version(stored) == cmp::Ordering::Greater {
+                    *stored = heartbeat.clone();
+                }
+            }
+            newest
+        }
+
+        fn insert_all(heartbeats: &[Heartbeat]) -> Storage {
+            let mut storage = storage();
+            for heartbeat in heartbeats {
+                storage.insert(heartbeat.clone(), NOW).unwrap();
+            }
+            storage
+        }
+
+        fn assert_same_version(stored: &Heartbeat, expected: &Heartbeat) {
+            assert_eq!(stored.id, expected.id);
+            assert_eq!(
+                stored.cmp_version(expected),
+                cmp::Ordering::Equal,
+                "stored {:?}, expected {:?}",
+                stored,
+                expected
+            );
+        }
+
+        proptest! {
+            #[test]
+            fn the_newest_heartbeat_of_every_node_is_stored(heartbeats in vec(arb_heartbeat(), 1..40)) {
+                let storage = insert_all(&heartbeats);
+                for (id, expected) in newest(&heartbeats) {
+                    assert_same_version(&storage.data[&id].heartbeat, &expected);
+                }
+            }
+
+            #[test]
+            fn the_order_of_arrival_does_not_change_what_is_stored(
+                (heartbeats, shuffled) in arb_interleavings()
+            ) {
+                let storage = insert_all(&heartbeats);
+                let other = insert_all(&shuffled);
+                prop_assert_eq!(storage.data.len(), other.data.len());
+                for (id, data) in &storage.data {
+                    assert_same_version(&data.heartbeat, &other.data[id].heartbeat);
+                }
+            }
+
+            #[test]
+            fn every_insert_is_counted_once(heartbeats in vec(arb_heartbeat(), 1..40)) {
+                let mut storage = storage();
+                for heartbeat in heartbeats {
+                    let id = heartbeat.id.clone();
+                    let before = storage.data.get(&id).cloned();
+                    let version = storage.version();
+                    let outcome = storage.insert(heartbeat, NOW).unwrap();
+                    let after = &storage.data[&id];
+                    let (updates, duplicates, of_current) = before.map_or((0, 0, 0), |data| {
+                        (data.updates_received, data.duplicates_received, data.duplicates_of_current)
+                    });
+                    match outcome {
+                        InsertOutcome::Applied => {
+                            prop_assert_eq!(after.updates_received, updates + 1);
+                            prop_assert_eq!(after.duplicates_received, duplicates);
+                            prop_assert_eq!(after.duplicates_of_current, 0);
+                            prop_assert!(storage.version() > version);
+                            prop_assert!(storage.sent_to_data.get(&id).is_none_or(Vec::is_empty));
+                        }
+                        InsertOutcome::DuplicateOfCurrent { duplicates: copies } => {
+                            prop_assert_eq!(after.updates_received, updates);
+                            prop_assert_eq!(after.duplicates_received, duplicates + 1);
+                            prop_assert_eq!(copies, of_current + 1);
+                            prop_assert_eq!(after.duplicates_of_current, copies);
+                            prop_assert_eq!(storage.version(), version);
+                        }
+                        InsertOutcome::Stale => {
+                            prop_assert_eq!(after.updates_received, updates);
+                            prop_assert_eq!(after.duplicates_received, duplicates + 1);
+                            prop_assert_eq!(after.duplicates_of_current, of_current);
+                            prop_assert_eq!(storage.version(), version);
+                        }
+                    }
+                }
+            }
+
+            #[test]
+            fn future_timestamps_are_stored_within_the_bound(
+                heartbeats in vec(arb_heartbeat(), 1..20),
+                ahead in 0..3600u64,
+            ) {
+                let mut storage = storage();
+                let latest_allowed = NOW + storage.timestamp_bounds().max_future_skew.as_secs();
+                for mut heartbeat in heartbeats {
+                    heartbeat.timestamp += ahead;
+                    storage.insert(heartbeat, NOW).unwrap();
+                }
+                for data in storage.iter_entries() {
+                    prop_assert!(data.heartbeat.timestamp <= latest_allowed);
+                }
+            }
+
+            #[test]
+            fn merging_keeps_the_newer_heartbeat_of_either_storage(
+                ours in vec(arb_heartbeat(), 0..20),
+                theirs in vec(arb_heartbeat(), 0..20),
+            ) {
+                let mut storage = insert_all(&ours);
+                let mut other = insert_all(&theirs).snapshot();
+                other.owner = NodeId::new("node-9").unwrap();
+                other.entries.retain(|entry| entry.id.as_str() != "node-0");
+                storage.merge(other, &ManualClock::new(NOW));
+
+                // snapshots keep the newest heartbeat of every node, without its milliseconds
+                let theirs: Vec<Heartbeat> = newest(&theirs)
+                    .into_values()
+                    .map(|heartbeat| Heartbeat {
+                        millis: 0,
+                        ..heartbeat
+                    })
+                    .collect();
+                let expected = newest(&[ours, theirs].concat());
+                prop_assert_eq!(storage.data.len(), expected.len() + 1);
+                for (id, expected) in expected {
+                    assert_same_version(&storage.data[&id].heartbeat, &expected);
+                }
+            }
+
+            #[test]
+            fn heartbeats_survive_encoding(heartbeat in arb_heartbeat()) {
+                let encoded = serde_json::to_vec(&heartbeat).unwrap();
+                let decoded: Heartbeat = serde_json::from_slice(&encoded).unwrap();
+                assert_same_version(&decoded, &heartbeat);
+                prop_assert!(encoded.len() <= MAX_DATAGRAM_SIZE);
+            }
+
+            #[test]
+            fn values_survive_encoding(
+                key in "[a-z0-9/._-]{1,32}",
+                value in vec(any::<u8>(), 0..32),
+                version in any::<u64>(),
+                timestamp in any::<u64>(),
+            ) {
+                let entry = KeyValue {
+                    key,
+                    value,
+                    origin: NodeId::new("node-1").unwrap(),
+                    address: "10.0.1.1:8000".parse().unwrap(),
+                    version,
+                    timestamp,
+                };
+                let decoded: KeyValue = serde_json::from_slice(&serde_json::to_vec(&entry).unwrap()).unwrap();
+                prop_assert_eq!(decoded, entry);
+            }
+
+            #[test]
+            fn snapshots_survive_encoding(heartbeats in vec(arb_heartbeat(), 0..20)) {
+                let mut storage = insert_all(&heartbeats);
+                storage.evaluate(
+                    &ManualClock::new(NOW),
+                    Duration::from_secs(10),
+                    Duration::from_secs(30),
+                    AgeSource::SenderTimestamp,
+                );
+                let snapshot = storage.snapshot();
+                let encoded = serde_json::to_string(&snapshot).unwrap();
+                let decoded: StorageSnapshot = serde_json::from_str(&encoded).unwrap();
+                prop_assert_eq!(&decoded, &snapshot);
+                let restored = Storage::from_snapshot(decoded).snapshot();
+                let mut restored_entries = restored.entries;
+                let mut entries = snapshot.entries;
+                restored_entries.sort_by(|a, b| a.id.cmp(&b.id));
+                entries.sort_by(|a, b| a.id.cmp(&b.id));
+                prop_assert_eq!(restored_entries, entries);
+            }
+        }
     }
 }
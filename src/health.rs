@@ -0,0 +1,91 @@
+use std::fmt;
+use std::time::Duration;
+
+// number of consecutive failed sends after which a node reports its sends as failing
+pub const FAILED_SENDS_THRESHOLD: u32 = 5;
+
+/// Something wrong with a node found by [`crate::gossip::Node::health`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthIssue {
+    /// the node was never run, or has been stopped
+    NotRunning,
+    Paused,
+    /// the node announced that it left the cluster and sends no more heartbeats
+    Left,
+    /// no heartbeat was sent successfully for longer than two heartbeat intervals
+    HeartbeatStale {
+        since: Duration,
+    },
+    /// the node loop has not come around for longer than it should
+    LoopStalled {
+        since: Duration,
+    },
+    /// the latest sends all failed
+    SendsFailing {
+        consecutive: u32,
+    },
+}
+
+impl fmt::Display for HealthIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthIssue::NotRunning => write!(f, "node is not running"),
+            HealthIssue::Paused => write!(f, "node is paused"),
+            HealthIssue::Left => write!(f, "node has left the cluster"),
+            HealthIssue::HeartbeatStale { since } => {
+                write!(f, "no heartbeat sent for {}ms", since.as_millis())
+            }
+            HealthIssue::LoopStalled { since } => {
+                write!(f, "node loop stalled for {}ms", since.as_millis())
+            }
+            HealthIssue::SendsFailing { consecutive } => {
+                write!(f, "last {} sends failed", consecutive)
+            }
+        }
+    }
+}
+
+/// Result of a node's self-check. Cheap enough to be polled by external health probes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HealthReport {
+    pub issues: Vec<HealthIssue>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.issues.is_empty() {
+            return write!(f, "healthy");
+        }
+        for (i, issue) in self.issues.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_report_lists_its_issues() {
+        assert_eq!(HealthReport::default().to_string(), "healthy");
+        let report = HealthReport {
+            issues: vec![
+                HealthIssue::Paused,
+                HealthIssue::SendsFailing { consecutive: 5 },
+            ],
+        };
+        assert!(!report.is_healthy());
+        assert_eq!(report.to_string(), "node is paused, last 5 sends failed");
+    }
+}
@@ -0,0 +1,212 @@
+//! Minimal HTTP status endpoint of a node, enabled with the `http` feature and
+//! [`crate::gossip::NodeConfigBuilder::http_address`]. Serves:
+//!
+//! - `GET /members`: the member list as a JSON array
+//! - `GET /stats`: the message counters as a JSON object
+//! - `GET /storage`: every storage entry, see [`crate::snapshot::StorageSnapshot`]
+//! - `GET /health`: 200 if the node's self-check passes, 503 with the issues found otherwise
+//! - `GET /metrics`: the counters in the Prometheus text format, see [`crate::prometheus`]
+//!
+//! Connections are handled one at a time on a thread of their own, so a slow client can only
+//! delay other clients, never the node loop. The same loop serves the `/metrics` of a whole
+//! simulated cluster, see [`serve_metrics`].
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use tracing::{error, info};
+
+use crate::gossip::{NodeHandle, Storage};
+use crate::prometheus::{self, NodeMetrics};
+
+// how often the accept loop checks whether the node was stopped
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+const JSON: &str = "application/json";
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+/// Binds `address` and serves the status of `node` until the node is stopped.
+pub(crate) fn serve(address: SocketAddr, node: NodeHandle) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    listener.set_nonblocking(true)?;
+    info!(%address, "Serving node status over http");
+    // a restarted node serves from a new listener, see Node::restart
+    let generation = node.generation();
+    thread::spawn(move || {
+        accept_loop(
+            listener,
+            || !node.is_shut_down() && node.generation() == generation,
+            |method, path| node_response(method, path, &node),
+        )
+    });
+    Ok(())
+}
+
+/// Binds `address` and serves `GET /metrics` with the metrics `collect` returns, labelled by
+/// node, for as long as `keep_serving` holds.
+pub(crate) fn serve_metrics(
+    address: SocketAddr,
+    keep_serving: impl Fn() -> bool + Send + 'static,
+    collect: impl Fn() -> Vec<NodeMetrics> + Send + 'static,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    listener.set_nonblocking(true)?;
+    info!(%address, "Serving cluster metrics over http");
+    thread::spawn(move || {
+        accept_loop(listener, keep_serving, |method, path| {
+            match (method, path) {
+                (Some("GET"), Some("/metrics")) => metrics(&collect()),
+                _ => other(method),
+            }
+        })
+    });
+    Ok(())
+}
+
+fn accept_loop(
+    listener: TcpListener,
+    keep_serving: impl Fn() -> bool,
+    respond: impl Fn(Option<&str>, Option<&str>) -> Response,
+) {
+    while keep_serving() {
+        match listener.accept() {
+            Ok((stream, _peer)) => {
+                if let Err(e) = handle_connection(stream, &respond) {
+                    error!(error = e.to_string(), "failed to answer http request");
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+            Err(e) => {
+                error!(error = e.to_string(), "failed to accept http connection");
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    respond: impl Fn(Option<&str>, Option<&str>) -> Response,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let request = read_request_head(&mut stream)?;
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let response = respond(request_line.next(), request_line.next());
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
+
+fn node_response(method: Option<&str>, path: Option<&str>, node: &NodeHandle) -> Response {
+    match (method, path) {
+        (Some("GET"), Some("/members")) => json(serde_json::to_string(&node.members())),
+        (Some("GET"), Some("/stats")) => json(serde_json::to_string(&node.stats())),
+        (Some("GET"), Some("/storage")) => json(serde_json::to_string(
+            &node.storage().with_read(Storage::snapshot),
+        )),
+        (Some("GET"), Some("/health")) => {
+            let health = node.health();
+            let body = serde_json::json!({
+                "healthy": health.is_healthy(),
+                "issues": health.issues.iter().map(|issue| issue.to_string()).collect::<Vec<_>>(),
+            })
+            .to_string();
+            Response {
+                status: if health.is_healthy() {
+                    "200 OK"
+                } else {
+                    "503 Service Unavailable"
+                },
+                content_type: JSON,
+                body,
+            }
+        }
+        (Some("GET"), Some("/metrics")) => metrics(&[NodeMetrics {
+            node: node.storage().with_read(|storage| storage.owner.clone()),
+            stats: node.stats(),
+            storage: node.storage_metrics(),
+        }]),
+        _ => other(method),
+    }
+}
+
+/// 404 for other paths, 405 for other methods.
+fn other(method: Option<&str>) -> Response {
+    let (status, body) = match method {
+        Some("GET") => ("404 Not Found", r#"{"error":"not found"}"#),
+        _ => (
+            "405 Method Not Allowed",
+            r#"{"error":"only GET is supported"}"#,
+        ),
+    };
+    Response {
+        status,
+        content_type: JSON,
+        body: body.to_string(),
+    }
+}
+
+fn metrics(nodes: &[NodeMetrics]) -> Response {
+    Response {
+        status: "200 OK",
+        content_type: prometheus::CONTENT_TYPE,
+        body: prometheus::render(nodes),
+    }
+}
+
+fn json(body: serde_json::Result<String>) -> Response {
+    match body {
+        Ok(body) => Response {
+            status: "200 OK",
+            content_type: JSON,
+            body,
+        },
+        Err(e) => Response {
+            status: "500 Internal Server Error",
+            content_type: JSON,
+            body: serde_json::json!({ "error": e.to_string() }).to_string(),
+        },
+    }
+}
+
+/// Reads until the end of the request headers; the endpoints take no body.
+fn read_request_head(stream: &mut TcpStream) -> io::Result<String> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+        if request.len() > MAX_REQUEST_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request headers too large",
+            ));
+        }
+    }
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
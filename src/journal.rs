@@ -0,0 +1,149 @@
+//! Bounded journal of the entry changes behind [`crate::gossip::Storage::version`], so that
+//! incremental consumers can ask what changed since the version they saw last with
+//! [`crate::gossip::Storage::changes_since`] instead of exporting the whole storage again.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use thiserror::Error;
+
+use crate::node_id::NodeId;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangeRecord {
+    /// version of the storage right after the change
+    pub version: u64,
+    pub id: NodeId,
+    pub kind: ChangeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    /// a newer heartbeat replaced the stored one
+    Updated,
+    StateChanged,
+    Removed,
+}
+
+/// The journal no longer holds every change since the requested version. The consumer has to
+/// start over from a snapshot, taken together with the version under the same read lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error(
+    "changes since version {since} are no longer journaled, the storage is at version {current}"
+)]
+pub struct ResyncRequired {
+    pub since: u64,
+    pub current: u64,
+}
+
+/// The latest `capacity` changes, oldest first. Several changes share a version when a single
+/// evaluation changed the state of several entries.
+#[derive(Debug, Clone)]
+pub struct ChangeJournal {
+    records: VecDeque<ChangeRecord>,
+    capacity: usize,
+    // highest version of a record dropped to make room
+    truncated_through: u64,
+}
+
+impl ChangeJournal {
+    pub fn new(capacity: usize) -> Self {
+        ChangeJournal {
+            records: VecDeque::new(),
+            capacity,
+            truncated_through: 0,
+        }
+    }
+
+    pub fn record(&mut self, record: ChangeRecord) {
+        if self.records.len() == self.capacity {
+            match self.records.pop_front() {
+                Some(dropped) => self.truncated_through = dropped.version,
+                // a capacity of 0 keeps nothing
+                None => {
+                    self.truncated_through = record.version;
+                    return;
+                }
+            }
+        }
+        self.records.push_back(record);
+    }
+
+    /// The changes after `version`, oldest first, given the storage is at `current`.
+    pub fn since(&self, version: u64, current: u64) -> Result<Vec<ChangeRecord>, ResyncRequired> {
+        // a version from the future was seen on another storage, e.g. before a restart
+        if version < self.truncated_through || version > current {
+            return Err(ResyncRequired {
+                since: version,
+                current,
+            });
+        }
+        let skip = self
+            .records
+            .partition_point(|record| record.version <= version);
+        Ok(self.records.iter().skip(skip).cloned().collect())
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(version: u64, id: &str) -> ChangeRecord {
+        ChangeRecord {
+            version,
+            id: NodeId::new(id).unwrap(),
+            kind: ChangeKind::Updated,
+        }
+    }
+
+    fn versions(records: Vec<ChangeRecord>) -> Vec<u64> {
+        records.into_iter().map(|record| record.version).collect()
+    }
+
+    #[test]
+    fn changes_since_a_version_are_the_later_ones() {
+        let mut journal = ChangeJournal::new(10);
+        journal.record(record(1, "node-1"));
+        // one evaluation changing two entries
+        journal.record(record(2, "node-1"));
+        journal.record(record(2, "node-2"));
+        journal.record(record(3, "node-2"));
+        assert_eq!(versions(journal.since(0, 3).unwrap()), [1, 2, 2, 3]);
+        assert_eq!(versions(journal.since(1, 3).unwrap()), [2, 2, 3]);
+        assert!(journal.since(3, 3).unwrap().is_empty());
+        assert_eq!(
+            journal.since(4, 3),
+            Err(ResyncRequired {
+                since: 4,
+                current: 3
+            })
+        );
+    }
+
+    #[test]
+    fn a_full_journal_asks_for_a_resync_past_what_it_dropped() {
+        let mut journal = ChangeJournal::new(2);
+        for version in 1..=4 {
+            journal.record(record(version, "node-1"));
+        }
+        assert_eq!(journal.len(), 2);
+        assert_eq!(versions(journal.since(2, 4).unwrap()), [3, 4]);
+        assert!(journal.since(1, 4).is_err());
+
+        let mut nothing = ChangeJournal::new(0);
+        nothing.record(record(1, "node-1"));
+        assert!(nothing.is_empty());
+        assert!(nothing.since(0, 1).is_err());
+        assert!(nothing.since(1, 1).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+//! Application data gossiped alongside membership. Every node can put values under keys with
+//! [`crate::gossip::Node::put`]; they spread like heartbeats and can be read on any node with
+//! [`crate::gossip::Node::get`].
+//!
+//! Values are kept per key and origin, the node that put them, and a newer version from the same
+//! origin replaces an older one. Reading a key returns the value put last, by the origins'
+//! clocks, whichever node put it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use crate::gossip::{HeartbeatError, InsertOutcome, MAX_DATAGRAM_SIZE};
+use crate::node_id::NodeId;
+
+/// Most bytes an entry takes encoded, key, value, origin and all, so that every stored entry fits
+/// the datagram it is sent in.
+pub const MAX_ENTRY_SIZE: usize = MAX_DATAGRAM_SIZE;
+/// Most keys a single origin can have values under.
+pub const MAX_KEYS_PER_NODE: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: Vec<u8>,
+    /// the node that put the value
+    pub origin: NodeId,
+    /// address of the origin
+    pub address: SocketAddr,
+    /// counts the puts of the key by the origin
+    pub version: u64,
+    /// clock time of the origin when it put the value
+    pub timestamp: u64,
+}
+
+impl KeyValue {
+    /// Bytes of the entry encoded as it is sent.
+    pub fn size(&self) -> usize {
+        serde_json::to_vec(self).map_or(usize::MAX, |encoded| encoded.len())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct StoredValue {
+    entry: KeyValue,
+    // copies received of the stored version
+    duplicates: u64,
+}
+
+/// The values a node knows, part of its [`crate::gossip::Storage`].
+#[derive(Debug, Clone, Default)]
+pub struct KvStore {
+    entries: HashMap<(String, NodeId), StoredValue>,
+}
+
+impl KvStore {
+    /// Stores `entry` unless the same or a newer version from its origin is already stored.
+    /// Entries over [`MAX_ENTRY_SIZE`] and new keys of an origin that already has
+    /// [`MAX_KEYS_PER_NODE`] are rejected.
+    pub fn apply(&mut self, entry: KeyValue) -> Result<InsertOutcome, HeartbeatError> {
+        let size = entry.size();
+        if size > MAX_ENTRY_SIZE {
+            return Err(HeartbeatError::ValueTooLarge {
+                size,
+                max: MAX_ENTRY_SIZE,
+            });
+        }
+        let slot = (entry.key.clone(), entry.origin.clone());
+        match self.entries.get_mut(&slot) {
+            Some(stored) if entry.version < stored.entry.version => Ok(InsertOutcome::Stale),
+            Some(stored) if entry.version == stored.entry.version => {
+                stored.duplicates += 1;
+                Ok(InsertOutcome::DuplicateOfCurrent {
+                    duplicates: stored.duplicates,
+                })
+            }
+            Some(stored) => {
+                *stored = StoredValue {
+                    entry,
+                    duplicates: 0,
+                };
+                Ok(InsertOutcome::Applied)
+            }
+            None => {
+                if self.keys_of(&entry.origin) >= MAX_KEYS_PER_NODE {
+                    return Err(HeartbeatError::TooManyKeys {
+                        origin: entry.origin,
+                        max: MAX_KEYS_PER_NODE,
+                    });
+                }
+                self.entries.insert(
+                    slot,
+                    StoredValue {
+                        entry,
+                        duplicates: 0,
+                    },
+                );
+                Ok(InsertOutcome::Applied)
+            }
+        }
+    }
+
+    /// The value put last under `key`, by any origin.
+    pub fn get(&self, key: &str) -> Option<&KeyValue> {
+        self.entries
+            .values()
+            .map(|stored| &stored.entry)
+            .filter(|entry| entry.key == key)
+            .max_by(|a, b| {
+                (a.timestamp, a.version, &a.origin).cmp(&(b.timestamp, b.version, &b.origin))
+            })
+    }
+
+    /// The version the next put of `key` by `origin` gets.
+    pub fn next_version(&self, key: &str, origin: &NodeId) -> u64 {
+        self.entries
+            .get(&(key.to_string(), origin.clone()))
+            .map_or(1, |stored| stored.entry.version + 1)
+    }
+
+    /// Entries put by `origin`.
+    pub fn by_origin<'a>(&'a self, origin: &'a NodeId) -> impl Iterator<Item = &'a KeyValue> {
+        self.entries
+            .values()
+            .map(|stored| &stored.entry)
+            .filter(move |entry| entry.origin == *origin)
+    }
+
+    /// Entries whose value under `key` is `value`, at most one per origin.
+    pub fn with_value<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+    ) -> impl Iterator<Item = &'a KeyValue> {
+        self.iter()
+            .filter(move |entry| entry.key == key && entry.value == value)
+    }
+
+    /// Every stored entry, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &KeyValue> {
+        self.entries.values().map(|stored| &stored.entry)
+    }
+
+    fn keys_of(&self, origin: &NodeId) -> usize {
+        self.by_origin(origin).count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: &str, value: &[u8]) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: value.to_vec(),
+            origin: NodeId::new("node-0").unwrap(),
+            address: "127.0.0.1:8000".parse().unwrap(),
+            version: 1,
+            timestamp: 1_700_000_000,
+        }
+    }
+
+    /// An entry taking exactly [`MAX_ENTRY_SIZE`] bytes encoded, its key padded to get there.
+    fn largest_entry() -> KeyValue {
+        let mut entry = entry("", b"value");
+        entry.key = "k".repeat(MAX_ENTRY_SIZE - entry.size());
+        assert_eq!(entry.size(), MAX_ENTRY_SIZE);
+        entry
+    }
+
+    #[test]
+    fn entries_up_to_the_limit_are_stored() {
+        let mut store = KvStore::default();
+        let entry = largest_entry();
+        assert!(matches!(
+            store.apply(entry.clone()),
+            Ok(InsertOutcome::Applied)
+        ));
+        assert_eq!(store.get(&entry.key), Some(&entry));
+    }
+
+    #[test]
+    fn entries_over_the_limit_are_rejected() {
+        let mut store = KvStore::default();
+        let mut entry = largest_entry();
+        entry.key.push('k');
+        assert!(matches!(
+            store.apply(entry.clone()),
+            Err(HeartbeatError::ValueTooLarge { size, max })
+                if size == MAX_ENTRY_SIZE + 1 && max == MAX_ENTRY_SIZE
+        ));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn newer_versions_replace_older_ones() {
+        let mut store = KvStore::default();
+        let first = entry("color", b"red");
+        let second = KeyValue {
+            value: b"blue".to_vec(),
+            version: 2,
+            ..first.clone()
+        };
+        store.apply(second.clone()).unwrap();
+        assert!(matches!(store.apply(first), Ok(InsertOutcome::Stale)));
+        assert!(matches!(
+            store.apply(second.clone()),
+            Ok(InsertOutcome::DuplicateOfCurrent { duplicates: 1 })
+        ));
+        assert_eq!(store.get("color"), Some(&second));
+    }
+}
@@ -0,0 +1,33 @@
+pub mod admin;
+pub mod clock;
+pub mod defaults;
+pub mod event_log;
+pub mod faults;
+pub mod forward;
+pub mod gossip;
+pub mod health;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod journal;
+pub mod kv;
+pub mod membership;
+pub mod memory_net;
+pub mod node_id;
+#[cfg(feature = "sim")]
+pub mod plot;
+#[cfg(feature = "sim")]
+pub mod processes;
+pub mod prometheus;
+pub mod query;
+#[cfg(feature = "sim")]
+pub mod report;
+#[cfg(feature = "sim")]
+pub mod scenario;
+pub mod sharded;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod sim_runtime;
+pub mod snapshot;
+pub mod stats;
+pub mod summary;
+pub mod trace;
@@ -1,238 +1,3583 @@
-mod gossip;
+// the json! of the run settings nests deeper than the default allows
+#![recursion_limit = "256"]
 
-use core::time;
-use rand::{seq::SliceRandom, thread_rng};
-use std::collections::HashMap;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, Command, CommandFactory, FromArgMatches, Parser};
+use muck::clock::{Clock, SystemClock};
+use muck::defaults;
+use muck::faults::{FaultInjector, Misbehavior, Partition};
+use muck::gossip;
+use muck::membership::{PeerState, Subscription};
+use muck::memory_net::{Fabric, FabricStats, MemoryNetwork};
+use muck::node_id::NodeId;
+use muck::plot::{
+    plot, EventConvergence, EventRecord, PlotReport, PlotSettings, SampleWriter, Timeline,
+};
+use muck::processes::NodeProcess;
+use muck::report::{
+    ByzantineResults, ChurnResults, ConvergenceEntry, Detection, EventEntry, FabricTotals,
+    FaultTotals, GroupResults, LatencyDistribution, LinkResults, NewcomerResults, NodeTime,
+    PartitionResults, Percentiles, Results, RunReport,
+};
+use muck::scenario::{
+    format_group, parse_groups, parse_node, parse_nodes, Action, EventError, NodeGroup,
+    NodeSelection, ScheduledEvent,
+};
+use muck::sim::{
+    create_node, describe_added, describe_indexes, node_address, node_id, select_indexes, Churn,
+    SimConfig, Simulation, PORT_BASE,
+};
+use muck::sim_runtime::SimRuntime;
+use muck::stats::NodeStats;
+use muck::trace::{HeartbeatTrace, TraceRecord};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde_json::json;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufWriter, Write};
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::mpsc;
+use std::sync::{Arc, PoisonError};
 use std::thread::{self, sleep};
-use std::time::Duration;
-use textplots::{ColorPlot, Shape};
+use std::time::{Duration, Instant, SystemTime};
 use tracing::error;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
-const PORT_BASE: u64 = 8000;
-const NUMBER_SEED_NODES: u64 = 2;
-const NUMBER_NODES: u64 = 100;
-const HEALTHY_THRESHOLD_SECS: u64 = 30;
-const HEARTBEAT_INTERVAL_SECS: u64 = 5;
-const POLL_INTERVAL_MILISECS: u64 = 10;
-const HEARTBEAT_SPREAD: usize = 5;
-const NUMBER_NODES_TO_KILL: usize = 20;
-const KILL_NODES_AFTER_N_SECS: u64 = 60;
-const START_ALL_NODES_AFTER_N_SECS: u64 = 40;
-// factor of which the probability of forwarding information should decrease given times the
-// information already been sent
-const DECAY_FACTOR: f64 = 0.8;
-
-fn main() {
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// default of --sample-interval
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+// with --processes, how long the nodes have to answer and how often the numbers are printed
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+// key the first node puts with --put-value
+const VALUE_KEY: &str = "greeting";
+// clusters from this size on are too slow to measure in a debug build
+const LARGE_CLUSTER: u64 = 1000;
+
+fn main() -> ExitCode {
     let subscriber = FmtSubscriber::builder()
         .with_env_filter(EnvFilter::new("error")) // Set the log level to ERROR
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
-    let mut seed_nodes = vec![];
-    for i in 0..NUMBER_SEED_NODES {
-        let port = PORT_BASE + i;
-        let address = "0.0.0.0:".to_string() + &port.to_string();
-        seed_nodes.push((i.to_string(), address));
+    // ctrl-c stops the simulation and prints a report instead of killing the process
+    let interrupted = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, interrupted.clone())
+            .expect("failed to install signal handler");
     }
 
-    // start inital nodes
-    let mut all_shared_storages: HashMap<String, Arc<Mutex<gossip::Storage>>> = HashMap::new();
-    let is_alive_flags: Arc<Mutex<Vec<Arc<AtomicBool>>>> = Arc::new(Mutex::new(Vec::new()));
+    // `muck node ...` runs a single node, as started by --processes
+    if std::env::args().nth(1).as_deref() == Some("node") {
+        let args = NodeArgs::parse_from(std::env::args().skip(1));
+        return run_node(&args, &interrupted);
+    }
 
-    for i in 0..NUMBER_NODES {
-        let port = PORT_BASE + i;
-        let address = "0.0.0.0:".to_string() + &port.to_string();
-        let storage = gossip::setup_storage(i.to_string(), address.clone(), seed_nodes.clone());
-        let shared_storage = Arc::new(Mutex::new(storage));
-        all_shared_storages.insert(i.to_string(), shared_storage.clone());
+    let mut args = parse_args();
+    if let Err(e) = args.validate() {
+        Args::command().error(ErrorKind::ValueValidation, e).exit();
+    }
+    // unseeded runs draw a master seed, printed in the banner, so that they can be repeated
+    args.seed.get_or_insert_with(rand::random);
 
-        let is_alive = Arc::new(AtomicBool::new(true));
-        let is_alive_clone = is_alive.clone();
+    let failures = if args.processes {
+        orchestrate(&args, &interrupted)
+    } else if args.sweep.is_empty() && args.repeat == 1 {
+        // what the charts show is kept even when nobody asked for it
+        let out = args
+            .out
+            .clone()
+            .or_else(|| (!args.headless).then(default_out));
+        simulate(args, out.as_deref(), &interrupted).failures
+    } else {
+        run_batch(&args, &interrupted)
+    };
+    exit_code(&failures)
+}
 
-        is_alive_flags
-            .lock()
-            .expect("Failed to get is_alive_flags")
-            .push(is_alive);
+/// Where the samples of an interactive run go without `--out`, a file named after the time the
+/// run started in the working directory, e.g. gossip-run-2026-10-17T09-30-00Z.csv.
+fn default_out() -> PathBuf {
+    let started = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    PathBuf::from(format!("gossip-run-{}.csv", started.replace(':', "-")))
+}
 
-        let node = gossip::Node::new(
-            i.to_string(),
-            address,
-            shared_storage.clone(),
-            HEARTBEAT_INTERVAL_SECS,
-            HEARTBEAT_SPREAD,
-            POLL_INTERVAL_MILISECS,
-            DECAY_FACTOR,
-            is_alive_clone,
+/// Runs one simulation until it is interrupted, its duration is up or every node has stopped,
+/// then prints its report. With `out`, the samples go there and the report next to them. Nodes
+/// giving up before the end and a summary that can't be written fail the run.
+fn simulate(args: Args, out: Option<&Path>, interrupted: &AtomicBool) -> RunOutcome {
+    let samples = match out {
+        Some(path) => match fs::File::create(path) {
+            Ok(file) => match SampleWriter::new(Box::new(BufWriter::new(file))) {
+                Ok(writer) => Some(writer),
+                Err(e) => Args::command()
+                    .error(
+                        ErrorKind::Io,
+                        format!("can't write to {}: {}", path.display(), e),
+                    )
+                    .exit(),
+            },
+            Err(e) => Args::command()
+                .error(
+                    ErrorKind::Io,
+                    format!("can't create {}: {}", path.display(), e),
+                )
+                .exit(),
+        },
+        None => None,
+    };
+    let number_nodes = args.nodes;
+    let runtime = args.sim_runtime();
+    let clock: Arc<dyn Clock> = match &runtime {
+        Some(runtime) => runtime.time().clock().clone(),
+        None => Arc::new(SystemClock),
+    };
+    let faults = args.faults(&clock);
+    let trace = args.trace_heartbeat.map(|trace| {
+        Arc::new(HeartbeatTrace::new(
+            node_id(trace.node),
+            clock.elapsed() + trace.after,
+            clock.clone(),
+        ))
+    });
+    let node_args = args.clone();
+    let simulation = Arc::new(Simulation::new(SimConfig {
+        seeds: args.seeds,
+        nodes: number_nodes,
+        suspect_timeout: args.suspect_timeout,
+        dead_timeout: args.dead_timeout,
+        restart: args.restart,
+        faults: faults.clone(),
+        memory_network: args
+            .in_memory
+            .then(|| Arc::new(args.memory_network().clock(clock.clone()))),
+        runtime: runtime.clone(),
+        warm_start: args.warm_start,
+        trace: trace.clone(),
+        node_config: Arc::new(move |index| node_args.node_config(index)),
+    }));
+    if let Some(address) = args.metrics_address {
+        if let Err(e) = simulation.serve_metrics(address) {
+            Args::command()
+                .error(
+                    ErrorKind::Io,
+                    format!("can't serve metrics on {}: {}", address, e),
+                )
+                .exit()
+        }
+    }
+    let timeline = simulation.timeline().clone();
+    let threads = match &runtime {
+        Some(runtime) if runtime.is_stepped() => "one thread in simulated time".to_string(),
+        Some(runtime) => format!("{} worker threads", runtime.threads()),
+        None => "one thread each".to_string(),
+    };
+    let banner = args.banner(&threads);
+    println!("Simulating {}", banner);
+    if cfg!(debug_assertions) && number_nodes >= LARGE_CLUSTER {
+        println!(
+            "This is a debug build, too slow for {} nodes to keep up; run with --release for \
+             meaningful numbers",
+            number_nodes
         );
-        let _ = node.run();
     }
 
-    let is_alive_flags_shared = is_alive_flags.clone();
-    let _cancellation_thread = thread::spawn(move || {
-        thread::sleep(Duration::from_secs(KILL_NODES_AFTER_N_SECS));
+    // start inital nodes, leaving those of a startup ramp to a thread of their own
+    let memory_before = resident_memory();
+    let mut event_log: Option<Subscription> = None;
+    let mut ramp = vec![];
+    for (i, start_at) in args.start_offsets().into_iter().enumerate() {
+        let i = i as u64;
+        if !start_at.is_zero() {
+            ramp.push((i, start_at));
+            continue;
+        }
+        let Some(node) = simulation.start(i) else {
+            continue;
+        };
+        if event_log.is_none() {
+            event_log = Some(node.node.subscribe());
+        }
+    }
+
+    let writer = simulation.nodes().first().map(|node| node.handle.clone());
+    if let (Some(put_after), Some(writer)) = (args.put_value, writer) {
+        let timeline_shared = timeline.clone();
+        let _put_thread = timeline.time.spawn(move || {
+            timeline_shared.time.sleep(put_after);
+            if timeline_shared.stopping.load(Ordering::SeqCst) {
+                return;
+            }
+            if let Err(e) = writer.put(VALUE_KEY, "hello") {
+                error!(error = e.to_string(), "failed to put value");
+                return;
+            }
+            if let Ok(mut value_put) = timeline_shared.value_put.lock() {
+                *value_put = Some((VALUE_KEY.to_string(), timeline_shared.time.elapsed()));
+            }
+        });
+    }
 
-        {
-            let flags = is_alive_flags_shared.lock().unwrap();
-            let num_flags = flags.len();
+    if !ramp.is_empty() {
+        let simulation_shared = simulation.clone();
+        let _ramp_thread = timeline
+            .time
+            .spawn(move || simulation_shared.run_ramp(ramp));
+    }
+    let schedule = args.schedule();
+    let schedule_seed = args.derived_seed(SeedPurpose::Schedule, 0);
+    let simulation_shared = simulation.clone();
+    let _scheduler_thread = timeline.time.spawn(move || {
+        simulation_shared.run_schedule(&schedule, schedule_seed);
+    });
+    if let Some(mtbf) = args.churn_mtbf {
+        let churn = Churn {
+            mtbf,
+            downtime: args.churn_downtime,
+            after: args.churn_after,
+        };
+        let churn_seed = args.derived_seed(SeedPurpose::Churn, 0);
+        let simulation_shared = simulation.clone();
+        let _churn_thread = timeline
+            .time
+            .spawn(move || simulation_shared.run_churn(&churn, churn_seed));
+    }
+
+    // commands typed while the charts are shown, see Control
+    let (summary_requests, summary_requested) = mpsc::channel();
+    if !args.headless {
+        let controls_seed = args
+            .derived_seed(SeedPurpose::Controls, 0)
+            .unwrap_or_else(rand::random);
+        let simulation_shared = simulation.clone();
+        let _controls_thread = thread::spawn(move || {
+            run_controls(&simulation_shared, controls_seed, summary_requests);
+        });
+    }
+
+    let plotter = plot(
+        simulation.cluster().clone(),
+        event_log,
+        summary_requested,
+        PlotSettings {
+            header: banner,
+            suspect_timeout: args.suspect_timeout,
+            dead_timeout: args.dead_timeout,
+            clock: clock.clone(),
+            headless: args.headless,
+            faults: faults.clone(),
+            samples,
+            convergence_samples: args.convergence_samples,
+            sample_interval: args.sample_interval,
+            churn_from: args.churn_mtbf.map(|_| args.churn_after),
+            node_groups: args
+                .group_ranges()
+                .into_iter()
+                .map(|(group, range)| (group.name.clone(), range))
+                .collect(),
+            byzantine: args
+                .byzantine
+                .iter()
+                .flat_map(|nodes| nodes.0.iter().copied())
+                .collect(),
+            links: args.links(),
+            startup_ramp: args.start_rate.is_some() || args.start_jitter.is_some(),
+            chart_heartbeats_received: args.chart_heartbeats_received,
+        },
+        timeline.clone(),
+    );
+
+    // a run ends at --duration or ctrl-c, the nodes aren't supposed to give up before
+    let mut failures = vec![];
+    if !simulation.run_until(args.duration, interrupted) {
+        failures.push(format!(
+            "every node stopped after {}s, before the end of the run",
+            timeline.time.elapsed().as_secs()
+        ));
+    }
+
+    simulation.stop();
+    let report = timeline.time.join(plotter).unwrap_or_else(|_| {
+        error!("plotting thread panicked");
+        PlotReport::default()
+    });
+
+    let nodes = simulation.nodes();
+    let footprint = Footprint {
+        nodes: nodes.len(),
+        memory: resident_memory()
+            .zip(memory_before)
+            .map(|(after, before)| after.saturating_sub(before)),
+        elapsed: timeline.time.elapsed(),
+    };
+    simulation.shutdown();
 
-            let mut indices = (0..num_flags).collect::<Vec<_>>();
-            let mut rng = thread_rng();
-            indices.shuffle(&mut rng);
+    let total_stats: NodeStats = nodes.iter().map(|node| node.node.stats()).sum();
+    let network = Network {
+        faults: faults.as_deref(),
+        fabric: simulation
+            .config()
+            .memory_network
+            .as_ref()
+            .and_then(|network| network.fabric())
+            .map(Fabric::stats),
+    };
+    print_report(&report, &total_stats, &footprint, &timeline, &network);
+    if let Some(trace) = &trace {
+        let ids: Vec<NodeId> = nodes.iter().map(|node| node_id(node.index)).collect();
+        failures.extend(write_trace(trace, &ids, &args.trace_dot));
+    }
+    let outcome = RunOutcome::new(&report, &total_stats, &timeline);
+    failures.extend(check_assertions(
+        &args,
+        &outcome,
+        &timeline,
+        footprint.message_rate(&total_stats),
+    ));
+    if let Some(out) = out {
+        let path = out.with_extension("json");
+        let run_report = run_report(
+            &args,
+            &report,
+            &total_stats,
+            &footprint,
+            &timeline,
+            &network,
+            &failures,
+        );
+        let written = fs::File::create(&path).and_then(|file| {
+            let mut file = BufWriter::new(file);
+            serde_json::to_writer_pretty(&mut file, &run_report)?;
+            writeln!(file)?;
+            file.flush()
+        });
+        match written {
+            Ok(()) => println!(
+                "Samples written to {}, report to {}",
+                out.display(),
+                path.display()
+            ),
+            // whoever runs the simulation unattended is after this file
+            Err(e) => failures.push(format!(
+                "can't write the report to {}: {}",
+                path.display(),
+                e
+            )),
+        }
+    }
+    RunOutcome {
+        failures,
+        ..outcome
+    }
+}
 
-            for &index in &indices[0..NUMBER_NODES_TO_KILL] {
-                flags[index].store(false, Ordering::SeqCst);
+/// The assertions given with the --assert flags that the run broke, each saying how.
+fn check_assertions(
+    args: &Args,
+    outcome: &RunOutcome,
+    timeline: &Timeline,
+    message_rate: f64,
+) -> Vec<String> {
+    let mut failures = vec![];
+    if let Some(within) = args.assert_converged_within {
+        let converged = timeline
+            .converged_at
+            .lock()
+            .map(|converged_at| converged_at.len())
+            .unwrap_or_default();
+        match outcome.converged_after {
+            _ if converged < args.nodes as usize => failures.push(format!(
+                "only {} of {} nodes converged, expected all of them within {}",
+                converged,
+                args.nodes,
+                humantime::format_duration(within)
+            )),
+            Some(after) if after > within => failures.push(format!(
+                "the last node converged after {}s, expected within {}",
+                after.as_secs(),
+                humantime::format_duration(within)
+            )),
+            _ => {}
+        }
+        let events = timeline
+            .events
+            .lock()
+            .map(|events| events.clone())
+            .unwrap_or_default();
+        for (event, converged_after) in events.iter().zip(&outcome.event_convergence) {
+            match converged_after {
+                None => failures.push(format!(
+                    "the cluster didn't converge after [{}s] {}, expected within {}",
+                    event.at.as_secs(),
+                    event.description,
+                    humantime::format_duration(within)
+                )),
+                Some(after) if *after > within => failures.push(format!(
+                    "the cluster converged {:.1}s after [{}s] {}, expected within {}",
+                    after.as_secs_f64(),
+                    event.at.as_secs(),
+                    event.description,
+                    humantime::format_duration(within)
+                )),
+                _ => {}
             }
         }
+    }
+    if let Some(max) = args.assert_max_messages_per_node_per_sec {
+        if message_rate > max {
+            failures.push(format!(
+                "the nodes sent {:.1} datagrams per node and second, expected at most {}",
+                message_rate, max
+            ));
+        }
+    }
+    if let Some(latency) = args.assert_detection_latency {
+        let detected = timeline
+            .detected_at
+            .lock()
+            .map(|detected_at| detected_at.len())
+            .unwrap_or_default();
+        let observer = timeline
+            .detection_observer
+            .lock()
+            .ok()
+            .and_then(|observer| observer.clone());
+        match (observer, outcome.slowest_detection) {
+            (None, _) => failures.push(format!(
+                "no kill was watched to the end, expected its victims detected within {}",
+                humantime::format_duration(latency)
+            )),
+            (Some((observer, victims)), _) if detected < victims => failures.push(format!(
+                "node {} detected {} of {} killed nodes, expected all of them within {}",
+                observer,
+                detected,
+                victims,
+                humantime::format_duration(latency)
+            )),
+            (Some((observer, _)), Some(slowest)) if slowest > latency => failures.push(format!(
+                "node {} took {}s to detect a killed node, expected at most {}",
+                observer,
+                slowest.as_secs(),
+                humantime::format_duration(latency)
+            )),
+            _ => {}
+        }
+    }
+    failures
+}
+
+/// Exit code of the process: failure if anything went wrong in any run, after saying what.
+fn exit_code(failures: &[String]) -> ExitCode {
+    if failures.is_empty() {
+        return ExitCode::SUCCESS;
+    }
+    eprintln!();
+    eprintln!("Failed:");
+    for failure in failures {
+        eprintln!("  {}", failure);
+    }
+    ExitCode::FAILURE
+}
 
-        thread::sleep(Duration::from_secs(START_ALL_NODES_AFTER_N_SECS));
+/// What the nodes of a run cost the machine, taken before they are stopped.
+struct Footprint {
+    nodes: usize,
+    /// resident memory the process grew by since before the first node started, unknown
+    /// without /proc
+    memory: Option<u64>,
+    elapsed: Duration,
+}
 
-        {
-            let flags = is_alive_flags_shared
+impl Footprint {
+    fn memory_per_node(&self) -> Option<u64> {
+        self.memory
+            .filter(|_| self.nodes > 0)
+            .map(|memory| memory / self.nodes as u64)
+    }
+
+    /// Datagrams sent per node and second, over the whole run.
+    fn message_rate(&self, total_stats: &NodeStats) -> f64 {
+        if self.nodes == 0 || self.elapsed.is_zero() {
+            return 0.0;
+        }
+        total_stats.messages_sent() as f64 / self.nodes as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// KiB written per node and second, over the whole run.
+    fn bandwidth(&self, total_stats: &NodeStats) -> f64 {
+        if self.nodes == 0 || self.elapsed.is_zero() {
+            return 0.0;
+        }
+        total_stats.bytes_sent as f64 / 1024.0 / self.nodes as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// What the simulated network did to the datagrams of a run.
+struct Network<'a> {
+    faults: Option<&'a FaultInjector>,
+    /// the links of the in-memory network, if they have a bandwidth
+    fabric: Option<FabricStats>,
+}
+
+/// Resident memory of the process in bytes, read from /proc on linux.
+fn resident_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// The headline numbers of a run, compared between the runs of a sweep.
+struct RunOutcome {
+    seed: Option<u64>,
+    messages_sent: u64,
+    /// when the last node first saw every other node with a fresh heartbeat
+    converged_after: Option<Duration>,
+    /// how long the cluster took to converge after each scenario event, unset if it didn't
+    event_convergence: Vec<Option<Duration>>,
+    /// the longest it took to see a victim of the first kill dead
+    slowest_detection: Option<Duration>,
+    /// why the run counts as failed, empty if it doesn't
+    failures: Vec<String>,
+}
+
+impl RunOutcome {
+    fn new(report: &PlotReport, total_stats: &NodeStats, timeline: &Timeline) -> Self {
+        RunOutcome {
+            seed: None,
+            messages_sent: total_stats.messages_sent(),
+            converged_after: timeline
+                .converged_at
+                .lock()
+                .ok()
+                .and_then(|converged_at| converged_at.last().map(|(_, after)| *after)),
+            event_convergence: report
+                .event_convergence
+                .iter()
+                .map(|convergence| convergence.converged_after)
+                .collect(),
+            slowest_detection: timeline
+                .detected_at
                 .lock()
-                .expect("failed to get is_alive_flags");
-            for flag in flags.iter() {
-                flag.store(true, Ordering::SeqCst);
+                .ok()
+                .and_then(|detected_at| detected_at.iter().map(|(_, after)| *after).max()),
+            failures: vec![],
+        }
+    }
+
+    /// Whether the cluster didn't converge again after one of the events.
+    fn timed_out(&self) -> bool {
+        self.event_convergence.iter().any(Option::is_none)
+    }
+
+    /// The slowest convergence after an event, unset without events.
+    fn slowest_event_convergence(&self) -> Option<Duration> {
+        self.event_convergence.iter().flatten().max().copied()
+    }
+}
+
+/// Runs the simulation once for every combination of the swept settings, as many times as
+/// asked, one run after the other, and compares the runs in tables. Stops early on ctrl-c.
+/// Returns the failures of all runs, each prefixed with its run.
+fn run_batch(args: &Args, interrupted: &AtomicBool) -> Vec<String> {
+    let combinations = if args.sweep.is_empty() {
+        vec![("-".to_string(), args.clone())]
+    } else {
+        sweep_args(&args.sweep).unwrap_or_else(|e| {
+            Args::command()
+                .error(ErrorKind::ValueValidation, format!("--sweep: {}", e))
+                .exit()
+        })
+    };
+    let number_runs = combinations.len() * args.repeat;
+    // the outcomes of the runs of every combination
+    let mut outcomes: Vec<(String, Vec<RunOutcome>)> = vec![];
+    'runs: for (label, combination_args) in combinations {
+        if let Err(e) = combination_args.validate() {
+            Args::command()
+                .error(ErrorKind::ValueValidation, format!("{}: {}", label, e))
+                .exit();
+        }
+        let mut runs = vec![];
+        for _ in 0..args.repeat {
+            if interrupted.load(Ordering::SeqCst) {
+                outcomes.push((label, runs));
+                break 'runs;
+            }
+            let index = outcomes.iter().map(|(_, runs)| runs.len()).sum::<usize>() + runs.len();
+            let mut run_args = combination_args.clone();
+            // every run draws from its own seed, derived from the master seed of the batch
+            run_args.seed = args.derived_seed(SeedPurpose::Run, index as u64);
+            run_args.headless = true;
+            println!();
+            println!(
+                "Run {} of {}{}",
+                index + 1,
+                number_runs,
+                if args.sweep.is_empty() {
+                    String::new()
+                } else {
+                    format!(": {}", label)
+                }
+            );
+            let out = args
+                .out
+                .as_deref()
+                .map(|path| suffixed(path, &(index + 1).to_string()));
+            let mut outcome = simulate(run_args.clone(), out.as_deref(), interrupted);
+            outcome.seed = run_args.seed;
+            runs.push(outcome);
+        }
+        outcomes.push((label, runs));
+    }
+
+    let mut failures: Vec<String> = outcomes
+        .iter()
+        .flat_map(|(_, runs)| runs)
+        .enumerate()
+        .flat_map(|(index, outcome)| {
+            outcome
+                .failures
+                .iter()
+                .map(move |failure| format!("run {}: {}", index + 1, failure))
+        })
+        .collect();
+    let seconds = |duration: Option<Duration>| {
+        duration.map_or("-".to_string(), |d| format!("{:.1}", d.as_secs_f64()))
+    };
+    let mut run_rows = vec![];
+    for (label, runs) in &outcomes {
+        for (repetition, outcome) in runs.iter().enumerate() {
+            run_rows.push(vec![
+                label.clone(),
+                (repetition + 1).to_string(),
+                outcome
+                    .seed
+                    .map_or("-".to_string(), |seed| seed.to_string()),
+                outcome.messages_sent.to_string(),
+                seconds(outcome.converged_after),
+                match outcome.slowest_event_convergence() {
+                    _ if outcome.timed_out() => "timeout".to_string(),
+                    slowest => seconds(slowest),
+                },
+                seconds(outcome.slowest_detection),
+            ]);
+        }
+    }
+    let run_header = [
+        "settings",
+        "run",
+        "seed",
+        "messages",
+        "converged_s",
+        "event_convergence_s",
+        "slowest_detection_s",
+    ];
+    println!();
+    println!("Runs:");
+    print_table(&run_header, &run_rows);
+
+    let mut statistics_rows = vec![];
+    for (label, runs) in &outcomes {
+        let secs = |duration: Duration| duration.as_secs_f64();
+        let metrics: [(&str, Vec<f64>); 4] = [
+            (
+                "messages",
+                runs.iter().map(|run| run.messages_sent as f64).collect(),
+            ),
+            (
+                "converged_s",
+                runs.iter()
+                    .filter_map(|run| run.converged_after)
+                    .map(secs)
+                    .collect(),
+            ),
+            (
+                "event_convergence_s",
+                runs.iter()
+                    .filter(|run| !run.timed_out())
+                    .filter_map(RunOutcome::slowest_event_convergence)
+                    .map(secs)
+                    .collect(),
+            ),
+            (
+                "slowest_detection_s",
+                runs.iter()
+                    .filter_map(|run| run.slowest_detection)
+                    .map(secs)
+                    .collect(),
+            ),
+        ];
+        for (metric, values) in metrics {
+            let Some(statistics) = Statistics::of(values) else {
+                continue;
+            };
+            statistics_rows.push(vec![
+                label.clone(),
+                metric.to_string(),
+                statistics.runs.to_string(),
+                format!("{:.1}", statistics.mean),
+                format!("{:.1}", statistics.stddev),
+                format!("{:.1}", statistics.p50),
+                format!("{:.1}", statistics.p95),
+            ]);
+        }
+    }
+    let statistics_header = ["settings", "metric", "runs", "mean", "stddev", "p50", "p95"];
+    if args.repeat > 1 {
+        println!();
+        println!("Statistics over the runs of each setting, runs without a value left out:");
+        print_table(&statistics_header, &statistics_rows);
+    }
+
+    if let Some(path) = &args.out {
+        let mut tables = vec![("runs", &run_header[..], &run_rows)];
+        if args.repeat > 1 {
+            tables.push(("statistics", &statistics_header[..], &statistics_rows));
+        }
+        for (suffix, header, rows) in tables {
+            let path = suffixed(path, suffix);
+            let mut table = header.join(",") + "\n";
+            for row in rows {
+                table += &row.join(",");
+                table += "\n";
+            }
+            if let Err(e) = fs::write(&path, table) {
+                failures.push(format!("can't write {}: {}", path.display(), e));
+            }
+        }
+    }
+    failures
+}
+
+/// Prints rows below a header, every column as wide as its widest cell.
+fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].len())
+                .chain([header[column].len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let header: Vec<String> = header.iter().map(|cell| cell.to_string()).collect();
+    for row in [&header].into_iter().chain(rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("  {}", cells.join("  ").trim_end());
+    }
+}
+
+/// How a metric varied over the runs of a batch.
+struct Statistics {
+    runs: usize,
+    mean: f64,
+    stddev: f64,
+    p50: f64,
+    p95: f64,
+}
+
+impl Statistics {
+    /// The statistics of `values`, unset if there are none.
+    fn of(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        let runs = values.len();
+        let mean = values.iter().sum::<f64>() / runs as f64;
+        let variance = values
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f64>()
+            / runs as f64;
+        Some(Statistics {
+            runs,
+            mean,
+            stddev: variance.sqrt(),
+            p50: values[runs / 2],
+            p95: values[(runs * 95 / 100).min(runs - 1)],
+        })
+    }
+}
+
+/// `path` with `suffix` appended to its file stem, e.g. run-2.csv for run.csv.
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, suffix, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, suffix),
+    };
+    path.with_file_name(name)
+}
+
+fn mean_duration(durations: &[Duration]) -> Option<Duration> {
+    let count = u32::try_from(durations.len())
+        .ok()
+        .filter(|count| *count > 0)?;
+    Some(durations.iter().sum::<Duration>() / count)
+}
+
+/// The report of a run, written next to the samples file, see [`muck::report`].
+fn run_report(
+    args: &Args,
+    report: &PlotReport,
+    total_stats: &NodeStats,
+    footprint: &Footprint,
+    timeline: &Timeline,
+    network: &Network,
+    failures: &[String],
+) -> RunReport {
+    let faults = network.faults;
+    let seconds = |duration: Duration| duration.as_secs_f64();
+    let events = timeline
+        .events
+        .lock()
+        .map(|events| {
+            events
+                .iter()
+                .enumerate()
+                .map(|(index, event)| {
+                    let converged_after = report
+                        .event_convergence
+                        .get(index)
+                        .and_then(|convergence| convergence.converged_after);
+                    EventEntry {
+                        at: seconds(event.at),
+                        description: event.description.clone(),
+                        nodes: event.nodes.clone(),
+                        converged_after: converged_after.map(seconds),
+                        timed_out: converged_after.is_none(),
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let node_times = |times: &[(String, Duration)]| -> Vec<NodeTime> {
+        times
+            .iter()
+            .map(|(id, after)| NodeTime {
+                node: id.clone(),
+                after: seconds(*after),
+            })
+            .collect()
+    };
+    let converged = timeline
+        .converged_at
+        .lock()
+        .map(|converged_at| node_times(&converged_at))
+        .unwrap_or_default();
+    let detection = match (
+        timeline.detection_observer.lock().as_deref(),
+        timeline.detected_at.lock(),
+    ) {
+        (Ok(Some((observer, number_victims))), Ok(detected_at)) => Some(Detection {
+            observer: observer.clone(),
+            victims: *number_victims,
+            detected: node_times(&detected_at),
+        }),
+        _ => None,
+    };
+    let delays = &report.propagation_delays;
+    let propagation_delays = delays.last().map(|max| Percentiles {
+        median: delays[delays.len() / 2],
+        p90: delays[delays.len() * 9 / 10],
+        max: *max,
+    });
+    let links = report
+        .links
+        .iter()
+        .map(|link| {
+            let (from, to) = (
+                node_address(PORT_BASE + link.from),
+                node_address(PORT_BASE + link.to),
+            );
+            let stats = faults
+                .and_then(|faults| {
+                    faults
+                        .links()
+                        .into_iter()
+                        .find(|(sender, receiver, _)| (*sender, *receiver) == (from, to))
+                })
+                .map(|(_, _, stats)| stats)
+                .unwrap_or_default();
+            LinkResults {
+                from: link.from,
+                to: link.to,
+                sent: stats.sent,
+                delivered: stats.delivered,
+                delivery_rate: stats.delivery_rate(),
+                samples: link.samples,
+                seen_alive: link.seen_alive,
+                final_state: link.final_state,
             }
+        })
+        .collect();
+    let faults = faults.map(|faults| {
+        let stats = faults.stats();
+        FaultTotals {
+            datagrams: stats.datagrams,
+            dropped: stats.dropped,
+            partitioned: stats.partitioned,
+            delayed: stats.delayed,
+            mean_delay: seconds(stats.mean_delay()),
+            duplicated: stats.duplicated,
+            reordered: stats.reordered,
+            forged_timestamps: stats.forged_timestamps,
+            phantoms: stats.phantoms,
+            replayed: stats.replayed,
+            flipped: stats.flipped,
+        }
+    });
+    let fabric = network.fabric.map(|stats| FabricTotals {
+        datagrams: stats.datagrams,
+        queued: stats.queued,
+        tail_dropped: stats.tail_dropped,
+        mean_queueing: seconds(stats.mean_queueing()),
+    });
+    let byzantine = report.byzantine.as_ref().map(|byzantine| {
+        let honest = &byzantine.honest_stats;
+        ByzantineResults {
+            mean_correct_views: byzantine.mean_correct_views,
+            final_honest: byzantine.final_honest,
+            final_correct_views: byzantine.final_correct_views,
+            peak_phantoms: byzantine.peak_phantoms,
+            final_phantoms: byzantine.final_phantoms,
+            decode_errors: honest.decode_errors,
+            future_timestamps: honest.future_timestamps,
+            stale_heartbeats: honest.stale_heartbeats,
+            addresses_rejected: honest.addresses_rejected,
+            entries_evicted: honest.entries_evicted,
         }
     });
+    let partitions = report
+        .partitions
+        .iter()
+        .map(|partition| PartitionResults {
+            group_sizes: partition.group_sizes.clone(),
+            lowest_group_informed: partition.lowest_group_informed.clone(),
+            reconverged_after: partition.reconverged_after.map(seconds),
+        })
+        .collect();
+    let churn = match (&report.churn, timeline.churn.lock()) {
+        (Some(churn), Ok(tally)) => Some(ChurnResults {
+            kills: tally.kills,
+            revivals: tally.revivals,
+            mean_alive: churn.mean_alive,
+            mean_correct_views: churn.mean_correct_views,
+            mean_fully_informed: churn.mean_fully_informed,
+            messages_per_node: churn.messages_per_node,
+            detections: tally.detections.len(),
+            mean_detection: mean_duration(&tally.detections).map(seconds),
+            revived_undetected: tally.revived_undetected,
+        }),
+        _ => None,
+    };
+    let groups = report
+        .node_groups
+        .iter()
+        .map(|group| GroupResults {
+            name: group.name.clone(),
+            nodes: group.nodes,
+            peak_fully_informed: group.peak_fully_informed,
+            final_fully_informed: group.final_fully_informed,
+            messages_sent: group.messages_sent,
+            peak_falsely_suspected: group.peak_falsely_suspected,
+        })
+        .collect();
+    let newcomers = report
+        .newcomers
+        .iter()
+        .map(|newcomer| NewcomerResults {
+            node: newcomer.index,
+            added_at: seconds(newcomer.added_at),
+            informed_after: newcomer.informed_after.map(seconds),
+        })
+        .collect();
+    RunReport {
+        config: args.settings(),
+        passed: failures.is_empty(),
+        failures: failures.to_vec(),
+        events,
+        convergence: report
+            .convergence
+            .iter()
+            .map(ConvergenceEntry::from)
+            .collect(),
+        samples: report.samples.clone(),
+        membership_events: report.membership_events.clone(),
+        nodes: report.nodes.clone(),
+        results: Results {
+            duration: seconds(timeline.time.elapsed()),
+            peak_fully_informed: report.peak_fully_informed,
+            final_fully_informed: report.final_fully_informed,
+            messages_sent: total_stats.messages_sent(),
+            heartbeats_sent: total_stats.heartbeats_sent,
+            gossip_forwarded: total_stats.gossip_forwarded,
+            duplicates_received: total_stats.duplicates_received,
+            duplicate_forwards: total_stats.duplicate_forwards,
+            messages_per_node_second: footprint.message_rate(total_stats),
+            bytes_sent: total_stats.bytes_sent,
+            bytes_received: total_stats.bytes_received,
+            kib_per_node_second: footprint.bandwidth(total_stats),
+            memory: footprint.memory,
+            memory_per_node: footprint.memory_per_node(),
+            converged,
+            detection,
+            propagation_delays,
+            propagation_latency: LatencyDistribution::of(&report.propagation_latency),
+            value_readable_after: report.value_readable_after.map(seconds),
+            final_value_readable: report.final_value_readable,
+            reconverged_after: report.reconverged_after.map(seconds),
+            faults,
+            fabric,
+            byzantine,
+            links,
+            partitions,
+            churn,
+            groups,
+            newcomers,
+        },
+    }
+}
 
-    let is_alive_flags_shared = is_alive_flags.clone();
-    plot(
-        &all_shared_storages,
-        is_alive_flags_shared,
-        NUMBER_NODES,
-        HEALTHY_THRESHOLD_SECS,
-        HEARTBEAT_INTERVAL_SECS,
+/// Runs the node given on the command line until the process is interrupted or the node stops.
+/// Stopping the node saves its view to the snapshot file, if any, but a node killed with SIGKILL
+/// has to make do with the view it saved last.
+fn run_node(args: &NodeArgs, interrupted: &AtomicBool) -> ExitCode {
+    let config = match args.config() {
+        Ok(config) => config,
+        Err(e) => NodeArgs::command()
+            .error(ErrorKind::ValueValidation, e.to_string())
+            .exit(),
+    };
+    let seed_nodes = args
+        .seed
+        .iter()
+        .map(|seed| (seed.id.clone(), seed.address))
+        .collect();
+    let storage =
+        gossip::setup_storage(config.id.clone(), config.address, seed_nodes, &SystemClock);
+    let Some(node) = create_node(config, storage, None) else {
+        error!(node_id = args.id.to_string(), "giving up on node");
+        return ExitCode::FAILURE;
+    };
+    let handle = match node.run() {
+        Ok(handle) => handle,
+        Err(e) => {
+            error!(error = e.to_string(), "failed to run node");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    while !interrupted.load(Ordering::SeqCst) && handle.is_running() {
+        sleep(STOP_POLL_INTERVAL);
+    }
+    match handle.shutdown() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!(error = e.to_string(), "node exited with an error");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs every node as a process of its own until the run is interrupted, its duration is up or
+/// no node process is left, then prints a report. Kill events kill the processes with SIGKILL
+/// and revive events start them again, restoring the view the node saved last. The orchestrator
+/// only learns about the cluster from the nodes' admin queries, every --sample-interval. Returns
+/// why the run failed, if it did.
+fn orchestrate(args: &Args, interrupted: &AtomicBool) -> Vec<String> {
+    let program = match std::env::current_exe() {
+        Ok(program) => program,
+        Err(e) => Args::command()
+            .error(ErrorKind::Io, format!("can't find the muck binary: {}", e))
+            .exit(),
+    };
+    let snapshots = std::env::temp_dir().join(format!("muck-{}", process::id()));
+    if let Err(e) = fs::create_dir_all(&snapshots) {
+        Args::command()
+            .error(
+                ErrorKind::Io,
+                format!("can't create {}: {}", snapshots.display(), e),
+            )
+            .exit()
+    }
+    let spawn = |index: u64| spawn_node_process(args, &program, &snapshots, index);
+
+    println!("{}", args.banner("a process each"));
+    let started = Instant::now();
+    let mut processes: Vec<NodeProcess> = (0..args.nodes).filter_map(spawn).collect();
+    let schedule = args.schedule();
+    let mut rng = match args.derived_seed(SeedPurpose::Schedule, 0) {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    // indexes of the nodes killed and not revived yet
+    let mut killed: Vec<u64> = vec![];
+    let mut executed = 0;
+    let mut events: Vec<EventRecord> = vec![];
+    let mut event_convergence: Vec<EventConvergence> = vec![];
+    let mut tally = ProcessTally {
+        started: processes.len(),
+        ..Default::default()
+    };
+    let mut next_sample = started + args.sample_interval;
+
+    while !interrupted.load(Ordering::SeqCst)
+        && args
+            .duration
+            .is_none_or(|duration| started.elapsed() < duration)
+        && processes.iter().any(NodeProcess::is_running)
+    {
+        while let Some(event) = schedule
+            .get(executed)
+            .filter(|event| started.elapsed() >= event.at)
+        {
+            executed += 1;
+            let (outcome, nodes) = match &event.action {
+                Action::Kill { nodes: selection } => {
+                    let candidates = processes
+                        .iter()
+                        .filter(|process| process.is_running() && !killed.contains(&process.index))
+                        .map(|process| process.index)
+                        .collect();
+                    let victims = select_indexes(selection, candidates, args.seeds, &mut rng);
+                    for process in &mut processes {
+                        if !victims.contains(&process.index) {
+                            continue;
+                        }
+                        if let Err(e) = process.kill() {
+                            error!(
+                                node_id = process.index,
+                                error = e.to_string(),
+                                "failed to kill node process"
+                            );
+                        }
+                        tally.killed += 1;
+                    }
+                    killed.extend(&victims);
+                    (format!("killed {}", describe_indexes(&victims)), victims)
+                }
+                Action::Revive { nodes: selection } => {
+                    let revived = select_indexes(selection, killed.clone(), args.seeds, &mut rng);
+                    for process in &mut processes {
+                        if !revived.contains(&process.index) {
+                            continue;
+                        }
+                        match process.spawn() {
+                            Ok(()) => tally.started += 1,
+                            Err(e) => error!(
+                                node_id = process.index,
+                                error = e.to_string(),
+                                "failed to start node process"
+                            ),
+                        }
+                    }
+                    killed.retain(|index| !revived.contains(index));
+                    (format!("revived {}", describe_indexes(&revived)), revived)
+                }
+                Action::AddNodes { count } => {
+                    let first = processes.len() as u64;
+                    let mut added = vec![];
+                    for index in first..first + *count as u64 {
+                        if PORT_BASE + index > u64::from(u16::MAX) {
+                            error!(node_id = index, "no port left for the node, skipping it");
+                            break;
+                        }
+                        if let Some(process) = spawn(index) {
+                            processes.push(process);
+                            added.push(index);
+                            tally.started += 1;
+                        }
+                    }
+                    (describe_added(&added), added)
+                }
+                _ => (
+                    "skipped, not supported with --processes".to_string(),
+                    vec![],
+                ),
+            };
+            let record = EventRecord {
+                at: started.elapsed(),
+                description: format!("{}: {}", event, outcome),
+                nodes,
+            };
+            println!("[{}s] {}", record.at.as_secs(), record.description);
+            events.push(record);
+        }
+
+        if Instant::now() < next_sample {
+            sleep(STOP_POLL_INTERVAL.min(next_sample.saturating_duration_since(Instant::now())));
+            continue;
+        }
+        next_sample += args.sample_interval;
+        let at = started.elapsed();
+        for process in &mut processes {
+            if let Err(status) = process.poll() {
+                println!(
+                    "[{}s] node {} exited on its own, {}",
+                    at.as_secs(),
+                    process.index,
+                    status
+                );
+                tally.exited += 1;
+            }
+        }
+        let sample = sample_processes(&mut processes);
+        tally.peak_fully_informed = tally.peak_fully_informed.max(sample.fully_informed);
+        tally.converged_at = tally.converged_at.or(sample.converged().then_some(at));
+        event_convergence.resize_with(events.len(), EventConvergence::default);
+        for (convergence, event) in event_convergence.iter_mut().zip(&events) {
+            convergence.track(event.at, at, sample.converged(), args.convergence_samples);
+        }
+        if at.as_secs().is_multiple_of(PROGRESS_INTERVAL.as_secs()) {
+            println!(
+                "[{}s] {} of {} nodes running, {} fully informed, {} with a correct view, {} \
+                 unreachable, {} datagrams sent",
+                at.as_secs(),
+                sample.running,
+                processes.len(),
+                sample.fully_informed,
+                sample.correct_views,
+                sample.unreachable,
+                sample.messages_sent
+            );
+        }
+        tally.last = sample;
+    }
+
+    let mut failures = vec![];
+    if !processes.iter().any(NodeProcess::is_running) {
+        failures.push(format!(
+            "every node process stopped after {}s, before the end of the run",
+            started.elapsed().as_secs()
+        ));
+    }
+
+    for process in &mut processes {
+        if let Err(e) = process.kill() {
+            error!(
+                node_id = process.index,
+                error = e.to_string(),
+                "failed to kill node process"
+            );
+        }
+    }
+    if let Err(e) = fs::remove_dir_all(&snapshots) {
+        error!(
+            path = snapshots.display().to_string(),
+            error = e.to_string(),
+            "failed to remove the snapshots"
+        );
+    }
+
+    let last = &tally.last;
+    println!();
+    println!("Simulation stopped after {}s", started.elapsed().as_secs());
+    println!(
+        "Fully informed nodes: peak {}, final {}",
+        tally.peak_fully_informed, last.fully_informed
+    );
+    println!(
+        "Correct views: {} of {} running nodes, {} unreachable",
+        last.correct_views, last.running, last.unreachable
+    );
+    println!("Messages sent: {}", last.messages_sent);
+    match tally.converged_at {
+        Some(at) => println!(
+            "Every running node first had a correct view after {}s",
+            at.as_secs()
+        ),
+        None => println!("The running nodes never all had a correct view"),
+    }
+    println!(
+        "Node processes: {} started, {} killed, {} exited on their own",
+        tally.started, tally.killed, tally.exited
     );
+    if !events.is_empty() {
+        println!("Scenario events:");
+    }
+    for (index, event) in events.iter().enumerate() {
+        let converged = match event_convergence
+            .get(index)
+            .and_then(|convergence| convergence.converged_after)
+        {
+            Some(after) => format!("converged after {:.1}s", after.as_secs_f64()),
+            None => "no convergence before the end".to_string(),
+        };
+        println!(
+            "  [{:.3}s] {}; {}",
+            event.at.as_secs_f64(),
+            event.description,
+            converged
+        );
+    }
+    if tally.exited > 0 {
+        failures.push(format!(
+            "{} node processes exited on their own",
+            tally.exited
+        ));
+    }
+    failures
+}
+
+/// What the orchestrator counted over a run with --processes.
+#[derive(Default)]
+struct ProcessTally {
+    started: usize,
+    killed: usize,
+    exited: usize,
+    peak_fully_informed: usize,
+    /// when every running node first had a correct view
+    converged_at: Option<Duration>,
+    last: ProcessSample,
+}
+
+/// The cluster as the running node processes describe it.
+#[derive(Default)]
+struct ProcessSample {
+    running: usize,
+    /// running nodes that didn't answer the admin queries
+    unreachable: usize,
+    /// nodes that see every node as alive
+    fully_informed: usize,
+    /// nodes that see exactly the running nodes as alive
+    correct_views: usize,
+    /// by every process of every node, as far as they told
+    messages_sent: u64,
+}
+
+impl ProcessSample {
+    fn converged(&self) -> bool {
+        self.running > 0 && self.correct_views == self.running
+    }
+}
+
+/// Asks every running node for its members and counters, all at once since nodes that were just
+/// started or are overloaded take a while to answer.
+fn sample_processes(processes: &mut [NodeProcess]) -> ProcessSample {
+    let number_nodes = processes.len();
+    let running: HashSet<NodeId> = processes
+        .iter()
+        .filter(|process| process.is_running())
+        .map(|process| node_id(process.index))
+        .collect();
+    let views: Vec<Option<HashSet<NodeId>>> = thread::scope(|scope| {
+        let queries: Vec<_> = processes
+            .iter_mut()
+            .filter(|process| process.is_running())
+            .map(|process| {
+                scope.spawn(move || {
+                    let members = process.members(QUERY_TIMEOUT).ok()?;
+                    if let Err(e) = process.refresh_stats(QUERY_TIMEOUT) {
+                        error!(
+                            node_id = process.index,
+                            error = e.to_string(),
+                            "stats query failed"
+                        );
+                    }
+                    Some(
+                        members
+                            .into_iter()
+                            .filter(|member| member.state == PeerState::Alive)
+                            .map(|member| member.id)
+                            .collect(),
+                    )
+                })
+            })
+            .collect();
+        queries
+            .into_iter()
+            .map(|query| query.join().ok().flatten())
+            .collect()
+    });
 
-    loop {
-        sleep(time::Duration::from_secs(5));
+    let alive_views: Vec<&HashSet<NodeId>> = views.iter().flatten().collect();
+    ProcessSample {
+        running: running.len(),
+        unreachable: views.len() - alive_views.len(),
+        fully_informed: alive_views
+            .iter()
+            .filter(|alive| alive.len() == number_nodes)
+            .count(),
+        correct_views: alive_views
+            .iter()
+            .filter(|alive| ***alive == running)
+            .count(),
+        messages_sent: processes.iter().map(NodeProcess::messages_sent).sum(),
     }
 }
 
-fn plot(
-    all_shared_storages: &HashMap<String, Arc<Mutex<gossip::Storage>>>,
-    is_alive_flags: Arc<Mutex<Vec<Arc<AtomicBool>>>>,
-    number_nodes: u64,
-    healthy_threshold_secs: u64,
-    hearthbeat_interval_secs: u64,
+/// Starts the node with the given index as a process of its own, None if it couldn't be
+/// started.
+fn spawn_node_process(
+    args: &Args,
+    program: &Path,
+    snapshots: &Path,
+    index: u64,
+) -> Option<NodeProcess> {
+    let config = match args.node_config(index) {
+        Ok(config) => config,
+        Err(e) => {
+            error!(
+                node_id = index,
+                error = e.to_string(),
+                "invalid node config"
+            );
+            return None;
+        }
+    };
+    let seed_nodes: Vec<(NodeId, SocketAddr)> = (0..args.seeds)
+        .map(|seed| (node_id(seed), node_address(PORT_BASE + seed)))
+        .collect();
+    let snapshot = snapshots.join(format!("node-{}.json", index));
+    let mut command = process::Command::new(program);
+    command
+        .arg("node")
+        .args(NodeArgs::flags(&config, &seed_nodes, &snapshot));
+    let mut process = NodeProcess::new(index, config.address, command);
+    match process.spawn() {
+        Ok(()) => Some(process),
+        Err(e) => {
+            error!(
+                node_id = index,
+                error = e.to_string(),
+                "failed to start node process"
+            );
+            None
+        }
+    }
+}
+
+/// A command typed while the charts are shown: a key, for some a value after it, and enter.
+#[derive(Debug, Clone, PartialEq)]
+enum Control {
+    /// enter alone prints a summary of the first node below the charts
+    Summary,
+    /// f stops redrawing the charts, or starts again; sampling goes on either way
+    ToggleDisplay,
+    /// k kills a random running node, k N kills node N
+    Kill(Option<u64>),
+    /// r revives every killed node
+    ReviveAll,
+    /// s splits the nodes into two random halves, s 30s for 30 seconds only
+    Split(Option<Duration>),
+    /// d 1.5 changes the decay factor of every node
+    Decay(f64),
+    /// + adds a node, +N adds N of them
+    AddNodes(usize),
+}
+
+impl FromStr for Control {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        let mut chars = line.chars();
+        let Some(key) = chars.next() else {
+            return Ok(Control::Summary);
+        };
+        let value = chars.as_str().trim();
+        match (key, value) {
+            ('f', "") => Ok(Control::ToggleDisplay),
+            ('k', "") => Ok(Control::Kill(None)),
+            ('k', node) => node
+                .parse()
+                .map(|node| Control::Kill(Some(node)))
+                .map_err(|_| format!("{:?} is not a node index", node)),
+            ('r', "") => Ok(Control::ReviveAll),
+            ('s', "") => Ok(Control::Split(None)),
+            ('s', duration) => humantime::parse_duration(duration)
+                .map(|duration| Control::Split(Some(duration)))
+                .map_err(|e| format!("{:?} is not a duration: {}", duration, e)),
+            ('d', decay) => match decay.parse::<f64>() {
+                Ok(decay) if decay.is_finite() && decay > 0.0 => Ok(Control::Decay(decay)),
+                _ => Err(format!("{:?} is not a decay factor above 0", decay)),
+            },
+            ('+', "") => Ok(Control::AddNodes(1)),
+            ('+', count) => count
+                .parse()
+                .map(Control::AddNodes)
+                .map_err(|_| format!("{:?} is not a number of nodes", count)),
+            _ => Err(format!("unknown command {:?}", line)),
+        }
+    }
+}
+
+/// Carries out the commands typed on stdin while the charts are shown, until stdin closes. The
+/// ones acting on the cluster run like scheduled events and are recorded with them, the outcome
+/// of the latest command shows in the status line above the charts.
+///
+/// Commands are read a line at a time rather than key by key: a terminal in raw mode would stay
+/// that way if the simulation ended while waiting for a key.
+fn run_controls(simulation: &Simulation, seed: u64, summary_requests: mpsc::Sender<()>) {
+    let timeline = simulation.timeline();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let set_status = |status: String| {
+        *timeline
+            .control_status
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(status);
+    };
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let control = match line.parse::<Control>() {
+            Ok(control) => control,
+            Err(e) => {
+                set_status(e);
+                continue;
+            }
+        };
+        let action = match control {
+            Control::Summary => {
+                if summary_requests.send(()).is_err() {
+                    break;
+                }
+                continue;
+            }
+            Control::ToggleDisplay => {
+                let frozen = !timeline.display_frozen.fetch_xor(true, Ordering::Relaxed);
+                set_status(if frozen {
+                    "charts frozen, f and enter to redraw them".to_string()
+                } else {
+                    "charts redrawn again".to_string()
+                });
+                continue;
+            }
+            Control::Kill(node) => Action::Kill {
+                nodes: match node {
+                    Some(node) => NodeSelection::Nodes(vec![node]),
+                    None => NodeSelection::Random(1),
+                },
+            },
+            Control::ReviveAll => Action::Revive {
+                nodes: NodeSelection::All,
+            },
+            Control::Split(duration) => {
+                let mut nodes: Vec<u64> =
+                    simulation.nodes().iter().map(|node| node.index).collect();
+                nodes.shuffle(&mut rng);
+                let mut other_half = nodes.split_off(nodes.len() / 2);
+                nodes.sort_unstable();
+                other_half.sort_unstable();
+                Action::Partition {
+                    groups: vec![nodes, other_half],
+                    duration,
+                }
+            }
+            Control::Decay(decay) => Action::Config {
+                nodes: NodeSelection::All,
+                update: gossip::ConfigUpdate {
+                    decay_factor: Some(decay),
+                    ..Default::default()
+                },
+            },
+            Control::AddNodes(count) => Action::AddNodes { count },
+        };
+        let event = ScheduledEvent {
+            at: Duration::from_secs(timeline.time.elapsed().as_secs()),
+            action,
+        };
+        let (outcome, _) = simulation.inject(&event, &mut rng);
+        set_status(format!("{}: {}", event, outcome));
+    }
+}
+
+/// Prints how far the traced heartbeat spread and writes its dissemination graph to `path`.
+/// Returns why the graph couldn't be written, if it couldn't.
+fn write_trace(trace: &HeartbeatTrace, nodes: &[NodeId], path: &Path) -> Option<String> {
+    let records = trace.records();
+    match trace.timestamp() {
+        Some(timestamp) => {
+            // the first datagram that reached each node
+            let mut reached: HashMap<&NodeId, &TraceRecord> = HashMap::new();
+            for record in &records {
+                reached.entry(&record.to).or_insert(record);
+            }
+            let last = reached.values().max_by_key(|record| record.at);
+            println!(
+                "Traced heartbeat {} of node {}: reached {} of {} other nodes over {} datagrams, \
+                 the last after {:.3}s and {} hops",
+                timestamp,
+                trace.origin(),
+                reached.len(),
+                nodes.len().saturating_sub(1),
+                records.len(),
+                last.map_or(0.0, |record| record.at.as_secs_f64()),
+                last.map_or(0, |record| record.hop)
+            );
+        }
+        None => println!(
+            "Traced no heartbeat, node {} sent none after the given time",
+            trace.origin()
+        ),
+    }
+    match fs::write(path, trace.dot(nodes)) {
+        Ok(()) => {
+            println!("Dissemination graph written to {}", path.display());
+            None
+        }
+        Err(e) => Some(format!(
+            "can't write the dissemination graph to {}: {}",
+            path.display(),
+            e
+        )),
+    }
+}
+
+fn print_report(
+    report: &PlotReport,
+    total_stats: &NodeStats,
+    footprint: &Footprint,
+    timeline: &Timeline,
+    network: &Network,
 ) {
-    const PURPLE: rgb::RGB8 = rgb::RGB8::new(0xE0, 0x80, 0xFF);
-    const GREEN: rgb::RGB8 = rgb::RGB8::new(0x00, 0xFF, 0x00);
-    const YELLOW: rgb::RGB8 = rgb::RGB8::new(0xFF, 0xFF, 0x00);
-    // const BLUE: rgb::RGB8 = rgb::RGB8::new(0x00, 0x00, 0xFF);
-
-    let term = console::Term::stdout();
-    term.hide_cursor().unwrap();
-    term.clear_screen().unwrap();
-
-    let all_shared_storages_cloned = all_shared_storages.clone();
-
-    let _ = thread::spawn(move || {
-        let mut fully_informed: Vec<(f32, f32)> = vec![];
-        let mut know_all: Vec<(f32, f32)> = vec![];
-        let mut messages_sent: Vec<(f32, f32)> = vec![];
-        let mut number_nodes_alive: Vec<(f32, f32)> = vec![];
-        let mut max_n_messages_sent = 0.0;
-        let mut i = 0;
-        loop {
-            let (n_fully_informed, n_know_all, n_messages_sent) = calculate_metrics(
-                &all_shared_storages_cloned,
-                number_nodes,
-                healthy_threshold_secs,
-                hearthbeat_interval_secs,
-            );
-
-            let flags = is_alive_flags.lock().expect("failed to get is_alive_flags");
-            let number_alive = flags
+    println!();
+    println!(
+        "Simulation stopped after {}s",
+        timeline.time.elapsed().as_secs()
+    );
+    println!(
+        "Fully informed nodes: peak {}, final {}",
+        report.peak_fully_informed, report.final_fully_informed
+    );
+    println!(
+        "Messages sent: {} ({} heartbeats, {} forwarded)",
+        total_stats.messages_sent(),
+        total_stats.heartbeats_sent,
+        total_stats.gossip_forwarded
+    );
+    println!(
+        "Messages per node and second: {:.1}",
+        footprint.message_rate(total_stats)
+    );
+    println!(
+        "Bytes sent: {} KiB, {} KiB received, {:.2} KiB sent per node and second",
+        total_stats.bytes_sent / 1024,
+        total_stats.bytes_received / 1024,
+        footprint.bandwidth(total_stats)
+    );
+    if let (Some(memory), Some(per_node)) = (footprint.memory, footprint.memory_per_node()) {
+        println!(
+            "Memory: {} MiB resident for {} nodes, {} KiB per node",
+            memory / (1024 * 1024),
+            footprint.nodes,
+            per_node / 1024
+        );
+    }
+    let killed = timeline
+        .killed_at
+        .lock()
+        .map(|at| at.is_some())
+        .unwrap_or(false);
+    if let Ok(converged_at) = timeline.converged_at.lock() {
+        match (converged_at.first(), converged_at.last()) {
+            (Some((first_id, first)), Some((last_id, last))) => println!(
+                "Nodes converged: {}, first node {} after {}s, last node {} after {}s",
+                converged_at.len(),
+                first_id,
+                first.as_secs(),
+                last_id,
+                last.as_secs()
+            ),
+            _ => println!("No node converged"),
+        }
+    }
+    if let (Ok(observer), Ok(detected_at)) = (
+        timeline.detection_observer.lock(),
+        timeline.detected_at.lock(),
+    ) {
+        if let Some((observer, number_victims)) = observer.as_ref() {
+            let latencies: Vec<String> = detected_at
                 .iter()
-                .filter(|flag| flag.load(Ordering::SeqCst))
-                .count();
+                .map(|(id, after)| format!("{} after {}s", id, after.as_secs()))
+                .collect();
+            println!(
+                "Killed nodes detected by node {}: {} of {}{}{}",
+                observer,
+                detected_at.len(),
+                number_victims,
+                if latencies.is_empty() { "" } else { ", " },
+                latencies.join(", ")
+            );
+        }
+    }
+    let delays = &report.propagation_delays;
+    if let Some(max) = delays.last() {
+        println!(
+            "Propagation delay of the latest heartbeats: median {}s, 90th percentile {}s, max {}s",
+            delays[delays.len() / 2],
+            delays[delays.len() * 9 / 10],
+            max
+        );
+    }
+    if let Some(latency) = LatencyDistribution::of(&report.propagation_latency) {
+        let millis = |seconds: f64| (seconds * 1000.0).round();
+        println!(
+            "Propagation latency of the {} heartbeats applied: median {}ms, 95th percentile {}ms, \
+             99th percentile {}ms, max {}ms{}",
+            latency.count,
+            millis(latency.p50),
+            millis(latency.p95),
+            millis(latency.p99),
+            millis(latency.max),
+            if latency.ahead > 0 {
+                format!(
+                    ", {} stamped ahead of their receiver left out",
+                    latency.ahead
+                )
+            } else {
+                String::new()
+            }
+        );
+    }
+    if let Ok(Some((key, _))) = timeline.value_put.lock().as_deref() {
+        match report.value_readable_after {
+            Some(after) => println!(
+                "Value of {} readable on every node {}s after the put",
+                key,
+                after.as_secs()
+            ),
+            None => println!(
+                "Value of {} not readable on every node, on {} of {} at the end",
+                key, report.final_value_readable, footprint.nodes
+            ),
+        }
+    }
+    match report.reconverged_after {
+        Some(after) => println!("Reconverged {}s after the kill", after.as_secs()),
+        None if killed => println!("Did not reconverge after the kill"),
+        None => println!("Stopped before any node was killed"),
+    }
+    if let Ok(events) = timeline.events.lock() {
+        if !events.is_empty() {
+            println!("Scenario events:");
+        }
+        for (index, event) in events.iter().enumerate() {
+            let converged = match report
+                .event_convergence
+                .get(index)
+                .and_then(|convergence| convergence.converged_after)
+            {
+                Some(after) => format!("converged after {:.1}s", after.as_secs_f64()),
+                None => "no convergence before the end".to_string(),
+            };
+            println!(
+                "  [{:.3}s] {}; {}",
+                event.at.as_secs_f64(),
+                event.description,
+                converged
+            );
+        }
+    }
+    if let (Some(churn), Ok(tally)) = (&report.churn, timeline.churn.lock()) {
+        println!(
+            "Churn: {} kills, {} revivals; on average {:.1}% of the nodes alive, {:.1}% of them \
+             with a correct view, {:.1}% of all nodes fully informed, {:.1} datagrams sent per \
+             alive node and second",
+            tally.kills,
+            tally.revivals,
+            100.0 * churn.mean_alive,
+            100.0 * churn.mean_correct_views,
+            100.0 * churn.mean_fully_informed,
+            churn.messages_per_node
+        );
+        if let Some(mean) = mean_duration(&tally.detections) {
+            println!(
+                "Churned nodes seen as gone by every alive node after {:.1}s on average ({} of {} \
+                 kills, {} revived before)",
+                mean.as_secs_f64(),
+                tally.detections.len(),
+                tally.kills,
+                tally.revived_undetected
+            );
+        }
+    }
+    for group in &report.node_groups {
+        println!(
+            "Group {} ({} nodes): fully informed peak {}, final {}; {} messages sent; at most {} \
+             alive nodes seen as suspect or dead",
+            group.name,
+            group.nodes,
+            group.peak_fully_informed,
+            group.final_fully_informed,
+            group.messages_sent,
+            group.peak_falsely_suspected
+        );
+    }
+    if !report.newcomers.is_empty() {
+        let newcomers: Vec<String> = report
+            .newcomers
+            .iter()
+            .map(|newcomer| match newcomer.informed_after {
+                Some(after) => format!("{} after {}s", newcomer.index, after.as_secs()),
+                None => format!("{} never", newcomer.index),
+            })
+            .collect();
+        println!(
+            "Added nodes informed about every alive node: {}",
+            newcomers.join(", ")
+        );
+    }
+    if let Some(fabric) = &network.fabric {
+        println!(
+            "Link queues: {} of {} datagrams queued for {}ms on average, {} dropped at the tail \
+             ({:.1}%)",
+            fabric.queued,
+            fabric.datagrams,
+            fabric.mean_queueing().as_millis(),
+            fabric.tail_dropped,
+            100.0 * fabric.tail_dropped as f64 / fabric.datagrams.max(1) as f64
+        );
+    }
+    let Some(faults) = network.faults else {
+        return;
+    };
+    let stats = faults.stats();
+    println!(
+        "Injected loss: {} of {} datagrams dropped ({:.1}%)",
+        stats.dropped,
+        stats.datagrams,
+        100.0 * stats.dropped as f64 / stats.datagrams.max(1) as f64
+    );
+    if stats.delayed > 0 {
+        println!(
+            "Injected latency: {} datagrams delayed by {}ms on average",
+            stats.delayed,
+            stats.mean_delay().as_millis()
+        );
+    }
+    if stats.duplicated > 0 {
+        println!(
+            "Injected duplicates: {} extra copies sent; {} copies of known heartbeats received, \
+             {} of them passed on ({:.1}%)",
+            stats.duplicated,
+            total_stats.duplicates_received,
+            total_stats.duplicate_forwards,
+            100.0 * total_stats.duplicate_forwards as f64
+                / total_stats.duplicates_received.max(1) as f64
+        );
+    }
+    if stats.reordered > 0 {
+        println!(
+            "Injected reordering: {} datagrams held back; {} heartbeats arrived stale and were \
+             ignored",
+            stats.reordered, total_stats.stale_heartbeats
+        );
+    }
+    let links = faults.links();
+    for link in &report.links {
+        let (from, to) = (
+            node_address(PORT_BASE + link.from),
+            node_address(PORT_BASE + link.to),
+        );
+        let Some((_, _, stats)) = links
+            .iter()
+            .find(|(sender, receiver, _)| (*sender, *receiver) == (from, to))
+        else {
+            continue;
+        };
+        println!(
+            "Link {} -> {}: {} of {} datagrams delivered ({:.1}%); node {} saw node {} as alive \
+             in {} of {} samples{}",
+            link.from,
+            link.to,
+            stats.delivered,
+            stats.sent,
+            100.0 * stats.delivery_rate(),
+            link.to,
+            link.from,
+            link.seen_alive,
+            link.samples,
+            match link.final_state {
+                Some(state) => format!(", {:?} at the end", state).to_lowercase(),
+                None => String::new(),
+            }
+        );
+    }
+    if let Some(byzantine) = &report.byzantine {
+        println!(
+            "Byzantine nodes corrupted {} datagrams: {} forged timestamps, {} phantom ids, {} \
+             replays, {} flipped bits",
+            stats.tampered(),
+            stats.forged_timestamps,
+            stats.phantoms,
+            stats.replayed,
+            stats.flipped
+        );
+        println!(
+            "Honest nodes with a correct view: {} of {} at the end, {:.1}% on average; phantom \
+             entries held: peak {}, final {}",
+            byzantine.final_correct_views,
+            byzantine.final_honest,
+            100.0 * byzantine.mean_correct_views,
+            byzantine.peak_phantoms,
+            byzantine.final_phantoms
+        );
+        let honest = &byzantine.honest_stats;
+        println!(
+            "Rejected by the honest nodes: {} undecodable datagrams, {} timestamps from the \
+             future, {} stale heartbeats, {} unreachable addresses, {} entries evicted",
+            honest.decode_errors,
+            honest.future_timestamps,
+            honest.stale_heartbeats,
+            honest.addresses_rejected,
+            honest.entries_evicted
+        );
+    }
+    if !faults.partitions().is_empty() {
+        println!(
+            "Datagrams dropped between partitioned groups: {}",
+            stats.partitioned
+        );
+    }
+    for (index, (partition, partition_report)) in faults
+        .partitions()
+        .iter()
+        .zip(&report.partitions)
+        .enumerate()
+    {
+        let groups: Vec<String> = partition_report
+            .group_sizes
+            .iter()
+            .zip(&partition_report.lowest_group_informed)
+            .map(|(size, lowest)| match lowest {
+                Some(lowest) => format!("{} of {}", lowest, size),
+                None => format!("? of {}", size),
+            })
+            .collect();
+        let healed = match (partition.until, partition_report.reconverged_after) {
+            (Some(_), Some(after)) => format!("reconverged {}s after healing", after.as_secs()),
+            (Some(until), None) if faults.elapsed() >= until => {
+                "did not reconverge after healing".to_string()
+            }
+            _ => "stopped before healing".to_string(),
+        };
+        println!(
+            "Partition {}: fewest nodes informed about their group {}; {}",
+            index + 1,
+            groups.join(", "),
+            healed
+        );
+    }
+}
 
-            fully_informed.push((i as f32, n_fully_informed));
-            know_all.push((i as f32, n_know_all as f32));
-            number_nodes_alive.push((i as f32, number_alive as f32));
-            messages_sent.push((i as f32, n_messages_sent));
+/// Simulates a cluster of gossiping nodes on localhost: the nodes discover each other through
+/// their seeds, some of them are killed and revived later, then the decay is raised and some nodes
+/// stop forwarding, while charts show how the cluster copes.
+#[derive(Parser, Debug, Clone)]
+#[command(version)]
+struct Args {
+    /// Number of nodes, listening on consecutive ports from 8000
+    #[arg(long, default_value_t = 100)]
+    nodes: u64,
+    /// Number of nodes every node knows from the start
+    #[arg(long, default_value_t = 2)]
+    seeds: u64,
+    /// Starts the nodes one after the other at this rate instead of all at once, as N/s or N/m,
+    /// node 0 right away
+    #[arg(long, value_name = "N/s", value_parser = parse_rate)]
+    start_rate: Option<f64>,
+    /// Starts every node but node 0 at a random time in this window instead of all at once, as
+    /// UNTIL or FROM..UNTIL counted from the start, e.g. 0..30s
+    #[arg(long, value_name = "[FROM..]UNTIL", value_parser = parse_window)]
+    start_jitter: Option<StartWindow>,
+    /// Starts every node with a fresh heartbeat of every other node in its storage, as if the
+    /// cluster had converged already, so the run begins fully informed; nodes added later still
+    /// join through the seeds
+    #[arg(long)]
+    warm_start: bool,
+    /// Time between two heartbeats of a node
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "5s")]
+    heartbeat_interval: Duration,
+    /// Number of peers every heartbeat, own or forwarded, is sent to
+    #[arg(long, default_value_t = defaults::HEARTBEAT_SPREAD)]
+    spread: usize,
+    /// How quickly the forwarding probability drops with the copies a node has received
+    #[arg(long, default_value_t = defaults::DECAY_FACTOR)]
+    decay: f64,
+    /// Age at which a peer counts as suspect
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "30s")]
+    suspect_timeout: Duration,
+    /// Age at which a peer counts as dead
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "60s")]
+    dead_timeout: Duration,
+    /// Number of nodes killed, at most all of them; like the other flags down to --leaves, only
+    /// used without --event
+    #[arg(long, default_value_t = 20)]
+    kill: usize,
+    /// When the nodes are killed, counted from the start
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "60s")]
+    kill_after: Duration,
+    /// When the killed nodes are revived, counted from the kill
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "40s")]
+    revive_after: Duration,
+    /// When the decay is raised, counted from the revival
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "50s")]
+    change_decay_after: Duration,
+    /// Decay every node switches to
+    #[arg(long, default_value_t = 1.6)]
+    changed_decay: f64,
+    /// When some nodes stop forwarding, counted from the decay change
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "50s")]
+    leaves_after: Duration,
+    /// Number of nodes that stop forwarding gossip and only send their own heartbeats, at most
+    /// all of them
+    #[arg(long, default_value_t = 20)]
+    leaves: usize,
+    /// Master seed of peer selection, forwarding decisions, the nodes picked by events and the
+    /// injected faults; drawn at random and printed when not given. Only --in-memory runs
+    /// without --workers repeat exactly
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Hosts all nodes on a pool of this many threads instead of a thread per node
+    #[arg(long)]
+    workers: Option<usize>,
+    /// Connects the nodes through an in-memory network instead of UDP sockets on loopback. The
+    /// nodes take turns on one thread in simulated time, unless --workers hosts them, as
+    /// clusters of thousands of nodes need
+    #[arg(long)]
+    in_memory: bool,
+    /// Stops the killed nodes and restarts them later, instead of pausing and resuming them
+    #[arg(long)]
+    restart: bool,
+    /// Has the first node put a value this long after the start and charts how many nodes can
+    /// read it
+    #[arg(long, value_parser = humantime::parse_duration)]
+    put_value: Option<Duration>,
+    /// Probability with which any datagram a node sends is lost
+    #[arg(long, value_parser = parse_probability, default_value_t = 0.0)]
+    loss: f64,
+    /// Loss of the datagrams sent by one node, as NODE=P; repeatable, overrides --loss
+    #[arg(long, value_name = "NODE=P", value_parser = parse_source_loss)]
+    source_loss: Vec<SourceLoss>,
+    /// Loss of the datagrams sent by one node to another, as FROM:TO=P; repeatable, overrides
+    /// --loss and --source-loss
+    #[arg(long, value_name = "FROM:TO=P", value_parser = parse_pair_loss)]
+    pair_loss: Vec<PairLoss>,
+    /// Time every datagram a node sends takes to arrive, on top of the loopback's
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "0s")]
+    latency: Duration,
+    /// Largest random delay added to the latency of every datagram; datagrams sent less than
+    /// this apart may arrive out of order
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "0s")]
+    jitter: Duration,
+    /// Latency of the datagrams sent by one node to another, as FROM:TO=DURATION; repeatable,
+    /// overrides --latency
+    #[arg(long, value_name = "FROM:TO=DURATION", value_parser = parse_pair_latency)]
+    pair_latency: Vec<PairLatency>,
+    /// Probability with which a datagram that isn't lost is sent more than once, like a network
+    /// duplicating packets
+    #[arg(long, value_parser = parse_probability, default_value_t = 0.0)]
+    duplicate: f64,
+    /// Extra copies sent of a duplicated datagram
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 1)]
+    duplicate_copies: u64,
+    /// Probability with which a datagram that isn't lost is held back for up to
+    /// --reorder-window, so it arrives after newer ones
+    #[arg(long, value_parser = parse_probability, default_value_t = 0.0)]
+    reorder: f64,
+    /// Longest a reordered datagram is held back; longer than --heartbeat-interval to overtake
+    /// the next heartbeat
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "5s")]
+    reorder_window: Duration,
+    /// Bytes per second every link from one node to another carries on the in-memory network,
+    /// like 64k or 1M; datagrams wait for those sent on the link before them. Unlimited when not
+    /// given
+    #[arg(long, value_name = "BYTES", value_parser = parse_bytes)]
+    bandwidth: Option<u64>,
+    /// Bandwidth of the link from one node to another, as FROM:TO=BYTES; repeatable, overrides
+    /// --bandwidth
+    #[arg(long, value_name = "FROM:TO=BYTES", value_parser = parse_pair_bandwidth)]
+    pair_bandwidth: Vec<PairBandwidth>,
+    /// Bytes of datagrams waiting for a link with a bandwidth, datagrams beyond it are dropped
+    #[arg(long, value_name = "BYTES", value_parser = parse_bytes, default_value = "64k")]
+    link_queue: u64,
+    /// Scenario event, as space separated settings like "at=60s action=kill nodes=random:20";
+    /// repeatable, replaces the kill, revival, decay change and leaves given by the flags above.
+    /// Actions are kill, revive, stop-forwarding (nodes=all, seeds, random:N or indexes like
+    /// id:3,17,40-42, any of them followed by ,exclude: and another, e.g.
+    /// random:20,exclude:seeds), partition (groups=GROUPS
+    /// and optionally for=DURATION), config (nodes, heartbeat_interval, spread, decay), add-nodes
+    /// (count=N) and set-loss (loss=P)
+    #[arg(long, value_name = "SETTINGS", value_parser = parse_event)]
+    event: Vec<ScheduledEvent>,
+    /// Cuts groups of nodes off from each other for a while, as GROUPS@FROM..UNTIL with the
+    /// groups separated by / and made of node indexes and ranges, e.g. 0-49/50-99@1m..2m;
+    /// repeatable, partitions may overlap, without UNTIL the partition never heals
+    #[arg(long, value_name = "GROUPS@FROM..UNTIL", value_parser = parse_partition)]
+    partition: Vec<PartitionSpec>,
+    /// Nodes that misbehave, as node indexes and ranges like 0-4,9: they corrupt a share of the
+    /// datagrams they send, and the report tells how the honest nodes fared
+    #[arg(long, value_name = "NODES", value_parser = parse_node_list)]
+    byzantine: Option<NodeList>,
+    /// How the byzantine nodes misbehave, comma separated: future-timestamps, phantom-ids,
+    /// replay and flip-bytes
+    #[arg(long, value_delimiter = ',', default_values_t = Misbehavior::ALL)]
+    misbehavior: Vec<Misbehavior>,
+    /// Share of the datagrams of a byzantine node it corrupts
+    #[arg(long, value_parser = parse_probability, default_value_t = 0.5)]
+    misbehavior_rate: f64,
+    /// Most entries the storage of a node holds, the stalest peers are evicted beyond it;
+    /// unbounded when not given
+    #[arg(long)]
+    max_entries: Option<usize>,
+    /// Runs without charts, printing only the configuration and the final report
+    #[arg(long)]
+    headless: bool,
+    /// Also charts the heartbeats the storages counted as received next to the datagrams, the
+    /// estimate the message chart used to show, for comparison
+    #[arg(long)]
+    chart_heartbeats_received: bool,
+    /// Has every node fail after a random time with this mean and come back after
+    /// --churn-downtime on average, over and over, on top of the scenario events
+    #[arg(long, value_parser = humantime::parse_duration)]
+    churn_mtbf: Option<Duration>,
+    /// Mean time a node failed by the churn stays down
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "30s")]
+    churn_downtime: Duration,
+    /// When the churn starts, counted from the start; the report averages the metrics from then
+    /// on
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "60s")]
+    churn_after: Duration,
+    /// Nodes configured apart from the others, as space separated settings like
+    /// "name=quiet nodes=90 heartbeat_interval=10s spread=3"; repeatable, the groups take
+    /// consecutive nodes from node 0 on and their metrics are charted and reported apart.
+    /// Settings are name, nodes, heartbeat_interval, spread, decay, suspect_timeout and
+    /// dead_timeout
+    #[arg(long, value_name = "SETTINGS", value_parser = parse_group)]
+    group: Vec<NodeGroup>,
+    /// Stops the simulation after this long instead of running until ctrl-c
+    #[arg(long, value_parser = humantime::parse_duration)]
+    duration: Option<Duration>,
+    /// Runs the simulation once for every combination of the given values and compares the
+    /// runs, e.g. decay=0.4,0.6,0.8 spread=3,5,8; runs are headless and need --duration. With
+    /// --out, every run writes its own files, numbered from 1, and the comparison goes to the
+    /// same path suffixed with -runs
+    #[arg(long, value_name = "SETTING=V1,V2,...", num_args = 1.., value_parser = parse_sweep_axis)]
+    sweep: Vec<SweepAxis>,
+    /// Runs the simulation, or every combination of a sweep, this many times with different
+    /// seeds and sums the runs up in statistics, also written to the --out path suffixed with
+    /// -statistics; runs are headless and need --duration
+    #[arg(long, default_value_t = 1)]
+    repeat: usize,
+    /// Consecutive samples in which every alive node must see exactly the alive nodes as alive
+    /// for the cluster to count as converged after a scenario event
+    #[arg(long, default_value_t = 3)]
+    convergence_samples: usize,
+    /// Fails the run unless every node saw every node with a fresh heartbeat within this long of
+    /// the start, and the cluster converged again within this long of every scenario event
+    #[arg(long, value_parser = humantime::parse_duration)]
+    assert_converged_within: Option<Duration>,
+    /// Fails the run if the nodes sent more datagrams per node and second than this, on average
+    /// over the run
+    #[arg(long, value_name = "N")]
+    assert_max_messages_per_node_per_sec: Option<f64>,
+    /// Fails the run unless a surviving node saw every victim of the first kill dead within this
+    /// long of the kill
+    #[arg(long, value_parser = humantime::parse_duration)]
+    assert_detection_latency: Option<Duration>,
+    /// Time between two samples of the cluster's metrics, charted and written to --out; every
+    /// sample looks at every node's view, which takes a while with thousands of nodes
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1s")]
+    sample_interval: Duration,
+    /// Runs every node as an OS process of its own, started with `muck node`, killed with SIGKILL
+    /// and started again by the kill and revive events; the cluster is watched through the
+    /// nodes' admin queries. Only kill, revive and add-nodes events run
+    #[arg(long)]
+    processes: bool,
+    /// Writes the metrics of the cluster to this CSV file every second, and a JSON report of
+    /// the run to the same path with a .json extension when it stops. Interactive runs write
+    /// them to gossip-run-TIMESTAMP.csv without it
+    #[arg(long, value_name = "PATH")]
+    out: Option<PathBuf>,
+    /// Follows one heartbeat through the cluster, the first NODE sends once AFTER has passed,
+    /// e.g. 3@20s, and writes which node passed it to which to --trace-dot
+    #[arg(long, value_name = "NODE@AFTER", value_parser = parse_trace)]
+    trace_heartbeat: Option<TraceSpec>,
+    /// Graphviz file the dissemination graph of --trace-heartbeat goes to, drawn with
+    /// `dot -Tpng trace.dot -o trace.png`
+    #[arg(long, value_name = "PATH", default_value = "trace.dot")]
+    trace_dot: PathBuf,
+    /// Serves the counters of every node in the Prometheus text format at /metrics on this
+    /// address while the simulation runs, e.g. 127.0.0.1:9100
+    #[arg(long, value_name = "ADDRESS")]
+    metrics_address: Option<SocketAddr>,
+    /// Scenario file whose settings replace the defaults, see scenarios/; flags given on the
+    /// command line still win
+    #[arg(long)]
+    config: Option<PathBuf>,
+}
 
-            if n_messages_sent > max_n_messages_sent {
-                max_n_messages_sent = n_messages_sent
-            }
+/// The random streams drawn from the master seed.
+#[derive(Debug, Clone, Copy, Hash)]
+enum SeedPurpose {
+    /// peer selection and forwarding decisions of a node
+    Node,
+    /// the nodes picked by the scheduled events
+    Schedule,
+    /// losses and jitter of the injected faults
+    Faults,
+    /// the master seed of a run of a sweep or repetition
+    Run,
+    /// the nodes killed and revived by the churn and when
+    Churn,
+    /// the nodes picked by the commands typed during the simulation
+    Controls,
+    /// when the nodes start with --start-jitter
+    Startup,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SourceLoss {
+    from: u64,
+    loss: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PairLoss {
+    from: u64,
+    to: u64,
+    loss: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PairLatency {
+    from: u64,
+    to: u64,
+    latency: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PairBandwidth {
+    from: u64,
+    to: u64,
+    bandwidth: u64,
+}
+
+/// A setting and the values a sweep runs the simulation with.
+#[derive(Debug, Clone)]
+struct SweepAxis {
+    /// as given, e.g. heartbeat-interval
+    key: String,
+    /// the id of its flag, e.g. heartbeat_interval
+    id: String,
+    values: Vec<String>,
+}
+
+fn parse_sweep_axis(value: &str) -> Result<SweepAxis, String> {
+    let (key, values) = value.split_once('=').ok_or("expected SETTING=V1,V2,...")?;
+    let values: Vec<String> = values.split(',').map(str::to_string).collect();
+    if key.is_empty() || values.iter().any(String::is_empty) {
+        return Err("expected SETTING=V1,V2,...".to_string());
+    }
+    Ok(SweepAxis {
+        key: key.to_string(),
+        id: key.replace('-', "_"),
+        values,
+    })
+}
+
+/// When the nodes of a startup ramp start, see --start-jitter.
+#[derive(Debug, Clone, Copy)]
+struct StartWindow {
+    from: Duration,
+    until: Duration,
+}
+
+impl fmt::Display for StartWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}..{}",
+            humantime::format_duration(self.from),
+            humantime::format_duration(self.until)
+        )
+    }
+}
+
+/// The heartbeat followed with --trace-heartbeat.
+#[derive(Debug, Clone, Copy)]
+struct TraceSpec {
+    node: u64,
+    after: Duration,
+}
+
+impl fmt::Display for TraceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}@{}",
+            self.node,
+            humantime::format_duration(self.after)
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PartitionSpec {
+    groups: Vec<Vec<u64>>,
+    from: Duration,
+    until: Option<Duration>,
+}
+
+/// Node indexes, given as indexes and ranges.
+#[derive(Debug, Clone)]
+struct NodeList(Vec<u64>);
+
+fn parse_node_list(value: &str) -> Result<NodeList, String> {
+    parse_nodes(value).map(NodeList)
+}
 
-            term.move_cursor_to(0, 0).unwrap();
-            println!("Yellow = N nodes that has the latest heartbeat for each node.");
-            // println!("Blue = N nodes that know about all other nodes");
-            textplots::Chart::new_with_y_range(200, 50, 0.0, i as f32, 0.0, number_nodes as f32)
-                .linecolorplot(&Shape::Lines(&fully_informed), YELLOW)
-                // .linecolorplot(&Shape::Lines(&know_all), BLUE) // NOT SURE IF USEFUL
-                .display();
+fn parse_partition(value: &str) -> Result<PartitionSpec, String> {
+    let (groups, window) = value.split_once('@').ok_or("expected GROUPS@FROM..UNTIL")?;
+    let (from, until) = match window.split_once("..") {
+        Some((from, until)) => (from, Some(until)),
+        None => (window, None),
+    };
+    let parse_time = |time: &str| humantime::parse_duration(time).map_err(|e| e.to_string());
+    let from = parse_time(from)?;
+    let until = until.map(parse_time).transpose()?;
+    if until.is_some_and(|until| until <= from) {
+        return Err("the partition must heal after it starts".to_string());
+    }
+    Ok(PartitionSpec {
+        groups: parse_groups(groups)?,
+        from,
+        until,
+    })
+}
+
+/// Nodes started per second, from N/s or N/m.
+fn parse_rate(value: &str) -> Result<f64, String> {
+    let (count, per) = value.split_once('/').ok_or("expected N/s or N/m")?;
+    let seconds = match per {
+        "s" => 1.0,
+        "m" => 60.0,
+        _ => return Err("expected N/s or N/m".to_string()),
+    };
+    match count.parse::<f64>() {
+        Ok(count) if count > 0.0 && count.is_finite() => Ok(count / seconds),
+        Ok(_) => Err("the rate must be above 0".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_trace(value: &str) -> Result<TraceSpec, String> {
+    let (node, after) = value.split_once('@').ok_or("expected NODE@AFTER")?;
+    Ok(TraceSpec {
+        node: parse_node(node)?,
+        after: humantime::parse_duration(after).map_err(|e| e.to_string())?,
+    })
+}
+
+fn parse_window(value: &str) -> Result<StartWindow, String> {
+    let (from, until) = value.split_once("..").unwrap_or(("0s", value));
+    let parse_time = |time: &str| humantime::parse_duration(time).map_err(|e| e.to_string());
+    let window = StartWindow {
+        from: parse_time(from)?,
+        until: parse_time(until)?,
+    };
+    if window.until <= window.from {
+        return Err("the window must end after it starts".to_string());
+    }
+    Ok(window)
+}
+
+fn parse_event(value: &str) -> Result<ScheduledEvent, String> {
+    value.parse().map_err(|e: EventError| e.to_string())
+}
 
-            println!("Number Message");
-            textplots::Chart::new_with_y_range(200, 50, 0.0, i as f32, 0.0, max_n_messages_sent)
-                .linecolorplot(&Shape::Lines(&messages_sent), PURPLE)
-                .display();
+fn parse_group(value: &str) -> Result<NodeGroup, String> {
+    value.parse().map_err(|e: EventError| e.to_string())
+}
 
-            println!("Number Alive Nodes");
-            textplots::Chart::new_with_y_range(200, 50, 0.0, i as f32, 0.0, number_nodes as f32)
-                .linecolorplot(&Shape::Lines(&number_nodes_alive), GREEN)
-                .display();
+fn parse_probability(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+        Ok(_) => Err("must be between 0 and 1".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-            sleep(time::Duration::from_millis(1000));
-            i += 1;
+fn parse_source_loss(value: &str) -> Result<SourceLoss, String> {
+    let (from, loss) = value.split_once('=').ok_or("expected NODE=P")?;
+    Ok(SourceLoss {
+        from: parse_node(from)?,
+        loss: parse_probability(loss)?,
+    })
+}
+
+/// Splits `FROM:TO=VALUE` into the two nodes and the value.
+fn parse_pair(value: &str) -> Result<(u64, u64, &str), String> {
+    let (pair, value) = value.split_once('=').ok_or("expected FROM:TO=VALUE")?;
+    let (from, to) = pair.split_once(':').ok_or("expected FROM:TO=VALUE")?;
+    Ok((parse_node(from)?, parse_node(to)?, value))
+}
+
+fn parse_pair_loss(value: &str) -> Result<PairLoss, String> {
+    let (from, to, loss) = parse_pair(value)?;
+    Ok(PairLoss {
+        from,
+        to,
+        loss: parse_probability(loss)?,
+    })
+}
+
+fn parse_pair_latency(value: &str) -> Result<PairLatency, String> {
+    let (from, to, latency) = parse_pair(value)?;
+    Ok(PairLatency {
+        from,
+        to,
+        latency: humantime::parse_duration(latency).map_err(|e| e.to_string())?,
+    })
+}
+
+fn parse_pair_bandwidth(value: &str) -> Result<PairBandwidth, String> {
+    let (from, to, bandwidth) = parse_pair(value)?;
+    Ok(PairBandwidth {
+        from,
+        to,
+        bandwidth: parse_bytes(bandwidth)?,
+    })
+}
+
+/// A number of bytes above 0, with k, M or G for thousands, millions or billions of them.
+fn parse_bytes(value: &str) -> Result<u64, String> {
+    let (number, factor) = match value.char_indices().last() {
+        Some((at, 'k')) => (&value[..at], 1_000),
+        Some((at, 'M')) => (&value[..at], 1_000_000),
+        Some((at, 'G')) => (&value[..at], 1_000_000_000),
+        _ => (value, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(factor))
+        .filter(|bytes| *bytes > 0)
+        .ok_or_else(|| {
+            format!(
+                "expected a number of bytes like 1500, 64k or 1M, got {:?}",
+                value
+            )
+        })
+}
+
+/// Bytes the way [`parse_bytes`] reads them, rounded to the largest unit.
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        1_000_000_000.. => format!("{:.1}GB", bytes as f64 / 1e9),
+        1_000_000.. => format!("{:.1}MB", bytes as f64 / 1e6),
+        1_000.. => format!("{:.1}kB", bytes as f64 / 1e3),
+        _ => format!("{}B", bytes),
+    }
+}
+
+/// Runs a single node until it is stopped, as one of the processes of `muck --processes`.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "muck node", bin_name = "muck node")]
+struct NodeArgs {
+    /// Id of the node
+    #[arg(long)]
+    id: NodeId,
+    /// Address the node listens on and advertises to its peers
+    #[arg(long)]
+    listen: SocketAddr,
+    /// Node known from the start, as ID@ADDRESS; repeatable
+    #[arg(long, value_name = "ID@ADDRESS", value_parser = parse_seed_node)]
+    seed: Vec<SeedNode>,
+    /// Time between two heartbeats of the node
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "5s")]
+    heartbeat_interval: Duration,
+    /// Number of peers every heartbeat, own or forwarded, is sent to
+    #[arg(long, default_value_t = defaults::HEARTBEAT_SPREAD)]
+    spread: usize,
+    /// How quickly the forwarding probability drops with the copies the node has received
+    #[arg(long, default_value_t = defaults::DECAY_FACTOR)]
+    decay: f64,
+    /// Age at which a peer counts as suspect
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "30s")]
+    suspect_timeout: Duration,
+    /// Age at which a peer counts as dead
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "60s")]
+    dead_timeout: Duration,
+    /// Number of nodes the cluster is expected to have
+    #[arg(long)]
+    expected_cluster_size: Option<usize>,
+    /// Seed of peer selection and forwarding decisions, drawn at random when not given
+    #[arg(long)]
+    rng_seed: Option<u64>,
+    /// File the node saves its view to, and restores it from when it starts
+    #[arg(long, value_name = "PATH")]
+    snapshot: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+struct SeedNode {
+    id: NodeId,
+    address: SocketAddr,
+}
+
+fn parse_seed_node(value: &str) -> Result<SeedNode, String> {
+    let (id, address) = value.split_once('@').ok_or("expected ID@ADDRESS")?;
+    Ok(SeedNode {
+        id: id
+            .parse()
+            .map_err(|e: muck::node_id::NodeIdError| e.to_string())?,
+        address: address
+            .parse()
+            .map_err(|_| format!("invalid address: {}", address))?,
+    })
+}
+
+impl NodeArgs {
+    /// The flags of a node process running with `config`, the reverse of [`NodeArgs::config`].
+    fn flags(
+        config: &gossip::NodeConfig,
+        seed_nodes: &[(NodeId, SocketAddr)],
+        snapshot: &Path,
+    ) -> Vec<String> {
+        let duration = |duration: Duration| humantime::format_duration(duration).to_string();
+        let mut flags = vec![
+            "--id".to_string(),
+            config.id.to_string(),
+            "--listen".to_string(),
+            config.address.to_string(),
+            "--heartbeat-interval".to_string(),
+            duration(config.heartbeat_interval),
+            "--spread".to_string(),
+            config.heartbeat_spread.to_string(),
+            "--decay".to_string(),
+            config.decay_factor.to_string(),
+            "--suspect-timeout".to_string(),
+            duration(config.suspect_timeout),
+            "--dead-timeout".to_string(),
+            duration(config.dead_timeout),
+            "--snapshot".to_string(),
+            snapshot.display().to_string(),
+        ];
+        for (id, address) in seed_nodes {
+            flags.extend(["--seed".to_string(), format!("{}@{}", id, address)]);
         }
-    });
+        if let Some(size) = config.expected_cluster_size {
+            flags.extend(["--expected-cluster-size".to_string(), size.to_string()]);
+        }
+        if let Some(seed) = config.rng_seed {
+            flags.extend(["--rng-seed".to_string(), seed.to_string()]);
+        }
+        flags
+    }
+
+    fn config(&self) -> Result<gossip::NodeConfig, gossip::ConfigError> {
+        let config = gossip::NodeConfig::builder()
+            .id(self.id.to_string())
+            .address(self.listen.to_string())
+            .heartbeat_interval(self.heartbeat_interval)
+            .spread(self.spread)
+            .poll_interval(defaults::POLL_INTERVAL)
+            .decay(self.decay)
+            .suspect_timeout(self.suspect_timeout)
+            .dead_timeout(self.dead_timeout);
+        let config = match self.expected_cluster_size {
+            Some(size) => config.expected_cluster_size(size),
+            None => config,
+        };
+        let config = match &self.snapshot {
+            Some(path) => config.snapshot_path(path),
+            None => config,
+        };
+        match self.rng_seed {
+            Some(seed) => config.rng_seed(seed),
+            None => config,
+        }
+        .build()
+    }
 }
 
-fn calculate_metrics(
-    all_shared_storages: &HashMap<String, Arc<Mutex<gossip::Storage>>>,
-    number_nodes: u64,
-    healthy_threshold_secs: u64,
-    hearthbeat_interval_secs: u64,
-) -> (f32, f32, f32) {
-    // then check to see if each node has the latest info about each other node
-    let mut n_messages_sent = 0;
-    let mut n_fully_informed = 0;
-    let mut n_know_all = 0;
-    for (_, shared_storage) in all_shared_storages {
-        let storage = match shared_storage.lock() {
-            Ok(guard) => guard,
-            Err(PoisonError { .. }) => {
-                error!("failed to lock shared storage");
-                continue;
+/// Parses the command line, with the settings of the `--config` file, if any, as defaults.
+fn parse_args() -> Args {
+    Args::from_arg_matches(&command().get_matches()).unwrap_or_else(|e| e.exit())
+}
+
+/// The command line interface, defaulting to the settings of the scenario file given with
+/// --config.
+fn command() -> Command {
+    let matches = Args::command().get_matches();
+    match matches.get_one::<PathBuf>("config") {
+        Some(path) => scenario_defaults(Args::command(), path).unwrap_or_else(|e| {
+            Args::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("in {}: {}", path.display(), e),
+                )
+                .exit()
+        }),
+        None => Args::command(),
+    }
+}
+
+/// The settings of every run of a sweep, named after the swept values. Each combination of the
+/// values becomes the default of its setting, like in a scenario file.
+fn sweep_args(sweep: &[SweepAxis]) -> Result<Vec<(String, Args)>, String> {
+    let command = command();
+    let matches = command.clone().get_matches();
+    for axis in sweep {
+        let Some(arg) = command.get_arguments().find(|arg| {
+            arg.get_id() == axis.id.as_str()
+                && arg.get_long().is_some()
+                && !["config", "sweep"].contains(&axis.id.as_str())
+        }) else {
+            return Err(format!("unknown setting `{}`", axis.key));
+        };
+        if matches!(arg.get_action(), ArgAction::Append) {
+            return Err(format!("`{}` is repeatable and can't be swept", axis.key));
+        }
+        if matches.value_source(&axis.id) == Some(ValueSource::CommandLine) {
+            return Err(format!(
+                "`{}` is swept and set on the command line",
+                axis.key
+            ));
+        }
+        for value in &axis.values {
+            if let Err(reason) = check_setting(arg, value) {
+                return Err(format!(
+                    "invalid value {:?} for `{}`: {}",
+                    value, axis.key, reason
+                ));
+            }
+        }
+    }
+    if sweep
+        .iter()
+        .enumerate()
+        .any(|(index, axis)| sweep[..index].iter().any(|other| other.id == axis.id))
+    {
+        return Err("a setting is swept twice".to_string());
+    }
+
+    let mut combinations: Vec<Vec<(&SweepAxis, &String)>> = vec![vec![]];
+    for axis in sweep {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combination| {
+                axis.values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((axis, value));
+                    combination
+                })
+            })
+            .collect();
+    }
+    combinations
+        .into_iter()
+        .map(|combination| {
+            let mut run_command = command.clone();
+            let mut label = vec![];
+            for (axis, value) in combination {
+                run_command = run_command.mut_arg(&axis.id, |arg| arg.default_value(value.clone()));
+                label.push(format!("{}={}", axis.key, value));
             }
+            let args =
+                Args::from_arg_matches(&run_command.get_matches()).map_err(|e| e.to_string())?;
+            Ok((label.join(" "), args))
+        })
+        .collect()
+}
+
+/// Parses `value` the way `arg` would parse it on the command line.
+fn check_setting(arg: &Arg, value: &str) -> Result<(), String> {
+    if !arg.get_action().takes_values() {
+        return match value {
+            "true" | "false" => Ok(()),
+            _ => Err("expected true or false".to_string()),
         };
-        let storage_snapshot = storage.clone();
-        drop(storage);
+    }
+    // joined with = so that a negative number isn't taken for a flag
+    let flag = format!("--{}={}", arg.get_long().unwrap_or_default(), value);
+    Command::new("setting")
+        .no_binary_name(true)
+        .arg(arg.clone())
+        .try_get_matches_from([flag])
+        .map(|_| ())
+        .map_err(|e| {
+            e.source()
+                .map_or_else(|| e.kind().to_string(), |source| source.to_string())
+        })
+}
 
-        if storage_snapshot.data.len() >= number_nodes as usize {
-            n_know_all += 1;
+/// Makes the settings of the scenario file at `path` the defaults of `command`. Settings are
+/// named like the flags, in snake or kebab case, and checked just like them.
+fn scenario_defaults(mut command: Command, path: &Path) -> Result<Command, String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let settings: toml::Table = contents
+        .parse()
+        .map_err(|e: toml::de::Error| e.to_string())?;
+    for (key, value) in settings {
+        let id = key.replace('-', "_");
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id.as_str() && arg.get_long().is_some() && id != "config")
+        else {
+            return Err(format!("unknown setting `{}`", key));
+        };
+        // repeatable flags take an array of values
+        let values = match value {
+            toml::Value::Array(values) if matches!(arg.get_action(), ArgAction::Append) => values
+                .into_iter()
+                .map(|value| match value {
+                    // an event given as a table, see scenarios/
+                    toml::Value::Table(table) => table
+                        .into_iter()
+                        .map(|(key, value)| {
+                            scalar_setting(&key, value).map(|value| format!("{}={}", key, value))
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                        .map(|settings| settings.join(" ")),
+                    value => scalar_setting(&key, value),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            value => vec![scalar_setting(&key, value)?],
+        };
+        for value in &values {
+            if let Err(reason) = check_setting(arg, value) {
+                return Err(format!(
+                    "invalid value {:?} for `{}`: {}",
+                    value, key, reason
+                ));
+            }
         }
+        command = command.mut_arg(id, |arg| arg.default_values(values));
+    }
+    Ok(command)
+}
 
-        let mut nr_with_latest = 0;
-        for (_, data) in &storage_snapshot.data {
-            let seconds_since = gossip::now_unix() - data.heartbeat.timestamp.clone();
+/// The command line spelling of the setting `key`.
+fn scalar_setting(key: &str, value: toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(value) => Ok(value),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        other => Err(format!(
+            "`{}` must be a string, a number or a boolean, not {}",
+            key,
+            other.type_str()
+        )),
+    }
+}
 
-            if seconds_since < hearthbeat_interval_secs {
-                n_messages_sent += data.received_count;
+impl Args {
+    /// Checks what clap can't check on a single flag.
+    fn validate(&self) -> Result<(), String> {
+        if self.nodes == 0 || self.nodes > u64::from(u16::MAX) - PORT_BASE {
+            return Err(format!(
+                "--nodes must be between 1 and {}",
+                u64::from(u16::MAX) - PORT_BASE
+            ));
+        }
+        if self.seeds == 0 || self.seeds > self.nodes {
+            return Err("--seeds must be between 1 and --nodes".to_string());
+        }
+        if self.workers == Some(0) {
+            return Err("--workers must be greater than 0".to_string());
+        }
+        if self.repeat == 0 {
+            return Err("--repeat must be greater than 0".to_string());
+        }
+        if (!self.sweep.is_empty() || self.repeat > 1) && self.duration.is_none() {
+            return Err("--sweep and --repeat need a --duration for every run".to_string());
+        }
+        if self.churn_mtbf.is_some_and(|mtbf| mtbf.is_zero()) || self.churn_downtime.is_zero() {
+            return Err("--churn-mtbf and --churn-downtime must be longer than 0s".to_string());
+        }
+        if self.start_rate.is_some() && self.start_jitter.is_some() {
+            return Err("--start-rate and --start-jitter can't be used together".to_string());
+        }
+        if self.warm_start && (self.start_rate.is_some() || self.start_jitter.is_some()) {
+            return Err(
+                "--warm-start has every node converged from the start, it can't ramp up"
+                    .to_string(),
+            );
+        }
+        if self.convergence_samples == 0 {
+            return Err("--convergence-samples must be greater than 0".to_string());
+        }
+        if self.sample_interval.is_zero() {
+            return Err("--sample-interval must be longer than 0s".to_string());
+        }
+        if self
+            .trace_heartbeat
+            .is_some_and(|trace| trace.node >= self.nodes)
+        {
+            return Err(format!(
+                "--trace-heartbeat traces a node from 0 to {}",
+                self.nodes - 1
+            ));
+        }
+        if self.trace_heartbeat.is_some() && (!self.sweep.is_empty() || self.repeat > 1) {
+            return Err(
+                "--trace-heartbeat traces a single run, not a --sweep or --repeat".to_string(),
+            );
+        }
+        if self.metrics_address.is_some() && (!self.sweep.is_empty() || self.repeat > 1) {
+            return Err(
+                "--metrics-address serves a single run, not a --sweep or --repeat".to_string(),
+            );
+        }
+        if self.processes {
+            // the nodes share nothing with the orchestrator but their admin queries
+            let unsupported = [
+                ("--workers", self.workers.is_some()),
+                ("--in-memory", self.in_memory),
+                ("--sweep", !self.sweep.is_empty()),
+                ("--repeat", self.repeat > 1),
+                ("--churn-mtbf", self.churn_mtbf.is_some()),
+                (
+                    "the --assert flags",
+                    self.assert_converged_within.is_some()
+                        || self.assert_max_messages_per_node_per_sec.is_some()
+                        || self.assert_detection_latency.is_some(),
+                ),
+                (
+                    "--start-rate and --start-jitter",
+                    self.start_rate.is_some() || self.start_jitter.is_some(),
+                ),
+                ("--warm-start", self.warm_start),
+                ("--put-value", self.put_value.is_some()),
+                ("--out", self.out.is_some()),
+                ("--trace-heartbeat", self.trace_heartbeat.is_some()),
+                ("--metrics-address", self.metrics_address.is_some()),
+                (
+                    "the network faults",
+                    self.loss > 0.0
+                        || !self.source_loss.is_empty()
+                        || !self.pair_loss.is_empty()
+                        || !self.latency.is_zero()
+                        || !self.jitter.is_zero()
+                        || !self.pair_latency.is_empty()
+                        || self.duplicate > 0.0
+                        || self.reorder > 0.0
+                        || !self.partition.is_empty()
+                        || self.byzantine.is_some(),
+                ),
+            ];
+            if let Some((flag, _)) = unsupported.iter().find(|(_, given)| *given) {
+                return Err(format!("{} can't be used with --processes", flag));
+            }
+        }
+        if (self.bandwidth.is_some() || !self.pair_bandwidth.is_empty()) && !self.in_memory {
+            return Err("--bandwidth and --pair-bandwidth need --in-memory".to_string());
+        }
+        if let Some(out) = &self.out {
+            if out.extension().is_some_and(|extension| extension == "json") {
+                return Err("--out can't be a .json file, the report goes there".to_string());
+            }
+        }
+        let faulty_nodes = self
+            .source_loss
+            .iter()
+            .map(|source| source.from)
+            .chain(self.pair_loss.iter().flat_map(|pair| [pair.from, pair.to]))
+            .chain(
+                self.pair_latency
+                    .iter()
+                    .flat_map(|pair| [pair.from, pair.to]),
+            )
+            .chain(
+                self.pair_bandwidth
+                    .iter()
+                    .flat_map(|pair| [pair.from, pair.to]),
+            )
+            .chain(
+                self.partition
+                    .iter()
+                    .flat_map(|partition| partition.groups.iter().flatten().copied()),
+            )
+            .chain(
+                self.byzantine
+                    .iter()
+                    .flat_map(|nodes| nodes.0.iter().copied()),
+            );
+        for node in faulty_nodes {
+            if node >= self.nodes {
+                return Err(format!(
+                    "node {} given for the network faults doesn't exist, nodes go from 0 to {}",
+                    node,
+                    self.nodes - 1
+                ));
+            }
+        }
+        if self.schedule().iter().any(|event| {
+            matches!(
+                event.action,
+                Action::AddNodes { count } if count as u64 > u64::from(u16::MAX) - PORT_BASE - self.nodes
+            )
+        }) {
+            return Err("add-nodes can't add that many nodes".to_string());
+        }
+        if self.group.iter().map(|group| group.nodes).sum::<u64>() > self.nodes {
+            return Err("the node groups have more nodes than --nodes".to_string());
+        }
+        for (position, group) in self.group.iter().enumerate() {
+            if self.group[..position]
+                .iter()
+                .any(|other| other.name == group.name)
+            {
+                return Err(format!("there are two node groups named {}", group.name));
             }
+        }
+        // everything else a node checks itself, in every group
+        let mut first_indexes: Vec<u64> = self
+            .group_ranges()
+            .iter()
+            .filter(|(_, range)| !range.is_empty())
+            .map(|(_, range)| range.start)
+            .collect();
+        first_indexes.push(self.nodes - 1);
+        for index in first_indexes {
+            self.node_config(index).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
 
-            if seconds_since < healthy_threshold_secs {
-                nr_with_latest += 1
+    /// The events to execute in order, as given with --event or else as given by the kill,
+    /// revival, decay change and leaves flags.
+    fn schedule(&self) -> Vec<ScheduledEvent> {
+        let mut schedule = self.event.clone();
+        if schedule.is_empty() {
+            let kill_at = self.kill_after;
+            let revive_at = kill_at + self.revive_after;
+            let change_decay_at = revive_at + self.change_decay_after;
+            let leaves_at = change_decay_at + self.leaves_after;
+            if self.kill > 0 {
+                schedule.push(ScheduledEvent {
+                    at: kill_at,
+                    action: Action::Kill {
+                        nodes: NodeSelection::Random(self.kill),
+                    },
+                });
+                schedule.push(ScheduledEvent {
+                    at: revive_at,
+                    action: Action::Revive {
+                        nodes: NodeSelection::All,
+                    },
+                });
+            }
+            // once the network has recovered, the decay factor is raised to show its effect on
+            // the number of messages sent
+            schedule.push(ScheduledEvent {
+                at: change_decay_at,
+                action: Action::Config {
+                    nodes: NodeSelection::All,
+                    update: gossip::ConfigUpdate {
+                        decay_factor: Some(self.changed_decay),
+                        ..Default::default()
+                    },
+                },
+            });
+            // later still, some nodes stop forwarding and only send their own heartbeats
+            if self.leaves > 0 {
+                schedule.push(ScheduledEvent {
+                    at: leaves_at,
+                    action: Action::StopForwarding {
+                        nodes: NodeSelection::Random(self.leaves),
+                    },
+                });
             }
         }
+        // events at the same time run in the order they were given
+        schedule.sort_by_key(|event| event.at);
+        schedule
+    }
 
-        if nr_with_latest != number_nodes {
-            continue;
+    /// The network connecting the nodes with --in-memory, with links of a limited bandwidth if
+    /// asked for.
+    fn memory_network(&self) -> MemoryNetwork {
+        if self.bandwidth.is_none() && self.pair_bandwidth.is_empty() {
+            return MemoryNetwork::new();
+        }
+        let address = |node| node_address(PORT_BASE + node);
+        let mut fabric = Fabric::new(self.link_queue);
+        if let Some(bandwidth) = self.bandwidth {
+            fabric = fabric.bandwidth(bandwidth);
+        }
+        for pair in &self.pair_bandwidth {
+            fabric = fabric.pair_bandwidth(address(pair.from), address(pair.to), pair.bandwidth);
         }
+        MemoryNetwork::with_fabric(fabric)
+    }
 
-        n_fully_informed += 1
+    /// What hosts the nodes instead of a thread each: the pool of --workers, or for in-memory
+    /// nodes a stepped runtime, so that a seeded run plays out the same every time. It keeps up
+    /// with the wall clock while the charts are drawn or the metrics served.
+    fn sim_runtime(&self) -> Option<Arc<SimRuntime>> {
+        let watched = !self.headless || self.metrics_address.is_some();
+        match self.workers {
+            Some(workers) => Some(Arc::new(SimRuntime::new(workers))),
+            None if self.in_memory => Some(Arc::new(SimRuntime::stepped(watched))),
+            None => None,
+        }
     }
 
-    return (
-        n_fully_informed as f32,
-        n_know_all as f32,
-        n_messages_sent as f32,
-    );
+    /// The injector dropping and delaying datagrams as asked by the loss and latency flags, none
+    /// if the network is to be left alone and the scenario doesn't change it.
+    fn faults(&self, clock: &Arc<dyn Clock>) -> Option<Arc<FaultInjector>> {
+        let scheduled = self.schedule().iter().any(|event| {
+            matches!(
+                event.action,
+                Action::Partition { .. } | Action::SetLoss { .. }
+            )
+        });
+        // the cluster can be split by hand while the charts are shown
+        if !scheduled
+            && self.headless
+            && self.loss == 0.0
+            && self.source_loss.is_empty()
+            && self.pair_loss.is_empty()
+            && self.latency.is_zero()
+            && self.jitter.is_zero()
+            && self.pair_latency.is_empty()
+            && self.duplicate == 0.0
+            && self.reorder == 0.0
+            && self.partition.is_empty()
+            && self.byzantine.is_none()
+        {
+            return None;
+        }
+        let address = |node| node_address(PORT_BASE + node);
+        let mut faults = FaultInjector::new(
+            self.derived_seed(SeedPurpose::Faults, 0)
+                .unwrap_or_else(rand::random),
+        )
+        .clock(clock.clone())
+        .loss(self.loss);
+        for source in &self.source_loss {
+            faults = faults.source_loss(address(source.from), source.loss);
+        }
+        for pair in &self.pair_loss {
+            faults = faults.pair_loss(address(pair.from), address(pair.to), pair.loss);
+        }
+        faults = faults.latency(self.latency, self.jitter);
+        for pair in &self.pair_latency {
+            faults = faults.pair_latency(address(pair.from), address(pair.to), pair.latency);
+        }
+        faults = faults
+            .duplication(self.duplicate, self.duplicate_copies)
+            .reorder(self.reorder, self.reorder_window);
+        for &node in self.byzantine.iter().flat_map(|nodes| &nodes.0) {
+            faults = faults.byzantine(address(node), &self.misbehavior, self.misbehavior_rate);
+        }
+        for partition in &self.partition {
+            faults = faults.partition(Partition {
+                groups: partition
+                    .groups
+                    .iter()
+                    .map(|group| group.iter().map(|&node| address(node)).collect())
+                    .collect(),
+                from: partition.from,
+                until: partition.until,
+            });
+        }
+        Some(Arc::new(faults))
+    }
+
+    fn node_config(&self, index: u64) -> Result<gossip::NodeConfig, gossip::ConfigError> {
+        // the settings of the node's group override the common ones
+        let group = self.group_of(index).map(|(group, _)| group);
+        let update = group.map(|group| &group.update);
+        let config = gossip::NodeConfig::builder()
+            .id(node_id(index))
+            .address(node_address(PORT_BASE + index).to_string())
+            .heartbeat_interval(
+                update
+                    .and_then(|update| update.heartbeat_interval)
+                    .unwrap_or(self.heartbeat_interval),
+            )
+            .spread(
+                update
+                    .and_then(|update| update.heartbeat_spread)
+                    .unwrap_or(self.spread),
+            )
+            .poll_interval(defaults::POLL_INTERVAL)
+            .decay(
+                update
+                    .and_then(|update| update.decay_factor)
+                    .unwrap_or(self.decay),
+            )
+            .suspect_timeout(
+                group
+                    .and_then(|group| group.suspect_timeout)
+                    .unwrap_or(self.suspect_timeout),
+            )
+            .dead_timeout(
+                group
+                    .and_then(|group| group.dead_timeout)
+                    .unwrap_or(self.dead_timeout),
+            )
+            .expected_cluster_size(self.nodes as usize);
+        let config = match self.max_entries {
+            Some(max_entries) => config.max_entries(max_entries),
+            None => config,
+        };
+        match self.derived_seed(SeedPurpose::Node, index) {
+            Some(seed) => config.rng_seed(seed),
+            None => config,
+        }
+        .build()
+    }
+
+    /// Both directions of every link given a loss or latency of its own, as the indexes of the
+    /// sender and the receiver, in the order the fault injector reports them.
+    fn links(&self) -> Vec<(u64, u64)> {
+        let mut links: Vec<(u64, u64)> = self
+            .pair_loss
+            .iter()
+            .map(|pair| (pair.from, pair.to))
+            .chain(self.pair_latency.iter().map(|pair| (pair.from, pair.to)))
+            .flat_map(|(from, to)| [(from, to), (to, from)])
+            .collect();
+        links.sort();
+        links.dedup();
+        links
+    }
+
+    /// The group the node with the given index belongs to and the indexes of the group, None for
+    /// nodes with the common config.
+    fn group_of(&self, index: u64) -> Option<(&NodeGroup, Range<u64>)> {
+        self.group_ranges()
+            .into_iter()
+            .find(|(_, range)| range.contains(&index))
+    }
+
+    /// Every node group with the indexes of its nodes, which follow each other in the order of
+    /// the groups.
+    fn group_ranges(&self) -> Vec<(&NodeGroup, Range<u64>)> {
+        let mut first = 0;
+        self.group
+            .iter()
+            .map(|group| {
+                let range = first..first + group.nodes;
+                first = range.end;
+                (group, range)
+            })
+            .collect()
+    }
+
+    /// When each of the initial nodes starts, counted from the start: all at once unless the
+    /// startup is ramped with --start-rate or --start-jitter.
+    fn start_offsets(&self) -> Vec<Duration> {
+        if let Some(rate) = self.start_rate {
+            return (0..self.nodes)
+                .map(|index| Duration::from_secs_f64(index as f64 / rate))
+                .collect();
+        }
+        let Some(window) = self.start_jitter else {
+            return vec![Duration::ZERO; self.nodes as usize];
+        };
+        let mut rng = match self.derived_seed(SeedPurpose::Startup, 0) {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut offsets = vec![Duration::ZERO];
+        offsets.extend((1..self.nodes).map(|_| rng.gen_range(window.from..=window.until)));
+        offsets
+    }
+
+    /// The seed of one of the random streams of the simulation, derived from the master seed.
+    /// Hashing rather than adding the index keeps the streams of neighbouring master seeds apart,
+    /// so that `--seed 1` and `--seed 2` share no node's stream.
+    fn derived_seed(&self, purpose: SeedPurpose, index: u64) -> Option<u64> {
+        let seed = self.seed?;
+        // DefaultHasher::new is keyed the same in every process
+        let mut hasher = DefaultHasher::new();
+        (seed, purpose, index).hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    /// The effective configuration, with the keys and values of a scenario file.
+    fn settings(&self) -> serde_json::Value {
+        let duration = |duration: Duration| humantime::format_duration(duration).to_string();
+        json!({
+            "nodes": self.nodes,
+            "seeds": self.seeds,
+            "start_rate": self.start_rate.map(|rate| format!("{}/s", rate)),
+            "start_jitter": self.start_jitter.map(|window| window.to_string()),
+            "warm_start": self.warm_start,
+            "heartbeat_interval": duration(self.heartbeat_interval),
+            "spread": self.spread,
+            "decay": self.decay,
+            "suspect_timeout": duration(self.suspect_timeout),
+            "dead_timeout": duration(self.dead_timeout),
+            "kill": self.kill,
+            "kill_after": duration(self.kill_after),
+            "revive_after": duration(self.revive_after),
+            "change_decay_after": duration(self.change_decay_after),
+            "changed_decay": self.changed_decay,
+            "leaves_after": duration(self.leaves_after),
+            "leaves": self.leaves,
+            "seed": self.seed,
+            "workers": self.workers,
+            "in_memory": self.in_memory,
+            "restart": self.restart,
+            "put_value": self.put_value.map(duration),
+            "loss": self.loss,
+            "source_loss": self
+                .source_loss
+                .iter()
+                .map(|source| format!("{}={}", source.from, source.loss))
+                .collect::<Vec<_>>(),
+            "pair_loss": self
+                .pair_loss
+                .iter()
+                .map(|pair| format!("{}:{}={}", pair.from, pair.to, pair.loss))
+                .collect::<Vec<_>>(),
+            "latency": duration(self.latency),
+            "jitter": duration(self.jitter),
+            "duplicate": self.duplicate,
+            "duplicate_copies": self.duplicate_copies,
+            "reorder": self.reorder,
+            "reorder_window": duration(self.reorder_window),
+            "pair_latency": self
+                .pair_latency
+                .iter()
+                .map(|pair| format!("{}:{}={}", pair.from, pair.to, duration(pair.latency)))
+                .collect::<Vec<_>>(),
+            "bandwidth": self.bandwidth,
+            "pair_bandwidth": self
+                .pair_bandwidth
+                .iter()
+                .map(|pair| format!("{}:{}={}", pair.from, pair.to, pair.bandwidth))
+                .collect::<Vec<_>>(),
+            "link_queue": self.link_queue,
+            "partition": self
+                .partition
+                .iter()
+                .map(|partition| {
+                    let groups: Vec<String> =
+                        partition.groups.iter().map(|group| format_group(group)).collect();
+                    match partition.until {
+                        Some(until) => format!(
+                            "{}@{}..{}",
+                            groups.join("/"),
+                            duration(partition.from),
+                            duration(until)
+                        ),
+                        None => format!("{}@{}", groups.join("/"), duration(partition.from)),
+                    }
+                })
+                .collect::<Vec<_>>(),
+            "event": self.event.iter().map(|event| event.to_string()).collect::<Vec<_>>(),
+            "churn_mtbf": self.churn_mtbf.map(duration),
+            "churn_downtime": duration(self.churn_downtime),
+            "churn_after": duration(self.churn_after),
+            "group": self.group.iter().map(|group| group.to_string()).collect::<Vec<_>>(),
+            "duration": self.duration.map(duration),
+            "sweep": self
+                .sweep
+                .iter()
+                .map(|axis| format!("{}={}", axis.key, axis.values.join(",")))
+                .collect::<Vec<_>>(),
+            "repeat": self.repeat,
+            "convergence_samples": self.convergence_samples,
+            "assert_converged_within": self.assert_converged_within.map(duration),
+            "assert_max_messages_per_node_per_sec": self.assert_max_messages_per_node_per_sec,
+            "assert_detection_latency": self.assert_detection_latency.map(duration),
+            "sample_interval": duration(self.sample_interval),
+            "headless": self.headless,
+            "chart_heartbeats_received": self.chart_heartbeats_received,
+            "processes": self.processes,
+            "byzantine": self.byzantine.as_ref().map(|nodes| format_group(&nodes.0)),
+            "misbehavior": self
+                .misbehavior
+                .iter()
+                .map(Misbehavior::to_string)
+                .collect::<Vec<_>>(),
+            "misbehavior_rate": self.misbehavior_rate,
+            "max_entries": self.max_entries,
+            "trace_heartbeat": self.trace_heartbeat.map(|trace| trace.to_string()),
+            "metrics_address": self.metrics_address,
+        })
+    }
+
+    /// One line summing up the effective configuration.
+    fn banner(&self, threads: &str) -> String {
+        let mut banner = format!(
+            "{} nodes ({} seeds) on {}, heartbeat every {} to {} peers, decay {}, suspect after {}, \
+             dead after {}; ",
+            self.nodes,
+            self.seeds,
+            threads,
+            humantime::format_duration(self.heartbeat_interval),
+            self.spread,
+            self.decay,
+            humantime::format_duration(self.suspect_timeout),
+            humantime::format_duration(self.dead_timeout),
+        );
+        if self.event.is_empty() {
+            banner += &format!(
+                "kill {} after {}, revive after {}{}, decay {} after {}, {} leaves after {}",
+                self.kill,
+                humantime::format_duration(self.kill_after),
+                humantime::format_duration(self.revive_after),
+                if self.restart { " by restart" } else { "" },
+                self.changed_decay,
+                humantime::format_duration(self.change_decay_after),
+                self.leaves,
+                humantime::format_duration(self.leaves_after),
+            );
+        } else {
+            banner += &format!(
+                "{} scheduled events{}",
+                self.event.len(),
+                if self.restart {
+                    ", kills by restart"
+                } else {
+                    ""
+                }
+            );
+        }
+        for (group, range) in self.group_ranges() {
+            banner += &format!(
+                ", group {} of nodes {}..{}",
+                group.name, range.start, range.end
+            );
+            if let Some(interval) = group.update.heartbeat_interval {
+                banner += &format!(" every {}", humantime::format_duration(interval));
+            }
+            if let Some(spread) = group.update.heartbeat_spread {
+                banner += &format!(" to {} peers", spread);
+            }
+            if let Some(decay) = group.update.decay_factor {
+                banner += &format!(" decay {}", decay);
+            }
+        }
+        if let Some(rate) = self.start_rate {
+            banner += &format!(", starting {} nodes a second", rate);
+        }
+        if let Some(window) = self.start_jitter {
+            banner += &format!(", starting within {}", window);
+        }
+        if self.warm_start {
+            banner += ", starting converged";
+        }
+        if let Some(seed) = self.seed {
+            banner += &format!(", seed {}", seed);
+        }
+        if let Some(duration) = self.duration {
+            banner += &format!(", for {}", humantime::format_duration(duration));
+        }
+        if self.loss > 0.0 {
+            banner += &format!(", {}% loss", self.loss * 100.0);
+        }
+        if self.duplicate > 0.0 {
+            banner += &format!(
+                ", {}% duplicated {}x",
+                self.duplicate * 100.0,
+                self.duplicate_copies + 1
+            );
+        }
+        if self.reorder > 0.0 {
+            banner += &format!(
+                ", {}% reordered within {}",
+                self.reorder * 100.0,
+                humantime::format_duration(self.reorder_window)
+            );
+        }
+        if !self.source_loss.is_empty() || !self.pair_loss.is_empty() {
+            banner += &format!(
+                ", loss set for {} senders and {} pairs",
+                self.source_loss.len(),
+                self.pair_loss.len()
+            );
+        }
+        if !self.latency.is_zero() || !self.jitter.is_zero() {
+            banner += &format!(
+                ", latency {} + up to {}",
+                humantime::format_duration(self.latency),
+                humantime::format_duration(self.jitter)
+            );
+        }
+        if !self.pair_latency.is_empty() {
+            banner += &format!(", latency set for {} pairs", self.pair_latency.len());
+        }
+        for partition in &self.partition {
+            let sizes: Vec<String> = partition
+                .groups
+                .iter()
+                .map(|group| group.len().to_string())
+                .collect();
+            banner += &format!(
+                ", partition into {} nodes after {}",
+                sizes.join("/"),
+                humantime::format_duration(partition.from)
+            );
+            if let Some(until) = partition.until {
+                banner += &format!(" until {}", humantime::format_duration(until));
+            }
+        }
+        if let Some(nodes) = &self.byzantine {
+            let misbehaviors: Vec<String> = self
+                .misbehavior
+                .iter()
+                .map(Misbehavior::to_string)
+                .collect();
+            banner += &format!(
+                ", byzantine nodes {} corrupting {}% of their datagrams ({})",
+                format_group(&nodes.0),
+                self.misbehavior_rate * 100.0,
+                misbehaviors.join(", ")
+            );
+        }
+        if let Some(max_entries) = self.max_entries {
+            banner += &format!(", at most {} entries per node", max_entries);
+        }
+        if let Some(after) = self.put_value {
+            banner += &format!(", value put after {}", humantime::format_duration(after));
+        }
+        if self.in_memory {
+            banner += ", in-memory network";
+            if let Some(bandwidth) = self.bandwidth {
+                banner += &format!(
+                    " with links of {}/s and {} queues",
+                    format_bytes(bandwidth),
+                    format_bytes(self.link_queue)
+                );
+            }
+        }
+        if self.sample_interval != SAMPLE_INTERVAL {
+            banner += &format!(
+                ", sampled every {}",
+                humantime::format_duration(self.sample_interval)
+            );
+        }
+        banner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The command line with the scenario file holding `contents` as its defaults.
+    fn scenario(name: &str, contents: &str) -> Result<Command, String> {
+        let path =
+            std::env::temp_dir().join(format!("muck-scenario-{}-{}.toml", process::id(), name));
+        fs::write(&path, contents).unwrap();
+        let command = scenario_defaults(Args::command(), &path);
+        let _ = fs::remove_file(&path);
+        command
+    }
+
+    fn args(command: Command, flags: &[&str]) -> Args {
+        let matches = command
+            .try_get_matches_from(std::iter::once("muck").chain(flags.iter().copied()))
+            .unwrap();
+        Args::from_arg_matches(&matches).unwrap()
+    }
+
+    #[test]
+    fn scenario_settings_become_defaults_that_flags_override() {
+        let command = scenario(
+            "defaults",
+            r#"
+            nodes = 7
+            decay = 0.5
+            heartbeat-interval = "2s"
+            event = [{ at = "3s", action = "kill", nodes = "all" }]
+            "#,
+        )
+        .unwrap();
+        let from_file = args(command.clone(), &[]);
+        assert_eq!(from_file.nodes, 7);
+        assert_eq!(from_file.decay, 0.5);
+        assert_eq!(from_file.event.len(), 1);
+        assert_eq!(
+            from_file.event[0].to_string(),
+            "at=3s action=kill nodes=all"
+        );
+        assert_eq!(args(command, &["--nodes", "9"]).nodes, 9);
+    }
+
+    #[test]
+    fn scenario_settings_are_checked_like_flags() {
+        let error = |contents| scenario("invalid", contents).err().unwrap();
+        assert_eq!(error("colour = 1"), "unknown setting `colour`");
+        assert_eq!(error("config = \"other.toml\""), "unknown setting `config`");
+        assert!(error("nodes = \"many\"").starts_with("invalid value \"many\" for `nodes`"));
+        assert!(error("nodes = [1, 2]").contains("not array"));
+        assert!(error("nodes =").contains("line 1"), "{}", error("nodes ="));
+    }
+
+    #[test]
+    fn every_shipped_scenario_loads() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let command = scenario_defaults(Args::command(), &path)
+                .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            let args = args(command, &[]);
+            assert!(args.validate().is_ok(), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn streams_are_derived_from_the_master_seed() {
+        let seeded = |seed: &str| args(Args::command(), &["--seed", seed]);
+        let one = seeded("1");
+        assert_eq!(
+            one.derived_seed(SeedPurpose::Node, 3),
+            seeded("1").derived_seed(SeedPurpose::Node, 3)
+        );
+        assert_ne!(
+            one.derived_seed(SeedPurpose::Node, 3),
+            one.derived_seed(SeedPurpose::Node, 4)
+        );
+        assert_ne!(
+            one.derived_seed(SeedPurpose::Node, 0),
+            one.derived_seed(SeedPurpose::Schedule, 0)
+        );
+        assert_ne!(
+            one.derived_seed(SeedPurpose::Schedule, 0),
+            one.derived_seed(SeedPurpose::Faults, 0)
+        );
+        // neighbouring master seeds share no node's stream
+        let two = seeded("2");
+        for index in 0..100 {
+            assert_ne!(
+                one.derived_seed(SeedPurpose::Node, index + 1),
+                two.derived_seed(SeedPurpose::Node, index)
+            );
+        }
+        assert_eq!(
+            args(Args::command(), &[]).derived_seed(SeedPurpose::Node, 0),
+            None
+        );
+    }
 }
@@ -0,0 +1,482 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::gossip::{Heartbeat, NodeHeartbeatData, Storage};
+use crate::node_id::NodeId;
+
+/// Health of a peer as seen from the local node, derived from the age of its latest heartbeat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PeerState {
+    Alive,
+    Suspect,
+    Dead,
+    /// the peer announced that it left the cluster
+    Left,
+}
+
+impl PeerState {
+    pub fn of(
+        heartbeat: &Heartbeat,
+        age_secs: u64,
+        suspect_after: Duration,
+        dead_after: Duration,
+    ) -> PeerState {
+        if heartbeat.is_leaving() {
+            return PeerState::Left;
+        }
+        PeerState::from_age(age_secs, suspect_after, dead_after)
+    }
+
+    pub fn from_age(age_secs: u64, suspect_after: Duration, dead_after: Duration) -> PeerState {
+        if age_secs >= dead_after.as_secs() {
+            PeerState::Dead
+        } else if age_secs >= suspect_after.as_secs() {
+            PeerState::Suspect
+        } else {
+            PeerState::Alive
+        }
+    }
+
+    /// Why a peer whose latest heartbeat is `age_secs` old is in this state.
+    pub fn cause(self, age_secs: u64, suspect_after: Duration, dead_after: Duration) -> String {
+        match self {
+            PeerState::Alive => format!("fresh heartbeat, {}s old", age_secs),
+            PeerState::Suspect => format!(
+                "no heartbeat for {}s, suspect after {}s",
+                age_secs,
+                suspect_after.as_secs()
+            ),
+            PeerState::Dead => format!(
+                "no heartbeat for {}s, dead after {}s",
+                age_secs,
+                dead_after.as_secs()
+            ),
+            PeerState::Left => "announced leave".to_string(),
+        }
+    }
+}
+
+/// Which clock a peer's age is measured against when deciding whether it is alive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AgeSource {
+    /// the timestamp the peer put into its heartbeat, i.e. the peer's clock
+    #[default]
+    SenderTimestamp,
+    /// when the heartbeat arrived here, i.e. the local clock; immune to clock skew between
+    /// nodes
+    LocalReceipt,
+}
+
+// transitions kept per peer, older ones are dropped
+pub const STATE_HISTORY_LEN: usize = 16;
+
+/// A change of a peer's [`PeerState`], recorded by [`crate::gossip::Storage::evaluate`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StateTransition {
+    pub state: PeerState,
+    /// local time the change was noticed at
+    pub at: u64,
+    pub cause: String,
+}
+
+/// Point-in-time view of a single member, returned by value from
+/// [`crate::gossip::Node::members`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberInfo {
+    pub id: NodeId,
+    pub address: SocketAddr,
+    /// timestamp of the latest heartbeat known for the member
+    pub last_heartbeat: u64,
+    /// age according to the member's own timestamp
+    pub age: Duration,
+    /// time since the member's latest heartbeat arrived here
+    pub observed_age: Duration,
+    pub state: PeerState,
+    /// heartbeats of the member applied here
+    pub updates_received: u64,
+    /// copies received of heartbeats already known here
+    pub duplicates_received: u64,
+}
+
+impl MemberInfo {
+    /// The member stored in `data`, as of local clock time `now`.
+    pub fn of(data: &NodeHeartbeatData, now: u64, state: PeerState) -> MemberInfo {
+        MemberInfo {
+            id: data.heartbeat.id().clone(),
+            address: data.heartbeat.address(),
+            last_heartbeat: data.heartbeat.timestamp(),
+            age: Duration::from_secs(data.claimed_age(now)),
+            observed_age: Duration::from_secs(data.observed_age(now)),
+            state,
+            updates_received: data.updates_received,
+            duplicates_received: data.duplicates_received,
+        }
+    }
+}
+
+/// Details about the peer a [`MembershipEvent`] refers to.
+#[derive(Debug, Clone)]
+pub struct PeerChange {
+    pub id: NodeId,
+    pub address: SocketAddr,
+    /// timestamp of the latest heartbeat known for the peer
+    pub last_heartbeat: u64,
+    /// local time the change was observed at
+    pub observed_at: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum MembershipEvent {
+    NodeJoined(PeerChange),
+    NodeRecovered(PeerChange),
+    NodeSuspect(PeerChange),
+    NodeDead(PeerChange),
+    NodeLeft(PeerChange),
+    NodeRemoved(PeerChange),
+    /// a node at another address sent heartbeats using the local node's id
+    IdConflict(PeerChange),
+    /// the local node's loop panicked, `reason` tells whether it is restarted
+    LoopPanicked(PeerChange),
+    /// a value put by the peer was stored, see [`crate::kv`]; `reason` names the key
+    KeyChanged(PeerChange),
+}
+
+impl MembershipEvent {
+    pub fn peer(&self) -> &PeerChange {
+        match self {
+            MembershipEvent::NodeJoined(peer)
+            | MembershipEvent::NodeRecovered(peer)
+            | MembershipEvent::NodeSuspect(peer)
+            | MembershipEvent::NodeDead(peer)
+            | MembershipEvent::NodeLeft(peer)
+            | MembershipEvent::NodeRemoved(peer)
+            | MembershipEvent::IdConflict(peer)
+            | MembershipEvent::LoopPanicked(peer)
+            | MembershipEvent::KeyChanged(peer) => peer,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MembershipEvent::NodeJoined(_) => "joined",
+            MembershipEvent::NodeRecovered(_) => "recovered",
+            MembershipEvent::NodeSuspect(_) => "suspect",
+            MembershipEvent::NodeDead(_) => "dead",
+            MembershipEvent::NodeLeft(_) => "left",
+            MembershipEvent::NodeRemoved(_) => "removed",
+            MembershipEvent::IdConflict(_) => "id conflict",
+            MembershipEvent::LoopPanicked(_) => "loop panicked",
+            MembershipEvent::KeyChanged(_) => "key changed",
+        }
+    }
+}
+
+// number of events a subscriber can lag behind before the oldest ones are dropped
+pub const EVENT_BUFFER_CAPACITY: usize = 1024;
+
+/// Receiving end of [`crate::gossip::Node::subscribe`]. Holds at most
+/// [`EVENT_BUFFER_CAPACITY`] undelivered events; when a slow reader falls further behind the
+/// oldest events are dropped and counted in [`Subscription::dropped`].
+pub struct Subscription {
+    queue: Arc<EventQueue>,
+}
+
+impl Subscription {
+    pub fn try_recv(&self) -> Option<MembershipEvent> {
+        self.queue.lock().pop_front()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<MembershipEvent> {
+        let events = self.queue.lock();
+        let result = self
+            .queue
+            .available
+            .wait_timeout_while(events, timeout, |events| events.is_empty());
+        let (mut events, _) = match result {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        events.pop_front()
+    }
+
+    /// Number of events dropped because this subscriber lagged behind.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+}
+
+struct EventQueue {
+    events: Mutex<VecDeque<MembershipEvent>>,
+    available: Condvar,
+    dropped: AtomicU64,
+}
+
+impl EventQueue {
+    fn lock(&self) -> MutexGuard<'_, VecDeque<MembershipEvent>> {
+        self.events.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn push(&self, event: MembershipEvent) {
+        let mut events = self.lock();
+        if events.len() >= EVENT_BUFFER_CAPACITY {
+            events.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        events.push_back(event);
+        self.available.notify_one();
+    }
+}
+
+/// Fans membership events out to every live subscription of a node.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<Arc<EventQueue>>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&self) -> Subscription {
+        let queue = Arc::new(EventQueue {
+            events: Mutex::new(VecDeque::new()),
+            available: Condvar::new(),
+            dropped: AtomicU64::new(0),
+        });
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(queue.clone());
+        }
+        Subscription { queue }
+    }
+
+    pub fn publish(&self, event: MembershipEvent) {
+        let Ok(mut subscribers) = self.subscribers.lock() else {
+            return;
+        };
+        // forget subscriptions whose receiving end was dropped
+        subscribers.retain(|queue| Arc::strong_count(queue) > 1);
+        for queue in subscribers.iter() {
+            queue.push(event.clone());
+        }
+    }
+}
+
+/// Remembers the last state reported for every peer so that only transitions become events.
+/// Owned by the node loop, so it needs no locking of its own.
+pub struct MembershipTracker {
+    own_id: NodeId,
+    suspect_after: Duration,
+    dead_after: Duration,
+    age_source: AgeSource,
+    // last reported state and address of every peer
+    states: HashMap<NodeId, (PeerState, SocketAddr)>,
+}
+
+impl MembershipTracker {
+    pub fn new(own_id: NodeId, suspect_after: Duration, dead_after: Duration) -> Self {
+        MembershipTracker {
+            own_id,
+            suspect_after,
+            dead_after,
+            age_source: AgeSource::default(),
+            states: HashMap::new(),
+        }
+    }
+
+    /// Makes room for `size` peers up front.
+    pub fn with_expected_size(mut self, size: usize) -> Self {
+        self.states.reserve(size);
+        self
+    }
+
+    /// Measures the age of stored entries with `age_source`, see [`MembershipTracker::observe_entry`].
+    pub fn with_age_source(mut self, age_source: AgeSource) -> Self {
+        self.age_source = age_source;
+        self
+    }
+
+    /// Re-evaluates every entry in `storage` against the thresholds and returns the resulting
+    /// transitions, including peers that disappeared from the storage.
+    pub fn evaluate(&mut self, storage: &Storage, now: u64) -> Vec<MembershipEvent> {
+        let mut events = vec![];
+        for data in storage.data.values() {
+            if let Some(event) = self.observe_entry(data, now) {
+                events.push(event);
+            }
+        }
+
+        let removed: Vec<NodeId> = self
+            .states
+            .keys()
+            .filter(|id| !storage.data.contains_key(*id))
+            .cloned()
+            .collect();
+        for id in removed {
+            let Some((_, address)) = self.states.remove(&id) else {
+                continue;
+            };
+            events.push(MembershipEvent::NodeRemoved(PeerChange {
+                id,
+                address,
+                last_heartbeat: 0,
+                observed_at: now,
+                reason: "no longer in storage".to_string(),
+            }));
+        }
+
+        events
+    }
+
+    /// Stops tracking a peer that was taken out of the storage on purpose and reports it as
+    /// removed with `reason`. Returns `None` for peers that were never reported.
+    pub fn forget(
+        &mut self,
+        heartbeat: &Heartbeat,
+        now: u64,
+        reason: &str,
+    ) -> Option<MembershipEvent> {
+        let (_, address) = self.states.remove(heartbeat.id())?;
+        Some(MembershipEvent::NodeRemoved(PeerChange {
+            id: heartbeat.id().clone(),
+            address,
+            last_heartbeat: heartbeat.timestamp(),
+            observed_at: now,
+            reason: reason.to_string(),
+        }))
+    }
+
+    /// Evaluates a single peer, e.g. right after one of its heartbeats was inserted.
+    /// The heartbeat's age is taken from its own timestamp.
+    pub fn observe(&mut self, heartbeat: &Heartbeat, now: u64) -> Option<MembershipEvent> {
+        self.observe_aged(heartbeat, now.saturating_sub(heartbeat.timestamp()), now)
+    }
+
+    /// Evaluates a stored entry, measuring its age as configured with
+    /// [`MembershipTracker::with_age_source`].
+    /// Provisional entries are not observed, the peer has not joined as far as we know.
+    pub fn observe_entry(&mut self, data: &NodeHeartbeatData, now: u64) -> Option<MembershipEvent> {
+        if data.provisional {
+            return None;
+        }
+        self.observe_aged(&data.heartbeat, data.age(now, self.age_source), now)
+    }
+
+    fn observe_aged(
+        &mut self,
+        heartbeat: &Heartbeat,
+        age: u64,
+        now: u64,
+    ) -> Option<MembershipEvent> {
+        if *heartbeat.id() == self.own_id {
+            return None;
+        }
+        let state = PeerState::of(heartbeat, age, self.suspect_after, self.dead_after);
+        let change = PeerChange {
+            id: heartbeat.id().clone(),
+            address: heartbeat.address(),
+            last_heartbeat: heartbeat.timestamp(),
+            observed_at: now,
+            reason: String::new(),
+        };
+        self.transition(change, state, age)
+    }
+
+    fn transition(
+        &mut self,
+        mut change: PeerChange,
+        state: PeerState,
+        age: u64,
+    ) -> Option<MembershipEvent> {
+        let previous = self
+            .states
+            .insert(change.id.clone(), (state, change.address))
+            .map(|(previous, _)| previous);
+        if previous.is_none() {
+            change.reason = format!("first seen, heartbeat {}s old", age);
+            return Some(MembershipEvent::NodeJoined(change));
+        }
+        if previous == Some(state) {
+            return None;
+        }
+        change.reason = state.cause(age, self.suspect_after, self.dead_after);
+        Some(match state {
+            PeerState::Alive => MembershipEvent::NodeRecovered(change),
+            PeerState::Suspect => MembershipEvent::NodeSuspect(change),
+            PeerState::Dead => MembershipEvent::NodeDead(change),
+            PeerState::Left => MembershipEvent::NodeLeft(change),
+        })
+    }
+}
+
+/// Condition checked by [`crate::gossip::Node::on_convergence`]: the node's view covers the
+/// cluster when every member's heartbeat is younger than `threshold` and, if given, the node
+/// knows at least `expected_size` members, itself included. Members that announced a leave are
+/// not counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConvergenceWatch {
+    pub threshold: Duration,
+    pub expected_size: Option<usize>,
+}
+
+impl ConvergenceWatch {
+    /// Returns the number of members and, when the view does not cover the cluster, why not.
+    pub fn check(&self, storage: &Storage, now: u64) -> (usize, Option<String>) {
+        let mut members = 0;
+        let mut stale = 0;
+        for data in storage.data.values() {
+            if data.heartbeat.is_leaving() || data.provisional {
+                continue;
+            }
+            members += 1;
+            if now.saturating_sub(data.heartbeat.timestamp()) >= self.threshold.as_secs() {
+                stale += 1;
+            }
+        }
+
+        let missing = if stale > 0 {
+            Some(format!(
+                "{} of {} members have no heartbeat younger than {}s",
+                stale,
+                members,
+                self.threshold.as_secs()
+            ))
+        } else {
+            match self.expected_size {
+                Some(expected) if members < expected => Some(format!(
+                    "knows {} of {} expected members",
+                    members, expected
+                )),
+                _ => None,
+            }
+        };
+        (members, missing)
+    }
+}
+
+/// Details about a change reported by a [`ConvergenceEvent`].
+#[derive(Debug, Clone)]
+pub struct ConvergenceChange {
+    /// number of members known when the change was observed
+    pub members: usize,
+    /// local time the change was observed at
+    pub observed_at: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum ConvergenceEvent {
+    /// the node's view started covering the whole cluster
+    Converged(ConvergenceChange),
+    /// the node's view stopped covering the whole cluster
+    Diverged(ConvergenceChange),
+}
+
+impl ConvergenceEvent {
+    pub fn change(&self) -> &ConvergenceChange {
+        match self {
+            ConvergenceEvent::Converged(change) | ConvergenceEvent::Diverged(change) => change,
+        }
+    }
+}
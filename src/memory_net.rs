@@ -0,0 +1,426 @@
+//! An in-memory stand-in for UDP on loopback, for simulations with more nodes than sockets and
+//! ports can comfortably hold. Nodes given a [`MemoryNetwork`] with
+//! [`crate::gossip::NodeConfigBuilder::memory_network`] bind their address on it instead of a
+//! socket, and their datagrams are handed from inbox to inbox without touching the kernel.
+//!
+//! Datagrams keep UDP's manners: one sent to an address nobody bound is lost, one that finds
+//! the inbox of its receiver full is dropped, and nothing is ever acknowledged. Tools that talk
+//! to nodes over real sockets, like `gossip-ctl` and [`crate::admin::query`], can't reach them.
+//!
+//! Without a [`Fabric`], datagrams of any size arrive at once. With one, every link carries so
+//! many bytes per second and queues the datagrams waiting for it, like the queue of a NIC: a
+//! burst arrives late, and what doesn't fit the queue is dropped at its tail.
+//!
+//! The network tells the time by the wall clock, or by the clock it is given with
+//! [`MemoryNetwork::clock`], like the simulated time of a stepped
+//! [`crate::sim_runtime::SimRuntime`].
+
+use std::cmp::{self, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+use crate::clock::{Clock, SystemClock};
+
+/// Datagrams an inbox holds before new ones are dropped, like a socket's receive buffer.
+const INBOX_CAPACITY: usize = 1024;
+/// Links a fabric keeps track of before it forgets the idle ones; a link that is idle again is
+/// no different from one that never carried anything.
+const TRACKED_LINKS: usize = 4096;
+
+/// A datagram, its sender and when it is due at its receiver on the network's clock, at once
+/// when unset.
+type Datagram = (Vec<u8>, SocketAddr, Option<Duration>);
+
+/// Addresses bound by nodes and the inboxes of their datagrams, usually shared by all the nodes
+/// of a simulation.
+pub struct MemoryNetwork {
+    inboxes: RwLock<HashMap<SocketAddr, SyncSender<Datagram>>>,
+    fabric: Option<Fabric>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for MemoryNetwork {
+    fn default() -> Self {
+        MemoryNetwork {
+            inboxes: RwLock::default(),
+            fabric: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl fmt::Debug for MemoryNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryNetwork")
+            .field("bound", &self.len())
+            .finish()
+    }
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A network whose links carry the datagrams as `fabric` says.
+    pub fn with_fabric(fabric: Fabric) -> Self {
+        MemoryNetwork {
+            fabric: Some(fabric),
+            ..Self::default()
+        }
+    }
+
+    /// Times the links and the datagrams held back by `clock` instead of the wall clock.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn fabric(&self) -> Option<&Fabric> {
+        self.fabric.as_ref()
+    }
+
+    /// Number of addresses currently bound.
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<SocketAddr, SyncSender<Datagram>>> {
+        self.inboxes.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<SocketAddr, SyncSender<Datagram>>> {
+        self.inboxes.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Binds `address` until the returned socket is dropped. Fails like a socket would if the
+    /// address is taken, and for port 0, as there is no port to pick one from.
+    pub(crate) fn bind(self: &Arc<Self>, address: SocketAddr) -> io::Result<MemorySocket> {
+        if address.port() == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                "an in-memory address needs a port",
+            ));
+        }
+        let mut inboxes = self.write();
+        if inboxes.contains_key(&address) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is bound already", address),
+            ));
+        }
+        let (sender, receiver) = mpsc::sync_channel(INBOX_CAPACITY);
+        inboxes.insert(address, sender);
+        Ok(MemorySocket {
+            network: self.clone(),
+            address,
+            inbox: receiver,
+            in_flight: Mutex::new(InFlight::default()),
+        })
+    }
+
+    /// Puts `message` into the inbox of `to`, if anyone bound it and the inbox has room. Like
+    /// with UDP, an address bound on the unspecified ip receives for every ip with its port.
+    /// With a fabric, the message first has to make it through the link to `to`.
+    pub(crate) fn deliver(&self, message: &[u8], from: SocketAddr, to: SocketAddr) {
+        self.deliver_after(message, from, to, Duration::ZERO);
+    }
+
+    /// Delivers `message` like [`MemoryNetwork::deliver`], only `delay` later; it waits in the
+    /// inbox of `to` until then.
+    pub(crate) fn deliver_after(
+        &self,
+        message: &[u8],
+        from: SocketAddr,
+        to: SocketAddr,
+        delay: Duration,
+    ) {
+        let now = self.clock.elapsed();
+        let due = match &self.fabric {
+            Some(fabric) => match fabric.transmit(from, to, message.len(), now) {
+                Some(due) => Some(due + delay),
+                None => return,
+            },
+            None => (!delay.is_zero()).then_some(now + delay),
+        };
+        let inboxes = self.read();
+        let unspecified = match to {
+            SocketAddr::V4(_) => IpAddr::from(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::from(Ipv6Addr::UNSPECIFIED),
+        };
+        let inbox = inboxes
+            .get(&to)
+            .or_else(|| inboxes.get(&SocketAddr::new(unspecified, to.port())));
+        if let Some(inbox) = inbox {
+            // a full inbox loses the datagram, a gone receiver doesn't matter either
+            let _ = inbox.try_send((message.to_vec(), from, due));
+        }
+    }
+}
+
+/// An address bound on a [`MemoryNetwork`], with the same non-blocking calls as a UDP socket.
+pub(crate) struct MemorySocket {
+    network: Arc<MemoryNetwork>,
+    address: SocketAddr,
+    inbox: Receiver<Datagram>,
+    // datagrams taken from the inbox that are still on their way, with a fabric
+    in_flight: Mutex<InFlight>,
+}
+
+impl MemorySocket {
+    pub(crate) fn network(&self) -> &Arc<MemoryNetwork> {
+        &self.network
+    }
+
+    /// Takes the next datagram, truncated to `buf` like UDP does, or fails with
+    /// [`io::ErrorKind::WouldBlock`] when there is none.
+    pub(crate) fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (message, from) = self.next_arrived().ok_or(io::ErrorKind::WouldBlock)?;
+        let size = message.len().min(buf.len());
+        buf[..size].copy_from_slice(&message[..size]);
+        Ok((size, from))
+    }
+
+    /// The earliest datagram that is due, in the order they are due rather than the order they
+    /// were sent in, as datagrams on different links or held back overtake each other.
+    fn next_arrived(&self) -> Option<(Vec<u8>, SocketAddr)> {
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if in_flight.pending.is_empty() {
+            // the common case of datagrams that arrive at once skips the queue
+            match self.inbox.try_recv() {
+                Ok((message, from, None)) => return Some((message, from)),
+                Ok((message, from, Some(due))) => in_flight.push(due, message, from),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => return None,
+            }
+        }
+        let now = self.network.clock.elapsed();
+        while let Ok((message, from, due)) = self.inbox.try_recv() {
+            in_flight.push(due.unwrap_or(now), message, from);
+        }
+        in_flight.pop_due(now)
+    }
+
+    pub(crate) fn send_to(&self, message: &[u8], to: SocketAddr) -> io::Result<usize> {
+        self.network.deliver(message, self.address, to);
+        Ok(message.len())
+    }
+}
+
+impl Drop for MemorySocket {
+    /// Frees the address; datagrams still in the inbox are lost.
+    fn drop(&mut self) {
+        self.network.write().remove(&self.address);
+    }
+}
+
+/// Datagrams received but not due yet, earliest first.
+#[derive(Debug, Default)]
+struct InFlight {
+    pending: BinaryHeap<Reverse<Arrival>>,
+    sequence: u64,
+}
+
+impl InFlight {
+    fn push(&mut self, due: Duration, message: Vec<u8>, from: SocketAddr) {
+        let sequence = self.sequence;
+        self.sequence += 1;
+        self.pending.push(Reverse(Arrival {
+            due,
+            sequence,
+            message,
+            from,
+        }));
+    }
+
+    fn pop_due(&mut self, now: Duration) -> Option<(Vec<u8>, SocketAddr)> {
+        if self.pending.peek()?.0.due > now {
+            return None;
+        }
+        let Reverse(arrival) = self.pending.pop()?;
+        Some((arrival.message, arrival.from))
+    }
+}
+
+#[derive(Debug)]
+struct Arrival {
+    due: Duration,
+    // keeps datagrams due at the same instant in the order they were received
+    sequence: u64,
+    message: Vec<u8>,
+    from: SocketAddr,
+}
+
+impl PartialEq for Arrival {
+    fn eq(&self, other: &Self) -> bool {
+        (self.due, self.sequence) == (other.due, other.sequence)
+    }
+}
+
+impl Eq for Arrival {}
+
+impl PartialOrd for Arrival {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Arrival {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (self.due, self.sequence).cmp(&(other.due, other.sequence))
+    }
+}
+
+/// The links of a [`MemoryNetwork`], one for each direction between two addresses. A link with
+/// a bandwidth sends one datagram after the other, so a datagram waits for those queued before
+/// it and then takes its size over the bandwidth to arrive; once the queue holds as many bytes
+/// as it can take, more datagrams are dropped. Links without a bandwidth pass everything at
+/// once.
+#[derive(Debug)]
+pub struct Fabric {
+    bandwidth: Option<u64>,
+    pair_bandwidth: HashMap<(SocketAddr, SocketAddr), u64>,
+    queue: u64,
+    links: Mutex<Links>,
+    datagrams: AtomicU64,
+    queued: AtomicU64,
+    tail_dropped: AtomicU64,
+    total_queueing_us: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Links {
+    /// when each link is done with the datagrams queued so far, on the network's clock
+    busy_until: HashMap<(SocketAddr, SocketAddr), Duration>,
+    /// number of tracked links at which the idle ones are forgotten
+    prune_at: usize,
+}
+
+/// What the links of a [`Fabric`] did to the datagrams so far.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FabricStats {
+    /// datagrams sent on a link with a bandwidth
+    pub datagrams: u64,
+    /// datagrams that had to wait for others queued before them
+    pub queued: u64,
+    /// datagrams dropped because the queue of their link was full
+    pub tail_dropped: u64,
+    /// sum of the time the queued datagrams waited
+    pub total_queueing: Duration,
+}
+
+impl FabricStats {
+    pub fn mean_queueing(&self) -> Duration {
+        if self.queued == 0 {
+            return Duration::ZERO;
+        }
+        self.total_queueing / self.queued as u32
+    }
+}
+
+impl Fabric {
+    /// Links without a bandwidth until given one, with a queue of `queue` bytes each.
+    pub fn new(queue: u64) -> Self {
+        Fabric {
+            bandwidth: None,
+            pair_bandwidth: HashMap::new(),
+            queue,
+            links: Mutex::new(Links {
+                busy_until: HashMap::new(),
+                prune_at: TRACKED_LINKS,
+            }),
+            datagrams: AtomicU64::new(0),
+            queued: AtomicU64::new(0),
+            tail_dropped: AtomicU64::new(0),
+            total_queueing_us: AtomicU64::new(0),
+        }
+    }
+
+    /// Has every link carry `bytes_per_second`.
+    pub fn bandwidth(mut self, bytes_per_second: u64) -> Self {
+        self.bandwidth = Some(bytes_per_second);
+        self
+    }
+
+    /// Has the link from `from` to `to` carry `bytes_per_second` instead.
+    pub fn pair_bandwidth(
+        mut self,
+        from: SocketAddr,
+        to: SocketAddr,
+        bytes_per_second: u64,
+    ) -> Self {
+        self.pair_bandwidth.insert((from, to), bytes_per_second);
+        self
+    }
+
+    pub fn stats(&self) -> FabricStats {
+        FabricStats {
+            datagrams: self.datagrams.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            tail_dropped: self.tail_dropped.load(Ordering::Relaxed),
+            total_queueing: Duration::from_micros(self.total_queueing_us.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn links(&self) -> MutexGuard<'_, Links> {
+        self.links.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queues `size` bytes on the link from `from` to `to` at `now`. Returns when they will
+    /// have arrived, or None if the queue of the link is full.
+    fn transmit(
+        &self,
+        from: SocketAddr,
+        to: SocketAddr,
+        size: usize,
+        now: Duration,
+    ) -> Option<Duration> {
+        let Some(bandwidth) = self
+            .pair_bandwidth
+            .get(&(from, to))
+            .copied()
+            .or(self.bandwidth)
+            .filter(|bandwidth| *bandwidth > 0)
+        else {
+            return Some(now);
+        };
+        self.datagrams.fetch_add(1, Ordering::Relaxed);
+        let mut links = self.links();
+        if links.busy_until.len() >= links.prune_at {
+            links.busy_until.retain(|_, until| *until > now);
+            links.prune_at = TRACKED_LINKS.max(links.busy_until.len() * 2);
+        }
+        let start = links
+            .busy_until
+            .get(&(from, to))
+            .copied()
+            .filter(|until| *until > now)
+            .unwrap_or(now);
+        let waiting = start - now;
+        let backlog = waiting.as_secs_f64() * bandwidth as f64;
+        if backlog + size as f64 > self.queue as f64 {
+            self.tail_dropped.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        if !waiting.is_zero() {
+            self.queued.fetch_add(1, Ordering::Relaxed);
+            self.total_queueing_us
+                .fetch_add(waiting.as_micros() as u64, Ordering::Relaxed);
+        }
+        let done = start + Duration::from_secs_f64(size as f64 / bandwidth as f64);
+        links.busy_until.insert((from, to), done);
+        Some(done)
+    }
+}
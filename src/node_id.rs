@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+
+// long enough for a UUID or a host name, short enough to keep heartbeats in one small datagram
+pub const MAX_NODE_ID_LEN: usize = 64;
+
+/// Identity of a node. Ids are non-empty, at most [`MAX_NODE_ID_LEN`] bytes long and made of
+/// ASCII letters, digits, `-`, `_`, `.` and `:`. On the wire an id is a plain string, and
+/// received ids are validated like any other. Clones share the id's text rather than copying
+/// it, so keeping an id in another map or log doesn't allocate.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct NodeId(Arc<str>);
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum NodeIdError {
+    #[error("node id must not be empty")]
+    Empty,
+    #[error("node id is {len} bytes long, at most {max} are allowed")]
+    TooLong { len: usize, max: usize },
+    #[error(
+        "node id contains {0:?}, only ASCII letters, digits, '-', '_', '.' and ':' are allowed"
+    )]
+    InvalidChar(char),
+}
+
+impl NodeId {
+    pub fn new(id: impl Into<String>) -> Result<Self, NodeIdError> {
+        let id = id.into();
+        if id.is_empty() {
+            return Err(NodeIdError::Empty);
+        }
+        if id.len() > MAX_NODE_ID_LEN {
+            return Err(NodeIdError::TooLong {
+                len: id.len(),
+                max: MAX_NODE_ID_LEN,
+            });
+        }
+        if let Some(c) = id
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':')))
+        {
+            return Err(NodeIdError::InvalidChar(c));
+        }
+        Ok(NodeId(id.into()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = NodeIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NodeId::new(s)
+    }
+}
+
+impl TryFrom<String> for NodeId {
+    type Error = NodeIdError;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        NodeId::new(id)
+    }
+}
+
+impl TryFrom<&str> for NodeId {
+    type Error = NodeIdError;
+
+    fn try_from(id: &str) -> Result<Self, Self::Error> {
+        NodeId::new(id)
+    }
+}
+
+impl From<NodeId> for String {
+    fn from(id: NodeId) -> String {
+        id.0.to_string()
+    }
+}
+
+impl AsRef<str> for NodeId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+// lets maps keyed by NodeId be queried with a &str
+impl Borrow<str> for NodeId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for NodeId {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for NodeId {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
@@ -0,0 +1,1532 @@
+//! Live terminal charts of a running simulation, only built with the `sim` feature.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use textplots::{ColorPlot, Shape};
+use tracing::error;
+
+use crate::clock::Clock;
+use crate::faults::{FaultInjector, Partition};
+use crate::gossip;
+use crate::membership::{PeerState, Subscription};
+use crate::node_id::NodeId;
+use crate::report::{MembershipEntry, NodeEntry, SampleEntry};
+use crate::scenario::{Cluster, SimNode};
+use crate::sim::describe_indexes;
+use crate::sim_runtime::SimTime;
+use crate::stats::{LatencyHistogram, NodeStats};
+
+const EVENT_LOG_LINES: usize = 8;
+const SCENARIO_EVENT_LINES: usize = 4;
+// convergence changes listed below the charts, and nodes named in each
+const CONVERGENCE_LINES: usize = 4;
+const CONVERGENCE_NODES_SHOWN: usize = 10;
+// how often a long wait between samples checks whether the simulation stopped
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the samples file is flushed, a run that crashes loses at most the samples since.
+pub const SAMPLES_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// When things happened during the simulation, shared between the threads driving it.
+pub struct Timeline {
+    /// the time the simulation runs on, counted from its start
+    pub time: SimTime,
+    /// set once the first victims have been killed, since the start
+    pub killed_at: Mutex<Option<Duration>>,
+    /// set on ctrl-c, tells the plotting thread to wrap up
+    pub stopping: AtomicBool,
+    /// when each node's view first covered the whole cluster, reported by the nodes themselves
+    pub converged_at: Mutex<Vec<(String, Duration)>>,
+    /// how long after the kill each victim first showed up in the observer's dead peers
+    pub detected_at: Mutex<Vec<(String, Duration)>>,
+    /// the surviving node that watched for the victims of the first kill and how many victims
+    /// there were, set once it stopped watching
+    pub detection_observer: Mutex<Option<(String, usize)>>,
+    /// the key put by the first node and when since the start, with `--put-value`
+    pub value_put: Mutex<Option<(String, Duration)>>,
+    /// the scenario events executed so far, in order
+    pub events: Mutex<Vec<EventRecord>>,
+    /// what the churn did so far
+    pub churn: Mutex<ChurnTally>,
+    /// set while the charts aren't redrawn, toggled by the commands typed during the simulation
+    pub display_frozen: AtomicBool,
+    /// the outcome of the latest command typed during the simulation
+    pub control_status: Mutex<Option<String>>,
+}
+
+/// The nodes killed and revived by the churn of the simulation.
+#[derive(Debug, Clone, Default)]
+pub struct ChurnTally {
+    pub kills: usize,
+    pub revivals: usize,
+    /// how long after each kill no alive node saw the victim as alive anymore
+    pub detections: Vec<Duration>,
+    /// victims revived before every alive node noticed they were gone
+    pub revived_undetected: usize,
+}
+
+/// A scenario event as it was executed, see [`crate::scenario`].
+#[derive(Debug, Clone)]
+pub struct EventRecord {
+    /// since the start of the simulation, taken when the event was executed
+    pub at: Duration,
+    /// the event and what it did, e.g. which nodes it killed
+    pub description: String,
+    /// indexes of the nodes it applied to, see [`crate::report::EventEntry::nodes`]
+    pub nodes: Vec<u64>,
+}
+
+/// What the plotting thread needs to know about the simulation.
+pub struct PlotSettings {
+    /// shown above the charts
+    pub header: String,
+    /// timeouts the nodes judge their peers by, and the clock they measure their ages with
+    pub suspect_timeout: Duration,
+    pub dead_timeout: Duration,
+    pub clock: Arc<dyn Clock>,
+    /// collect the metrics for the report without drawing anything
+    pub headless: bool,
+    /// the network faults of the simulation, their partitions are charted group by group
+    pub faults: Option<Arc<FaultInjector>>,
+    /// gets a CSV row for every [`Sample`], whether the charts are drawn or not
+    pub samples: Option<SampleWriter>,
+    /// time between two samples, every sample looks at the view of every node
+    pub sample_interval: Duration,
+    /// consecutive samples in which every alive node must have a correct view for the cluster
+    /// to count as converged after a scenario event
+    pub convergence_samples: usize,
+    /// when the churn starts, since the start of the simulation; the metrics from then on are
+    /// averaged in [`ChurnReport`]
+    pub churn_from: Option<Duration>,
+    /// groups of nodes configured apart from the others, by name and node indexes, charted and
+    /// reported apart
+    pub node_groups: Vec<(String, Range<u64>)>,
+    /// indexes of the nodes that corrupt the datagrams they send, the others are reported apart
+    /// in [`ByzantineReport`]
+    pub byzantine: HashSet<u64>,
+    /// links with a loss or latency of their own, as indexes of the sender and the receiver, in
+    /// both directions; how each receiver sees its sender is reported in [`LinkReport`]
+    pub links: Vec<(u64, u64)>,
+    /// whether the initial nodes start over time, charting the nodes started against the nodes
+    /// known
+    pub startup_ramp: bool,
+    /// also chart the heartbeats the storages counted as received, an estimate of the datagrams
+    /// received that misses drops and every other kind of message, for comparison
+    pub chart_heartbeats_received: bool,
+}
+
+/// The metrics of the whole cluster at one tick of the plotting thread, both charted and written
+/// to the samples file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sample {
+    /// since the start of the simulation
+    pub at: Duration,
+    pub nodes: usize,
+    /// running and not paused
+    pub alive: usize,
+    /// nodes with a fresh heartbeat of every node
+    pub fully_informed: usize,
+    /// nodes that know every node, whatever the age of its heartbeat
+    pub know_all: usize,
+    /// nodes a node knows on average, itself included, whatever the age of their heartbeats
+    pub mean_known: f64,
+    /// alive nodes that see exactly the alive nodes as alive
+    pub correct_views: usize,
+    /// heartbeats received since the previous tick, first copies and duplicates, as counted by
+    /// the storages: entries dropped since take their counts with them
+    pub heartbeats_received: u64,
+    pub duplicates: u64,
+    /// datagrams sent since the previous tick
+    pub messages_sent: u64,
+    /// datagrams sent since the start
+    pub total_messages_sent: u64,
+    /// datagrams read from their sockets by the nodes since the previous tick
+    pub messages_received: u64,
+    /// datagrams read since the start
+    pub total_messages_received: u64,
+    /// how long the heartbeats the nodes applied since the previous tick took to reach them,
+    /// median, 95th and 99th percentile; see [`LatencyHistogram`]
+    pub latency_p50: Option<Duration>,
+    pub latency_p95: Option<Duration>,
+    pub latency_p99: Option<Duration>,
+    /// KiB per second the nodes wrote to and read from their sockets since the previous tick
+    pub sent_kib_per_second: f64,
+    pub received_kib_per_second: f64,
+    /// the most KiB per second a single node wrote and read together since the previous tick
+    pub max_node_kib_per_second: f64,
+    /// bytes written and read since the start
+    pub total_bytes_sent: u64,
+    pub total_bytes_received: u64,
+    /// victims of the first kill the observer has seen dead so far
+    pub detected: usize,
+    /// the longest it took the observer to see one of them dead
+    pub slowest_detection: Option<Duration>,
+}
+
+impl Sample {
+    pub const CSV_HEADER: &'static str = "seconds,nodes,alive,fully_informed,know_all,\
+        correct_views,heartbeats_received,duplicates,messages_sent,total_messages_sent,detected,\
+        slowest_detection_ms,mean_known,messages_received,total_messages_received,latency_p50_ms,\
+        latency_p95_ms,latency_p99_ms,sent_kib_per_s,received_kib_per_s,max_node_kib_per_s,\
+        total_bytes_sent,total_bytes_received";
+
+    /// The sample as a row below [`Sample::CSV_HEADER`], without the line break.
+    pub fn csv_row(&self) -> String {
+        format!(
+            "{:.3},{},{},{},{},{},{},{},{},{},{},{},{:.1},{},{},{},{},{},{:.2},{:.2},{:.2},{},{}",
+            self.at.as_secs_f64(),
+            self.nodes,
+            self.alive,
+            self.fully_informed,
+            self.know_all,
+            self.correct_views,
+            self.heartbeats_received,
+            self.duplicates,
+            self.messages_sent,
+            self.total_messages_sent,
+            self.detected,
+            millis(self.slowest_detection),
+            self.mean_known,
+            self.messages_received,
+            self.total_messages_received,
+            millis(self.latency_p50),
+            millis(self.latency_p95),
+            millis(self.latency_p99),
+            self.sent_kib_per_second,
+            self.received_kib_per_second,
+            self.max_node_kib_per_second,
+            self.total_bytes_sent,
+            self.total_bytes_received
+        )
+    }
+}
+
+/// Writes the samples of a run as CSV, the header first and then a row for every [`Sample`],
+/// flushing every [`SAMPLES_FLUSH_INTERVAL`] so the file can be read while the run goes on.
+pub struct SampleWriter {
+    writer: Box<dyn Write + Send>,
+    flushed_at: Instant,
+}
+
+impl SampleWriter {
+    /// Writes [`Sample::CSV_HEADER`] to `writer` right away.
+    pub fn new(mut writer: Box<dyn Write + Send>) -> io::Result<Self> {
+        writeln!(writer, "{}", Sample::CSV_HEADER)?;
+        writer.flush()?;
+        Ok(SampleWriter {
+            writer,
+            flushed_at: Instant::now(),
+        })
+    }
+
+    pub fn write(&mut self, sample: &Sample) -> io::Result<()> {
+        writeln!(self.writer, "{}", sample.csv_row())?;
+        if self.flushed_at.elapsed() >= SAMPLES_FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.flushed_at = Instant::now();
+        self.writer.flush()
+    }
+}
+
+/// Milliseconds for a CSV cell, empty if unknown.
+fn millis(duration: Option<Duration>) -> String {
+    duration
+        .map(|duration| duration.as_millis().to_string())
+        .unwrap_or_default()
+}
+
+/// Final values of the metrics collected by the plotting thread.
+#[derive(Debug, Default)]
+pub struct PlotReport {
+    pub peak_fully_informed: f32,
+    pub final_fully_informed: f32,
+    /// time from pausing the victims until every node was fully informed again
+    pub reconverged_after: Option<Duration>,
+    /// seconds the latest heartbeat of every peer took to reach each node, sorted, taken when
+    /// the simulation stopped
+    pub propagation_delays: Vec<u64>,
+    /// how long every heartbeat applied by a node took to reach it, of all nodes
+    pub propagation_latency: LatencyHistogram,
+    /// time from putting the value until every node could read it
+    pub value_readable_after: Option<Duration>,
+    /// nodes that could read the value at the last sample, once it was put
+    pub final_value_readable: usize,
+    /// one for each partition of the faults, in the order they were added
+    pub partitions: Vec<PartitionReport>,
+    /// one for each scenario event the plotting thread saw executed, in order
+    pub event_convergence: Vec<EventConvergence>,
+    /// every time the whole cluster converged or diverged, in order
+    pub convergence: Vec<ConvergenceRecord>,
+    /// one for each node added while the simulation ran, in the order they were added
+    pub newcomers: Vec<NewcomerReport>,
+    /// the metrics of the cluster under churn, once it started
+    pub churn: Option<ChurnReport>,
+    /// one for each node group, in the order they were given
+    pub node_groups: Vec<NodeGroupReport>,
+    /// how the honest nodes fared, when some nodes are byzantine
+    pub byzantine: Option<ByzantineReport>,
+    /// one for each of [`PlotSettings::links`], in the same order
+    pub links: Vec<LinkReport>,
+    /// every sample with the other metrics charted, in order
+    pub samples: Vec<SampleEntry>,
+    /// the membership events of the first node, in the order they were seen
+    pub membership_events: Vec<MembershipEntry>,
+    /// every node when the simulation stopped
+    pub nodes: Vec<NodeEntry>,
+}
+
+/// How the receiver of a link saw its sender. Gossip passes heartbeats around a broken link, so
+/// a receiver may see its sender as alive although it hears nothing from it directly.
+#[derive(Debug, Clone, Default)]
+pub struct LinkReport {
+    pub from: u64,
+    pub to: u64,
+    /// samples in which both nodes were alive, and those in which the receiver saw the sender as
+    /// alive
+    pub samples: usize,
+    pub seen_alive: usize,
+    /// the state of the sender according to the receiver when the simulation stopped
+    pub final_state: Option<PeerState>,
+}
+
+/// How the honest nodes fared next to byzantine ones.
+#[derive(Debug, Clone, Default)]
+pub struct ByzantineReport {
+    pub samples: usize,
+    /// share of the alive honest nodes that saw exactly the alive nodes as alive, averaged over
+    /// the samples
+    pub mean_correct_views: f64,
+    /// alive honest nodes, and those of them with a correct view, when the simulation stopped
+    pub final_honest: usize,
+    pub final_correct_views: usize,
+    /// entries of nodes that don't exist in the storages of the honest nodes, at most at once and
+    /// when the simulation stopped
+    pub peak_phantoms: usize,
+    pub final_phantoms: usize,
+    /// counters of the honest nodes, including the inputs they rejected or clamped, taken when
+    /// the simulation stopped
+    pub honest_stats: NodeStats,
+    correct_fraction: f64,
+}
+
+impl ByzantineReport {
+    fn add(&mut self, honest: usize, correct_views: usize, phantoms: usize, stats: NodeStats) {
+        self.samples += 1;
+        self.correct_fraction += correct_views as f64 / honest.max(1) as f64;
+        self.mean_correct_views = self.correct_fraction / self.samples as f64;
+        self.final_honest = honest;
+        self.final_correct_views = correct_views;
+        self.peak_phantoms = self.peak_phantoms.max(phantoms);
+        self.final_phantoms = phantoms;
+        self.honest_stats = stats;
+    }
+}
+
+/// How the nodes of a group configured apart from the others fared.
+#[derive(Debug, Clone, Default)]
+pub struct NodeGroupReport {
+    pub name: String,
+    pub nodes: u64,
+    pub peak_fully_informed: usize,
+    pub final_fully_informed: usize,
+    /// datagrams sent by the nodes of the group, taken when the simulation stopped
+    pub messages_sent: u64,
+    /// most alive nodes of the group that some alive node saw as suspect or dead at once
+    pub peak_falsely_suspected: usize,
+}
+
+/// The metrics of the cluster averaged over the samples taken under churn.
+#[derive(Debug, Clone, Default)]
+pub struct ChurnReport {
+    pub samples: usize,
+    /// share of the nodes that were alive
+    pub mean_alive: f64,
+    /// share of the alive nodes that saw exactly the alive nodes as alive
+    pub mean_correct_views: f64,
+    /// share of the nodes with a fresh heartbeat of every node
+    pub mean_fully_informed: f64,
+    /// datagrams sent per alive node and second
+    pub messages_per_node: f64,
+    /// sums of the samples the means are taken from
+    alive_fraction: f64,
+    correct_fraction: f64,
+    fully_informed_fraction: f64,
+    messages: u64,
+    alive_samples: u64,
+}
+
+impl ChurnReport {
+    fn add(&mut self, sample: &Sample) {
+        let share = |count: usize, total: usize| count as f64 / total.max(1) as f64;
+        self.samples += 1;
+        self.alive_fraction += share(sample.alive, sample.nodes);
+        self.correct_fraction += share(sample.correct_views, sample.alive);
+        self.fully_informed_fraction += share(sample.fully_informed, sample.nodes);
+        self.messages += sample.messages_sent;
+        self.alive_samples += sample.alive as u64;
+
+        let samples = self.samples as f64;
+        self.mean_alive = self.alive_fraction / samples;
+        self.mean_correct_views = self.correct_fraction / samples;
+        self.mean_fully_informed = self.fully_informed_fraction / samples;
+        self.messages_per_node = self.messages as f64 / self.alive_samples.max(1) as f64;
+    }
+}
+
+/// How long a node added while the simulation ran took to learn about the cluster.
+#[derive(Debug, Clone)]
+pub struct NewcomerReport {
+    pub index: u64,
+    /// since the start of the simulation
+    pub added_at: Duration,
+    /// time from its start until it had a fresh heartbeat of every alive node, unset if it never
+    /// had
+    pub informed_after: Option<Duration>,
+}
+
+/// How long the views of the cluster took to be correct again after a scenario event.
+#[derive(Debug, Clone, Default)]
+pub struct EventConvergence {
+    /// time from the event until the first of the consecutive samples in which every alive node
+    /// saw exactly the alive nodes as alive, unset if the simulation stopped before
+    pub converged_after: Option<Duration>,
+    /// correct samples in a row since the event, and when the first of them was taken
+    streak: usize,
+    correct_since: Option<Duration>,
+}
+
+impl EventConvergence {
+    /// Counts the sample taken at `sample_at` towards the convergence after the event executed at
+    /// `event_at`, `converged` telling whether every alive node saw exactly the alive nodes as
+    /// alive. The cluster counts as converged after `samples` correct samples in a row.
+    pub fn track(
+        &mut self,
+        event_at: Duration,
+        sample_at: Duration,
+        converged: bool,
+        samples: usize,
+    ) {
+        if self.converged_after.is_some() || sample_at < event_at {
+            return;
+        }
+        if !converged {
+            self.streak = 0;
+            self.correct_since = None;
+            return;
+        }
+        self.streak += 1;
+        let since = *self.correct_since.get_or_insert(sample_at);
+        if self.streak >= samples {
+            self.converged_after = Some(since - event_at);
+        }
+    }
+}
+
+/// A change of the convergence of the whole cluster: whether every alive node sees exactly the
+/// alive nodes as alive, for [`PlotSettings::convergence_samples`] samples in a row.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConvergenceRecord {
+    /// confirmed by the sample taken `at`, the first of the correct samples in a row was taken
+    /// `since`
+    Converged { at: Duration, since: Duration },
+    /// the sample taken `at` found the alive nodes with the indexes `nodes` seeing other nodes
+    /// alive, after the cluster had converged
+    Diverged { at: Duration, nodes: Vec<u64> },
+}
+
+impl ConvergenceRecord {
+    pub fn at(&self) -> Duration {
+        match self {
+            ConvergenceRecord::Converged { at, .. } | ConvergenceRecord::Diverged { at, .. } => *at,
+        }
+    }
+}
+
+impl std::fmt::Display for ConvergenceRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{:.3}s] ", self.at().as_secs_f64())?;
+        match self {
+            ConvergenceRecord::Converged { since, .. } => write!(
+                f,
+                "converged, every alive node's view correct since {:.3}s",
+                since.as_secs_f64()
+            ),
+            ConvergenceRecord::Diverged { nodes, .. } => {
+                let shown = &nodes[..nodes.len().min(CONVERGENCE_NODES_SHOWN)];
+                write!(f, "diverged, wrong view on {}", describe_indexes(shown))?;
+                if nodes.len() > shown.len() {
+                    write!(f, " and {} more", nodes.len() - shown.len())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Follows the convergence of the whole cluster from sample to sample.
+#[derive(Debug, Default)]
+struct ConvergenceTracker {
+    converged: bool,
+    /// correct samples in a row, and when the first of them was taken
+    streak: usize,
+    correct_since: Option<Duration>,
+}
+
+impl ConvergenceTracker {
+    /// Counts the sample taken `at`, in which the alive nodes `diverging` didn't see exactly the
+    /// alive nodes as alive, and returns the change it made, if any.
+    fn track(
+        &mut self,
+        at: Duration,
+        alive: usize,
+        diverging: &[u64],
+        samples: usize,
+    ) -> Option<ConvergenceRecord> {
+        if alive == 0 || !diverging.is_empty() {
+            self.streak = 0;
+            self.correct_since = None;
+            if !self.converged {
+                return None;
+            }
+            self.converged = false;
+            return Some(ConvergenceRecord::Diverged {
+                at,
+                nodes: diverging.to_vec(),
+            });
+        }
+        self.streak += 1;
+        let since = *self.correct_since.get_or_insert(at);
+        if self.converged || self.streak < samples {
+            return None;
+        }
+        self.converged = true;
+        Some(ConvergenceRecord::Converged { at, since })
+    }
+}
+
+/// How the cluster fared during and after a [`Partition`].
+#[derive(Debug, Clone, Default)]
+pub struct PartitionReport {
+    pub group_sizes: Vec<usize>,
+    /// fewest nodes of each group that had a fresh heartbeat of every node of their group while
+    /// the partition was active, unset if it never was
+    pub lowest_group_informed: Vec<Option<usize>>,
+    /// time from the healing of the partition until every node was fully informed again
+    pub reconverged_after: Option<Duration>,
+}
+
+/// Charts the nodes of `cluster`, including the ones added while the simulation runs.
+pub fn plot(
+    cluster: Cluster,
+    event_log: Option<Subscription>,
+    summary_requested: Receiver<()>,
+    mut settings: PlotSettings,
+    timeline: Arc<Timeline>,
+) -> JoinHandle<PlotReport> {
+    const PURPLE: rgb::RGB8 = rgb::RGB8::new(0xE0, 0x80, 0xFF);
+    const GREEN: rgb::RGB8 = rgb::RGB8::new(0x00, 0xFF, 0x00);
+    const YELLOW: rgb::RGB8 = rgb::RGB8::new(0xFF, 0xFF, 0x00);
+    const BLUE: rgb::RGB8 = rgb::RGB8::new(0x00, 0x00, 0xFF);
+    const CYAN: rgb::RGB8 = rgb::RGB8::new(0x00, 0xFF, 0xFF);
+    const RED: rgb::RGB8 = rgb::RGB8::new(0xFF, 0x00, 0x00);
+    // one after the other for the groups of a partition
+    const GROUP_COLORS: [rgb::RGB8; 4] = [
+        rgb::RGB8::new(0xFF, 0x80, 0x00),
+        rgb::RGB8::new(0x00, 0xFF, 0x80),
+        rgb::RGB8::new(0xFF, 0x40, 0x80),
+        rgb::RGB8::new(0x80, 0x80, 0xFF),
+    ];
+    const GROUP_COLOR_NAMES: [&str; 4] = ["orange", "green", "pink", "lilac"];
+
+    let term = console::Term::stdout();
+    if !settings.headless {
+        term.hide_cursor().unwrap();
+        term.clear_screen().unwrap();
+    }
+
+    let time = timeline.time.clone();
+    time.spawn(move || {
+        let mut samples = settings.samples.take();
+        let mut report = PlotReport::default();
+        // whether the kill has shown up in the metrics yet
+        let mut diverged = false;
+        let mut fully_informed: Vec<(f32, f32)> = vec![];
+        let mut know_all: Vec<(f32, f32)> = vec![];
+        let mut heartbeats_received: Vec<(f32, f32)> = vec![];
+        let mut previous_received = 0;
+        let mut previous_duplicates = 0;
+        let mut datagrams_sent: Vec<(f32, f32)> = vec![];
+        let mut datagrams_received: Vec<(f32, f32)> = vec![];
+        let mut previous_stats = NodeStats::default();
+        let mut previous_at = Duration::ZERO;
+        let mut previous_latency = LatencyHistogram::default();
+        let mut latency_p95: Vec<(f32, f32)> = vec![];
+        let mut max_latency_p95 = 0.0;
+        // bytes each node wrote and read, by its index
+        let mut previous_node_bytes: Vec<u64> = vec![];
+        let mut bandwidth_sent: Vec<(f32, f32)> = vec![];
+        let mut bandwidth_received: Vec<(f32, f32)> = vec![];
+        let mut busiest_node: Vec<(f32, f32)> = vec![];
+        let mut max_bandwidth = 0.0;
+        let mut number_nodes_alive: Vec<(f32, f32)> = vec![];
+        // the alive nodes at the first sample after each scenario event
+        let mut event_marks: Vec<(f32, f32)> = vec![];
+        let mut nodes_started: Vec<(f32, f32)> = vec![];
+        let mut mean_known: Vec<(f32, f32)> = vec![];
+        let mut value_readable: Vec<(f32, f32)> = vec![];
+        // nodes informed about their whole group, for every group of every partition
+        let mut group_informed: Vec<Vec<Vec<(f32, f32)>>> = vec![];
+        // fully informed nodes of every node group
+        let mut node_group_informed: Vec<Vec<(f32, f32)>> = vec![];
+        let mut max_datagram_rate = 0.0;
+        let mut recent_events: VecDeque<String> = VecDeque::new();
+        let mut convergence = ConvergenceTracker::default();
+        let mut summary: Option<String> = None;
+        let mut i = 0;
+        while !timeline.stopping.load(Ordering::SeqCst) {
+            let nodes: Vec<SimNode> = cluster
+                .read()
+                .map(|nodes| nodes.clone())
+                .unwrap_or_default();
+            let all_nodes: Vec<Arc<gossip::Node>> =
+                nodes.iter().map(|node| node.node.clone()).collect();
+            let number_nodes = all_nodes.len() as u64;
+            let (n_fully_informed, n_know_all, total_known, total_updates, total_duplicates) =
+                calculate_metrics(&all_nodes, number_nodes, &settings);
+            let total_received = total_updates + total_duplicates;
+            // entries dropped since the last round take their counts with them
+            let n_heartbeats_received = total_received.saturating_sub(previous_received);
+            let n_duplicates = total_duplicates.saturating_sub(previous_duplicates);
+            previous_received = total_received;
+            previous_duplicates = total_duplicates;
+
+            report.peak_fully_informed = report.peak_fully_informed.max(n_fully_informed);
+            report.final_fully_informed = n_fully_informed;
+            let killed_at = timeline.killed_at.lock().ok().and_then(|at| *at);
+            if let Some(killed_at) = killed_at {
+                if n_fully_informed < number_nodes as f32 {
+                    diverged = true;
+                } else if diverged && report.reconverged_after.is_none() {
+                    report.reconverged_after =
+                        Some(timeline.time.elapsed().saturating_sub(killed_at));
+                }
+            }
+
+            let alive_nodes: Vec<&SimNode> = nodes
+                .iter()
+                .filter(|node| node.handle.is_running() && !node.handle.is_paused())
+                .collect();
+            let number_alive = alive_nodes.len();
+            let alive_ids: HashSet<NodeId> = alive_nodes
+                .iter()
+                .map(|node| {
+                    node.node
+                        .storage()
+                        .with_read(|storage| storage.owner.clone())
+                })
+                .collect();
+            let diverging = incorrect_views(&alive_nodes, &alive_ids, &settings);
+            let correct_views = number_alive - diverging.len();
+
+            if !settings.byzantine.is_empty() {
+                let honest: Vec<&SimNode> = nodes
+                    .iter()
+                    .filter(|node| !settings.byzantine.contains(&node.index))
+                    .collect();
+                let alive_honest: Vec<&SimNode> = alive_nodes
+                    .iter()
+                    .copied()
+                    .filter(|node| !settings.byzantine.contains(&node.index))
+                    .collect();
+                let honest_correct_views =
+                    calculate_correct_views(&alive_honest, &alive_ids, &settings);
+                let phantoms = calculate_phantoms(&alive_honest, &nodes);
+                let honest_stats: NodeStats = honest.iter().map(|node| node.node.stats()).sum();
+                report
+                    .byzantine
+                    .get_or_insert_with(ByzantineReport::default)
+                    .add(
+                        alive_honest.len(),
+                        honest_correct_views,
+                        phantoms,
+                        honest_stats,
+                    );
+            }
+
+            if !settings.node_groups.is_empty() {
+                if report.node_groups.is_empty() {
+                    report.node_groups = settings
+                        .node_groups
+                        .iter()
+                        .map(|(name, range)| NodeGroupReport {
+                            name: name.clone(),
+                            nodes: range.end - range.start,
+                            ..Default::default()
+                        })
+                        .collect();
+                    node_group_informed = vec![vec![]; settings.node_groups.len()];
+                }
+                let group_metrics =
+                    calculate_node_group_metrics(&nodes, &alive_ids, number_nodes, &settings);
+                for (group, (n_informed, n_suspected)) in group_metrics.into_iter().enumerate() {
+                    let group_report = &mut report.node_groups[group];
+                    group_report.peak_fully_informed =
+                        group_report.peak_fully_informed.max(n_informed);
+                    group_report.final_fully_informed = n_informed;
+                    group_report.peak_falsely_suspected =
+                        group_report.peak_falsely_suspected.max(n_suspected);
+                    node_group_informed[group].push((i as f32, n_informed as f32));
+                }
+            }
+
+            if report.links.is_empty() {
+                report.links = settings
+                    .links
+                    .iter()
+                    .map(|&(from, to)| LinkReport {
+                        from,
+                        to,
+                        ..Default::default()
+                    })
+                    .collect();
+            }
+            for link in &mut report.links {
+                let find = |index| nodes.iter().find(|node| node.index == index);
+                let (Some(sender), Some(receiver)) = (find(link.from), find(link.to)) else {
+                    continue;
+                };
+                let sender_id = sender
+                    .node
+                    .storage()
+                    .with_read(|storage| storage.owner.clone());
+                let receiver_id = receiver
+                    .node
+                    .storage()
+                    .with_read(|storage| storage.owner.clone());
+                link.final_state = receiver.node.peer_state(sender_id.as_str());
+                if alive_ids.contains(&sender_id) && alive_ids.contains(&receiver_id) {
+                    link.samples += 1;
+                    if link.final_state == Some(PeerState::Alive) {
+                        link.seen_alive += 1;
+                    }
+                }
+            }
+
+            for node in &nodes {
+                let Some(added_at) = node.added_at else {
+                    continue;
+                };
+                let newcomer = match report
+                    .newcomers
+                    .iter()
+                    .position(|newcomer| newcomer.index == node.index)
+                {
+                    Some(position) => &mut report.newcomers[position],
+                    None => {
+                        report.newcomers.push(NewcomerReport {
+                            index: node.index,
+                            added_at,
+                            informed_after: None,
+                        });
+                        let last = report.newcomers.len() - 1;
+                        &mut report.newcomers[last]
+                    }
+                };
+                if newcomer.informed_after.is_none()
+                    && seen_alive(&node.node, &settings).is_superset(&alive_ids)
+                {
+                    newcomer.informed_after =
+                        Some(timeline.time.elapsed().saturating_sub(added_at));
+                }
+            }
+
+            // the nodes' own counters, every datagram whatever it carries; a node's counters
+            // only ever go up, and nodes are never removed from the cluster
+            let node_stats: Vec<NodeStats> = all_nodes.iter().map(|node| node.stats()).collect();
+            let total_stats: NodeStats = node_stats.iter().copied().sum();
+            let n_datagrams_sent = total_stats.messages_sent() - previous_stats.messages_sent();
+            let n_datagrams_received =
+                total_stats.messages_received - previous_stats.messages_received;
+            let n_bytes_sent = total_stats.bytes_sent - previous_stats.bytes_sent;
+            let n_bytes_received = total_stats.bytes_received - previous_stats.bytes_received;
+            previous_stats = total_stats;
+            let total_latency: LatencyHistogram = all_nodes
+                .iter()
+                .map(|node| node.propagation_latency())
+                .sum();
+            let latency = total_latency.since(&previous_latency);
+            previous_latency = total_latency;
+            previous_node_bytes.resize(node_stats.len(), 0);
+            let max_node_bytes = node_stats
+                .iter()
+                .zip(&mut previous_node_bytes)
+                .map(|(stats, previous)| {
+                    let bytes = stats.bytes_sent + stats.bytes_received;
+                    let since = bytes.saturating_sub(*previous);
+                    *previous = bytes;
+                    since
+                })
+                .max()
+                .unwrap_or(0);
+
+            // per second, whatever the time between two samples
+            let at = timeline.time.elapsed();
+            let seconds = at.saturating_sub(previous_at).as_secs_f64();
+            previous_at = at;
+            let per_second = |count: u64| {
+                if seconds > 0.0 {
+                    count as f64 / seconds
+                } else {
+                    0.0
+                }
+            };
+            let kib_per_second = |bytes: u64| per_second(bytes) / 1024.0;
+
+            let (detected, slowest_detection) = timeline
+                .detected_at
+                .lock()
+                .map(|detected_at| {
+                    let slowest = detected_at.iter().map(|(_, after)| *after).max();
+                    (detected_at.len(), slowest)
+                })
+                .unwrap_or_default();
+            let sample = Sample {
+                at,
+                nodes: all_nodes.len(),
+                alive: number_alive,
+                fully_informed: n_fully_informed as usize,
+                know_all: n_know_all as usize,
+                mean_known: total_known as f64 / number_nodes.max(1) as f64,
+                correct_views,
+                heartbeats_received: n_heartbeats_received,
+                duplicates: n_duplicates,
+                messages_sent: n_datagrams_sent,
+                total_messages_sent: total_stats.messages_sent(),
+                messages_received: n_datagrams_received,
+                total_messages_received: total_stats.messages_received,
+                latency_p50: latency.percentile(0.5),
+                latency_p95: latency.percentile(0.95),
+                latency_p99: latency.percentile(0.99),
+                sent_kib_per_second: kib_per_second(n_bytes_sent),
+                received_kib_per_second: kib_per_second(n_bytes_received),
+                max_node_kib_per_second: kib_per_second(max_node_bytes),
+                total_bytes_sent: total_stats.bytes_sent,
+                total_bytes_received: total_stats.bytes_received,
+                detected,
+                slowest_detection,
+            };
+            write_sample(&mut samples, &sample);
+            let mut entry = SampleEntry::from(&sample);
+            entry.node_groups = report
+                .node_groups
+                .iter()
+                .map(|group| group.final_fully_informed)
+                .collect();
+            if settings.churn_from.is_some_and(|from| sample.at >= from) {
+                report
+                    .churn
+                    .get_or_insert_with(ChurnReport::default)
+                    .add(&sample);
+            }
+
+            let executed: Vec<Duration> = timeline
+                .events
+                .lock()
+                .map(|events| events.iter().map(|event| event.at).collect())
+                .unwrap_or_default();
+            report
+                .event_convergence
+                .resize_with(executed.len(), EventConvergence::default);
+            let converged = sample.alive > 0 && sample.correct_views == sample.alive;
+            for (convergence, at) in report.event_convergence.iter_mut().zip(executed) {
+                convergence.track(at, sample.at, converged, settings.convergence_samples);
+            }
+            report.convergence.extend(convergence.track(
+                sample.at,
+                sample.alive,
+                &diverging,
+                settings.convergence_samples,
+            ));
+
+            let value_put = timeline.value_put.lock().ok().and_then(|put| put.clone());
+            if let Some((key, put_at)) = &value_put {
+                let n_readable = all_nodes
+                    .iter()
+                    .filter(|node| node.get(key).is_some())
+                    .count();
+                report.final_value_readable = n_readable;
+                entry.value_readable = Some(n_readable);
+                if n_readable as u64 == number_nodes && report.value_readable_after.is_none() {
+                    report.value_readable_after = Some(sample.at.saturating_sub(*put_at));
+                }
+                value_readable.push((i as f32, n_readable as f32));
+            }
+
+            if let Some(faults) = &settings.faults {
+                let elapsed = faults.elapsed();
+                let partitions = faults.partitions();
+                for partition in &partitions[report.partitions.len()..] {
+                    group_informed.push(vec![vec![]; partition.groups.len()]);
+                    report.partitions.push(PartitionReport {
+                        group_sizes: partition.groups.iter().map(|group| group.len()).collect(),
+                        lowest_group_informed: vec![None; partition.groups.len()],
+                        reconverged_after: None,
+                    });
+                }
+                entry.partitions = vec![None; partitions.len()];
+                for (index, partition) in partitions.iter().enumerate() {
+                    let partition_report = &mut report.partitions[index];
+                    if partition.is_active(elapsed) {
+                        let informed = calculate_group_informed(&all_nodes, partition, &settings);
+                        entry.partitions[index] = Some(informed.clone());
+                        for (group, n_informed) in informed.into_iter().enumerate() {
+                            let lowest = &mut partition_report.lowest_group_informed[group];
+                            *lowest = Some(lowest.map_or(n_informed, |n| n.min(n_informed)));
+                            group_informed[index][group].push((i as f32, n_informed as f32));
+                        }
+                    } else if let Some(until) = partition.until.filter(|until| elapsed >= *until) {
+                        if partition_report.reconverged_after.is_none()
+                            && n_fully_informed == number_nodes as f32
+                        {
+                            partition_report.reconverged_after = Some(elapsed - until);
+                        }
+                    }
+                }
+            }
+
+            fully_informed.push((i as f32, sample.fully_informed as f32));
+            know_all.push((i as f32, sample.know_all as f32));
+            number_nodes_alive.push((i as f32, sample.alive as f32));
+            event_marks.resize(
+                report.event_convergence.len(),
+                (i as f32, sample.alive as f32),
+            );
+            nodes_started.push((i as f32, sample.nodes as f32));
+            mean_known.push((i as f32, sample.mean_known as f32));
+            datagrams_sent.push((i as f32, per_second(sample.messages_sent) as f32));
+            datagrams_received.push((i as f32, per_second(sample.messages_received) as f32));
+            if settings.chart_heartbeats_received {
+                heartbeats_received.push((i as f32, per_second(sample.heartbeats_received) as f32));
+            }
+            max_datagram_rate = [&datagrams_sent, &datagrams_received, &heartbeats_received]
+                .iter()
+                .filter_map(|series| series.last())
+                .map(|(_, rate)| *rate)
+                .fold(max_datagram_rate, f32::max);
+            bandwidth_sent.push((i as f32, sample.sent_kib_per_second as f32));
+            bandwidth_received.push((i as f32, sample.received_kib_per_second as f32));
+            busiest_node.push((i as f32, sample.max_node_kib_per_second as f32));
+            max_bandwidth = [
+                sample.sent_kib_per_second,
+                sample.received_kib_per_second,
+                sample.max_node_kib_per_second,
+            ]
+            .into_iter()
+            .fold(max_bandwidth, |max, rate| f32::max(max, rate as f32));
+            if let Some(p95) = sample.latency_p95 {
+                let p95 = p95.as_millis() as f32;
+                latency_p95.push((i as f32, p95));
+                max_latency_p95 = f32::max(max_latency_p95, p95);
+            }
+
+            report.samples.push(entry);
+
+            if let Some(subscription) = &event_log {
+                while let Some(event) = subscription.try_recv() {
+                    let peer = event.peer();
+                    recent_events.push_back(format!(
+                        "[{}] node {} {}: {}",
+                        peer.observed_at,
+                        peer.id,
+                        event.name(),
+                        peer.reason
+                    ));
+                    if recent_events.len() > EVENT_LOG_LINES {
+                        recent_events.pop_front();
+                    }
+                    report.membership_events.push(MembershipEntry {
+                        at: sample.at.as_secs_f64(),
+                        observed_at: peer.observed_at,
+                        event: event.name().to_string(),
+                        peer: peer.id.to_string(),
+                        reason: peer.reason.clone(),
+                    });
+                }
+            }
+
+            if settings.headless {
+                wait_for_sample(&timeline, settings.sample_interval);
+                i += 1;
+                continue;
+            }
+
+            term.move_cursor_to(0, 0).unwrap();
+            println!("Simulating {}", settings.header);
+            let status = timeline
+                .control_status
+                .lock()
+                .ok()
+                .and_then(|status| status.clone());
+            println!(
+                "{:<100}",
+                status
+                    .map(|status| format!("> {}", status))
+                    .unwrap_or_default()
+            );
+            if timeline.display_frozen.load(Ordering::Relaxed) {
+                wait_for_sample(&timeline, settings.sample_interval);
+                i += 1;
+                continue;
+            }
+            println!("Yellow = N nodes that has the latest heartbeat for each node.");
+            // println!("Blue = N nodes that know about all other nodes");
+            textplots::Chart::new_with_y_range(200, 50, 0.0, i as f32, 0.0, number_nodes as f32)
+                .linecolorplot(&Shape::Lines(&fully_informed), YELLOW)
+                // .linecolorplot(&Shape::Lines(&know_all), BLUE) // NOT SURE IF USEFUL
+                .display();
+
+            if settings.chart_heartbeats_received {
+                println!(
+                    "Datagrams per second of the whole cluster (blue = sent, cyan = received, \
+                     purple = heartbeats received as counted by the storages)"
+                );
+            } else {
+                println!(
+                    "Datagrams per second of the whole cluster (blue = sent, cyan = received)"
+                );
+            }
+            let mut lines = vec![
+                (Shape::Lines(&datagrams_sent), BLUE),
+                (Shape::Lines(&datagrams_received), CYAN),
+            ];
+            if settings.chart_heartbeats_received {
+                lines.push((Shape::Lines(&heartbeats_received), PURPLE));
+            }
+            let mut chart =
+                textplots::Chart::new_with_y_range(200, 50, 0.0, i as f32, 0.0, max_datagram_rate);
+            lines
+                .iter()
+                .fold(&mut chart, |chart, (shape, color)| {
+                    chart.linecolorplot(shape, *color)
+                })
+                .display();
+
+            println!(
+                "KiB per second of the whole cluster (blue = sent, cyan = received) and of the \
+                 busiest node (red = sent and received)"
+            );
+            textplots::Chart::new_with_y_range(200, 50, 0.0, i as f32, 0.0, max_bandwidth)
+                .linecolorplot(&Shape::Lines(&bandwidth_sent), BLUE)
+                .linecolorplot(&Shape::Lines(&bandwidth_received), CYAN)
+                .linecolorplot(&Shape::Lines(&busiest_node), RED)
+                .display();
+
+            println!(
+                "Propagation latency in ms, 95th percentile of the heartbeats applied each sample"
+            );
+            textplots::Chart::new_with_y_range(200, 50, 0.0, i as f32, 0.0, max_latency_p95)
+                .linecolorplot(&Shape::Lines(&latency_p95), YELLOW)
+                .display();
+
+            println!("Number Alive Nodes (red = scenario events)");
+            textplots::Chart::new_with_y_range(200, 50, 0.0, i as f32, 0.0, number_nodes as f32)
+                .linecolorplot(&Shape::Lines(&number_nodes_alive), GREEN)
+                .linecolorplot(&Shape::Points(&event_marks), RED)
+                .display();
+
+            if settings.startup_ramp {
+                println!("Nodes started (green) and known by a node on average (yellow)");
+                textplots::Chart::new_with_y_range(
+                    200,
+                    50,
+                    0.0,
+                    i as f32,
+                    0.0,
+                    number_nodes as f32,
+                )
+                .linecolorplot(&Shape::Lines(&nodes_started), GREEN)
+                .linecolorplot(&Shape::Lines(&mean_known), YELLOW)
+                .display();
+            }
+
+            if let Some((key, _)) = &value_put {
+                println!("Number Nodes that can read the value of {}", key);
+                textplots::Chart::new_with_y_range(
+                    200,
+                    50,
+                    0.0,
+                    i as f32,
+                    0.0,
+                    number_nodes as f32,
+                )
+                .linecolorplot(&Shape::Lines(&value_readable), CYAN)
+                .display();
+            }
+
+            if !node_group_informed.is_empty() {
+                let names: Vec<String> = report
+                    .node_groups
+                    .iter()
+                    .enumerate()
+                    .map(|(group, report)| {
+                        format!(
+                            "{} = {}",
+                            GROUP_COLOR_NAMES[group % GROUP_COLORS.len()],
+                            report.name
+                        )
+                    })
+                    .collect();
+                println!("Fully informed nodes per group ({})", names.join(", "));
+                let max_group = report
+                    .node_groups
+                    .iter()
+                    .map(|group| group.nodes)
+                    .max()
+                    .unwrap_or_default();
+                let shapes: Vec<Shape> = node_group_informed
+                    .iter()
+                    .map(|series| Shape::Lines(series))
+                    .collect();
+                let mut chart = textplots::Chart::new_with_y_range(
+                    200,
+                    50,
+                    0.0,
+                    i as f32,
+                    0.0,
+                    max_group as f32,
+                );
+                shapes
+                    .iter()
+                    .enumerate()
+                    .fold(&mut chart, |chart, (group, shape)| {
+                        chart.linecolorplot(shape, GROUP_COLORS[group % GROUP_COLORS.len()])
+                    })
+                    .display();
+            }
+
+            for (index, groups) in group_informed.iter().enumerate() {
+                if groups.iter().all(|series| series.is_empty()) {
+                    continue;
+                }
+                println!(
+                    "Partition {}: N nodes that have the latest heartbeat of every node in their group",
+                    index + 1
+                );
+                let max_group = report.partitions[index]
+                    .group_sizes
+                    .iter()
+                    .max()
+                    .copied()
+                    .unwrap_or_default();
+                let shapes: Vec<Shape> = groups.iter().map(|series| Shape::Lines(series)).collect();
+                let mut chart = textplots::Chart::new_with_y_range(
+                    200,
+                    50,
+                    0.0,
+                    i as f32,
+                    0.0,
+                    max_group as f32,
+                );
+                shapes
+                    .iter()
+                    .enumerate()
+                    .fold(&mut chart, |chart, (group, shape)| {
+                        chart.linecolorplot(shape, GROUP_COLORS[group % GROUP_COLORS.len()])
+                    })
+                    .display();
+            }
+
+            if let Ok(events) = timeline.events.lock() {
+                if !events.is_empty() {
+                    println!("Scenario events");
+                    for event in events.iter().rev().take(SCENARIO_EVENT_LINES).rev() {
+                        println!(
+                            "{:<100}",
+                            format!("[{:.3}s] {}", event.at.as_secs_f64(), event.description)
+                        );
+                    }
+                }
+            }
+
+            if !report.convergence.is_empty() {
+                println!("Convergence of the cluster");
+                for record in report
+                    .convergence
+                    .iter()
+                    .rev()
+                    .take(CONVERGENCE_LINES)
+                    .rev()
+                {
+                    println!("{:<100}", record.to_string());
+                }
+            }
+
+            if event_log.is_some() {
+                println!("Membership events seen by the first node");
+                for line in &recent_events {
+                    println!("{:<100}", line);
+                }
+            }
+
+            if summary_requested.try_iter().count() > 0 {
+                summary = all_nodes
+                    .first()
+                    .map(|node| format!("{:#}", node.summary()));
+            }
+            match &summary {
+                Some(summary) => {
+                    println!("Summary of the first node (press enter to refresh)");
+                    for line in summary.lines() {
+                        println!("{:<100}", line);
+                    }
+                }
+                None => println!(
+                    "Type and press enter: nothing for a summary of the first node, k [NODE] to kill, \
+                     r to revive, s [FOR] to split the cluster, d FACTOR to change the decay, \
+                     +[N] to add nodes, f to freeze the charts"
+                ),
+            }
+
+            wait_for_sample(&timeline, settings.sample_interval);
+            i += 1;
+        }
+
+        if !settings.headless {
+            term.show_cursor().unwrap();
+        }
+        if let Some(Err(e)) = samples.as_mut().map(|writer| writer.flush()) {
+            error!(error = e.to_string(), "failed to write samples");
+        }
+        let all_nodes: Vec<Arc<gossip::Node>> = cluster
+            .read()
+            .map(|nodes| nodes.iter().map(|node| node.node.clone()).collect())
+            .unwrap_or_default();
+        report.propagation_delays = propagation_delays(&all_nodes);
+        report.propagation_latency = all_nodes
+            .iter()
+            .map(|node| node.propagation_latency())
+            .sum();
+        let value_put = timeline.value_put.lock().ok().and_then(|put| put.clone());
+        if let Ok(nodes) = cluster.read() {
+            let newest = newest_heartbeats(&nodes);
+            report.nodes = nodes
+                .iter()
+                .map(|node| {
+                    node_entry(
+                        node,
+                        value_put.as_ref().map(|(key, _)| key.as_str()),
+                        &newest,
+                    )
+                })
+                .collect();
+        }
+        if let Ok(nodes) = cluster.read() {
+            for (group_report, (_, range)) in
+                report.node_groups.iter_mut().zip(&settings.node_groups)
+            {
+                group_report.messages_sent = nodes
+                    .iter()
+                    .filter(|node| range.contains(&node.index))
+                    .map(|node| node.node.stats().messages_sent())
+                    .sum();
+            }
+        }
+        report
+    })
+}
+
+/// The timestamp of the latest heartbeat of every running node, from its own storage.
+fn newest_heartbeats(nodes: &[SimNode]) -> HashMap<NodeId, u64> {
+    nodes
+        .iter()
+        .filter(|node| node.handle.is_running())
+        .filter_map(|node| {
+            node.node.storage().with_read(|storage| {
+                storage
+                    .data
+                    .get(&storage.owner)
+                    .map(|entry| (storage.owner.clone(), entry.heartbeat.timestamp()))
+            })
+        })
+        .collect()
+}
+
+/// The state of `node` for the report, `key` being the value put with `--put-value`, if any, and
+/// `newest` the latest heartbeats of the running nodes.
+fn node_entry(node: &SimNode, key: Option<&str>, newest: &HashMap<NodeId, u64>) -> NodeEntry {
+    let summary = node.node.summary();
+    let heartbeat_lag = node.handle.is_running().then(|| {
+        node.node.storage().with_read(|storage| {
+            newest
+                .iter()
+                .filter_map(|(id, &timestamp)| {
+                    let stored = storage.data.get(id)?;
+                    Some(timestamp.saturating_sub(stored.heartbeat.timestamp()))
+                })
+                .max()
+                .unwrap_or(0)
+        })
+    });
+    NodeEntry {
+        index: node.index,
+        id: summary.id.to_string(),
+        address: summary.address,
+        running: node.handle.is_running(),
+        paused: node.handle.is_paused(),
+        added_at: node.added_at.map(|at| at.as_secs_f64()),
+        peers_known: summary.peers_known,
+        peers_alive: summary.peers_alive,
+        value_readable: key.map(|key| node.node.get(key).is_some()),
+        heartbeat_lag,
+        stats: node.node.stats(),
+    }
+}
+
+/// Sleeps until the next sample is due, or until the simulation stops.
+fn wait_for_sample(timeline: &Timeline, interval: Duration) {
+    let due = timeline.time.elapsed() + interval;
+    while !timeline.stopping.load(Ordering::SeqCst) {
+        let left = due.saturating_sub(timeline.time.elapsed());
+        if left.is_zero() {
+            break;
+        }
+        timeline.time.sleep(left.min(STOP_POLL_INTERVAL));
+    }
+}
+
+/// Writes a row to the samples file, giving up on it after the first error.
+fn write_sample(samples: &mut Option<SampleWriter>, sample: &Sample) {
+    if let Some(Err(e)) = samples.as_mut().map(|writer| writer.write(sample)) {
+        error!(error = e.to_string(), "failed to write samples");
+        *samples = None;
+    }
+}
+
+fn propagation_delays(all_nodes: &[Arc<gossip::Node>]) -> Vec<u64> {
+    let mut delays = vec![];
+    for node in all_nodes {
+        node.storage().with_read(|storage| {
+            delays.extend(
+                storage
+                    .iter_entries()
+                    .filter(|data| *data.heartbeat.id() != storage.owner && !data.provisional)
+                    .map(|data| data.propagation_delay()),
+            )
+        });
+    }
+    delays.sort_unstable();
+    delays
+}
+
+/// Returns how many of `alive_nodes` have a fresh heartbeat of every alive node and of no other.
+fn calculate_correct_views(
+    alive_nodes: &[&SimNode],
+    alive_ids: &HashSet<NodeId>,
+    settings: &PlotSettings,
+) -> usize {
+    alive_nodes.len() - incorrect_views(alive_nodes, alive_ids, settings).len()
+}
+
+/// Indexes of the `alive_nodes` that see other nodes alive than those of `alive_ids`.
+fn incorrect_views(
+    alive_nodes: &[&SimNode],
+    alive_ids: &HashSet<NodeId>,
+    settings: &PlotSettings,
+) -> Vec<u64> {
+    alive_nodes
+        .iter()
+        .filter(|node| seen_alive(&node.node, settings) != *alive_ids)
+        .map(|node| node.index)
+        .collect()
+}
+
+/// Counts the entries of `observers` whose id none of `nodes` has.
+fn calculate_phantoms(observers: &[&SimNode], nodes: &[SimNode]) -> usize {
+    let ids: HashSet<NodeId> = nodes
+        .iter()
+        .map(|node| {
+            node.node
+                .storage()
+                .with_read(|storage| storage.owner.clone())
+        })
+        .collect();
+    observers
+        .iter()
+        .map(|node| {
+            node.node.storage().with_read(|storage| {
+                storage
+                    .iter_entries()
+                    .filter(|data| !ids.contains(data.heartbeat.id()))
+                    .count()
+            })
+        })
+        .sum()
+}
+
+/// Returns, for every node group, how many of its nodes are fully informed and how many of its
+/// alive nodes some alive node sees as suspect or dead, judged by that node's own timeouts.
+fn calculate_node_group_metrics(
+    nodes: &[SimNode],
+    alive_ids: &HashSet<NodeId>,
+    number_nodes: u64,
+    settings: &PlotSettings,
+) -> Vec<(usize, usize)> {
+    let mut suspected: HashSet<NodeId> = HashSet::new();
+    for node in nodes {
+        let storage = node.node.storage();
+        if !alive_ids.contains(&storage.with_read(|storage| storage.owner.clone())) {
+            continue;
+        }
+        storage.with_read(|storage| {
+            suspected.extend(
+                storage
+                    .iter_entries()
+                    .filter(|data| matches!(data.state, PeerState::Suspect | PeerState::Dead))
+                    .map(|data| data.heartbeat.id())
+                    .filter(|id| alive_ids.contains(*id))
+                    .cloned(),
+            )
+        });
+    }
+    settings
+        .node_groups
+        .iter()
+        .map(|(_, range)| {
+            let members = nodes.iter().filter(|node| range.contains(&node.index));
+            let mut n_informed = 0;
+            let mut n_suspected = 0;
+            for node in members {
+                let (owner, metrics) = node.node.storage().with_read(|storage| {
+                    (
+                        storage.owner.clone(),
+                        storage.metrics(
+                            settings.clock.as_ref(),
+                            settings.suspect_timeout,
+                            settings.dead_timeout,
+                        ),
+                    )
+                });
+                if metrics.alive as u64 == number_nodes {
+                    n_informed += 1;
+                }
+                if suspected.contains(&owner) {
+                    n_suspected += 1;
+                }
+            }
+            (n_informed, n_suspected)
+        })
+        .collect()
+}
+
+/// The ids of the nodes `node` has a fresh heartbeat of, its own included.
+fn seen_alive(node: &gossip::Node, settings: &PlotSettings) -> HashSet<NodeId> {
+    let now = settings.clock.now();
+    node.storage().with_read(|storage| {
+        storage
+            .iter_entries()
+            .filter(|data| {
+                !data.provisional
+                    && PeerState::of(
+                        &data.heartbeat,
+                        data.claimed_age(now),
+                        settings.suspect_timeout,
+                        settings.dead_timeout,
+                    ) == PeerState::Alive
+            })
+            .map(|data| data.heartbeat.id().clone())
+            .collect()
+    })
+}
+
+/// Returns, for every group of `partition`, how many of its nodes have a fresh heartbeat of every
+/// node in the group.
+fn calculate_group_informed(
+    all_nodes: &[Arc<gossip::Node>],
+    partition: &Partition,
+    settings: &PlotSettings,
+) -> Vec<usize> {
+    let now = settings.clock.now();
+    let mut informed = vec![0; partition.groups.len()];
+    for node in all_nodes {
+        node.storage().with_read(|storage| {
+            let Some(group) = storage
+                .data
+                .get(&storage.owner)
+                .and_then(|own| partition.group_of(own.heartbeat.address()))
+            else {
+                return;
+            };
+            let members = &partition.groups[group];
+            let fresh = storage
+                .iter_entries()
+                .filter(|data| !data.provisional && members.contains(&data.heartbeat.address()))
+                .filter(|data| {
+                    PeerState::of(
+                        &data.heartbeat,
+                        data.claimed_age(now),
+                        settings.suspect_timeout,
+                        settings.dead_timeout,
+                    ) == PeerState::Alive
+                })
+                .count();
+            if fresh == members.len() {
+                informed[group] += 1;
+            }
+        });
+    }
+    informed
+}
+
+/// Returns how many nodes are fully informed, how many know every node, how many nodes they know
+/// in total, and how many first and duplicate copies of heartbeats the nodes have received in
+/// total.
+fn calculate_metrics(
+    all_nodes: &[Arc<gossip::Node>],
+    number_nodes: u64,
+    settings: &PlotSettings,
+) -> (f32, f32, usize, u64, u64) {
+    let mut total_known = 0;
+    let mut total_updates = 0;
+    let mut total_duplicates = 0;
+    let mut n_fully_informed = 0;
+    let mut n_know_all = 0;
+    for node in all_nodes {
+        // aggregate in place under the read lock, copying every storage is too slow for big
+        // simulations
+        let metrics = node.storage().with_read(|storage| {
+            storage.metrics(
+                settings.clock.as_ref(),
+                settings.suspect_timeout,
+                settings.dead_timeout,
+            )
+        });
+        total_updates += metrics.total_updates;
+        total_duplicates += metrics.total_duplicates;
+        total_known += metrics.entries - metrics.provisional;
+        if metrics.entries - metrics.provisional >= number_nodes as usize {
+            n_know_all += 1;
+        }
+        // a node is fully informed when it has a recent heartbeat of every node
+        if metrics.alive as u64 == number_nodes {
+            n_fully_informed += 1;
+        }
+    }
+
+    (
+        n_fully_informed as f32,
+        n_know_all as f32,
+        total_known,
+        total_updates,
+        total_duplicates,
+    )
+}
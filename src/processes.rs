@@ -0,0 +1,140 @@
+//! Nodes running as OS processes of their own, for `muck --processes`. The orchestrator shares no
+//! memory with them: it kills them for real, with SIGKILL, and learns about them only through the
+//! admin queries they answer on their gossip address, see [`crate::admin`].
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::Duration;
+use thiserror::Error;
+use tracing::error;
+
+use crate::admin::{self, AdminQuery};
+use crate::gossip::HeartbeatError;
+use crate::membership::MemberInfo;
+use crate::stats::NodeStats;
+
+#[derive(Debug, Error)]
+pub enum QueryError {
+    #[error("query failed: {0}")]
+    Query(#[from] HeartbeatError),
+    #[error("unexpected reply: {0}")]
+    Reply(#[from] serde_json::Error),
+}
+
+/// A node of the cluster and the process currently running it, if any. Killing the node kills
+/// the process, reviving it spawns a new one from the same command.
+pub struct NodeProcess {
+    pub index: u64,
+    /// address the node listens on
+    pub address: SocketAddr,
+    command: Command,
+    child: Option<Child>,
+    /// datagrams sent by the earlier processes of the node, whose counters are gone
+    earlier_messages_sent: u64,
+    /// counters of the current process as of the latest query
+    latest_stats: NodeStats,
+}
+
+impl NodeProcess {
+    /// A node run by `command`, not started yet. The node's output is discarded, its errors go
+    /// to the orchestrator's stderr.
+    pub fn new(index: u64, address: SocketAddr, mut command: Command) -> Self {
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit());
+        // ctrl-c in a terminal goes to the whole process group, the orchestrator stops the
+        // nodes itself
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        NodeProcess {
+            index,
+            address,
+            command,
+            child: None,
+            earlier_messages_sent: 0,
+            latest_stats: NodeStats::default(),
+        }
+    }
+
+    /// Starts a process for the node, unless one is running already.
+    pub fn spawn(&mut self) -> io::Result<()> {
+        if self.child.is_none() {
+            self.child = Some(self.command.spawn()?);
+        }
+        Ok(())
+    }
+
+    /// Kills the node's process without giving it a chance to clean up, SIGKILL on unix, and
+    /// waits for it to be gone.
+    pub fn kill(&mut self) -> io::Result<()> {
+        let Some(mut child) = self.child.take() else {
+            return Ok(());
+        };
+        self.earlier_messages_sent += self.latest_stats.messages_sent();
+        self.latest_stats = NodeStats::default();
+        child.kill()?;
+        child.wait()?;
+        Ok(())
+    }
+
+    /// Whether the node has a process. A process that exited on its own is reaped, and its
+    /// exit status returned once.
+    pub fn poll(&mut self) -> Result<bool, ExitStatus> {
+        let Some(child) = &mut self.child else {
+            return Ok(false);
+        };
+        match child.try_wait() {
+            Ok(None) => Ok(true),
+            Ok(Some(status)) => {
+                self.child = None;
+                self.earlier_messages_sent += self.latest_stats.messages_sent();
+                self.latest_stats = NodeStats::default();
+                Err(status)
+            }
+            // the process can't be waited on, it will show up as unreachable
+            Err(_) => Ok(true),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.child.is_some()
+    }
+
+    /// The node's member list, see [`crate::gossip::Node::members`].
+    pub fn members(&self, timeout: Duration) -> Result<Vec<MemberInfo>, QueryError> {
+        let reply = admin::query(self.admin_target(), AdminQuery::Members, timeout)?;
+        Ok(serde_json::from_value(reply)?)
+    }
+
+    /// Asks the node for its counters and keeps them for [`NodeProcess::messages_sent`].
+    pub fn refresh_stats(&mut self, timeout: Duration) -> Result<NodeStats, QueryError> {
+        let reply = admin::query(self.admin_target(), AdminQuery::Stats, timeout)?;
+        self.latest_stats = serde_json::from_value(reply)?;
+        Ok(self.latest_stats)
+    }
+
+    /// Datagrams sent by all processes of the node, as of the latest query.
+    pub fn messages_sent(&self) -> u64 {
+        self.earlier_messages_sent + self.latest_stats.messages_sent()
+    }
+
+    /// Admin queries are only answered for localhost, whatever address the node listens on.
+    fn admin_target(&self) -> SocketAddr {
+        SocketAddr::from((Ipv4Addr::LOCALHOST, self.address.port()))
+    }
+}
+
+impl Drop for NodeProcess {
+    /// No node outlives the orchestrator, even if it panics.
+    fn drop(&mut self) {
+        if let Err(e) = self.kill() {
+            error!(
+                node_id = self.index,
+                error = e.to_string(),
+                "failed to kill node process"
+            );
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Node counters in the Prometheus text exposition format, served at `/metrics` by the http
+//! endpoint of a node (see [`crate::http`]) and by `muck --metrics-address` for every node of a
+//! simulated cluster at once.
+//!
+//! Every sample carries a `node` label with the id of the node it belongs to, so the output of
+//! single nodes and of a whole simulation can be scraped side by side. The metric names, their
+//! types and labels are listed in [`COUNTERS`] and [`GAUGES`]; they are part of the interface of
+//! the crate and only ever get added to:
+//!
+//! | metric | type | labels |
+//! |---|---|---|
+//! | `gossip_messages_sent_total` | counter | `node` |
+//! | `gossip_heartbeats_sent_total` | counter | `node` |
+//! | `gossip_values_sent_total` | counter | `node` |
+//! | `gossip_messages_forwarded_total` | counter | `node` |
+//! | `gossip_messages_received_total` | counter | `node` |
+//! | `gossip_duplicates_received_total` | counter | `node` |
+//! | `gossip_decode_errors_total` | counter | `node` |
+//! | `gossip_sends_failed_total` | counter | `node` |
+//! | `gossip_stale_heartbeats_total` | counter | `node` |
+//! | `gossip_bytes_sent_total` | counter | `node` |
+//! | `gossip_bytes_received_total` | counter | `node` |
+//! | `gossip_members` | gauge | `node`, `state` = `alive`, `suspect`, `dead` or `left` |
+//! | `gossip_storage_entries` | gauge | `node` |
+
+use std::fmt::Write;
+
+use crate::node_id::NodeId;
+use crate::stats::{NodeStats, StorageMetrics};
+
+/// Content type of [`render`]'s output, version 0.0.4 of the text format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// A metric family with a sample per node, taken from a `T` of the node.
+pub struct Metric<T> {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub value: fn(&T) -> u64,
+}
+
+/// The counters exposed for every node.
+pub const COUNTERS: &[Metric<NodeStats>] = &[
+    Metric {
+        name: "gossip_messages_sent_total",
+        help: "Datagrams sent: own heartbeats, own values and forwarded gossip.",
+        value: NodeStats::messages_sent,
+    },
+    Metric {
+        name: "gossip_heartbeats_sent_total",
+        help: "Datagrams carrying the node's own heartbeat.",
+        value: |stats| stats.heartbeats_sent,
+    },
+    Metric {
+        name: "gossip_values_sent_total",
+        help: "Datagrams carrying one of the node's own values.",
+        value: |stats| stats.values_sent,
+    },
+    Metric {
+        name: "gossip_messages_forwarded_total",
+        help: "Datagrams carrying another node's heartbeat or value that was passed on.",
+        value: |stats| stats.gossip_forwarded,
+    },
+    Metric {
+        name: "gossip_messages_received_total",
+        help: "Datagrams read from the socket, including ones that failed to decode.",
+        value: |stats| stats.messages_received,
+    },
+    Metric {
+        name: "gossip_duplicates_received_total",
+        help: "Copies received of heartbeats already known, stale ones included.",
+        value: |stats| stats.duplicates_received,
+    },
+    Metric {
+        name: "gossip_decode_errors_total",
+        help: "Datagrams that could not be decoded.",
+        value: |stats| stats.decode_errors,
+    },
+    Metric {
+        name: "gossip_sends_failed_total",
+        help: "Send calls that failed, however many targets they had.",
+        value: |stats| stats.sends_failed,
+    },
+    Metric {
+        name: "gossip_stale_heartbeats_total",
+        help: "Heartbeats older than the one stored for their node.",
+        value: |stats| stats.stale_heartbeats,
+    },
+    Metric {
+        name: "gossip_bytes_sent_total",
+        help: "Bytes written to the socket, admin replies included.",
+        value: |stats| stats.bytes_sent,
+    },
+    Metric {
+        name: "gossip_bytes_received_total",
+        help: "Bytes of the datagrams read from the socket.",
+        value: |stats| stats.bytes_received,
+    },
+];
+
+/// The gauges exposed for every node besides `gossip_members`, which has a sample per state.
+pub const GAUGES: &[Metric<StorageMetrics>] = &[Metric {
+    name: "gossip_storage_entries",
+    help: "Entries in the node's storage, itself and provisional seeds included.",
+    value: |storage| storage.entries as u64,
+}];
+
+const MEMBERS: &str = "gossip_members";
+const MEMBERS_HELP: &str =
+    "Members the node knows by their state, itself included and provisional seeds left out.";
+
+/// What is exposed about one node.
+#[derive(Debug, Clone)]
+pub struct NodeMetrics {
+    pub node: NodeId,
+    pub stats: NodeStats,
+    /// the node's view, see [`crate::gossip::Node::storage_metrics`]
+    pub storage: StorageMetrics,
+}
+
+/// Renders the metrics of `nodes` in the Prometheus text format, each metric family once with a
+/// sample per node in the given order.
+pub fn render(nodes: &[NodeMetrics]) -> String {
+    let mut out = String::new();
+    for metric in COUNTERS {
+        header(&mut out, metric.name, metric.help, "counter");
+        for node in nodes {
+            let value = (metric.value)(&node.stats);
+            sample(&mut out, metric.name, &node.node, None, value);
+        }
+    }
+    for metric in GAUGES {
+        header(&mut out, metric.name, metric.help, "gauge");
+        for node in nodes {
+            let value = (metric.value)(&node.storage);
+            sample(&mut out, metric.name, &node.node, None, value);
+        }
+    }
+    header(&mut out, MEMBERS, MEMBERS_HELP, "gauge");
+    for node in nodes {
+        let storage = &node.storage;
+        for (state, count) in [
+            ("alive", storage.alive),
+            ("suspect", storage.suspect),
+            ("dead", storage.dead),
+            ("left", storage.left),
+        ] {
+            sample(&mut out, MEMBERS, &node.node, Some(state), count as u64);
+        }
+    }
+    out
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, node: &NodeId, state: Option<&str>, value: u64) {
+    let _ = write!(out, "{}{{node=\"{}\"", name, escaped(node.as_str()));
+    if let Some(state) = state {
+        let _ = write!(out, ",state=\"{}\"", state);
+    }
+    let _ = writeln!(out, "}} {}", value);
+}
+
+/// A label value with backslashes, quotes and line breaks escaped.
+fn escaped(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
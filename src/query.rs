@@ -0,0 +1,201 @@
+//! Queries over the members a storage knows, written once against [`MemberQuery`] and available
+//! on a live [`Storage`], on a [`StorageHandle`], which holds the read lock for the duration of
+//! the query, and on an exported [`StorageSnapshot`].
+//!
+//! Members are reported with the state of the latest membership evaluation, or the state
+//! exported with the snapshot; ages are measured against the `now` passed to the query.
+//! Provisional entries, seeds never heard from, are not members.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::gossip::{Storage, StorageHandle};
+use crate::membership::{MemberInfo, PeerState};
+use crate::node_id::NodeId;
+use crate::snapshot::StorageSnapshot;
+
+pub trait MemberQuery {
+    /// Calls `f` with every member as of local clock time `now`.
+    fn for_each_member(&self, now: u64, f: &mut dyn FnMut(MemberInfo));
+
+    /// Ids of the members that put `value` under `key`, see [`crate::kv`].
+    fn members_with_value(&self, key: &str, value: &[u8]) -> HashSet<NodeId>;
+
+    /// Members for which `predicate` holds.
+    fn members_where(
+        &self,
+        now: u64,
+        mut predicate: impl FnMut(&MemberInfo) -> bool,
+    ) -> Vec<MemberInfo>
+    where
+        Self: Sized,
+    {
+        let mut members = vec![];
+        self.for_each_member(now, &mut |member| {
+            if predicate(&member) {
+                members.push(member);
+            }
+        });
+        members
+    }
+
+    /// Members whose latest heartbeat is younger than `within` and that haven't left.
+    fn alive_within(&self, now: u64, within: Duration) -> Vec<MemberInfo>
+    where
+        Self: Sized,
+    {
+        self.members_where(now, |member| {
+            member.age < within && member.state != PeerState::Left
+        })
+    }
+
+    fn in_state(&self, now: u64, state: PeerState) -> Vec<MemberInfo>
+    where
+        Self: Sized,
+    {
+        self.members_where(now, |member| member.state == state)
+    }
+
+    /// Members whose latest value under `key` is `value`.
+    fn with_metadata(&self, now: u64, key: &str, value: &[u8]) -> Vec<MemberInfo>
+    where
+        Self: Sized,
+    {
+        let ids = self.members_with_value(key, value);
+        self.members_where(now, |member| ids.contains(&member.id))
+    }
+}
+
+impl MemberQuery for Storage {
+    fn for_each_member(&self, now: u64, f: &mut dyn FnMut(MemberInfo)) {
+        self.data
+            .values()
+            .filter(|data| !data.provisional)
+            .for_each(|data| f(MemberInfo::of(data, now, data.state)));
+    }
+
+    fn members_with_value(&self, key: &str, value: &[u8]) -> HashSet<NodeId> {
+        self.values
+            .with_value(key, value)
+            .map(|entry| entry.origin.clone())
+            .collect()
+    }
+}
+
+impl MemberQuery for StorageHandle {
+    fn for_each_member(&self, now: u64, f: &mut dyn FnMut(MemberInfo)) {
+        self.with_read(|storage| storage.for_each_member(now, f))
+    }
+
+    fn members_with_value(&self, key: &str, value: &[u8]) -> HashSet<NodeId> {
+        self.with_read(|storage| storage.members_with_value(key, value))
+    }
+}
+
+impl MemberQuery for StorageSnapshot {
+    fn for_each_member(&self, now: u64, f: &mut dyn FnMut(MemberInfo)) {
+        for entry in self.entries.iter().filter(|entry| !entry.provisional) {
+            // a snapshot doesn't tell when the heartbeats arrived
+            let age = Duration::from_secs(now.saturating_sub(entry.timestamp));
+            f(MemberInfo {
+                id: entry.id.clone(),
+                address: entry.address,
+                last_heartbeat: entry.timestamp,
+                age,
+                observed_age: age,
+                state: entry.state,
+                updates_received: entry.updates_received,
+                duplicates_received: entry.duplicates_received,
+            });
+        }
+    }
+
+    fn members_with_value(&self, key: &str, value: &[u8]) -> HashSet<NodeId> {
+        self.values
+            .iter()
+            .filter(|entry| entry.key == key && entry.value == value)
+            .map(|entry| entry.origin.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::KeyValue;
+    use crate::snapshot::SnapshotEntry;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn entry(id: &str, age: u64, state: PeerState) -> SnapshotEntry {
+        SnapshotEntry {
+            id: NodeId::new(id).unwrap(),
+            address: "10.0.1.1:8000".parse().unwrap(),
+            timestamp: NOW - age,
+            leaving: state == PeerState::Left,
+            state,
+            updates_received: 1,
+            duplicates_received: 0,
+            provisional: false,
+        }
+    }
+
+    fn snapshot() -> StorageSnapshot {
+        StorageSnapshot {
+            owner: NodeId::new("node-0").unwrap(),
+            entries: vec![
+                entry("node-0", 0, PeerState::Alive),
+                entry("node-1", 2, PeerState::Alive),
+                entry("node-2", 15, PeerState::Suspect),
+                entry("node-3", 1, PeerState::Left),
+                SnapshotEntry {
+                    provisional: true,
+                    ..entry("seed", 0, PeerState::Alive)
+                },
+            ],
+            values: vec![KeyValue {
+                key: "zone".to_string(),
+                value: b"a".to_vec(),
+                origin: NodeId::new("node-2").unwrap(),
+                address: "10.0.1.1:8000".parse().unwrap(),
+                version: 1,
+                timestamp: NOW,
+            }],
+        }
+    }
+
+    fn ids(members: Vec<MemberInfo>) -> Vec<String> {
+        let mut ids: Vec<String> = members
+            .into_iter()
+            .map(|member| member.id.to_string())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn storages_and_snapshots_answer_queries_alike() {
+        let snapshot = snapshot();
+        let storage = Storage::from_snapshot(snapshot.clone());
+        for (name, alive, suspect, zone) in [
+            (
+                "storage",
+                storage.alive_within(NOW, Duration::from_secs(10)),
+                storage.in_state(NOW, PeerState::Suspect),
+                storage.with_metadata(NOW, "zone", b"a"),
+            ),
+            (
+                "snapshot",
+                snapshot.alive_within(NOW, Duration::from_secs(10)),
+                snapshot.in_state(NOW, PeerState::Suspect),
+                snapshot.with_metadata(NOW, "zone", b"a"),
+            ),
+        ] {
+            assert_eq!(ids(alive), ["node-0", "node-1"], "{}", name);
+            assert_eq!(ids(suspect), ["node-2"], "{}", name);
+            assert_eq!(ids(zone), ["node-2"], "{}", name);
+        }
+        // provisional entries are not members
+        assert_eq!(storage.members_where(NOW, |_| true).len(), 4);
+    }
+}
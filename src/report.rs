@@ -0,0 +1,398 @@
+//! The report of a simulation, written as JSON next to its samples with `muck --out`, only built
+//! with the `sim` feature. It holds the config of the run, every event executed, the metrics of
+//! every sample and the final state of every node: everything the live charts show, so they can
+//! be drawn again offline, and the results printed at the end.
+//!
+//! Times are in seconds, since the start of the simulation unless said otherwise.
+
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+use crate::membership::PeerState;
+use crate::plot::{ConvergenceRecord, Sample};
+use crate::stats::{LatencyHistogram, NodeStats};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    /// the settings of the run, with the names of the scenario file keys
+    pub config: serde_json::Value,
+    pub passed: bool,
+    /// why the run failed, if it did
+    pub failures: Vec<String>,
+    /// scenario events and typed commands, in the order they were executed
+    pub events: Vec<EventEntry>,
+    /// every time the whole cluster converged or diverged, in order
+    #[serde(default)]
+    pub convergence: Vec<ConvergenceEntry>,
+    /// the metrics of the cluster at every sample, the data of the charts
+    pub samples: Vec<SampleEntry>,
+    /// what the first node saw happen to its peers
+    pub membership_events: Vec<MembershipEntry>,
+    /// every node when the simulation stopped, in the order they were started
+    pub nodes: Vec<NodeEntry>,
+    pub results: Results,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventEntry {
+    pub at: f64,
+    /// the event and what it did, e.g. which nodes it killed
+    pub description: String,
+    /// indexes of the nodes the event applied to: those it killed, revived or reconfigured, the
+    /// nodes it added, or those of the groups it cut off from each other
+    pub nodes: Vec<u64>,
+    /// time from the event until every alive node saw exactly the alive nodes as alive
+    pub converged_after: Option<f64>,
+    pub timed_out: bool,
+}
+
+/// A change of the convergence of the cluster, see [`crate::plot::ConvergenceRecord`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConvergenceEntry {
+    /// when the sample confirming the change was taken
+    pub at: f64,
+    /// `converged` or `diverged`
+    pub event: String,
+    /// for `converged`, when the first of the correct samples in a row was taken
+    pub since: Option<f64>,
+    /// for `diverged`, indexes of the alive nodes whose view was wrong
+    pub nodes: Vec<u64>,
+}
+
+impl From<&ConvergenceRecord> for ConvergenceEntry {
+    fn from(record: &ConvergenceRecord) -> Self {
+        let at = record.at().as_secs_f64();
+        match record {
+            ConvergenceRecord::Converged { since, .. } => ConvergenceEntry {
+                at,
+                event: "converged".to_string(),
+                since: Some(since.as_secs_f64()),
+                nodes: vec![],
+            },
+            ConvergenceRecord::Diverged { nodes, .. } => ConvergenceEntry {
+                at,
+                event: "diverged".to_string(),
+                since: None,
+                nodes: nodes.clone(),
+            },
+        }
+    }
+}
+
+/// A [`Sample`] and the metrics charted next to it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SampleEntry {
+    pub at: f64,
+    pub nodes: usize,
+    pub alive: usize,
+    pub fully_informed: usize,
+    pub know_all: usize,
+    pub mean_known: f64,
+    pub correct_views: usize,
+    /// since the previous sample
+    pub heartbeats_received: u64,
+    pub duplicates: u64,
+    /// datagrams the nodes sent and read since the previous sample, and since the start
+    pub messages_sent: u64,
+    pub total_messages_sent: u64,
+    #[serde(default)]
+    pub messages_received: u64,
+    #[serde(default)]
+    pub total_messages_received: u64,
+    /// how long the heartbeats applied since the previous sample took to reach their nodes
+    #[serde(default)]
+    pub latency_p50: Option<f64>,
+    #[serde(default)]
+    pub latency_p95: Option<f64>,
+    #[serde(default)]
+    pub latency_p99: Option<f64>,
+    /// KiB per second the nodes wrote and read since the previous sample, the most a single
+    /// node wrote and read together, and the bytes written and read since the start
+    #[serde(default)]
+    pub sent_kib_per_second: f64,
+    #[serde(default)]
+    pub received_kib_per_second: f64,
+    #[serde(default)]
+    pub max_node_kib_per_second: f64,
+    #[serde(default)]
+    pub total_bytes_sent: u64,
+    #[serde(default)]
+    pub total_bytes_received: u64,
+    pub detected: usize,
+    pub slowest_detection: Option<f64>,
+    /// nodes that could read the value put with `--put-value`, once it was put
+    pub value_readable: Option<usize>,
+    /// fully informed nodes of each node group, in the order the groups were given
+    pub node_groups: Vec<usize>,
+    /// for each partition added so far, the nodes of each group with a fresh heartbeat of every
+    /// node of their group; unset while the partition isn't active
+    pub partitions: Vec<Option<Vec<usize>>>,
+}
+
+impl From<&Sample> for SampleEntry {
+    fn from(sample: &Sample) -> Self {
+        SampleEntry {
+            at: sample.at.as_secs_f64(),
+            nodes: sample.nodes,
+            alive: sample.alive,
+            fully_informed: sample.fully_informed,
+            know_all: sample.know_all,
+            mean_known: sample.mean_known,
+            correct_views: sample.correct_views,
+            heartbeats_received: sample.heartbeats_received,
+            duplicates: sample.duplicates,
+            messages_sent: sample.messages_sent,
+            total_messages_sent: sample.total_messages_sent,
+            messages_received: sample.messages_received,
+            total_messages_received: sample.total_messages_received,
+            latency_p50: sample.latency_p50.map(|latency| latency.as_secs_f64()),
+            latency_p95: sample.latency_p95.map(|latency| latency.as_secs_f64()),
+            latency_p99: sample.latency_p99.map(|latency| latency.as_secs_f64()),
+            sent_kib_per_second: sample.sent_kib_per_second,
+            received_kib_per_second: sample.received_kib_per_second,
+            max_node_kib_per_second: sample.max_node_kib_per_second,
+            total_bytes_sent: sample.total_bytes_sent,
+            total_bytes_received: sample.total_bytes_received,
+            detected: sample.detected,
+            slowest_detection: sample.slowest_detection.map(|after| after.as_secs_f64()),
+            ..Default::default()
+        }
+    }
+}
+
+/// A change of a peer's state as the first node saw it, see
+/// [`crate::membership::MembershipEvent`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MembershipEntry {
+    /// when the plotting thread picked the event up
+    pub at: f64,
+    /// the first node's clock when it saw the change
+    pub observed_at: u64,
+    pub event: String,
+    pub peer: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeEntry {
+    pub index: u64,
+    pub id: String,
+    pub address: SocketAddr,
+    pub running: bool,
+    pub paused: bool,
+    /// for nodes added while the simulation ran
+    pub added_at: Option<f64>,
+    pub peers_known: usize,
+    pub peers_alive: usize,
+    /// whether the node could read the value put with `--put-value`
+    pub value_readable: Option<bool>,
+    /// how far the heartbeats the node stores of the running nodes lag behind their latest
+    /// ones, at most; unset for stopped nodes
+    pub heartbeat_lag: Option<u64>,
+    pub stats: NodeStats,
+}
+
+/// The numbers printed at the end of the run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Results {
+    pub duration: f64,
+    pub peak_fully_informed: f32,
+    pub final_fully_informed: f32,
+    pub messages_sent: u64,
+    pub heartbeats_sent: u64,
+    pub gossip_forwarded: u64,
+    /// copies of known heartbeats the nodes received, and how often they passed one on
+    pub duplicates_received: u64,
+    pub duplicate_forwards: u64,
+    pub messages_per_node_second: f64,
+    /// bytes the nodes wrote to and read from their sockets, and KiB written per node and second
+    #[serde(default)]
+    pub bytes_sent: u64,
+    #[serde(default)]
+    pub bytes_received: u64,
+    #[serde(default)]
+    pub kib_per_node_second: f64,
+    /// bytes of resident memory the nodes took, unknown without /proc
+    pub memory: Option<u64>,
+    pub memory_per_node: Option<u64>,
+    /// when each node's view first covered the whole cluster
+    pub converged: Vec<NodeTime>,
+    pub detection: Option<Detection>,
+    /// seconds the latest heartbeat of every peer took to reach each node
+    pub propagation_delays: Option<Percentiles>,
+    /// how long every heartbeat a node applied took to reach it
+    #[serde(default)]
+    pub propagation_latency: Option<LatencyDistribution>,
+    pub value_readable_after: Option<f64>,
+    pub final_value_readable: usize,
+    pub reconverged_after: Option<f64>,
+    pub faults: Option<FaultTotals>,
+    pub fabric: Option<FabricTotals>,
+    pub byzantine: Option<ByzantineResults>,
+    pub links: Vec<LinkResults>,
+    pub partitions: Vec<PartitionResults>,
+    pub churn: Option<ChurnResults>,
+    pub groups: Vec<GroupResults>,
+    pub newcomers: Vec<NewcomerResults>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeTime {
+    pub node: String,
+    pub after: f64,
+}
+
+/// How fast the observer saw the victims of the first kill dead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Detection {
+    pub observer: String,
+    pub victims: usize,
+    /// since the kill
+    pub detected: Vec<NodeTime>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Percentiles {
+    pub median: u64,
+    pub p90: u64,
+    pub max: u64,
+}
+
+/// The distribution of a [`crate::stats::LatencyHistogram`], each latency as the upper bound of
+/// its bucket.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyDistribution {
+    /// heartbeats whose latency was recorded
+    pub count: u64,
+    /// heartbeats stamped ahead of the receiver's clock, left out
+    pub ahead: u64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+    /// every bucket anything was recorded in, shortest first
+    pub buckets: Vec<LatencyBucket>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyBucket {
+    pub up_to: f64,
+    pub count: u64,
+}
+
+impl LatencyDistribution {
+    /// None if nothing was recorded.
+    pub fn of(histogram: &LatencyHistogram) -> Option<Self> {
+        let percentile = |quantile| {
+            histogram
+                .percentile(quantile)
+                .map(|latency| latency.as_secs_f64())
+        };
+        Some(LatencyDistribution {
+            count: histogram.count(),
+            ahead: histogram.ahead(),
+            p50: percentile(0.5)?,
+            p90: percentile(0.9)?,
+            p95: percentile(0.95)?,
+            p99: percentile(0.99)?,
+            max: percentile(1.0)?,
+            buckets: histogram
+                .buckets()
+                .map(|(up_to, count)| LatencyBucket {
+                    up_to: up_to.as_secs_f64(),
+                    count,
+                })
+                .collect(),
+        })
+    }
+}
+
+/// What the fault injector did to the datagrams, see [`crate::faults::FaultStats`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FaultTotals {
+    pub datagrams: u64,
+    pub dropped: u64,
+    pub partitioned: u64,
+    pub delayed: u64,
+    pub mean_delay: f64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub forged_timestamps: u64,
+    pub phantoms: u64,
+    pub replayed: u64,
+    pub flipped: u64,
+}
+
+/// What the links of the in-memory network did to the datagrams, see
+/// [`crate::memory_net::FabricStats`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FabricTotals {
+    pub datagrams: u64,
+    pub queued: u64,
+    pub tail_dropped: u64,
+    pub mean_queueing: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ByzantineResults {
+    pub mean_correct_views: f64,
+    pub final_honest: usize,
+    pub final_correct_views: usize,
+    pub peak_phantoms: usize,
+    pub final_phantoms: usize,
+    pub decode_errors: u64,
+    pub future_timestamps: u64,
+    pub stale_heartbeats: u64,
+    pub addresses_rejected: u64,
+    pub entries_evicted: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkResults {
+    pub from: u64,
+    pub to: u64,
+    pub sent: u64,
+    pub delivered: u64,
+    pub delivery_rate: f64,
+    pub samples: usize,
+    pub seen_alive: usize,
+    pub final_state: Option<PeerState>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartitionResults {
+    pub group_sizes: Vec<usize>,
+    pub lowest_group_informed: Vec<Option<usize>>,
+    pub reconverged_after: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChurnResults {
+    pub kills: usize,
+    pub revivals: usize,
+    pub mean_alive: f64,
+    pub mean_correct_views: f64,
+    pub mean_fully_informed: f64,
+    pub messages_per_node: f64,
+    pub detections: usize,
+    pub mean_detection: Option<f64>,
+    pub revived_undetected: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupResults {
+    pub name: String,
+    pub nodes: u64,
+    pub peak_fully_informed: usize,
+    pub final_fully_informed: usize,
+    pub messages_sent: u64,
+    pub peak_falsely_suspected: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewcomerResults {
+    pub node: u64,
+    pub added_at: f64,
+    pub informed_after: Option<f64>,
+}
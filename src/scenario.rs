@@ -0,0 +1,464 @@
+//! Timed events scripting a simulation, only built with the `sim` feature. A scenario is a list
+//! of [`ScheduledEvent`]s, each an [`Action`] on some of the simulated nodes at a point in time
+//! counted from the start, executed in order by the scheduler of the simulation binary.
+//!
+//! Events are written as space separated `key=value` settings, e.g.
+//! `at=60s action=kill nodes=random:20`; scenario files give the same settings as a table.
+//!
+//! The [`NodeGroup`]s configuring some nodes apart from the others are written the same way.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::gossip;
+
+/// A node of a running simulation, named after its index.
+#[derive(Clone)]
+pub struct SimNode {
+    pub index: u64,
+    pub node: Arc<gossip::Node>,
+    pub handle: gossip::NodeHandle,
+    /// since the start of the simulation, for nodes added while it runs
+    pub added_at: Option<Duration>,
+}
+
+/// Every node started in a simulation so far, shared by the threads driving it. Nodes are only
+/// ever added.
+pub type Cluster = Arc<RwLock<Vec<SimNode>>>;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum EventError {
+    #[error("expected key=value, got `{0}`")]
+    Syntax(String),
+    #[error("missing `{0}`")]
+    Missing(&'static str),
+    #[error("`{key}` is set twice")]
+    Duplicate { key: String },
+    #[error("unknown action `{0}`, expected kill, revive, stop-forwarding, partition, config, add-nodes or set-loss")]
+    UnknownAction(String),
+    #[error("`{key}` doesn't apply to {action}")]
+    Unexpected { key: String, action: String },
+    #[error("invalid value `{value}` for `{key}`: {reason}")]
+    Value {
+        key: String,
+        value: String,
+        reason: String,
+    },
+}
+
+/// Which nodes an action applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeSelection {
+    /// every node the action can apply to, e.g. every killed node for a revival
+    All,
+    /// this many of them, chosen at random, or all of them if there are fewer
+    Random(usize),
+    /// exactly the nodes with these indexes, apart from those the action can't apply to
+    Nodes(Vec<u64>),
+    /// the seeds every node knows from the start, apart from those the action can't apply to
+    Seeds,
+    /// the nodes `nodes` picks once the nodes `excluded` picks are left out, e.g. to kill random
+    /// nodes other than the seeds
+    Excluding {
+        nodes: Box<NodeSelection>,
+        excluded: Box<NodeSelection>,
+    },
+}
+
+impl FromStr for NodeSelection {
+    type Err = String;
+
+    /// Reads `all`, `seeds`, `random:N` or node indexes, optionally prefixed with `id:`, any of
+    /// them followed by `,exclude:` and another selection.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((nodes, excluded)) = s.split_once(",exclude:") {
+            return Ok(NodeSelection::Excluding {
+                nodes: Box::new(nodes.parse()?),
+                excluded: Box::new(excluded.parse()?),
+            });
+        }
+        match s {
+            "all" => return Ok(NodeSelection::All),
+            "seeds" => return Ok(NodeSelection::Seeds),
+            _ => {}
+        }
+        let Some(count) = s.strip_prefix("random:") else {
+            return parse_nodes(s.strip_prefix("id:").unwrap_or(s))
+                .map(NodeSelection::Nodes)
+                .map_err(|e| {
+                    format!(
+                        "expected all, seeds, random:N or node indexes, optionally followed by \
+                         ,exclude: and another of them: {}",
+                        e
+                    )
+                });
+        };
+        count
+            .parse()
+            .map(NodeSelection::Random)
+            .map_err(|e| format!("`{}` is not a number of nodes: {}", count, e))
+    }
+}
+
+impl fmt::Display for NodeSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeSelection::All => write!(f, "all"),
+            NodeSelection::Random(count) => write!(f, "random:{}", count),
+            NodeSelection::Nodes(nodes) => {
+                let mut nodes = nodes.clone();
+                nodes.sort();
+                write!(f, "{}", format_group(&nodes))
+            }
+            NodeSelection::Seeds => write!(f, "seeds"),
+            NodeSelection::Excluding { nodes, excluded } => {
+                write!(f, "{},exclude:{}", nodes, excluded)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// pauses the nodes, or stops them when the simulation restarts its victims
+    Kill { nodes: NodeSelection },
+    /// resumes or restarts nodes killed before
+    Revive { nodes: NodeSelection },
+    /// has the nodes send their own heartbeats only
+    StopForwarding { nodes: NodeSelection },
+    /// cuts groups of node indexes off from each other, for good unless `duration` is set
+    Partition {
+        groups: Vec<Vec<u64>>,
+        duration: Option<Duration>,
+    },
+    /// changes the config of running nodes
+    Config {
+        nodes: NodeSelection,
+        update: gossip::ConfigUpdate,
+    },
+    /// starts this many new nodes, knowing the same seeds as the others
+    AddNodes { count: usize },
+    /// changes the probability with which any datagram is lost
+    SetLoss { loss: f64 },
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Kill { .. } => "kill",
+            Action::Revive { .. } => "revive",
+            Action::StopForwarding { .. } => "stop-forwarding",
+            Action::Partition { .. } => "partition",
+            Action::Config { .. } => "config",
+            Action::AddNodes { .. } => "add-nodes",
+            Action::SetLoss { .. } => "set-loss",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScheduledEvent {
+    /// since the start of the simulation
+    pub at: Duration,
+    pub action: Action,
+}
+
+impl FromStr for ScheduledEvent {
+    type Err = EventError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut settings = Settings::parse(s)?;
+        let at = settings.required("at", parse_duration)?;
+        let action = settings.required("action", |action| Ok(action.to_string()))?;
+        let action = match action.as_str() {
+            "kill" => Action::Kill {
+                nodes: settings.nodes()?,
+            },
+            "revive" => Action::Revive {
+                nodes: settings.nodes()?,
+            },
+            "stop-forwarding" => Action::StopForwarding {
+                nodes: settings.nodes()?,
+            },
+            "partition" => Action::Partition {
+                groups: settings.required("groups", parse_groups)?,
+                duration: settings.optional("for", parse_duration)?,
+            },
+            "config" => Action::Config {
+                nodes: settings.nodes()?,
+                update: gossip::ConfigUpdate {
+                    heartbeat_interval: settings.optional("heartbeat_interval", parse_duration)?,
+                    heartbeat_spread: settings.optional("spread", parse_number)?,
+                    decay_factor: settings.optional("decay", parse_number)?,
+                },
+            },
+            "add-nodes" => Action::AddNodes {
+                count: settings.required("count", parse_number)?,
+            },
+            "set-loss" => Action::SetLoss {
+                loss: settings.required("loss", parse_probability)?,
+            },
+            _ => return Err(EventError::UnknownAction(action)),
+        };
+        settings.finish(action.name())?;
+        Ok(ScheduledEvent { at, action })
+    }
+}
+
+impl fmt::Display for ScheduledEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "at={} action={}",
+            humantime::format_duration(self.at),
+            self.action.name()
+        )?;
+        match &self.action {
+            Action::Kill { nodes }
+            | Action::Revive { nodes }
+            | Action::StopForwarding { nodes } => write!(f, " nodes={}", nodes),
+            Action::Partition { groups, duration } => {
+                let groups: Vec<String> = groups.iter().map(|group| format_group(group)).collect();
+                write!(f, " groups={}", groups.join("/"))?;
+                match duration {
+                    Some(duration) => write!(f, " for={}", humantime::format_duration(*duration)),
+                    None => Ok(()),
+                }
+            }
+            Action::Config { nodes, update } => {
+                write!(f, " nodes={}", nodes)?;
+                if let Some(interval) = update.heartbeat_interval {
+                    write!(
+                        f,
+                        " heartbeat_interval={}",
+                        humantime::format_duration(interval)
+                    )?;
+                }
+                if let Some(spread) = update.heartbeat_spread {
+                    write!(f, " spread={}", spread)?;
+                }
+                if let Some(decay) = update.decay_factor {
+                    write!(f, " decay={}", decay)?;
+                }
+                Ok(())
+            }
+            Action::AddNodes { count } => write!(f, " count={}", count),
+            Action::SetLoss { loss } => write!(f, " loss={}", loss),
+        }
+    }
+}
+
+/// Nodes of a simulation configured apart from the others, e.g.
+/// `name=quiet nodes=90 heartbeat_interval=10s spread=3`. Groups take consecutive node indexes
+/// in the order they are given, the nodes after the last group keep the common config.
+#[derive(Debug, Clone)]
+pub struct NodeGroup {
+    pub name: String,
+    pub nodes: u64,
+    /// the settings that differ from the common config
+    pub update: gossip::ConfigUpdate,
+    pub suspect_timeout: Option<Duration>,
+    pub dead_timeout: Option<Duration>,
+}
+
+impl FromStr for NodeGroup {
+    type Err = EventError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut settings = Settings::parse(s)?;
+        let group = NodeGroup {
+            name: settings.required("name", |name| Ok(name.to_string()))?,
+            nodes: settings.required("nodes", parse_number)?,
+            update: gossip::ConfigUpdate {
+                heartbeat_interval: settings.optional("heartbeat_interval", parse_duration)?,
+                heartbeat_spread: settings.optional("spread", parse_number)?,
+                decay_factor: settings.optional("decay", parse_number)?,
+            },
+            suspect_timeout: settings.optional("suspect_timeout", parse_duration)?,
+            dead_timeout: settings.optional("dead_timeout", parse_duration)?,
+        };
+        settings.finish("a node group")?;
+        Ok(group)
+    }
+}
+
+impl fmt::Display for NodeGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "name={} nodes={}", self.name, self.nodes)?;
+        if let Some(interval) = self.update.heartbeat_interval {
+            write!(
+                f,
+                " heartbeat_interval={}",
+                humantime::format_duration(interval)
+            )?;
+        }
+        if let Some(spread) = self.update.heartbeat_spread {
+            write!(f, " spread={}", spread)?;
+        }
+        if let Some(decay) = self.update.decay_factor {
+            write!(f, " decay={}", decay)?;
+        }
+        if let Some(timeout) = self.suspect_timeout {
+            write!(
+                f,
+                " suspect_timeout={}",
+                humantime::format_duration(timeout)
+            )?;
+        }
+        if let Some(timeout) = self.dead_timeout {
+            write!(f, " dead_timeout={}", humantime::format_duration(timeout))?;
+        }
+        Ok(())
+    }
+}
+
+/// The `key=value` settings of an event, taken out one by one as they are parsed.
+struct Settings(HashMap<String, String>);
+
+impl Settings {
+    fn parse(s: &str) -> Result<Self, EventError> {
+        let mut settings = HashMap::new();
+        for setting in s.split_whitespace() {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| EventError::Syntax(setting.to_string()))?;
+            // scenario files may spell keys in kebab case like the flags
+            let key = key.replace('-', "_");
+            if settings.insert(key.clone(), value.to_string()).is_some() {
+                return Err(EventError::Duplicate { key });
+            }
+        }
+        Ok(Settings(settings))
+    }
+
+    fn optional<T>(
+        &mut self,
+        key: &'static str,
+        parse: impl FnOnce(&str) -> Result<T, String>,
+    ) -> Result<Option<T>, EventError> {
+        let Some(value) = self.0.remove(key) else {
+            return Ok(None);
+        };
+        parse(&value).map(Some).map_err(|reason| EventError::Value {
+            key: key.to_string(),
+            value,
+            reason,
+        })
+    }
+
+    fn required<T>(
+        &mut self,
+        key: &'static str,
+        parse: impl FnOnce(&str) -> Result<T, String>,
+    ) -> Result<T, EventError> {
+        self.optional(key, parse)?.ok_or(EventError::Missing(key))
+    }
+
+    /// The nodes the action applies to, all of them unless told otherwise.
+    fn nodes(&mut self) -> Result<NodeSelection, EventError> {
+        Ok(self
+            .optional("nodes", NodeSelection::from_str)?
+            .unwrap_or(NodeSelection::All))
+    }
+
+    /// Fails on the first setting that wasn't taken, by the action or group named `name`.
+    fn finish(self, name: &str) -> Result<(), EventError> {
+        match self.0.into_keys().min() {
+            Some(key) => Err(EventError::Unexpected {
+                key,
+                action: name.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    humantime::parse_duration(value).map_err(|e| e.to_string())
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, String>
+where
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|e: T::Err| e.to_string())
+}
+
+fn parse_probability(value: &str) -> Result<f64, String> {
+    match parse_number::<f64>(value)? {
+        p if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err("must be between 0 and 1".to_string()),
+    }
+}
+
+/// Writes a group of node indexes the way [`parse_groups`] reads it, with runs as ranges.
+pub fn format_group(group: &[u64]) -> String {
+    let mut parts: Vec<String> = vec![];
+    let mut nodes = group.iter().copied().peekable();
+    while let Some(first) = nodes.next() {
+        let mut last = first;
+        while nodes.next_if(|&node| node == last + 1).is_some() {
+            last += 1;
+        }
+        parts.push(if first == last {
+            first.to_string()
+        } else {
+            format!("{}-{}", first, last)
+        });
+    }
+    parts.join(",")
+}
+
+/// Parses node indexes and ranges separated by `,`, e.g. `0-4,9`, into the indexes in the order
+/// given.
+pub fn parse_nodes(value: &str) -> Result<Vec<u64>, String> {
+    let mut seen = HashSet::new();
+    let mut nodes = vec![];
+    for part in value.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (parse_node(first)?, parse_node(last)?),
+            None => (parse_node(part)?, parse_node(part)?),
+        };
+        if first > last {
+            return Err(format!("empty node range `{}`", part));
+        }
+        for node in first..=last {
+            if !seen.insert(node) {
+                return Err(format!("node {} is given twice", node));
+            }
+            nodes.push(node);
+        }
+    }
+    Ok(nodes)
+}
+
+/// Parses groups of node indexes and ranges separated by `/`, e.g. `0-49/50-99` or `0,5/1,6`.
+/// A node can be in one group only.
+pub fn parse_groups(value: &str) -> Result<Vec<Vec<u64>>, String> {
+    let mut seen = HashSet::new();
+    let mut groups = vec![];
+    for group in value.split('/') {
+        let nodes = parse_nodes(group)?;
+        for &node in &nodes {
+            if !seen.insert(node) {
+                return Err(format!("node {} is in more than one group", node));
+            }
+        }
+        groups.push(nodes);
+    }
+    if groups.len() < 2 {
+        return Err("a partition needs at least two groups".to_string());
+    }
+    Ok(groups)
+}
+
+/// Parses the index of a simulated node.
+pub fn parse_node(value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{}` is not a node index", value))
+}
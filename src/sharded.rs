@@ -0,0 +1,223 @@
+//! A storage split into shards by node id, each behind its own lock, so that inserts from the
+//! receive path and reads from target selection or metrics don't wait for one another unless
+//! they touch the same shard.
+//!
+//! # Consistency
+//!
+//! Everything about a single entry, [`ShardedStorage::insert`], [`ShardedStorage::get`] and
+//! [`ShardedStorage::remove`], happens under its shard's lock and behaves exactly like the same
+//! operation on a [`Storage`]. Operations over all entries, like [`ShardedStorage::snapshot`],
+//! [`ShardedStorage::len`] or [`ShardedStorage::select_n_random_addresses`], lock one shard at a
+//! time: an entry inserted or removed concurrently may or may not be seen, but every entry seen
+//! is itself consistent. The version is bumped after a change is visible in its shard, so a
+//! reader that saw version `v` and reads again sees at least every change up to `v`.
+//!
+//! Gossip tolerates this: a heartbeat missed by one round is picked up by the next.
+
+use rand::Rng;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::gossip::{
+    is_reachable, select_random_n, selectable_addresses, Heartbeat, HeartbeatError, InsertOutcome,
+    NodeHeartbeatData, Storage, TimestampBounds,
+};
+use crate::membership::PeerState;
+use crate::node_id::NodeId;
+use crate::snapshot::StorageSnapshot;
+
+type Shard = HashMap<NodeId, NodeHeartbeatData>;
+
+pub struct ShardedStorage {
+    shards: Vec<RwLock<Shard>>,
+    hasher: RandomState,
+    owner: NodeId,
+    /// entries never pruned, the owner and its seeds
+    pinned: HashSet<NodeId>,
+    bounds: TimestampBounds,
+    version: AtomicU64,
+}
+
+impl ShardedStorage {
+    /// Spreads the entries of `storage` across `shards` shards, at least one.
+    pub fn new(storage: Storage, shards: usize) -> Self {
+        let sharded = ShardedStorage {
+            shards: (0..shards.max(1))
+                .map(|_| RwLock::new(HashMap::new()))
+                .collect(),
+            hasher: RandomState::new(),
+            owner: storage.owner.clone(),
+            pinned: storage.pinned.clone(),
+            bounds: storage.timestamp_bounds(),
+            version: AtomicU64::new(storage.version()),
+        };
+        for (id, data) in storage.data {
+            sharded.write_shard(id.as_str()).insert(id, data);
+        }
+        sharded
+    }
+
+    fn shard(&self, id: &str) -> &RwLock<Shard> {
+        let index = self.hasher.hash_one(id) as usize % self.shards.len();
+        &self.shards[index]
+    }
+
+    fn write_shard(&self, id: &str) -> RwLockWriteGuard<'_, Shard> {
+        self.shard(id)
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn read_shards(&self) -> impl Iterator<Item = RwLockReadGuard<'_, Shard>> {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    pub fn owner(&self) -> &NodeId {
+        &self.owner
+    }
+
+    /// See [`Storage::version`].
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// Stores `heartbeat`, received at local time `now`, unless the stored heartbeat of its node
+    /// is as new or newer. Heartbeats advertising an address that can't be sent to are rejected,
+    /// timestamps are checked against the [`TimestampBounds`] of the storage it was built from.
+    pub fn insert(
+        &self,
+        mut heartbeat: Heartbeat,
+        now: u64,
+    ) -> Result<InsertOutcome, HeartbeatError> {
+        if !is_reachable(heartbeat.address()) {
+            return Err(HeartbeatError::UnreachableAddress {
+                id: heartbeat.id().clone(),
+                address: heartbeat.address(),
+            });
+        }
+        self.bounds.clamp(&mut heartbeat, now)?;
+        let id = heartbeat.id().clone();
+        let outcome = {
+            let mut shard = self.write_shard(id.as_str());
+            match shard.get_mut(&id) {
+                Some(data) => data.apply(heartbeat, now),
+                None => {
+                    let data = if self.bounds.is_stale(&heartbeat, now) {
+                        let received_at = heartbeat.timestamp();
+                        NodeHeartbeatData {
+                            state: PeerState::Suspect,
+                            ..NodeHeartbeatData::first_received(heartbeat, received_at)
+                        }
+                    } else {
+                        NodeHeartbeatData::first_received(heartbeat, now)
+                    };
+                    shard.insert(id, data);
+                    InsertOutcome::Applied
+                }
+            }
+        };
+        if outcome == InsertOutcome::Applied {
+            self.version.fetch_add(1, Ordering::Release);
+        }
+        Ok(outcome)
+    }
+
+    pub fn get(&self, id: &str) -> Option<NodeHeartbeatData> {
+        self.shard(id)
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(id)
+            .cloned()
+    }
+
+    /// Removes the entry of `id`. Like [`Storage::remove`], the owner's own entry stays.
+    pub fn remove(&self, id: &str) -> Option<NodeHeartbeatData> {
+        if id == self.owner.as_str() {
+            return None;
+        }
+        let removed = self.write_shard(id).remove(id);
+        if removed.is_some() {
+            self.version.fetch_add(1, Ordering::Release);
+        }
+        removed
+    }
+
+    /// Removes the entries older than `max_age`, except the pinned ones, and returns their ids.
+    pub fn prune(&self, max_age: Duration, clock: &dyn Clock) -> Vec<NodeId> {
+        let now = clock.now();
+        let mut expired = vec![];
+        for shard in &self.shards {
+            let mut shard = shard.write().unwrap_or_else(PoisonError::into_inner);
+            shard.retain(|id, data| {
+                let keep = self.pinned.contains(id)
+                    || now.saturating_sub(data.heartbeat.timestamp()) <= max_age.as_secs();
+                if !keep {
+                    expired.push(id.clone());
+                }
+                keep
+            });
+        }
+        if !expired.is_empty() {
+            self.version.fetch_add(1, Ordering::Release);
+        }
+        expired
+    }
+
+    pub fn len(&self) -> usize {
+        self.read_shards().map(|shard| shard.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Calls `f` with every entry, one shard at a time. `f` must not call back into the storage.
+    pub fn for_each_entry(&self, mut f: impl FnMut(&NodeHeartbeatData)) {
+        for shard in self.read_shards() {
+            shard.values().for_each(&mut f);
+        }
+    }
+
+    /// Picks up to `n` distinct addresses of entries that are neither one of `excluded_ids` nor
+    /// at one of `excluded_addresses`.
+    pub fn select_n_random_addresses(
+        &self,
+        n: usize,
+        excluded_ids: &[&NodeId],
+        excluded_addresses: &[SocketAddr],
+        rng: &mut impl Rng,
+    ) -> Vec<SocketAddr> {
+        let mut addresses = vec![];
+        for shard in self.read_shards() {
+            addresses.extend(selectable_addresses(
+                shard.values(),
+                excluded_ids,
+                excluded_addresses,
+            ));
+        }
+        // the same address may be listed in two shards
+        addresses.sort_unstable();
+        addresses.dedup();
+        select_random_n(addresses, n, rng)
+    }
+
+    /// Exports every entry, see [`Storage::snapshot`].
+    pub fn snapshot(&self) -> StorageSnapshot {
+        let mut entries = vec![];
+        self.for_each_entry(|data| entries.push(data.snapshot_entry()));
+        // values are kept by a Storage only
+        StorageSnapshot {
+            owner: self.owner.clone(),
+            entries,
+            values: vec![],
+        }
+    }
+}
@@ -0,0 +1,896 @@
+//! The simulation harness behind the muck binary, only built with the `sim` feature. A
+//! [`Simulation`] runs a cluster of nodes in this process, named after their index and bound to
+//! consecutive ports, and carries out [`Action`]s on them while the [`Timeline`] records what
+//! happened, for [`crate::plot`] to chart and report.
+//!
+//! The binary drives it from its flags; experiments of their own can script it directly:
+//!
+//! ```no_run
+//! use std::sync::atomic::AtomicBool;
+//! use std::time::Duration;
+//!
+//! use muck::scenario::{Action, NodeSelection, ScheduledEvent};
+//! use muck::sim::{SimConfig, Simulation};
+//! use rand::SeedableRng;
+//!
+//! let simulation = Simulation::new(SimConfig::new(10));
+//! for index in 0..10 {
+//!     simulation.start(index);
+//! }
+//! simulation.run_until(Some(Duration::from_secs(20)), &AtomicBool::new(false));
+//! let kill = ScheduledEvent {
+//!     at: Duration::from_secs(20),
+//!     action: Action::Kill { nodes: NodeSelection::Random(2) },
+//! };
+//! simulation.inject(&kill, &mut rand::rngs::StdRng::seed_from_u64(1));
+//! simulation.add_nodes(5);
+//! simulation.run_until(Some(Duration::from_secs(60)), &AtomicBool::new(false));
+//! println!("{:?}", simulation.metrics());
+//! simulation.shutdown();
+//! ```
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread::sleep;
+use std::time::Duration;
+use tracing::error;
+
+use crate::clock::Clock;
+use crate::defaults;
+use crate::faults::{FaultInjector, Partition};
+use crate::forward::DisabledPolicy;
+use crate::gossip;
+use crate::http;
+use crate::membership::{ConvergenceEvent, ConvergenceWatch, PeerState};
+use crate::memory_net::MemoryNetwork;
+use crate::node_id::NodeId;
+use crate::plot::{ChurnTally, EventRecord, Timeline};
+use crate::prometheus::NodeMetrics;
+use crate::scenario::{Action, Cluster, NodeSelection, ScheduledEvent, SimNode};
+use crate::sim_runtime::{SimRuntime, SimTime};
+use crate::snapshot::{SnapshotEntry, StorageSnapshot};
+use crate::stats::NodeStats;
+use crate::trace::HeartbeatTrace;
+
+/// port of the node with index 0, the others follow
+pub const PORT_BASE: u64 = 8000;
+const BIND_ATTEMPTS: u32 = 3;
+const BIND_RETRY_DELAY: Duration = Duration::from_millis(500);
+const DETECTION_POLL_INTERVAL: Duration = Duration::from_millis(200);
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The config of the node with the given index, see [`SimConfig::node_config`].
+pub type NodeConfigFn =
+    Arc<dyn Fn(u64) -> Result<gossip::NodeConfig, gossip::ConfigError> + Send + Sync>;
+
+/// How to run a [`Simulation`].
+#[derive(Clone)]
+pub struct SimConfig {
+    /// the first nodes, which every node knows from the start
+    pub seeds: u64,
+    /// nodes the cluster is expected to have, started with [`Simulation::start`]; those added
+    /// with [`Simulation::add_nodes`] get the indexes after them
+    pub nodes: u64,
+    /// when peers count as suspect and dead, for convergence and detection
+    pub suspect_timeout: Duration,
+    pub dead_timeout: Duration,
+    /// kill nodes by shutting them down and revive them by restarting, rather than pausing and
+    /// resuming them
+    pub restart: bool,
+    /// shared by every node, so that the partitions and loss of the actions reach them
+    pub faults: Option<Arc<FaultInjector>>,
+    /// the nodes talk over this instead of UDP sockets
+    pub memory_network: Option<Arc<MemoryNetwork>>,
+    /// hosts the nodes instead of a thread each; the simulation runs on the runtime's time,
+    /// see [`SimRuntime::stepped`]
+    pub runtime: Option<Arc<SimRuntime>>,
+    /// the nodes up to `nodes` start out with each other in their storage, as if the cluster
+    /// had converged already; see [`Simulation::start`]
+    pub warm_start: bool,
+    /// follows one heartbeat through the cluster, see [`crate::trace`]
+    pub trace: Option<Arc<HeartbeatTrace>>,
+    /// the simulation fills in the clock, faults, network and trace of every node's config
+    pub node_config: NodeConfigFn,
+}
+
+impl SimConfig {
+    /// `nodes` nodes with the default config, two of them seeds.
+    pub fn new(nodes: u64) -> Self {
+        SimConfig {
+            seeds: 2.min(nodes),
+            nodes,
+            suspect_timeout: defaults::SUSPECT_TIMEOUT,
+            dead_timeout: defaults::DEAD_TIMEOUT,
+            restart: false,
+            faults: None,
+            memory_network: None,
+            runtime: None,
+            warm_start: false,
+            trace: None,
+            node_config: Arc::new(move |index| {
+                gossip::NodeConfig::builder()
+                    .id(node_id(index))
+                    .address(node_address(PORT_BASE + index).to_string())
+                    .expected_cluster_size(nodes as usize)
+                    .build()
+            }),
+        }
+    }
+}
+
+/// Churn killing and reviving random nodes, see [`Simulation::run_churn`].
+#[derive(Debug, Clone, Copy)]
+pub struct Churn {
+    /// mean time a node runs before it is killed
+    pub mtbf: Duration,
+    /// mean time a killed node stays down
+    pub downtime: Duration,
+    /// since the start of the simulation
+    pub after: Duration,
+}
+
+/// A snapshot of a running simulation, see [`Simulation::metrics`].
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    /// since the start of the simulation
+    pub at: Duration,
+    pub nodes: usize,
+    /// running and not paused
+    pub alive: usize,
+    /// alive nodes seeing at least as many peers alive as there are other alive nodes
+    pub informed: usize,
+    /// peers seen alive by an alive node on average
+    pub mean_alive_peers: f64,
+    /// nodes whose view covered the whole cluster at some point
+    pub converged: usize,
+    /// of every node started so far
+    pub stats: NodeStats,
+}
+
+/// A cluster of simulated nodes and what happened to it. Shared between the threads driving
+/// it, every method takes `&self`.
+pub struct Simulation {
+    config: SimConfig,
+    seed_nodes: Vec<(NodeId, SocketAddr)>,
+    clock: Arc<dyn Clock>,
+    sim_runtime: Option<Arc<SimRuntime>>,
+    timeline: Arc<Timeline>,
+    cluster: Cluster,
+    /// index of the next node added while the simulation runs
+    next_index: AtomicU64,
+    /// see [`Simulation::killed`]
+    killed: Mutex<Vec<u64>>,
+}
+
+impl Simulation {
+    /// A simulation without nodes yet, its clock starting now. The faults and the network of
+    /// a simulation on a stepped runtime need the runtime's clock, see
+    /// [`FaultInjector::clock`] and [`MemoryNetwork::clock`].
+    pub fn new(config: SimConfig) -> Self {
+        let seed_nodes = (0..config.seeds)
+            .map(|seed| (node_id(seed), node_address(PORT_BASE + seed)))
+            .collect();
+        let time = match &config.runtime {
+            Some(runtime) => runtime.time(),
+            None => SimTime::wall(),
+        };
+        let clock = time.clock().clone();
+        let timeline = Arc::new(Timeline {
+            time,
+            killed_at: Mutex::new(None),
+            stopping: AtomicBool::new(false),
+            converged_at: Mutex::new(Vec::new()),
+            detected_at: Mutex::new(Vec::new()),
+            detection_observer: Mutex::new(None),
+            value_put: Mutex::new(None),
+            events: Mutex::new(Vec::new()),
+            churn: Mutex::new(ChurnTally::default()),
+            display_frozen: AtomicBool::new(false),
+            control_status: Mutex::new(None),
+        });
+        Simulation {
+            seed_nodes,
+            clock,
+            sim_runtime: config.runtime.clone(),
+            timeline,
+            cluster: Arc::new(RwLock::new(Vec::new())),
+            next_index: AtomicU64::new(config.nodes),
+            killed: Mutex::new(Vec::new()),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &SimConfig {
+        &self.config
+    }
+
+    pub fn timeline(&self) -> &Arc<Timeline> {
+        &self.timeline
+    }
+
+    pub fn cluster(&self) -> &Cluster {
+        &self.cluster
+    }
+
+    /// Every node started so far, in the order they were started.
+    pub fn nodes(&self) -> Vec<SimNode> {
+        read_cluster(&self.cluster).clone()
+    }
+
+    /// Threads of the pool the nodes run on, None if each node has a thread of its own, and 0
+    /// on a stepped runtime.
+    pub fn runtime_threads(&self) -> Option<usize> {
+        self.sim_runtime.as_ref().map(|runtime| runtime.threads())
+    }
+
+    /// Starts the node with the given index and adds it to the cluster, None if it couldn't be
+    /// started. With [`SimConfig::warm_start`], the nodes up to [`SimConfig::nodes`] already
+    /// know each other: their storages are merged with a fresh heartbeat of every other one
+    /// before their loops run, just as if those had been received.
+    pub fn start(&self, index: u64) -> Option<SimNode> {
+        let node = self.launch(index)?;
+        write_cluster(&self.cluster).push(node.clone());
+        Some(node)
+    }
+
+    /// Starts `count` new nodes knowing the seeds, and adds them to the cluster. Returns the
+    /// indexes of the nodes that started.
+    pub fn add_nodes(&self, count: usize) -> Vec<u64> {
+        let first = self.next_index.fetch_add(count as u64, Ordering::SeqCst);
+        let mut added = vec![];
+        for index in first..first + count as u64 {
+            if PORT_BASE + index > u64::from(u16::MAX) {
+                error!(node_id = index, "no port left for the node, skipping it");
+                break;
+            }
+            if let Some(mut node) = self.launch(index) {
+                node.added_at = Some(self.timeline.time.elapsed());
+                added.push(index);
+                write_cluster(&self.cluster).push(node);
+            }
+        }
+        added
+    }
+
+    /// Pauses the node, or stops it with [`SimConfig::restart`].
+    pub fn kill(&self, node: &SimNode) {
+        if self.config.restart {
+            if let Err(e) = node.handle.shutdown() {
+                error!(error = e.to_string(), "node exited with an error");
+            }
+        } else {
+            node.handle.pause();
+        }
+    }
+
+    /// Resumes the killed node, or restarts it with [`SimConfig::restart`].
+    pub fn revive(&self, node: &SimNode) {
+        let result = if self.config.restart {
+            node.handle.restart()
+        } else {
+            node.handle.resume()
+        };
+        if let Err(e) = result {
+            error!(error = e.to_string(), "failed to revive node");
+        }
+    }
+
+    /// Indexes of the nodes killed by the actions and not revived yet.
+    pub fn killed(&self) -> MutexGuard<'_, Vec<u64>> {
+        self.killed.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Carries out the event's action now and adds it to the events of the timeline. Returns
+    /// what it did and the nodes it applied to, see [`Simulation::execute`].
+    pub fn inject(&self, event: &ScheduledEvent, rng: &mut StdRng) -> (String, Vec<SimNode>) {
+        let (outcome, affected) = self.execute(&event.action, rng);
+        record_event(&self.timeline, event, &outcome, &affected);
+        (outcome, affected)
+    }
+
+    /// Carries out an action on the cluster, picking random nodes with `rng`. Returns what it
+    /// did and the nodes it applied to: those it killed, revived or reconfigured, the nodes it
+    /// added, or those of the groups it cut off from each other.
+    pub fn execute(&self, action: &Action, rng: &mut StdRng) -> (String, Vec<SimNode>) {
+        let nodes = self.nodes();
+        let seeds = self.config.seeds;
+        let mut killed = self.killed();
+        let selected = match action {
+            Action::Kill { nodes: selection } => {
+                let candidates: Vec<&SimNode> = nodes
+                    .iter()
+                    .filter(|node| !killed.contains(&node.index))
+                    .collect();
+                select(selection, candidates, seeds, rng)
+            }
+            Action::Revive { nodes: selection } => {
+                let candidates: Vec<&SimNode> = nodes
+                    .iter()
+                    .filter(|node| killed.contains(&node.index))
+                    .collect();
+                select(selection, candidates, seeds, rng)
+            }
+            Action::StopForwarding { nodes: selection }
+            | Action::Config {
+                nodes: selection, ..
+            } => select(selection, nodes.iter().collect(), seeds, rng),
+            Action::Partition { .. } | Action::AddNodes { .. } | Action::SetLoss { .. } => vec![],
+        };
+        // nodes the action applied to that weren't selected, by index
+        let mut others: Vec<u64> = vec![];
+        let outcome = match action {
+            Action::Kill { .. } => {
+                for victim in &selected {
+                    self.kill(victim);
+                    killed.push(victim.index);
+                }
+                format!("killed {}", describe_nodes(&selected))
+            }
+            Action::Revive { .. } => {
+                for node in &selected {
+                    self.revive(node);
+                    killed.retain(|index| *index != node.index);
+                }
+                format!("revived {}", describe_nodes(&selected))
+            }
+            Action::StopForwarding { .. } => {
+                for node in &selected {
+                    node.handle.set_forward_policy(Arc::new(DisabledPolicy));
+                }
+                format!("stopped forwarding on {}", describe_nodes(&selected))
+            }
+            Action::Partition { groups, duration } => match &self.config.faults {
+                Some(faults) => {
+                    let from = faults.elapsed();
+                    faults.add_partition(Partition {
+                        groups: groups
+                            .iter()
+                            .map(|group| {
+                                group
+                                    .iter()
+                                    .map(|&node| node_address(PORT_BASE + node))
+                                    .collect()
+                            })
+                            .collect(),
+                        from,
+                        until: duration.map(|duration| from + duration),
+                    });
+                    others = groups.concat();
+                    "partitioned".to_string()
+                }
+                None => "skipped, the network has no fault injection".to_string(),
+            },
+            Action::Config { update, .. } => {
+                for node in &selected {
+                    if let Err(e) = node.handle.update_config(update.clone()) {
+                        error!(error = e.to_string(), "failed to update node config");
+                    }
+                }
+                format!("updated {}", describe_nodes(&selected))
+            }
+            Action::AddNodes { count } => {
+                others = self.add_nodes(*count);
+                describe_added(&others)
+            }
+            Action::SetLoss { loss } => match &self.config.faults {
+                Some(faults) => {
+                    faults.set_loss(*loss);
+                    "loss changed".to_string()
+                }
+                None => "skipped, the network has no fault injection".to_string(),
+            },
+        };
+        let mut affected: Vec<SimNode> = selected.into_iter().cloned().collect();
+        if !others.is_empty() {
+            affected.extend(
+                read_cluster(&self.cluster)
+                    .iter()
+                    .filter(|node| others.contains(&node.index))
+                    .cloned(),
+            );
+        }
+        (outcome, affected)
+    }
+
+    /// Executes the scenario events in order, each at its time, until the simulation stops.
+    /// The first kill is watched by a surviving node, until the next revival, to tell how long
+    /// the victims take to look dead. Random nodes are picked from the stream of `seed`.
+    pub fn run_schedule(&self, schedule: &[ScheduledEvent], seed: Option<u64>) {
+        let timeline = &self.timeline;
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        for (position, event) in schedule.iter().enumerate() {
+            if !self.wait_until(event.at) {
+                return;
+            }
+
+            let (outcome, affected) = self.execute(&event.action, &mut rng);
+            if matches!(event.action, Action::Kill { .. }) {
+                let first_kill = timeline.killed_at.lock().is_ok_and(|mut killed_at| {
+                    let first = killed_at.is_none();
+                    killed_at.get_or_insert(timeline.time.elapsed());
+                    first
+                });
+                if first_kill {
+                    let killed = self.killed();
+                    let observer = read_cluster(&self.cluster)
+                        .iter()
+                        .find(|node| !killed.contains(&node.index))
+                        .cloned();
+                    let revive_at = schedule[position + 1..]
+                        .iter()
+                        .find(|event| matches!(event.action, Action::Revive { .. }))
+                        .map(|event| event.at);
+                    let timeline = timeline.clone();
+                    let threshold = self.config.suspect_timeout;
+                    if let Some(observer) = observer {
+                        let victims = affected.clone();
+                        timeline.time.clone().spawn(move || {
+                            watch_detection(&observer, &victims, revive_at, threshold, &timeline)
+                        });
+                    }
+                }
+            }
+
+            record_event(timeline, event, &outcome, &affected);
+        }
+    }
+
+    /// Starts the nodes of a startup ramp, each given with its time counted from the start,
+    /// until the simulation stops.
+    pub fn run_ramp(&self, mut ramp: Vec<(u64, Duration)>) {
+        ramp.sort_by_key(|&(index, start_at)| (start_at, index));
+        for (index, start_at) in ramp {
+            if !self.wait_until(start_at) {
+                return;
+            }
+            self.start(index);
+        }
+    }
+
+    /// Kills every alive node after a random time and revives it after another, from
+    /// [`Churn::after`] until the simulation stops. The times are exponentially distributed with
+    /// means [`Churn::mtbf`] and [`Churn::downtime`], so failures and revivals arrive as Poisson
+    /// processes. Nodes killed by the actions are left alone.
+    pub fn run_churn(&self, churn: &Churn, seed: Option<u64>) {
+        let timeline = &self.timeline;
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let start = churn.after;
+        // when each node fails next, or comes back if the churn killed it, by index and since
+        // the start
+        let mut due: HashMap<u64, (Duration, bool)> = HashMap::new();
+        // victims not seen as dead by every alive node yet, and when they were killed
+        let mut undetected: Vec<(u64, Duration)> = vec![];
+
+        while !timeline.stopping.load(Ordering::SeqCst) {
+            let now = timeline.time.elapsed();
+            if now < start {
+                timeline.time.sleep(STOP_POLL_INTERVAL.min(start - now));
+                continue;
+            }
+            let nodes = self.nodes();
+            let mut tally = ChurnTally::default();
+            for node in &nodes {
+                let (at, killed) = *due
+                    .entry(node.index)
+                    .or_insert_with(|| (now + exponential(churn.mtbf, &mut rng), false));
+                if at > now {
+                    continue;
+                }
+                if killed {
+                    self.revive(node);
+                    tally.revivals += 1;
+                    if let Some(position) = undetected
+                        .iter()
+                        .position(|(index, _)| *index == node.index)
+                    {
+                        undetected.remove(position);
+                        tally.revived_undetected += 1;
+                    }
+                    due.insert(node.index, (now + exponential(churn.mtbf, &mut rng), false));
+                } else if node.handle.is_running() && !node.handle.is_paused() {
+                    self.kill(node);
+                    tally.kills += 1;
+                    undetected.push((node.index, now));
+                    due.insert(
+                        node.index,
+                        (now + exponential(churn.downtime, &mut rng), true),
+                    );
+                } else {
+                    due.insert(node.index, (now + exponential(churn.mtbf, &mut rng), false));
+                }
+            }
+
+            let observers: Vec<&SimNode> = nodes
+                .iter()
+                .filter(|node| node.handle.is_running() && !node.handle.is_paused())
+                .collect();
+            undetected.retain(|(index, killed_at)| {
+                let id = node_id(*index);
+                let seen_alive = observers.iter().any(|observer| {
+                    observer.node.peer_state(id.as_str()) == Some(PeerState::Alive)
+                });
+                if !seen_alive {
+                    tally.detections.push(now.saturating_sub(*killed_at));
+                }
+                seen_alive
+            });
+
+            if let Ok(mut churn) = timeline.churn.lock() {
+                churn.kills += tally.kills;
+                churn.revivals += tally.revivals;
+                churn.detections.extend(tally.detections);
+                churn.revived_undetected += tally.revived_undetected;
+            }
+            timeline.time.sleep(STOP_POLL_INTERVAL);
+        }
+    }
+
+    /// Waits until `duration` from the start is up, `interrupted` is set or every node has
+    /// stopped. Returns whether any node still runs.
+    pub fn run_until(&self, duration: Option<Duration>, interrupted: &AtomicBool) -> bool {
+        let running = || {
+            read_cluster(&self.cluster)
+                .iter()
+                .any(|node| node.handle.is_running())
+        };
+        while !interrupted.load(Ordering::SeqCst)
+            && duration.is_none_or(|duration| self.timeline.time.elapsed() < duration)
+            && running()
+        {
+            self.timeline.time.sleep(STOP_POLL_INTERVAL);
+        }
+        running()
+    }
+
+    /// Tells the threads driving the simulation to wrap up, the nodes keep running until
+    /// [`Simulation::shutdown`].
+    pub fn stop(&self) {
+        self.timeline.stopping.store(true, Ordering::SeqCst);
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        let nodes = self.nodes();
+        let alive: Vec<&SimNode> = nodes
+            .iter()
+            .filter(|node| node.handle.is_running() && !node.handle.is_paused())
+            .collect();
+        let alive_peers: Vec<usize> = alive
+            .iter()
+            .map(|node| node.node.summary().peers_alive)
+            .collect();
+        Metrics {
+            at: self.timeline.time.elapsed(),
+            nodes: nodes.len(),
+            alive: alive.len(),
+            informed: alive_peers
+                .iter()
+                .filter(|&&peers| peers + 1 >= alive.len())
+                .count(),
+            mean_alive_peers: alive_peers.iter().sum::<usize>() as f64
+                / alive_peers.len().max(1) as f64,
+            converged: self
+                .timeline
+                .converged_at
+                .lock()
+                .map(|converged_at| converged_at.len())
+                .unwrap_or_default(),
+            stats: nodes.iter().map(|node| node.node.stats()).sum(),
+        }
+    }
+
+    /// Serves the counters of every node started so far at `GET /metrics` on `address`, in the
+    /// Prometheus text format with a `node` label, until the simulation stops. Nodes that were
+    /// killed keep their last values.
+    pub fn serve_metrics(self: &Arc<Self>, address: SocketAddr) -> io::Result<()> {
+        let simulation = self.clone();
+        let collect = move || {
+            simulation
+                .nodes()
+                .iter()
+                .map(|node| NodeMetrics {
+                    node: node_id(node.index),
+                    stats: node.node.stats(),
+                    storage: node.node.storage_metrics(),
+                })
+                .collect()
+        };
+        let timeline = self.timeline.clone();
+        http::serve_metrics(
+            address,
+            move || !timeline.stopping.load(Ordering::SeqCst),
+            collect,
+        )
+    }
+
+    /// Stops the threads driving the simulation, shuts every node down and stops the runtime
+    /// they were hosted on.
+    pub fn shutdown(&self) {
+        self.stop();
+        for node in self.nodes() {
+            if let Err(e) = node.handle.shutdown() {
+                error!(
+                    node_id = node.index,
+                    error = e.to_string(),
+                    "node exited with an error"
+                );
+            }
+        }
+        if let Some(runtime) = &self.sim_runtime {
+            runtime.stop();
+        }
+    }
+
+    /// Sleeps until `at` from the start, false if the simulation stopped before.
+    fn wait_until(&self, at: Duration) -> bool {
+        let time = &self.timeline.time;
+        while time.elapsed() < at {
+            if self.timeline.stopping.load(Ordering::SeqCst) {
+                return false;
+            }
+            time.sleep(STOP_POLL_INTERVAL.min(at.saturating_sub(time.elapsed())));
+        }
+        true
+    }
+
+    /// A fresh heartbeat of every initial node but the one with the given index, as another
+    /// node of a converged cluster would export them. They aren't provisional, so they replace
+    /// the made up entries of the seeds like received heartbeats do.
+    fn warm_snapshot(&self, index: u64) -> StorageSnapshot {
+        let now = self.clock.now();
+        StorageSnapshot {
+            owner: node_id(index),
+            entries: (0..self.config.nodes)
+                .filter(|&other| other != index)
+                .map(|other| SnapshotEntry {
+                    id: node_id(other),
+                    address: node_address(PORT_BASE + other),
+                    timestamp: now,
+                    leaving: false,
+                    state: PeerState::Alive,
+                    updates_received: 1,
+                    duplicates_received: 0,
+                    provisional: false,
+                })
+                .collect(),
+            values: vec![],
+        }
+    }
+
+    /// Creates and runs the node with the given index, None if it couldn't be started.
+    fn launch(&self, index: u64) -> Option<SimNode> {
+        let address = node_address(PORT_BASE + index);
+        let mut storage = gossip::setup_storage(
+            node_id(index),
+            address,
+            self.seed_nodes.clone(),
+            self.clock.as_ref(),
+        );
+        if self.config.warm_start && index < self.config.nodes {
+            storage.merge(self.warm_snapshot(index), self.clock.as_ref());
+        }
+        let config = match (self.config.node_config)(index) {
+            Ok(config) => gossip::NodeConfig {
+                clock: self.clock.clone(),
+                faults: self.config.faults.clone(),
+                memory_network: self.config.memory_network.clone(),
+                trace: self.config.trace.clone(),
+                ..config
+            },
+            Err(e) => {
+                error!(
+                    node_id = index,
+                    error = e.to_string(),
+                    "invalid node config"
+                );
+                return None;
+            }
+        };
+
+        let Some(node) = create_node(config, storage, self.sim_runtime.as_deref()) else {
+            error!(node_id = index, "giving up on node, skipping it");
+            return None;
+        };
+
+        let timeline = self.timeline.clone();
+        let node_id = index.to_string();
+        let mut first_convergence = true;
+        let watch = ConvergenceWatch {
+            threshold: self.config.suspect_timeout,
+            expected_size: Some(self.config.nodes as usize),
+        };
+        node.on_convergence(watch, move |event| {
+            if matches!(event, ConvergenceEvent::Converged(_)) && first_convergence {
+                first_convergence = false;
+                if let Ok(mut converged_at) = timeline.converged_at.lock() {
+                    converged_at.push((node_id.clone(), timeline.time.elapsed()));
+                }
+            }
+        });
+
+        match node.run() {
+            Ok(handle) => Some(SimNode {
+                index,
+                node: Arc::new(node),
+                handle,
+                added_at: None,
+            }),
+            Err(e) => {
+                error!(node_id = index, error = e.to_string(), "failed to run node");
+                None
+            }
+        }
+    }
+}
+
+/// Nodes of the simulation are named after their index.
+pub fn node_id(index: u64) -> NodeId {
+    NodeId::new(index.to_string()).expect("numeric ids are valid")
+}
+
+pub fn node_address(port: u64) -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], port as u16))
+}
+
+/// Creates a node, retrying a few times if its socket can't be set up (e.g. the port is still
+/// held by a previous run).
+pub fn create_node(
+    config: gossip::NodeConfig,
+    storage: gossip::Storage,
+    sim_runtime: Option<&SimRuntime>,
+) -> Option<gossip::Node> {
+    for attempt in 1..=BIND_ATTEMPTS {
+        let node = match sim_runtime {
+            Some(runtime) => gossip::Node::new_hosted(config.clone(), storage.clone(), runtime),
+            None => gossip::Node::new(config.clone(), storage.clone()),
+        };
+        match node {
+            Ok(node) => return Some(node),
+            Err(e) => {
+                error!(attempt, error = e.to_string(), "failed to create node");
+                sleep(BIND_RETRY_DELAY);
+            }
+        }
+    }
+    None
+}
+
+fn read_cluster(cluster: &Cluster) -> RwLockReadGuard<'_, Vec<SimNode>> {
+    cluster.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write_cluster(cluster: &Cluster) -> RwLockWriteGuard<'_, Vec<SimNode>> {
+    cluster.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Adds the event and what it did to the events of the timeline.
+fn record_event(timeline: &Timeline, event: &ScheduledEvent, outcome: &str, nodes: &[SimNode]) {
+    if let Ok(mut events) = timeline.events.lock() {
+        events.push(EventRecord {
+            at: timeline.time.elapsed(),
+            description: format!("{}: {}", event, outcome),
+            nodes: nodes.iter().map(|node| node.index).collect(),
+        });
+    }
+}
+
+/// The indexes `selection` picks among `candidates`, in order, the first `seeds` nodes being
+/// the seeds.
+pub fn select_indexes(
+    selection: &NodeSelection,
+    mut candidates: Vec<u64>,
+    seeds: u64,
+    rng: &mut StdRng,
+) -> Vec<u64> {
+    match selection {
+        NodeSelection::All => {}
+        NodeSelection::Random(count) => {
+            candidates.shuffle(rng);
+            candidates.truncate(*count);
+        }
+        NodeSelection::Nodes(nodes) => candidates.retain(|index| nodes.contains(index)),
+        NodeSelection::Seeds => candidates.retain(|&index| index < seeds),
+        NodeSelection::Excluding { nodes, excluded } => {
+            let excluded = select_indexes(excluded, candidates.clone(), seeds, rng);
+            candidates.retain(|index| !excluded.contains(index));
+            return select_indexes(nodes, candidates, seeds, rng);
+        }
+    }
+    candidates.sort();
+    candidates
+}
+
+/// The nodes `selection` picks among `candidates`, ordered by index.
+fn select<'a>(
+    selection: &NodeSelection,
+    candidates: Vec<&'a SimNode>,
+    seeds: u64,
+    rng: &mut StdRng,
+) -> Vec<&'a SimNode> {
+    let indexes = candidates.iter().map(|node| node.index).collect();
+    let picked: HashSet<u64> = select_indexes(selection, indexes, seeds, rng)
+        .into_iter()
+        .collect();
+    let mut selected: Vec<&SimNode> = candidates
+        .into_iter()
+        .filter(|node| picked.contains(&node.index))
+        .collect();
+    selected.sort_by_key(|node| node.index);
+    selected
+}
+
+/// What adding the nodes with the given indexes did, e.g. "added nodes 50, 51".
+pub fn describe_added(added: &[u64]) -> String {
+    let indexes: Vec<String> = added.iter().map(u64::to_string).collect();
+    match added.len() {
+        0 => "no node added".to_string(),
+        1 => format!("added node {}", indexes[0]),
+        _ => format!("added nodes {}", indexes.join(", ")),
+    }
+}
+
+fn describe_nodes(nodes: &[&SimNode]) -> String {
+    describe_indexes(&nodes.iter().map(|node| node.index).collect::<Vec<_>>())
+}
+
+/// The nodes with the given indexes, e.g. "nodes 3, 17".
+pub fn describe_indexes(nodes: &[u64]) -> String {
+    let indexes: Vec<String> = nodes.iter().map(u64::to_string).collect();
+    match nodes.len() {
+        0 => "no nodes".to_string(),
+        1 => format!("node {}", indexes[0]),
+        _ => format!("nodes {}", indexes.join(", ")),
+    }
+}
+
+/// Polls `observer` until `until` from the start, or until the simulation stops, and records
+/// when each victim first shows up among its dead peers.
+fn watch_detection(
+    observer: &SimNode,
+    victims: &[SimNode],
+    until: Option<Duration>,
+    threshold: Duration,
+    timeline: &Timeline,
+) {
+    let time = &timeline.time;
+    let killed_at = time.elapsed();
+    let mut undetected: Vec<String> = victims.iter().map(|node| node.index.to_string()).collect();
+    while until.is_none_or(|until| time.elapsed() < until)
+        && !timeline.stopping.load(Ordering::SeqCst)
+    {
+        if !undetected.is_empty() {
+            let dead = observer.handle.dead_peers(threshold);
+            undetected.retain(|victim| {
+                if !dead.iter().any(|member| member.id == victim.as_str()) {
+                    return true;
+                }
+                if let Ok(mut detected) = timeline.detected_at.lock() {
+                    detected.push((victim.to_string(), time.elapsed().saturating_sub(killed_at)));
+                }
+                false
+            });
+        }
+        time.sleep(DETECTION_POLL_INTERVAL);
+    }
+    if let Ok(mut observed_by) = timeline.detection_observer.lock() {
+        *observed_by = Some((observer.index.to_string(), victims.len()));
+    }
+}
+
+/// A random duration with an exponential distribution of the given mean.
+fn exponential(mean: Duration, rng: &mut StdRng) -> Duration {
+    let uniform: f64 = rng.gen();
+    mean.mul_f64(-(1.0 - uniform).ln())
+}
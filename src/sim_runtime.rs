@@ -0,0 +1,527 @@
+use std::cell::Cell;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use std::fmt;
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle, ThreadId};
+use std::time::{self, Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+
+/// How often a thread driving a stepped simulation checks whether the thread it joins is done,
+/// in simulated time.
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Numbers the stepped schedulers, so that a thread knows which one it drives.
+static STEPPED_SCHEDULERS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // the stepped scheduler the thread drives a simulation on and its number there
+    static DRIVER: Cell<Option<(u64, u64)>> = const { Cell::new(None) };
+}
+
+/// A unit of work hosted by a [`SimRuntime`], e.g. one iteration of a node loop.
+pub(crate) trait Task: Send {
+    /// Runs the task once. Returns how long to wait before running it again, or None when the
+    /// task is done.
+    fn run(&mut self) -> Option<Duration>;
+}
+
+/// Hosts many nodes at once, for simulations too large for a thread per node. Nodes created
+/// with [`crate::gossip::Node::new_hosted`] register with the runtime when run instead of
+/// spawning a thread. A node's work always runs on one thread at a time, so its loop state needs
+/// no locking.
+///
+/// The runtime is either a fixed pool of worker threads, see [`SimRuntime::new`], or stepped,
+/// see [`SimRuntime::stepped`]: then the hosted work runs one task at a time in simulated time,
+/// taking turns with the threads driving the simulation, so that a seeded simulation plays out
+/// the same on every run.
+///
+/// Dropping the runtime stops it; nodes still hosted on it stop making progress.
+pub struct SimRuntime {
+    scheduler: Arc<Scheduler>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl SimRuntime {
+    pub fn new(n_workers: usize) -> Self {
+        let scheduler = Arc::new(Scheduler::new(Arc::new(SystemClock), None));
+        let workers = (0..n_workers.max(1))
+            .map(|_| {
+                let scheduler = scheduler.clone();
+                thread::spawn(move || scheduler.work())
+            })
+            .collect();
+        SimRuntime { scheduler, workers }
+    }
+
+    /// A runtime without workers, whose clock only moves when the threads driving the
+    /// simulation, see [`SimTime`], sleep: the hosted task or driving thread due next then
+    /// runs, one at a time, with the clock set to when it was due. The calling thread is the
+    /// first driving thread. The clock starts at the current second of the wall clock.
+    ///
+    /// With `paced`, the simulated time doesn't run ahead of the wall clock, for simulations
+    /// watched as they run; otherwise it goes as fast as the work allows.
+    pub fn stepped(paced: bool) -> Self {
+        let epoch = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let clock = Arc::new(StepClock {
+            epoch_millis: epoch * 1000,
+            elapsed_nanos: AtomicU64::new(0),
+        });
+        let stepped = Stepped {
+            number: STEPPED_SCHEDULERS.fetch_add(1, atomic::Ordering::Relaxed),
+            clock: clock.clone(),
+            paced: paced.then(Instant::now),
+        };
+        let scheduler = Arc::new(Scheduler::new(clock, Some(stepped)));
+        scheduler.take_turn();
+        SimRuntime {
+            scheduler,
+            workers: vec![],
+        }
+    }
+
+    /// Number of worker threads, 0 for a stepped runtime.
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn is_stepped(&self) -> bool {
+        self.scheduler.stepped.is_some()
+    }
+
+    /// The time of a simulation hosted on the runtime, starting now.
+    pub fn time(&self) -> SimTime {
+        SimTime {
+            clock: self.scheduler.clock.clone(),
+            started: self.scheduler.clock.elapsed(),
+            stepped: self.is_stepped().then(|| self.scheduler.clone()),
+        }
+    }
+
+    /// Stops running the hosted work. Threads that drove a stepped simulation carry on by
+    /// themselves, sleeping on the wall clock.
+    pub fn stop(&self) {
+        self.scheduler.stop();
+    }
+
+    pub(crate) fn scheduler(&self) -> Arc<Scheduler> {
+        self.scheduler.clone()
+    }
+}
+
+impl Drop for SimRuntime {
+    fn drop(&mut self) {
+        self.scheduler.stop();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// The time a simulation runs on and the threads driving it, e.g. the one carrying out its
+/// scheduled events. Without a stepped runtime these are the wall clock and threads of their
+/// own. On a [`SimRuntime::stepped`] runtime, the driving threads take turns with the hosted
+/// work and sleep in simulated time; a thread started otherwise, e.g. one waiting for input,
+/// runs alongside whoever has the turn and sleeps on the wall clock.
+#[derive(Clone)]
+pub struct SimTime {
+    clock: Arc<dyn Clock>,
+    // when the simulation started, on the clock's elapsed time
+    started: Duration,
+    stepped: Option<Arc<Scheduler>>,
+}
+
+impl fmt::Debug for SimTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimTime")
+            .field("elapsed", &self.elapsed())
+            .field("stepped", &self.stepped.is_some())
+            .finish()
+    }
+}
+
+impl SimTime {
+    /// The wall clock, starting now.
+    pub fn wall() -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        SimTime {
+            started: clock.elapsed(),
+            clock,
+            stepped: None,
+        }
+    }
+
+    /// The clock of the nodes, the network and its faults.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Time since the simulation started.
+    pub fn elapsed(&self) -> Duration {
+        self.clock.elapsed().saturating_sub(self.started)
+    }
+
+    pub fn sleep(&self, timeout: Duration) {
+        match &self.stepped {
+            Some(scheduler) => scheduler.sleep(timeout),
+            None => thread::sleep(timeout),
+        }
+    }
+
+    /// Runs `f` on a new thread driving the simulation. On a stepped runtime it has its first
+    /// turn once the calling thread sleeps.
+    pub fn spawn<T, F>(&self, f: F) -> JoinHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        match &self.stepped {
+            Some(scheduler) => scheduler.spawn_driver(f),
+            None => thread::spawn(f),
+        }
+    }
+
+    /// Waits for a thread started with [`SimTime::spawn`] to finish. On a stepped runtime the
+    /// calling thread keeps taking turns meanwhile.
+    pub fn join<T>(&self, handle: JoinHandle<T>) -> thread::Result<T> {
+        if let Some(scheduler) = &self.stepped {
+            while !handle.is_finished() && !scheduler.has_finished(handle.thread().id()) {
+                scheduler.sleep(JOIN_POLL_INTERVAL);
+            }
+        }
+        handle.join()
+    }
+}
+
+/// The clock of a stepped runtime, moved forward by the runtime as it steps.
+#[derive(Debug)]
+struct StepClock {
+    epoch_millis: u64,
+    elapsed_nanos: AtomicU64,
+}
+
+impl StepClock {
+    fn advance_to(&self, at: Duration) {
+        self.elapsed_nanos
+            .fetch_max(at.as_nanos() as u64, atomic::Ordering::SeqCst);
+    }
+}
+
+impl Clock for StepClock {
+    fn now(&self) -> u64 {
+        self.now_millis() / 1000
+    }
+
+    fn now_millis(&self) -> u64 {
+        self.epoch_millis + self.elapsed().as_millis() as u64
+    }
+
+    fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed_nanos.load(atomic::Ordering::SeqCst))
+    }
+}
+
+/// What makes a scheduler stepped, see [`SimRuntime::stepped`].
+struct Stepped {
+    number: u64,
+    clock: Arc<StepClock>,
+    // when the simulated time started on the wall clock, if it is paced
+    paced: Option<Instant>,
+}
+
+/// Queue of hosted tasks ordered by when they are due next, on the elapsed time of its clock.
+pub(crate) struct Scheduler {
+    queue: Mutex<Queue>,
+    available: Condvar,
+    clock: Arc<dyn Clock>,
+    stepped: Option<Stepped>,
+}
+
+#[derive(Default)]
+struct Queue {
+    tasks: BinaryHeap<Reverse<Scheduled>>,
+    // tie breaker so that tasks due at the same instant run in the order they were scheduled
+    next_seq: u64,
+    stopped: bool,
+    // the driving thread whose turn it is on a stepped scheduler, and the number of the next
+    turn: Option<u64>,
+    next_driver: u64,
+    // driving threads that are done with their last turn
+    finished: HashSet<ThreadId>,
+}
+
+struct Scheduled {
+    due: Duration,
+    seq: u64,
+    work: Work,
+}
+
+enum Work {
+    Task(Box<dyn Task>),
+    /// a thread driving a stepped simulation, waiting for its turn
+    Driver(u64),
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.due, self.seq) == (other.due, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.due, self.seq).cmp(&(other.due, other.seq))
+    }
+}
+
+impl Scheduler {
+    fn new(clock: Arc<dyn Clock>, stepped: Option<Stepped>) -> Self {
+        Scheduler {
+            queue: Mutex::default(),
+            available: Condvar::new(),
+            clock,
+            stepped,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn is_stepped(&self) -> bool {
+        self.stepped.is_some()
+    }
+
+    /// Runs `task` as soon as a worker is free, or on a stepped scheduler as soon as the
+    /// driving thread that has the turn sleeps.
+    pub(crate) fn spawn(&self, task: Box<dyn Task>) {
+        self.schedule(Work::Task(task), self.clock.elapsed());
+    }
+
+    fn schedule(&self, work: Work, due: Duration) {
+        let mut queue = self.lock();
+        self.push(&mut queue, work, due);
+    }
+
+    fn push(&self, queue: &mut Queue, work: Work, due: Duration) {
+        if queue.stopped {
+            return;
+        }
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        queue.tasks.push(Reverse(Scheduled { due, seq, work }));
+        self.available.notify_one();
+    }
+
+    fn stop(&self) {
+        let mut queue = self.lock();
+        queue.stopped = true;
+        queue.tasks.clear();
+        self.available.notify_all();
+    }
+
+    fn work(&self) {
+        while let Some(mut task) = self.next_due() {
+            if let Some(delay) = task.run() {
+                self.schedule(Work::Task(task), self.clock.elapsed() + delay);
+            }
+        }
+    }
+
+    /// Blocks until the earliest task is due and takes it. Returns None once stopped.
+    fn next_due(&self) -> Option<Box<dyn Task>> {
+        let mut queue = self.lock();
+        loop {
+            if queue.stopped {
+                return None;
+            }
+            let now = self.clock.elapsed();
+            let wait = match queue.tasks.peek() {
+                Some(Reverse(next)) if next.due <= now => {
+                    let Reverse(next) = queue.tasks.pop()?;
+                    // another task may be due as well, let the next idle worker pick it up
+                    self.available.notify_one();
+                    match next.work {
+                        Work::Task(task) => return Some(task),
+                        Work::Driver(_) => continue,
+                    }
+                }
+                Some(Reverse(next)) => Some(next.due - now),
+                None => None,
+            };
+            queue = match wait {
+                Some(wait) => {
+                    self.available
+                        .wait_timeout(queue, wait)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .available
+                    .wait(queue)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+
+    /// Makes the calling thread a driving thread of a stepped scheduler and gives it the turn.
+    fn take_turn(&self) {
+        let Some(stepped) = &self.stepped else {
+            return;
+        };
+        let mut queue = self.lock();
+        let driver = queue.next_driver;
+        queue.next_driver += 1;
+        queue.turn = Some(driver);
+        DRIVER.set(Some((stepped.number, driver)));
+    }
+
+    /// The number of the calling thread if it drives a simulation on this stepped scheduler.
+    fn driver(&self) -> Option<u64> {
+        let stepped = self.stepped.as_ref()?;
+        DRIVER
+            .get()
+            .filter(|(number, _)| *number == stepped.number)
+            .map(|(_, driver)| driver)
+    }
+
+    /// Sleeps for `timeout`. A thread driving a simulation on a stepped scheduler sleeps in
+    /// simulated time: it runs the work due before it wakes up, or hands the turn to the driving
+    /// thread due next. Every other thread sleeps on the wall clock.
+    pub(crate) fn sleep(&self, timeout: Duration) {
+        let Some(driver) = self.driver() else {
+            thread::sleep(timeout);
+            return;
+        };
+        let mut queue = self.lock();
+        if queue.stopped {
+            drop(queue);
+            thread::sleep(timeout);
+            return;
+        }
+        let due = self.clock.elapsed() + timeout;
+        self.push(&mut queue, Work::Driver(driver), due);
+        let queue = self.pass_turn(queue);
+        self.wait_turn(queue, driver);
+    }
+
+    /// Starts a thread driving the simulation on a stepped scheduler, which has its first turn
+    /// after those due now.
+    fn spawn_driver<T, F>(self: &Arc<Self>, f: F) -> JoinHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let Some(stepped) = &self.stepped else {
+            return thread::spawn(f);
+        };
+        let number = stepped.number;
+        let driver = {
+            let mut queue = self.lock();
+            let driver = queue.next_driver;
+            queue.next_driver += 1;
+            let now = self.clock.elapsed();
+            self.push(&mut queue, Work::Driver(driver), now);
+            driver
+        };
+        let scheduler = self.clone();
+        thread::spawn(move || {
+            DRIVER.set(Some((number, driver)));
+            scheduler.wait_turn(scheduler.lock(), driver);
+            // the turn is handed on however f ends
+            let _last_turn = LastTurn { scheduler, driver };
+            f()
+        })
+    }
+
+    /// Whether the driving thread `thread` is done with its last turn.
+    fn has_finished(&self, thread: ThreadId) -> bool {
+        self.lock().finished.contains(&thread)
+    }
+
+    fn wait_turn(&self, queue: MutexGuard<'_, Queue>, driver: u64) {
+        let _queue = self
+            .available
+            .wait_while(queue, |queue| queue.turn != Some(driver) && !queue.stopped)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+
+    /// Hands the turn on: runs the hosted tasks due first one after the other, with the clock
+    /// set to when each was due, until the next thing due is a driving thread, whose turn it is
+    /// then.
+    fn pass_turn<'a>(&'a self, mut queue: MutexGuard<'a, Queue>) -> MutexGuard<'a, Queue> {
+        let Some(stepped) = &self.stepped else {
+            return queue;
+        };
+        loop {
+            if queue.stopped {
+                return queue;
+            }
+            let Some(Reverse(next)) = queue.tasks.peek() else {
+                queue.turn = None;
+                return queue;
+            };
+            let due = next.due;
+            if let Some(started) = stepped.paced {
+                let ahead = due.saturating_sub(started.elapsed());
+                if !ahead.is_zero() {
+                    // tasks spawned by other threads meanwhile may be due earlier
+                    drop(queue);
+                    thread::sleep(ahead);
+                    queue = self.lock();
+                    continue;
+                }
+            }
+            let Some(Reverse(next)) = queue.tasks.pop() else {
+                continue;
+            };
+            stepped.clock.advance_to(due);
+            match next.work {
+                Work::Driver(driver) => {
+                    queue.turn = Some(driver);
+                    self.available.notify_all();
+                    return queue;
+                }
+                Work::Task(mut task) => {
+                    drop(queue);
+                    let again = task.run();
+                    queue = self.lock();
+                    if let Some(delay) = again {
+                        let due = self.clock.elapsed() + delay;
+                        self.push(&mut queue, Work::Task(task), due);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Hands the turn of a driving thread on once it is done.
+struct LastTurn {
+    scheduler: Arc<Scheduler>,
+    driver: u64,
+}
+
+impl Drop for LastTurn {
+    fn drop(&mut self) {
+        DRIVER.set(None);
+        let mut queue = self.scheduler.lock();
+        queue.finished.insert(thread::current().id());
+        if queue.turn == Some(self.driver) {
+            let _queue = self.scheduler.pass_turn(queue);
+        }
+    }
+}
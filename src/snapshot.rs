@@ -0,0 +1,188 @@
+//! Snapshots of a node's view of the cluster. [`StorageSnapshot`] is the one export format of a
+//! [`crate::gossip::Storage`], shared by the admin queries, the http endpoint and the snapshot
+//! files.
+//!
+//! Snapshot files spare a restarted node from rebuilding its view from its seeds alone. Enabled
+//! with [`crate::gossip::NodeConfigBuilder::snapshot_path`]: the node saves a snapshot every
+//! [`SNAPSHOT_INTERVAL`] and when it is stopped, and restores it when it is created or restarted.
+
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::kv::KeyValue;
+use crate::membership::PeerState;
+use crate::node_id::NodeId;
+
+/// Bumped whenever the file format changes; files of another version are ignored.
+pub const SNAPSHOT_VERSION: u32 = 4;
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Every entry of a storage, taken with [`crate::gossip::Storage::snapshot`]. Bookkeeping that
+/// only matters to the running node, like the state history, is left out.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StorageSnapshot {
+    /// the node the storage belongs to
+    pub owner: NodeId,
+    pub entries: Vec<SnapshotEntry>,
+    /// application values, see [`crate::kv`]
+    #[serde(default)]
+    pub values: Vec<KeyValue>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    pub id: NodeId,
+    pub address: SocketAddr,
+    /// timestamp of the latest heartbeat of the node
+    pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub leaving: bool,
+    pub state: PeerState,
+    pub updates_received: u64,
+    pub duplicates_received: u64,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub provisional: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    version: u32,
+    /// clock time of the node when the snapshot was taken
+    saved_at: u64,
+    storage: StorageSnapshot,
+}
+
+// read first, so that a file of another version is reported as such rather than as garbage
+#[derive(Deserialize)]
+struct SnapshotVersion {
+    version: u32,
+}
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("failed to access snapshot file: {0}")]
+    Io(#[from] io::Error),
+    #[error("snapshot file is corrupt: {0}")]
+    Corrupt(#[from] serde_json::Error),
+    #[error("snapshot file has version {found}, expected {expected}")]
+    Version { found: u32, expected: u32 },
+}
+
+/// Writes `storage` to `path`. The file is written next to `path` first and then renamed over
+/// it, so a crash midway never leaves a half-written snapshot behind.
+pub fn save(path: &Path, storage: StorageSnapshot, saved_at: u64) -> Result<(), SnapshotError> {
+    let snapshot = SnapshotFile {
+        version: SNAPSHOT_VERSION,
+        saved_at,
+        storage,
+    };
+    let temp_path = temp_path(path);
+    let mut file = File::create(&temp_path)?;
+    serde_json::to_writer(&mut file, &snapshot)?;
+    file.flush()?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Reads the storage saved at `path`.
+pub fn load(path: &Path) -> Result<StorageSnapshot, SnapshotError> {
+    let contents = fs::read(path)?;
+    let SnapshotVersion { version } = serde_json::from_slice(&contents)?;
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::Version {
+            found: version,
+            expected: SNAPSHOT_VERSION,
+        });
+    }
+    let snapshot: SnapshotFile = serde_json::from_slice(&contents)?;
+    Ok(snapshot.storage)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_path = OsString::from(path.as_os_str());
+    temp_path.push(".tmp");
+    PathBuf::from(temp_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path in the temp dir for the test `name`, cleared of earlier runs.
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "muck-snapshot-{}-{}.json",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn snapshot() -> StorageSnapshot {
+        let id = NodeId::new("node-0").unwrap();
+        StorageSnapshot {
+            owner: id.clone(),
+            entries: vec![SnapshotEntry {
+                id,
+                address: "10.0.0.1:8000".parse().unwrap(),
+                timestamp: 1_700_000_000,
+                leaving: false,
+                state: PeerState::Alive,
+                updates_received: 3,
+                duplicates_received: 1,
+                provisional: false,
+            }],
+            values: vec![],
+        }
+    }
+
+    #[test]
+    fn a_saved_snapshot_loads_back() {
+        let path = path("round-trip");
+        save(&path, snapshot(), 1_700_000_001).unwrap();
+        assert!(!temp_path(&path).exists());
+        assert_eq!(load(&path).unwrap(), snapshot());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn foreign_and_broken_files_are_told_apart() {
+        let path = path("broken");
+        assert!(matches!(load(&path), Err(SnapshotError::Io(_))));
+        fs::write(&path, r#"{"version": 99}"#).unwrap();
+        assert!(matches!(
+            load(&path),
+            Err(SnapshotError::Version { found: 99, expected }) if expected == SNAPSHOT_VERSION
+        ));
+        fs::write(&path, "{\"version\": 1, \"sav").unwrap();
+        assert!(matches!(load(&path), Err(SnapshotError::Corrupt(_))));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn the_export_format_is_stable() {
+        let json = serde_json::to_string(&snapshot()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"owner":"node-0","entries":[{"id":"node-0","address":"10.0.0.1:8000","#.to_owned()
+                + r#""timestamp":1700000000,"state":"Alive","updates_received":3,"#
+                + r#""duplicates_received":1}],"values":[]}"#
+        );
+        // exports from before values were gossiped have none
+        let without_values = json.replace(r#","values":[]"#, "");
+        let parsed: StorageSnapshot = serde_json::from_str(&without_values).unwrap();
+        assert_eq!(parsed, snapshot());
+    }
+}
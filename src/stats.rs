@@ -0,0 +1,396 @@
+use serde::{Deserialize, Serialize};
+use std::iter::Sum;
+use std::ops::Add;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Snapshot of a node's message counters, returned by [`crate::gossip::Node::stats`].
+/// All counters are totals since the node was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeStats {
+    /// datagrams carrying the node's own heartbeat
+    pub heartbeats_sent: u64,
+    /// datagrams carrying another node's heartbeat or value that was passed on
+    pub gossip_forwarded: u64,
+    /// datagrams carrying one of the node's own values
+    pub values_sent: u64,
+    /// datagrams read from the socket, including ones that failed to decode
+    pub messages_received: u64,
+    pub decode_errors: u64,
+    /// send calls that failed, however many targets they had
+    pub sends_failed: u64,
+    /// received heartbeats that were not forwarded because of the decay
+    pub forwards_suppressed: u64,
+    /// admin queries ignored because their source is not in the allowlist
+    pub admin_rejected: u64,
+    /// entries evicted because the storage was full
+    pub entries_evicted: u64,
+    /// heartbeats ignored because they advertise an address that can't be sent to
+    pub addresses_rejected: u64,
+    /// locks taken over after a thread panicked while holding them
+    pub locks_recovered: u64,
+    /// heartbeats clamped or rejected because their timestamp lay too far in the future
+    pub future_timestamps: u64,
+    /// heartbeats older than the one stored for their node, e.g. replayed or long delayed
+    #[serde(default)]
+    pub stale_heartbeats: u64,
+    /// copies received of heartbeats already known, stale ones included
+    #[serde(default)]
+    pub duplicates_received: u64,
+    /// times such a copy of the stored heartbeat was passed on anyway, see
+    /// [`crate::forward::ForwardPolicy`]
+    #[serde(default)]
+    pub duplicate_forwards: u64,
+    /// bytes the node wrote to its socket, admin replies included and whatever the network did
+    /// to them; datagrams aren't compressed, so these are the encoded messages
+    #[serde(default)]
+    pub bytes_sent: u64,
+    /// bytes of the datagrams read from the socket, including ones that failed to decode
+    #[serde(default)]
+    pub bytes_received: u64,
+}
+
+impl NodeStats {
+    /// All datagrams the node sent, heartbeats, values and forwarded gossip alike.
+    pub fn messages_sent(&self) -> u64 {
+        self.heartbeats_sent + self.values_sent + self.gossip_forwarded
+    }
+}
+
+impl Add for NodeStats {
+    type Output = NodeStats;
+
+    fn add(self, other: NodeStats) -> NodeStats {
+        NodeStats {
+            heartbeats_sent: self.heartbeats_sent + other.heartbeats_sent,
+            gossip_forwarded: self.gossip_forwarded + other.gossip_forwarded,
+            values_sent: self.values_sent + other.values_sent,
+            messages_received: self.messages_received + other.messages_received,
+            decode_errors: self.decode_errors + other.decode_errors,
+            sends_failed: self.sends_failed + other.sends_failed,
+            forwards_suppressed: self.forwards_suppressed + other.forwards_suppressed,
+            admin_rejected: self.admin_rejected + other.admin_rejected,
+            entries_evicted: self.entries_evicted + other.entries_evicted,
+            addresses_rejected: self.addresses_rejected + other.addresses_rejected,
+            locks_recovered: self.locks_recovered + other.locks_recovered,
+            future_timestamps: self.future_timestamps + other.future_timestamps,
+            stale_heartbeats: self.stale_heartbeats + other.stale_heartbeats,
+            duplicates_received: self.duplicates_received + other.duplicates_received,
+            duplicate_forwards: self.duplicate_forwards + other.duplicate_forwards,
+            bytes_sent: self.bytes_sent + other.bytes_sent,
+            bytes_received: self.bytes_received + other.bytes_received,
+        }
+    }
+}
+
+impl Sum for NodeStats {
+    fn sum<I: Iterator<Item = NodeStats>>(iter: I) -> NodeStats {
+        iter.fold(NodeStats::default(), |total, stats| total + stats)
+    }
+}
+
+/// Everything the simulation charts about one storage, returned by
+/// [`crate::gossip::Storage::metrics`]. Provisional entries only count towards `entries` and
+/// `provisional`; states and ages are those of the other entries, the node itself included.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StorageMetrics {
+    pub entries: usize,
+    pub provisional: usize,
+    pub alive: usize,
+    pub suspect: usize,
+    pub dead: usize,
+    /// peers that announced they left
+    pub left: usize,
+    pub oldest_age: Option<Duration>,
+    /// whole seconds; ages past [`MEDIAN_AGE_LIMIT`] count as that limit
+    pub median_age: Option<Duration>,
+    pub total_updates: u64,
+    pub total_duplicates: u64,
+}
+
+/// Ages up to this many seconds are told apart when computing the median age.
+pub const MEDIAN_AGE_LIMIT: u64 = 127;
+
+/// Tally of ages in whole seconds, kept on the stack so computing metrics doesn't allocate.
+pub(crate) struct AgeHistogram {
+    counts: [usize; MEDIAN_AGE_LIMIT as usize + 1],
+    total: usize,
+}
+
+impl AgeHistogram {
+    pub fn new() -> Self {
+        AgeHistogram {
+            counts: [0; MEDIAN_AGE_LIMIT as usize + 1],
+            total: 0,
+        }
+    }
+
+    pub fn add(&mut self, age_secs: u64) {
+        self.counts[age_secs.min(MEDIAN_AGE_LIMIT) as usize] += 1;
+        self.total += 1;
+    }
+
+    /// The lower median, if any age was added.
+    pub fn median(&self) -> Option<Duration> {
+        if self.total == 0 {
+            return None;
+        }
+        let middle = (self.total - 1) / 2;
+        let mut seen = 0;
+        for (age, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen > middle {
+                return Some(Duration::from_secs(age as u64));
+            }
+        }
+        None
+    }
+}
+
+/// Latencies up to this many milliseconds, about 35 minutes, are told apart in a
+/// [`LatencyHistogram`]; longer ones count as that.
+pub const MAX_LATENCY_MILLIS: u64 = (1 << 21) - 1;
+
+// every power of two of milliseconds is split into this many buckets, so the bounds of a bucket
+// are at most an eighth apart
+const LATENCY_SUB_BUCKETS: u64 = 8;
+const LATENCY_SUB_BITS: u64 = LATENCY_SUB_BUCKETS.trailing_zeros() as u64;
+const LATENCY_BUCKETS: usize = latency_bucket(MAX_LATENCY_MILLIS) + 1;
+
+/// How long the heartbeats a node applied took from their origin to it, from the time the
+/// origin stamped them to their receipt, both read from the nodes' clocks. Kept HDR-style in
+/// buckets of exact milliseconds below 8ms and then of an eighth of a power of two each, so any
+/// percentile is off by at most 12.5%. Returned by [`crate::gossip::Node::propagation_latency`],
+/// the histograms of several nodes add up to that of them all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: Vec<u64>,
+    ahead: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            counts: vec![0; LATENCY_BUCKETS],
+            ahead: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, millis: u64) {
+        self.counts[latency_bucket(millis)] += 1;
+    }
+
+    /// Latencies recorded, those of [`LatencyHistogram::ahead`] left out.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Heartbeats stamped later than the receiver's clock said they arrived, by a clock running
+    /// ahead of the receiver's or a forged timestamp; their latency can't be told.
+    pub fn ahead(&self) -> u64 {
+        self.ahead
+    }
+
+    /// The latency `quantile` of those recorded are at most, e.g. 0.95 for the 95th percentile,
+    /// as the upper bound of its bucket. None if nothing was recorded.
+    pub fn percentile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((quantile * count as f64).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        for (bucket, n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Some(Duration::from_millis(bucket_bounds(bucket).1));
+            }
+        }
+        None
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.percentile(1.0)
+    }
+
+    /// The latencies recorded since the histogram was `earlier`.
+    pub fn since(&self, earlier: &LatencyHistogram) -> LatencyHistogram {
+        LatencyHistogram {
+            counts: self
+                .counts
+                .iter()
+                .zip(&earlier.counts)
+                .map(|(now, then)| now.saturating_sub(*then))
+                .collect(),
+            ahead: self.ahead.saturating_sub(earlier.ahead),
+        }
+    }
+
+    /// The buckets anything was recorded in, as their upper bound and count, shortest first.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| **n > 0)
+            .map(|(bucket, n)| (Duration::from_millis(bucket_bounds(bucket).1), *n))
+    }
+}
+
+impl Add for LatencyHistogram {
+    type Output = LatencyHistogram;
+
+    fn add(mut self, other: LatencyHistogram) -> LatencyHistogram {
+        for (count, n) in self.counts.iter_mut().zip(other.counts) {
+            *count += n;
+        }
+        self.ahead += other.ahead;
+        self
+    }
+}
+
+impl Sum for LatencyHistogram {
+    fn sum<I: Iterator<Item = LatencyHistogram>>(iter: I) -> LatencyHistogram {
+        iter.fold(LatencyHistogram::default(), |total, histogram| {
+            total + histogram
+        })
+    }
+}
+
+const fn latency_bucket(millis: u64) -> usize {
+    let millis = if millis > MAX_LATENCY_MILLIS {
+        MAX_LATENCY_MILLIS
+    } else {
+        millis
+    };
+    if millis < LATENCY_SUB_BUCKETS {
+        return millis as usize;
+    }
+    let magnitude = 63 - millis.leading_zeros() as u64;
+    let shift = magnitude - LATENCY_SUB_BITS;
+    let sub_bucket = (millis >> shift) - LATENCY_SUB_BUCKETS;
+    ((shift + 1) * LATENCY_SUB_BUCKETS + sub_bucket) as usize
+}
+
+/// The lowest and highest latency of a bucket, in milliseconds.
+fn bucket_bounds(bucket: usize) -> (u64, u64) {
+    let bucket = bucket as u64;
+    if bucket < LATENCY_SUB_BUCKETS {
+        return (bucket, bucket);
+    }
+    let shift = bucket / LATENCY_SUB_BUCKETS - 1;
+    let low = (LATENCY_SUB_BUCKETS + bucket % LATENCY_SUB_BUCKETS) << shift;
+    (low, low + (1 << shift) - 1)
+}
+
+/// The live [`LatencyHistogram`] of a node, updated by its loop.
+pub(crate) struct LatencyCounters {
+    counts: Vec<AtomicU64>,
+    ahead: AtomicU64,
+}
+
+impl Default for LatencyCounters {
+    fn default() -> Self {
+        LatencyCounters {
+            counts: (0..LATENCY_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            ahead: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LatencyCounters {
+    /// Records a heartbeat stamped at `stamped` and received at `received`, in milliseconds.
+    pub fn record(&self, stamped: u64, received: u64) {
+        match received.checked_sub(stamped) {
+            Some(millis) => self.counts[latency_bucket(millis)].fetch_add(1, Ordering::Relaxed),
+            None => self.ahead.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    pub fn snapshot(&self) -> LatencyHistogram {
+        LatencyHistogram {
+            counts: self
+                .counts
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed))
+                .collect(),
+            ahead: self.ahead.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Bytes through a node's socket, shared by its stats and the channel that writes and reads them,
+/// which is rebound when the node restarts.
+#[derive(Default)]
+pub(crate) struct ByteCounters {
+    pub sent: AtomicU64,
+    pub received: AtomicU64,
+}
+
+/// Live counters updated by the node loop.
+#[derive(Default)]
+pub(crate) struct StatsCounters {
+    pub heartbeats_sent: AtomicU64,
+    pub gossip_forwarded: AtomicU64,
+    pub values_sent: AtomicU64,
+    pub messages_received: AtomicU64,
+    pub decode_errors: AtomicU64,
+    pub sends_failed: AtomicU64,
+    pub forwards_suppressed: AtomicU64,
+    pub admin_rejected: AtomicU64,
+    pub entries_evicted: AtomicU64,
+    pub addresses_rejected: AtomicU64,
+    pub locks_recovered: AtomicU64,
+    pub future_timestamps: AtomicU64,
+    pub stale_heartbeats: AtomicU64,
+    pub duplicates_received: AtomicU64,
+    pub duplicate_forwards: AtomicU64,
+    /// kept apart from [`NodeStats`], which is a handful of totals
+    pub propagation: LatencyCounters,
+    pub bytes: Arc<ByteCounters>,
+}
+
+impl StatsCounters {
+    pub fn snapshot(&self) -> NodeStats {
+        NodeStats {
+            heartbeats_sent: self.heartbeats_sent.load(Ordering::Relaxed),
+            gossip_forwarded: self.gossip_forwarded.load(Ordering::Relaxed),
+            values_sent: self.values_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            decode_errors: self.decode_errors.load(Ordering::Relaxed),
+            sends_failed: self.sends_failed.load(Ordering::Relaxed),
+            forwards_suppressed: self.forwards_suppressed.load(Ordering::Relaxed),
+            admin_rejected: self.admin_rejected.load(Ordering::Relaxed),
+            entries_evicted: self.entries_evicted.load(Ordering::Relaxed),
+            addresses_rejected: self.addresses_rejected.load(Ordering::Relaxed),
+            locks_recovered: self.locks_recovered.load(Ordering::Relaxed),
+            future_timestamps: self.future_timestamps.load(Ordering::Relaxed),
+            stale_heartbeats: self.stale_heartbeats.load(Ordering::Relaxed),
+            duplicates_received: self.duplicates_received.load(Ordering::Relaxed),
+            duplicate_forwards: self.duplicate_forwards.load(Ordering::Relaxed),
+            bytes_sent: self.bytes.sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes.received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_median_age_is_the_lower_one_and_capped() {
+        let mut ages = AgeHistogram::new();
+        assert_eq!(ages.median(), None);
+        for age in [9, 1, 4, 6] {
+            ages.add(age);
+        }
+        assert_eq!(ages.median(), Some(Duration::from_secs(4)));
+        for _ in 0..5 {
+            ages.add(10_000);
+        }
+        assert_eq!(ages.median(), Some(Duration::from_secs(MEDIAN_AGE_LIMIT)));
+    }
+}
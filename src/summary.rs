@@ -0,0 +1,90 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::node_id::NodeId;
+
+// number of entries listed in a storage summary before the rest is collapsed into "+k more"
+pub const SUMMARY_DETAIL_LIMIT: usize = 10;
+
+/// One entry listed in a [`StorageSummary`].
+#[derive(Debug, Clone)]
+pub struct EntrySummary {
+    pub id: NodeId,
+    pub address: SocketAddr,
+    pub age: Duration,
+    pub updates_received: u64,
+    pub duplicates_received: u64,
+}
+
+/// Overview of a storage, returned by [`crate::gossip::Storage::summary`]. Only the
+/// [`SUMMARY_DETAIL_LIMIT`] stalest entries are listed, so it stays small for big clusters.
+#[derive(Debug, Clone)]
+pub struct StorageSummary {
+    pub entries: usize,
+    pub oldest_age: Option<Duration>,
+    /// stalest entries first
+    pub stalest: Vec<EntrySummary>,
+    /// entries left out of `stalest`
+    pub remaining: usize,
+}
+
+impl fmt::Display for StorageSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} entries", self.entries)?;
+        if let Some(age) = self.oldest_age {
+            write!(f, ", oldest {}s", age.as_secs())?;
+        }
+        for entry in &self.stalest {
+            write!(
+                f,
+                "\n  {:<12} {:<21} {:>5}s old, {} updates, {} duplicates",
+                entry.id,
+                entry.address,
+                entry.age.as_secs(),
+                entry.updates_received,
+                entry.duplicates_received
+            )?;
+        }
+        if self.remaining > 0 {
+            write!(f, "\n  +{} more", self.remaining)?;
+        }
+        Ok(())
+    }
+}
+
+/// Overview of a node, returned by [`crate::gossip::Node::summary`]. Displays as a single line;
+/// the alternate form (`{:#}`) also lists the stalest storage entries.
+#[derive(Debug, Clone)]
+pub struct NodeSummary {
+    pub id: NodeId,
+    pub address: SocketAddr,
+    pub paused: bool,
+    /// entries in the storage other than the node itself
+    pub peers_known: usize,
+    pub peers_alive: usize,
+    pub messages_sent: u64,
+    pub storage: StorageSummary,
+}
+
+impl fmt::Display for NodeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "node {} ({}){}: {} peers known, {} alive",
+            self.id,
+            self.address,
+            if self.paused { " [paused]" } else { "" },
+            self.peers_known,
+            self.peers_alive
+        )?;
+        if let Some(age) = self.storage.oldest_age {
+            write!(f, ", oldest entry {}s", age.as_secs())?;
+        }
+        write!(f, ", {} messages sent", self.messages_sent)?;
+        if f.alternate() {
+            write!(f, "\n{}", self.storage)?;
+        }
+        Ok(())
+    }
+}
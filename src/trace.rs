@@ -0,0 +1,253 @@
+//! Follows a single heartbeat through the cluster: which node passed it to which and when, to
+//! see how an update spreads and why it stalls. A [`HeartbeatTrace`] shared by the nodes of a
+//! simulated cluster picks one heartbeat of its origin node and records every receipt of it;
+//! handed to the nodes with [`crate::gossip::NodeConfigBuilder::trace`].
+//!
+//! A heartbeat is told apart from the others of its node by its timestamp. The receipts turn
+//! into the dissemination graph of the heartbeat, written as DOT with [`HeartbeatTrace::dot`]
+//! for Graphviz to draw.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::gossip::Heartbeat;
+use crate::node_id::NodeId;
+
+// fill of the nodes the heartbeat never reached
+const UNREACHED_COLOR: &str = "#cccccc";
+
+/// Records the way one heartbeat of `origin` spreads: the first one it sends once `clock` has
+/// reached `from`, on its elapsed time.
+#[derive(Debug)]
+pub struct HeartbeatTrace {
+    origin: NodeId,
+    from: Duration,
+    clock: Arc<dyn Clock>,
+    state: Mutex<TraceState>,
+}
+
+#[derive(Debug, Default)]
+struct TraceState {
+    /// the timestamp of the traced heartbeat and when its origin sent it, once it did
+    sent: Option<(u64, Duration)>,
+    /// every node that sent or received the heartbeat, to name the senders of the receipts
+    nodes: Vec<(SocketAddr, NodeId)>,
+    receipts: Vec<Receipt>,
+}
+
+#[derive(Debug, Clone)]
+struct Receipt {
+    from: SocketAddr,
+    to: NodeId,
+    at: Duration,
+}
+
+/// A datagram carrying the traced heartbeat from one node to another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    pub from: NodeId,
+    pub to: NodeId,
+    /// hops the heartbeat had taken when it arrived, 1 for datagrams sent by its origin
+    pub hop: usize,
+    /// since the origin sent the heartbeat
+    pub at: Duration,
+    /// since the sender first had the heartbeat
+    pub latency: Duration,
+}
+
+impl HeartbeatTrace {
+    pub fn new(origin: NodeId, from: Duration, clock: Arc<dyn Clock>) -> Self {
+        HeartbeatTrace {
+            origin,
+            from,
+            clock,
+            state: Mutex::new(TraceState::default()),
+        }
+    }
+
+    pub fn origin(&self) -> &NodeId {
+        &self.origin
+    }
+
+    /// The timestamp of the traced heartbeat, None until its origin sent it.
+    pub fn timestamp(&self) -> Option<u64> {
+        self.state().sent.map(|(timestamp, _)| timestamp)
+    }
+
+    /// Called by a node sending a heartbeat of its own, picks it if it's the one to trace.
+    pub fn sent(&self, heartbeat: &Heartbeat) {
+        let now = self.clock.elapsed();
+        if *heartbeat.id() != self.origin || now < self.from {
+            return;
+        }
+        let mut state = self.state();
+        if state.sent.is_none() {
+            state.sent = Some((heartbeat.timestamp(), now));
+            state
+                .nodes
+                .push((heartbeat.address(), heartbeat.id().clone()));
+        }
+    }
+
+    /// Called by the node `receiver` at `address` for every heartbeat it receives from `from`,
+    /// records those of the traced one.
+    pub fn received(
+        &self,
+        heartbeat: &Heartbeat,
+        from: SocketAddr,
+        receiver: &NodeId,
+        address: SocketAddr,
+    ) {
+        if *heartbeat.id() != self.origin {
+            return;
+        }
+        let mut state = self.state();
+        if state.sent.map(|(timestamp, _)| timestamp) != Some(heartbeat.timestamp()) {
+            return;
+        }
+        if !state.nodes.iter().any(|(_, id)| id == receiver) {
+            state.nodes.push((address, receiver.clone()));
+        }
+        state.receipts.push(Receipt {
+            from,
+            to: receiver.clone(),
+            at: self.clock.elapsed(),
+        });
+    }
+
+    /// Every datagram that delivered the heartbeat, in the order they arrived. Receipts from
+    /// a node that didn't have the heartbeat, like a forged one, are left out.
+    pub fn records(&self) -> Vec<TraceRecord> {
+        let state = self.state();
+        let Some((_, sent_at)) = state.sent else {
+            return vec![];
+        };
+        // when each node first had the heartbeat and after how many hops
+        let mut first: HashMap<&NodeId, (Duration, usize)> = HashMap::new();
+        first.insert(&self.origin, (sent_at, 0));
+        let mut records = vec![];
+        for receipt in &state.receipts {
+            let Some((_, sender)) = state
+                .nodes
+                .iter()
+                .find(|(address, _)| same_node(*address, receipt.from))
+            else {
+                continue;
+            };
+            let Some(&(sender_had_it, sender_hop)) = first.get(sender) else {
+                continue;
+            };
+            first
+                .entry(&receipt.to)
+                .or_insert((receipt.at, sender_hop + 1));
+            records.push(TraceRecord {
+                from: sender.clone(),
+                to: receipt.to.clone(),
+                hop: sender_hop + 1,
+                at: receipt.at.saturating_sub(sent_at),
+                latency: receipt.at.saturating_sub(sender_had_it),
+            });
+        }
+        records
+    }
+
+    /// The dissemination graph of the heartbeat in DOT, for `dot -Tpng`: an edge for every
+    /// datagram that delivered it, labelled with its hop and latency, and the `nodes` filled
+    /// from green to red by when they first had it, grey if they never did.
+    pub fn dot(&self, nodes: &[NodeId]) -> String {
+        let records = self.records();
+        let mut first: HashMap<&NodeId, Duration> = HashMap::new();
+        first.insert(&self.origin, Duration::ZERO);
+        for record in &records {
+            first.entry(&record.to).or_insert(record.at);
+        }
+        let slowest = first.values().max().copied().unwrap_or_default();
+
+        let mut dot = String::from("digraph heartbeat {\n");
+        let title = match self.timestamp() {
+            Some(timestamp) => format!(
+                "heartbeat {} of node {}, {} of {} nodes reached",
+                timestamp,
+                self.origin,
+                first.len(),
+                nodes.len().max(first.len())
+            ),
+            None => format!("node {} sent no heartbeat to trace", self.origin),
+        };
+        let _ = writeln!(dot, "  label={};", quoted(&title));
+        let _ = writeln!(dot, "  node [style=filled];");
+        let mut named: Vec<&NodeId> = nodes.iter().collect();
+        let mut others: Vec<&NodeId> = first
+            .keys()
+            .copied()
+            .filter(|node| !nodes.contains(node))
+            .collect();
+        others.sort();
+        named.extend(others);
+        for node in named {
+            let (color, label) = match first.get(node) {
+                Some(at) => (
+                    color(*at, slowest),
+                    format!("{}\n{:.3}s", node, at.as_secs_f64()),
+                ),
+                None => (
+                    UNREACHED_COLOR.to_string(),
+                    format!("{}\nnot reached", node),
+                ),
+            };
+            let _ = writeln!(
+                dot,
+                "  {} [label={}, fillcolor={}];",
+                quoted(node.as_str()),
+                quoted(&label),
+                quoted(&color)
+            );
+        }
+        for record in &records {
+            let label = format!("hop {}\n{}ms", record.hop, record.latency.as_millis());
+            let _ = writeln!(
+                dot,
+                "  {} -> {} [label={}];",
+                quoted(record.from.as_str()),
+                quoted(record.to.as_str()),
+                quoted(&label)
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn state(&self) -> MutexGuard<'_, TraceState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Whether a datagram from `from` was sent by the node bound to `address`. A node bound to the
+/// unspecified address sends from any address of its host, so only the ports are compared then.
+fn same_node(address: SocketAddr, from: SocketAddr) -> bool {
+    address == from || (address.ip().is_unspecified() && address.port() == from.port())
+}
+
+/// Green for the nodes that had the heartbeat first, shading to red for the last one.
+fn color(at: Duration, slowest: Duration) -> String {
+    let share = if slowest.is_zero() {
+        0.0
+    } else {
+        at.as_secs_f64() / slowest.as_secs_f64()
+    };
+    let red = (255.0 * share).round() as u8;
+    format!("#{:02x}{:02x}00", red, 255 - red)
+}
+
+/// A DOT string literal.
+fn quoted(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
@@ -0,0 +1,83 @@
+//! Checks the datagrams and bytes charted for the whole cluster add up to the counters of its
+//! nodes.
+#![cfg(feature = "sim")]
+
+mod common;
+
+use muck::report::RunReport;
+
+/// Runs a cluster whose nodes are all killed halfway, so their counters stop moving before the
+/// last samples, and reads the report.
+fn run() -> RunReport {
+    common::run_report(
+        "channels",
+        [
+            "--nodes",
+            "5",
+            "--heartbeat-interval",
+            "1s",
+            "--in-memory",
+            "--headless",
+            "--duration",
+            "7s",
+            "--event",
+            "at=3s action=kill nodes=all",
+        ],
+    )
+}
+
+#[test]
+fn aggregate_counts_are_the_sum_of_the_nodes() {
+    let report = run();
+    let last = report.samples.last().expect("no samples");
+    let sent: u64 = report
+        .nodes
+        .iter()
+        .map(|node| node.stats.messages_sent())
+        .sum();
+    let received: u64 = report
+        .nodes
+        .iter()
+        .map(|node| node.stats.messages_received)
+        .sum();
+    assert!(sent > 0 && received > 0, "no datagrams counted");
+    assert_eq!(last.total_messages_sent, sent);
+    assert_eq!(last.total_messages_received, received);
+
+    // the per sample counts add up to the totals
+    let samples = &report.samples;
+    assert_eq!(
+        samples
+            .iter()
+            .map(|sample| sample.messages_sent)
+            .sum::<u64>(),
+        sent
+    );
+    assert_eq!(
+        samples
+            .iter()
+            .map(|sample| sample.messages_received)
+            .sum::<u64>(),
+        received
+    );
+
+    let bytes_sent: u64 = report.nodes.iter().map(|node| node.stats.bytes_sent).sum();
+    let bytes_received: u64 = report
+        .nodes
+        .iter()
+        .map(|node| node.stats.bytes_received)
+        .sum();
+    assert_eq!(last.total_bytes_sent, bytes_sent);
+    assert_eq!(last.total_bytes_received, bytes_received);
+    assert_eq!(report.results.bytes_sent, bytes_sent);
+    // a heartbeat takes dozens of bytes of JSON
+    assert!(
+        bytes_sent > 10 * sent,
+        "{} bytes in {} datagrams",
+        bytes_sent,
+        sent
+    );
+    assert!(bytes_received > 10 * received);
+    assert!(samples.iter().all(|sample| sample.max_node_kib_per_second
+        <= sample.sent_kib_per_second + sample.received_kib_per_second));
+}
@@ -0,0 +1,46 @@
+//! Helpers shared by the tests that run the muck binary. Every test file uses only some of them.
+#![allow(dead_code)]
+
+use std::ffi::OsStr;
+use std::process::{self, Command, Output};
+
+use muck::report::RunReport;
+
+/// The muck binary, run from the crate root so that the scenario files are found.
+pub fn muck() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_muck"));
+    command.current_dir(env!("CARGO_MANIFEST_DIR"));
+    command
+}
+
+/// Fails the test with the output of muck unless it exited successfully.
+pub fn assert_success(output: &Output) {
+    assert!(
+        output.status.success(),
+        "muck failed:\n{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Runs muck with `args`, writing its samples and report to temporary files named after `name`,
+/// which must be unique among the runs of a test binary, and reads the report.
+pub fn run_report<I, S>(name: &str, args: I) -> RunReport
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let out = std::env::temp_dir().join(format!("muck-{}-{}.csv", name, process::id()));
+    let output = muck()
+        .args(args)
+        .arg("--out")
+        .arg(&out)
+        .output()
+        .expect("failed to run muck");
+    assert_success(&output);
+    let report = out.with_extension("json");
+    let json = std::fs::read_to_string(&report).expect("no report written");
+    let _ = std::fs::remove_file(&out);
+    let _ = std::fs::remove_file(&report);
+    serde_json::from_str(&json).expect("invalid report")
+}
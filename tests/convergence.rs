@@ -0,0 +1,65 @@
+//! Checks the report records when the cluster converged and diverged around a kill.
+#![cfg(feature = "sim")]
+
+mod common;
+
+use muck::report::RunReport;
+
+const KILLED: u64 = 5;
+
+/// Runs a cluster that converges, loses a node at 8s and converges again once the others find
+/// it suspect, and reads the report.
+fn run() -> RunReport {
+    let kill = format!("at=8s action=kill nodes=id:{}", KILLED);
+    common::run_report(
+        "convergence",
+        [
+            "--nodes",
+            "6",
+            "--heartbeat-interval",
+            "1s",
+            "--suspect-timeout",
+            "3s",
+            "--dead-timeout",
+            "6s",
+            "--in-memory",
+            "--headless",
+            "--duration",
+            "18s",
+            "--event",
+            &kill,
+        ],
+    )
+}
+
+#[test]
+fn convergence_is_recorded_around_a_kill() {
+    let report = run();
+    let convergence = &report.convergence;
+    let events: Vec<&str> = convergence
+        .iter()
+        .map(|entry| entry.event.as_str())
+        .collect();
+    assert_eq!(
+        events,
+        ["converged", "diverged", "converged"],
+        "{:#?}",
+        convergence
+    );
+
+    let (first, diverged, second) = (&convergence[0], &convergence[1], &convergence[2]);
+    assert!(first.at < 8.0, "{:#?}", first);
+    assert!(
+        diverged.at >= 8.0 && diverged.at < second.at,
+        "{:#?}",
+        diverged
+    );
+    // every survivor still sees the victim alive at first
+    assert!(!diverged.nodes.is_empty(), "{:#?}", diverged);
+    assert!(!diverged.nodes.contains(&KILLED), "{:#?}", diverged);
+    for converged in [first, second] {
+        let since = converged.since.expect("no start of the streak");
+        assert!(since < converged.at, "{:#?}", converged);
+        assert!(converged.nodes.is_empty());
+    }
+}
@@ -0,0 +1,38 @@
+//! Seeded in-memory runs step through simulated time on one thread, so running one twice must
+//! write the same samples.
+#![cfg(feature = "sim")]
+
+mod common;
+
+use std::fs;
+use std::process;
+
+fn samples(name: &str) -> Vec<u8> {
+    let out = std::env::temp_dir().join(format!("muck-{}-{}.csv", name, process::id()));
+    let output = common::muck()
+        .args(["--nodes", "10", "--in-memory", "--headless", "--seed", "42"])
+        .args(["--duration", "40s", "--kill", "2", "--kill-after", "20s"])
+        .args(["--loss", "0.1"])
+        .arg("--out")
+        .arg(&out)
+        .output()
+        .expect("failed to run muck");
+    common::assert_success(&output);
+    let samples = fs::read(&out).expect("no samples written");
+    let _ = fs::remove_file(&out);
+    let _ = fs::remove_file(out.with_extension("json"));
+    samples
+}
+
+#[test]
+fn seeded_in_memory_runs_write_identical_samples() {
+    let first = samples("determinism-first");
+    let second = samples("determinism-second");
+    assert!(!first.is_empty());
+    assert!(
+        first == second,
+        "the runs differ:\n{}\n{}",
+        String::from_utf8_lossy(&first),
+        String::from_utf8_lossy(&second)
+    );
+}
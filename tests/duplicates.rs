@@ -0,0 +1,68 @@
+//! Runs scenarios/duplicates.toml with and without the duplicates and compares the reports.
+#![cfg(feature = "sim")]
+
+mod common;
+
+use std::thread;
+
+use muck::report::RunReport;
+
+/// Runs the scenario with `--duplicate` set to `share` and reads its report.
+fn run(share: &str) -> RunReport {
+    common::run_report(
+        &format!("duplicates-{}", share),
+        [
+            "--config",
+            "scenarios/duplicates.toml",
+            "--duplicate",
+            share,
+        ],
+    )
+}
+
+#[test]
+fn duplicates_are_counted_but_not_spread() {
+    let baseline = thread::spawn(|| run("0"));
+    let duplicated = run("0.3");
+    let baseline = baseline.join().expect("baseline run panicked");
+
+    let nodes = duplicated.config["nodes"].as_f64().unwrap() as f32;
+    for report in [&baseline, &duplicated] {
+        assert_eq!(
+            report.results.final_fully_informed, nodes,
+            "{:?}",
+            report.results
+        );
+    }
+
+    let (with, without) = (&duplicated.results, &baseline.results);
+    assert!(with
+        .faults
+        .as_ref()
+        .is_some_and(|faults| faults.duplicated > 0));
+    assert!(
+        with.duplicates_received > without.duplicates_received,
+        "{} duplicates received with the injected ones, {} without",
+        with.duplicates_received,
+        without.duplicates_received
+    );
+
+    // a copy is at least the second receipt of its heartbeat, which the decay passes on with
+    // a probability of at most exp(-2 * decay)
+    let decay = duplicated.config["decay"].as_f64().unwrap();
+    let bound = with.duplicates_received as f64 * (-2.0 * decay).exp();
+    assert!(
+        with.duplicate_forwards as f64 <= bound,
+        "{} copies passed on, expected at most {:.0}",
+        with.duplicate_forwards,
+        bound
+    );
+    // and every copy passed on is forwarded to at most spread peers
+    let spread = duplicated.config["spread"].as_u64().unwrap();
+    assert!(
+        with.gossip_forwarded <= without.gossip_forwarded + spread * with.duplicate_forwards,
+        "{} datagrams forwarded with duplicates, {} without",
+        with.gossip_forwarded,
+        without.gossip_forwarded
+    );
+}
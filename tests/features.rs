@@ -0,0 +1,22 @@
+//! Checks that the library builds without the default features, as it is used by applications
+//! that want the gossip protocol but not the simulation.
+
+use std::process::Command;
+
+#[test]
+fn the_library_builds_without_default_features() {
+    let output = Command::new(env!("CARGO"))
+        .args(["check", "--lib", "--no-default-features", "--quiet"])
+        // a target directory of its own, so that checking without the features doesn't throw
+        // away the build of the tests
+        .arg("--target-dir")
+        .arg(concat!(env!("CARGO_TARGET_TMPDIR"), "/no-default-features"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "the library doesn't build without default features:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,62 @@
+//! Checks the propagation latency of the heartbeats is measured and reported.
+#![cfg(feature = "sim")]
+
+mod common;
+
+use muck::report::RunReport;
+
+const LATENCY: f64 = 0.2;
+
+/// Runs a cluster whose datagrams all take 200ms and reads the report.
+fn run() -> RunReport {
+    let latency = format!("{}ms", LATENCY * 1000.0);
+    common::run_report(
+        "latency",
+        [
+            "--nodes",
+            "5",
+            "--heartbeat-interval",
+            "1s",
+            "--latency",
+            &latency,
+            "--in-memory",
+            "--headless",
+            "--duration",
+            "6s",
+        ],
+    )
+}
+
+#[test]
+fn propagation_latency_covers_the_link_latency() {
+    let report = run();
+    let latency = report
+        .results
+        .propagation_latency
+        .expect("no latency recorded");
+    assert!(latency.count > 0);
+    assert_eq!(latency.ahead, 0);
+    assert_eq!(
+        latency
+            .buckets
+            .iter()
+            .map(|bucket| bucket.count)
+            .sum::<u64>(),
+        latency.count
+    );
+    // a heartbeat takes at least one datagram to arrive, the clock may be late by a millisecond
+    assert!(latency.p50 >= LATENCY - 0.001, "{:?}", latency);
+    assert!(
+        latency.p50 <= latency.p95 && latency.p95 <= latency.p99 && latency.p99 <= latency.max,
+        "{:?}",
+        latency
+    );
+    assert!(
+        report
+            .samples
+            .iter()
+            .filter_map(|sample| sample.latency_p95)
+            .all(|p95| p95 >= LATENCY - 0.001),
+        "a sample saw a heartbeat faster than its datagram"
+    );
+}
@@ -0,0 +1,110 @@
+//! Scrapes the Prometheus endpoint of a running simulation and checks the output parses and
+//! the sent messages go up.
+#![cfg(feature = "sim")]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+mod common;
+
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+
+const NODES: usize = 4;
+
+/// A port nothing listens on right now.
+fn free_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("no free port")
+}
+
+/// The body of `GET /metrics`, after checking the response is Prometheus text.
+fn scrape(address: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(address).expect("metrics endpoint not up");
+    write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").expect("no headers");
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+    assert!(
+        head.contains("Content-Type: text/plain; version=0.0.4"),
+        "{}",
+        head
+    );
+    body.to_string()
+}
+
+/// Every sample in `body` as its name, labels and value, checking the format on the way.
+fn parse(body: &str) -> Vec<(String, String, f64)> {
+    let mut samples = vec![];
+    for line in body.lines() {
+        if let Some(comment) = line.strip_prefix("# ") {
+            assert!(
+                comment.starts_with("HELP gossip_") || comment.starts_with("TYPE gossip_"),
+                "{:?}",
+                line
+            );
+            continue;
+        }
+        let (series, value) = line
+            .rsplit_once(' ')
+            .unwrap_or_else(|| panic!("no value in {:?}", line));
+        let (name, labels) = series
+            .split_once('{')
+            .unwrap_or_else(|| panic!("no labels in {:?}", line));
+        let labels = labels
+            .strip_suffix('}')
+            .unwrap_or_else(|| panic!("unterminated labels in {:?}", line));
+        assert!(labels.starts_with("node=\""), "{:?}", line);
+        let value = value
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid value in {:?}", line));
+        samples.push((name.to_string(), labels.to_string(), value));
+    }
+    samples
+}
+
+fn messages_sent(samples: &[(String, String, f64)]) -> f64 {
+    samples
+        .iter()
+        .filter(|(name, _, _)| name == "gossip_messages_sent_total")
+        .map(|(_, _, value)| value)
+        .sum()
+}
+
+#[test]
+fn metrics_endpoint_counts_messages() {
+    let address = free_address();
+    let mut muck = common::muck()
+        .args(["--nodes", &NODES.to_string(), "--heartbeat-interval", "1s"])
+        .args(["--in-memory", "--headless", "--duration", "8s"])
+        .args(["--metrics-address", &address.to_string()])
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("failed to run muck");
+
+    sleep(Duration::from_secs(3));
+    let first = parse(&scrape(address));
+    sleep(Duration::from_secs(3));
+    let second = parse(&scrape(address));
+    let status = muck.wait().expect("muck didn't run");
+    assert!(status.success());
+
+    let sent = first
+        .iter()
+        .filter(|(name, _, _)| name == "gossip_messages_sent_total")
+        .count();
+    assert_eq!(sent, NODES, "one sample per node");
+    assert!(
+        messages_sent(&second) > messages_sent(&first),
+        "{} sent, then {}",
+        messages_sent(&first),
+        messages_sent(&second)
+    );
+    assert!(second
+        .iter()
+        .any(|(name, labels, value)| name == "gossip_members"
+            && labels.contains("state=\"alive\"")
+            && *value > 0.0));
+}
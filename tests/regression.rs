@@ -0,0 +1,45 @@
+//! Runs scenarios/regression.toml, whose assertions fail the run if the protocol regressed.
+#![cfg(feature = "sim")]
+
+mod common;
+
+use std::process::Output;
+use std::thread;
+
+fn run(args: &[&str]) -> Output {
+    common::muck()
+        .args(["--config", "scenarios/regression.toml"])
+        .args(args)
+        .output()
+        .expect("failed to run muck")
+}
+
+#[test]
+fn cluster_reconverges_after_a_crash() {
+    // cut short before the kill, and with a budget no cluster can keep, the run must fail
+    let broken = thread::spawn(|| {
+        run(&[
+            "--duration",
+            "4s",
+            "--assert-max-messages-per-node-per-sec",
+            "1",
+        ])
+    });
+    let output = run(&[]);
+    common::assert_success(&output);
+
+    let output = broken.join().expect("broken run panicked");
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "the broken run passed");
+    for failure in [
+        "datagrams per node and second, expected at most 1",
+        "no kill was watched to the end",
+    ] {
+        assert!(
+            errors.contains(failure),
+            "{:?} missing:\n{}",
+            failure,
+            errors
+        );
+    }
+}
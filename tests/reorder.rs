@@ -0,0 +1,52 @@
+//! Runs scenarios/reorder.toml and checks that the nodes kept the newest heartbeats.
+#![cfg(feature = "sim")]
+
+mod common;
+
+use muck::report::RunReport;
+
+fn run() -> RunReport {
+    common::run_report("reorder", ["--config", "scenarios/reorder.toml"])
+}
+
+#[test]
+fn reordered_heartbeats_never_replace_newer_ones() {
+    let report = run();
+    let nodes = report.config["nodes"].as_f64().unwrap() as f32;
+    assert_eq!(
+        report.results.final_fully_informed, nodes,
+        "{:?}",
+        report.results
+    );
+
+    let reordered = report
+        .results
+        .faults
+        .as_ref()
+        .map_or(0, |faults| faults.reordered);
+    let stale: u64 = report
+        .nodes
+        .iter()
+        .map(|node| node.stats.stale_heartbeats)
+        .sum();
+    assert!(reordered > 0, "no datagram was reordered");
+    assert!(
+        stale > 0,
+        "{} datagrams reordered, but no heartbeat arrived stale",
+        reordered
+    );
+
+    // a stale heartbeat stored over a newer one would set its node back by up to the reorder
+    // window at every hop, until the node got suspected
+    let suspect_timeout = report.config["suspect_timeout"].as_str().unwrap();
+    let suspect_timeout = humantime::parse_duration(suspect_timeout).unwrap();
+    for node in &report.nodes {
+        let lag = node.heartbeat_lag.expect("every node runs until the end");
+        assert!(
+            lag < suspect_timeout.as_secs(),
+            "node {} stores heartbeats {}s behind the latest ones",
+            node.index,
+            lag
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Runs the metrics loop for a few ticks over in-memory nodes and checks the CSV it writes.
+#![cfg(feature = "sim")]
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::Duration;
+
+use muck::memory_net::MemoryNetwork;
+use muck::plot::{plot, PlotSettings, Sample, SampleWriter};
+use muck::sim::{SimConfig, Simulation};
+
+const NODES: u64 = 3;
+
+/// A samples file the test can read back once the loop is done.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn samples_file_has_a_row_per_sample() {
+    let simulation = Simulation::new(SimConfig {
+        memory_network: Some(Arc::new(MemoryNetwork::new())),
+        ..SimConfig::new(NODES)
+    });
+    for index in 0..NODES {
+        simulation.start(index).expect("node didn't start");
+    }
+
+    let buffer = SharedBuffer::default();
+    let samples = SampleWriter::new(Box::new(buffer.clone())).unwrap();
+    let (_summary, summary_requested) = mpsc::channel();
+    let config = simulation.config();
+    let settings = PlotSettings {
+        header: String::new(),
+        suspect_timeout: config.suspect_timeout,
+        dead_timeout: config.dead_timeout,
+        clock: simulation.timeline().time.clock().clone(),
+        headless: true,
+        faults: None,
+        samples: Some(samples),
+        sample_interval: Duration::from_millis(200),
+        convergence_samples: 3,
+        churn_from: None,
+        node_groups: vec![],
+        byzantine: HashSet::new(),
+        links: vec![],
+        startup_ramp: false,
+        chart_heartbeats_received: false,
+    };
+    let plotting = plot(
+        simulation.cluster().clone(),
+        None,
+        summary_requested,
+        settings,
+        simulation.timeline().clone(),
+    );
+    sleep(Duration::from_millis(1100));
+    simulation.stop();
+    let report = plotting.join().expect("metrics loop panicked");
+    simulation.shutdown();
+
+    let csv = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some(Sample::CSV_HEADER));
+    let columns = Sample::CSV_HEADER.split(',').count();
+    let rows: Vec<&str> = lines.collect();
+    assert!(rows.len() >= 3, "{}", csv);
+    assert_eq!(rows.len(), report.samples.len());
+    for row in &rows {
+        assert_eq!(row.split(',').count(), columns, "{}", row);
+        assert!(row.starts_with(|c: char| c.is_ascii_digit()), "{}", row);
+    }
+    let nodes: Vec<&str> = rows
+        .iter()
+        .map(|row| row.split(',').nth(1).unwrap())
+        .collect();
+    assert!(nodes.iter().all(|n| *n == NODES.to_string()), "{}", csv);
+}
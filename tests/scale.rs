@@ -0,0 +1,33 @@
+//! Smoke version of scenarios/scale.toml with 500 nodes, cut down to 20 seconds with more frequent
+//! heartbeats and samples, so that it is cheap enough for every `cargo test`, debug builds
+//! included.
+#![cfg(feature = "sim")]
+
+mod common;
+
+#[test]
+fn five_hundred_nodes_in_memory() {
+    let output = common::muck()
+        .args(["--config", "scenarios/scale.toml"])
+        .args(["--nodes", "500", "--put-value", "5s", "--duration", "20s"])
+        .args(["--heartbeat-interval", "2s", "--sample-interval", "2s"])
+        .output()
+        .expect("failed to run muck");
+    common::assert_success(&output);
+    let report = String::from_utf8_lossy(&output.stdout);
+
+    let peak_fully_informed: f32 = report
+        .lines()
+        .find_map(|line| line.strip_prefix("Fully informed nodes: peak "))
+        .and_then(|rest| rest.split(',').next())
+        .and_then(|peak| peak.parse().ok())
+        .unwrap_or_else(|| panic!("no fully informed nodes in the report:\n{}", report));
+    assert!(
+        peak_fully_informed > 0.0,
+        "the cluster never formed:\n{}",
+        report
+    );
+    for line in ["Messages per node and second: ", "Value of greeting "] {
+        assert!(report.contains(line), "{:?} missing:\n{}", line, report);
+    }
+}
@@ -0,0 +1,75 @@
+//! Traces a heartbeat through a tiny cluster and checks the dissemination graph written as DOT.
+#![cfg(feature = "sim")]
+
+mod common;
+
+use std::path::PathBuf;
+use std::process;
+
+const NODES: usize = 4;
+
+/// Runs a cluster whose nodes don't forward, so the traced heartbeat of node 0 goes straight
+/// from it to every other node, and reads the graph.
+fn run() -> String {
+    let dot: PathBuf = std::env::temp_dir().join(format!("muck-trace-{}.dot", process::id()));
+    let output = common::muck()
+        .args([
+            "--nodes",
+            &NODES.to_string(),
+            "--seeds",
+            "1",
+            "--spread",
+            "5",
+        ])
+        .args(["--heartbeat-interval", "2s", "--in-memory", "--headless"])
+        .args(["--event", "at=0s action=stop-forwarding nodes=all"])
+        .args(["--duration", "9s", "--seed", "1"])
+        .args(["--trace-heartbeat", "0@5s", "--trace-dot"])
+        .arg(&dot)
+        .output()
+        .expect("failed to run muck");
+    common::assert_success(&output);
+    let graph = std::fs::read_to_string(&dot).expect("no graph written");
+    let _ = std::fs::remove_file(&dot);
+    graph
+}
+
+#[test]
+fn traced_heartbeat_is_written_as_dot() {
+    let graph = run();
+    let lines: Vec<&str> = graph.lines().collect();
+    assert_eq!(lines.first(), Some(&"digraph heartbeat {"), "{}", graph);
+    assert_eq!(lines.last(), Some(&"}"), "{}", graph);
+
+    let mut nodes = vec![];
+    let mut edges = vec![];
+    for line in &lines[1..lines.len() - 1] {
+        let statement = line
+            .trim()
+            .strip_suffix(';')
+            .unwrap_or_else(|| panic!("unterminated statement {:?}", line));
+        // every quote opens or closes a string, none is escaped in this graph
+        assert_eq!(statement.matches('"').count() % 2, 0, "{:?}", line);
+        if statement.starts_with("label=") || statement.starts_with("node ") {
+            continue;
+        }
+        let (head, attributes) = statement
+            .split_once(" [")
+            .unwrap_or_else(|| panic!("no attributes in {:?}", line));
+        assert!(attributes.ends_with(']'), "{:?}", line);
+        match head.split_once(" -> ") {
+            Some((from, to)) => edges.push((from.to_string(), to.to_string())),
+            None => nodes.push(head.to_string()),
+        }
+    }
+
+    assert_eq!(nodes.len(), NODES, "{}", graph);
+    // one datagram from node 0 to each of the others, none passed on
+    let mut expected: Vec<(String, String)> = (1..NODES)
+        .map(|node| ("\"0\"".to_string(), format!("\"{}\"", node)))
+        .collect();
+    edges.sort();
+    expected.sort();
+    assert_eq!(edges, expected, "{}", graph);
+    assert!(!graph.contains("not reached"), "{}", graph);
+}